@@ -1,14 +1,14 @@
 import { ShelfPublic, Item } from "@/../../declarations/perpetua/perpetua.did";
 
 /**
- * Retrieves items from a shelf, sorted according to their positions.
+ * Retrieves items from a shelf, in the order given by its item_order.
  *
  * @param shelf - The ShelfPublic object.
- * @returns An array of Item objects, sorted by their defined positions.
- *          Returns an empty array if items or positions are missing or malformed.
+ * @returns An array of Item objects, in display order.
+ *          Returns an empty array if items or the order are missing or malformed.
  */
 export const getOrderedItems = (shelf: ShelfPublic): Item[] => {
-  if (!shelf || !shelf.items || !shelf.item_positions) {
+  if (!shelf || !shelf.items || !shelf.item_order) {
     return [];
   }
 
@@ -24,19 +24,11 @@ export const getOrderedItems = (shelf: ShelfPublic): Item[] => {
     }
   }
 
-  // Sort item_positions. Each element is [nat32, float64].
-  // We sort by the float64 value.
-  const sortedPositions = [...shelf.item_positions].sort((a, b) => {
-    // a[0] is id (nat32), a[1] is position (float64)
-    const posA = a[1];
-    const posB = b[1];
-    return posA - posB;
-  });
-
-  // Map sorted positions back to Item objects.
+  // item_order is vec nat32, the item ids already sorted for display.
+  // Map them back to Item objects.
   const orderedItems: Item[] = [];
-  for (const positionRecord of sortedPositions) {
-    const itemId = Number(positionRecord[0]); // Convert nat32 to number
+  for (const orderedId of shelf.item_order) {
+    const itemId = Number(orderedId); // Convert nat32 to number
     const item = itemsMap.get(itemId);
     if (item) {
       orderedItems.push(item);
@@ -33,12 +33,12 @@ import { usePerpetua } from "@/hooks/actors";
  */
 const denormalizeShelf = (normalizedShelf: NormalizedShelf): ShelfPublic => {
   // Assuming NormalizedShelf has a structure that needs mapping to ShelfPublic
-  // Particularly for fields like `items` and `item_positions` which are BTreeMaps in Candid
+  // Particularly for fields like `items` which are BTreeMaps in Candid
   const itemsArray: Array<[number, PerpetuaItem]> = normalizedShelf.items
     ? Object.entries(normalizedShelf.items).map(([key, value]) => [Number(key), value as unknown as PerpetuaItem])
     : [];
-  const itemPositionsArray: Array<[number, number]> = normalizedShelf.item_positions
-    ? normalizedShelf.item_positions.map(pos => [pos[0], pos[1]])
+  const itemOrderArray: Array<number> = normalizedShelf.item_order
+    ? [...normalizedShelf.item_order]
     : [];
 
   return {
@@ -47,7 +47,7 @@ const denormalizeShelf = (normalizedShelf: NormalizedShelf): ShelfPublic => {
     description: normalizedShelf.description || [], // Ensure it's an array
     owner: Principal.fromText(normalizedShelf.owner),
     items: itemsArray,
-    item_positions: itemPositionsArray,
+    item_order: itemOrderArray,
     created_at: BigInt(normalizedShelf.created_at),
     updated_at: BigInt(normalizedShelf.updated_at),
     appears_in: normalizedShelf.appears_in || [],
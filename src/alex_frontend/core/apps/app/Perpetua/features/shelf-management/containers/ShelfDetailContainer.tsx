@@ -83,18 +83,17 @@ export const ShelfDetailContainer: React.FC<ShelfDetailProps> = ({
 				.map(id => itemMap.has(id) ? [id, itemMap.get(id)!] as [number, Item] : null)
 				.filter((item): item is [number, Item] => item !== null);
 		}
-		if (!shelf.item_positions || !shelf.items) return [];
-		const positionEntries = shelf.item_positions.map(([id, position]) => ({ id, position }));
-		positionEntries.sort((a, b) => a.position - b.position);
-		return positionEntries
-			.map(({ id }) => {
+		if (!shelf.item_order || !shelf.items) return [];
+		// item_order already lists the item ids in display order
+		return shelf.item_order
+			.map(id => {
 				const itemPair = shelf.items?.find(([itemId]) => itemId === id);
 				return itemPair ? itemPair : null;
 			})
 			.filter((item): item is [number, Item] => item !== null);
 	}, [
 		shelf.items, 
-		shelf.item_positions, 
+		shelf.item_order, 
 		optimisticItemOrder 
 	]);
 	
@@ -105,7 +105,7 @@ export const useAddToShelf = () => {
           title: `Shelf ${shelfId.substring(0,5)}...`, 
           description: [],
           items: [], 
-          item_positions: [],
+          item_order: [],
           created_at: BigInt(0), 
           updated_at: BigInt(0), 
           appears_in: [],
@@ -384,7 +384,7 @@ const perpetuaSlice = createSlice({
           items: [], 
           tags: [],
           appears_in: [],
-          item_positions: [],
+          item_order: [],
           created_at: nowString, // Store as string (Matches updated type)
           updated_at: nowString, // Store as string (Matches updated type)
           public_editing: false, 
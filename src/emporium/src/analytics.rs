@@ -0,0 +1,249 @@
+use crate::listing_index::CURRENCIES;
+use crate::{
+    Bundle, Currency, ListingPriceKey, LogAction, SaleKind, SaleRecord, SalesBackfill, SellerSaleKey, TokenSaleKey,
+    VolumeBucket, VolumeKey, HOURLY_VOLUME, LISTED_COUNTS, LISTING, LISTING_PRICES, LOGS, SALES_BACKFILL,
+    SELLER_SALES, TOKEN_SALES,
+};
+use candid::{CandidType, Deserialize, Nat, Principal};
+use ic_cdk::query;
+
+const HOUR_NS: u64 = 60 * 60 * 1_000_000_000;
+const BACKFILL_BATCH_SIZE: usize = 500;
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum StatsRange {
+    Day,
+    Week,
+    Month,
+}
+
+impl StatsRange {
+    fn hours(&self) -> u64 {
+        match self {
+            StatsRange::Day => 24,
+            StatsRange::Week => 7 * 24,
+            StatsRange::Month => 30 * 24,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct MarketStats {
+    pub currency: Currency,
+    pub floor_price: Option<u64>, // cheapest unexpired single listing
+    pub listed_count: u64,        // single listings, bundles aren't counted
+    pub sales: u64,
+    pub volume: u64,
+    pub average_price: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SaleHistory {
+    pub sales: Vec<SaleRecord>,
+    pub total_pages: u64,
+    pub current_page: u64,
+    pub page_size: u64,
+}
+
+/**
+ * Floor price, listed count and the trailing sales volume, one entry per currency.
+ * Volume is counted in whole hours, so a Day covers the current hour and the 23 before it.
+ */
+#[query]
+pub fn get_market_stats(range: StatsRange) -> Vec<MarketStats> {
+    let now = ic_cdk::api::time();
+    let current_hour = now / HOUR_NS;
+    let first_hour = (current_hour + 1).saturating_sub(range.hours());
+
+    CURRENCIES
+        .iter()
+        .map(|currency| {
+            let start = VolumeKey { currency: *currency, hour: first_hour };
+            let end = VolumeKey { currency: *currency, hour: current_hour };
+            let totals = HOURLY_VOLUME.with(|volume| {
+                volume
+                    .borrow()
+                    .range(start..=end)
+                    .fold(VolumeBucket::default(), |mut totals, (_, bucket)| {
+                        totals.sales += bucket.sales;
+                        totals.volume = totals.volume.saturating_add(bucket.volume);
+                        totals
+                    })
+            });
+            MarketStats {
+                currency: *currency,
+                floor_price: floor_price(*currency, now),
+                listed_count: LISTED_COUNTS.with(|counts| counts.borrow().get(currency).unwrap_or(0)),
+                sales: totals.sales,
+                volume: totals.volume,
+                average_price: (totals.sales > 0).then(|| totals.volume / totals.sales),
+            }
+        })
+        .collect()
+}
+
+/**
+ * Every sale of a token, oldest first.
+ */
+#[query]
+pub fn get_token_price_history(token_id: Nat) -> Vec<SaleRecord> {
+    let start = TokenSaleKey { token_id: token_id.to_string(), time: 0 };
+    let end = TokenSaleKey { token_id: token_id.to_string(), time: u64::MAX };
+    TOKEN_SALES.with(|sales| sales.borrow().range(start..=end).map(|(_, sale)| sale).collect())
+}
+
+/**
+ * Sales made by a seller, newest first.
+ */
+#[query]
+pub fn get_seller_price_history(seller: Principal, page: Option<u64>, page_size: Option<u64>) -> SaleHistory {
+    let page = page.unwrap_or(1).max(1);
+    let page_size = page_size.unwrap_or(10).clamp(1, 100);
+
+    let start = SellerSaleKey { seller, time: 0, token_id: String::new() };
+    let end = SellerSaleKey { seller, time: u64::MAX, token_id: String::new() };
+    let mut sales: Vec<SaleRecord> =
+        SELLER_SALES.with(|sales| sales.borrow().range(start..end).map(|(_, sale)| sale).collect());
+    sales.reverse();
+    let total_pages = (sales.len() as u64).div_ceil(page_size);
+    let sales = sales
+        .into_iter()
+        .skip(((page - 1) * page_size) as usize)
+        .take(page_size as usize)
+        .collect();
+
+    SaleHistory {
+        sales,
+        total_pages,
+        current_page: page,
+        page_size,
+    }
+}
+
+pub fn record_sale(time: u64, token_id: &Nat, seller: Principal, buyer: Principal, price: u64, currency: Currency, kind: SaleKind) {
+    add_volume(currency, time, price);
+    add_sale_record(SaleRecord {
+        token_id: token_id.clone(),
+        seller,
+        buyer,
+        price,
+        currency,
+        time,
+        kind,
+    });
+}
+
+/// A bundle counts as one sale in the volume, each token's history gets its share of the price
+pub fn record_bundle_sale(time: u64, bundle: &Bundle, buyer: Principal) {
+    add_volume(bundle.currency, time, bundle.price);
+    let count = bundle.token_ids.len() as u64;
+    for (i, token_id) in bundle.token_ids.iter().enumerate() {
+        let share = bundle.price / count + if i == 0 { bundle.price % count } else { 0 };
+        add_sale_record(SaleRecord {
+            token_id: token_id.clone(),
+            seller: bundle.owner,
+            buyer,
+            price: share,
+            currency: bundle.currency,
+            time,
+            kind: SaleKind::Bundle { bundle_id: bundle.bundle_id },
+        });
+    }
+}
+
+/// Queues the replay of the logs into the sales history the first time analytics run, called on upgrade.
+///
+/// Only the logs written so far are replayed, later sales are recorded as they happen.
+pub fn start_sales_backfill() {
+    if SALES_BACKFILL.with(|cell| *cell.borrow().get() != SalesBackfill::NotStarted) {
+        return;
+    }
+    let last_log = LOGS.with(|logs| logs.borrow().last_key_value().map(|(key, _)| key));
+    let backfill = match last_log {
+        Some(last_log) if TOKEN_SALES.with(|sales| sales.borrow().is_empty()) => {
+            SalesBackfill::Running { next_log: 0, last_log }
+        }
+        _ => SalesBackfill::Done,
+    };
+    set_sales_backfill(backfill);
+}
+
+/// Replays the next batch of logs into the sales history, called from the timer.
+///
+/// Bundle sales don't log their price per token, so only single sales, offers and auctions are replayed.
+pub fn backfill_sales_from_logs() {
+    let SalesBackfill::Running { next_log, last_log } = SALES_BACKFILL.with(|cell| cell.borrow().get().clone()) else {
+        return;
+    };
+    let batch: Vec<_> = LOGS.with(|logs| {
+        logs.borrow()
+            .range(next_log..=last_log)
+            .take(BACKFILL_BATCH_SIZE)
+            .collect()
+    });
+    for (_, log) in &batch {
+        let (price, currency, kind) = match log.action {
+            LogAction::Sold { price, currency, .. } => (price, currency.unwrap_or_default(), SaleKind::Listing),
+            LogAction::OfferAccepted { amount, .. } => (amount, Currency::ICP, SaleKind::Offer),
+            LogAction::AuctionSold { price, .. } => (price, Currency::ICP, SaleKind::Auction),
+            _ => continue,
+        };
+        record_sale(log.timestamp, &log.token_id, log.seller, log.buyer, price, currency, kind);
+    }
+    let backfill = match batch.last() {
+        Some((key, _)) if batch.len() == BACKFILL_BATCH_SIZE && *key < last_log => {
+            SalesBackfill::Running { next_log: key + 1, last_log }
+        }
+        _ => SalesBackfill::Done,
+    };
+    set_sales_backfill(backfill);
+}
+
+fn set_sales_backfill(backfill: SalesBackfill) {
+    SALES_BACKFILL
+        .with(|cell| cell.borrow_mut().set(backfill))
+        .expect("Failed to save sales backfill progress");
+}
+
+fn floor_price(currency: Currency, now: u64) -> Option<u64> {
+    let start = ListingPriceKey { currency, price: 0, token_id: String::new() };
+    LISTING_PRICES.with(|index| {
+        index
+            .borrow()
+            .range(start..)
+            .take_while(|(key, _)| key.currency == currency)
+            .find(|(key, _)| {
+                // Expired listings stay indexed until the timer returns them
+                LISTING
+                    .with(|nfts| nfts.borrow().get(&key.token_id))
+                    .is_some_and(|nft| !nft.is_expired(now))
+            })
+            .map(|(key, _)| key.price)
+    })
+}
+
+fn add_volume(currency: Currency, time: u64, price: u64) {
+    let key = VolumeKey { currency, hour: time / HOUR_NS };
+    HOURLY_VOLUME.with(|volume| {
+        let mut volume = volume.borrow_mut();
+        let mut bucket = volume.get(&key).unwrap_or_default();
+        bucket.sales += 1;
+        bucket.volume = bucket.volume.saturating_add(price);
+        volume.insert(key, bucket);
+    });
+}
+
+fn add_sale_record(sale: SaleRecord) {
+    let token_id = sale.token_id.to_string();
+    SELLER_SALES.with(|sales| {
+        sales.borrow_mut().insert(
+            SellerSaleKey {
+                seller: sale.seller,
+                time: sale.time,
+                token_id: token_id.clone(),
+            },
+            sale.clone(),
+        )
+    });
+    TOKEN_SALES.with(|sales| sales.borrow_mut().insert(TokenSaleKey { token_id, time: sale.time }, sale));
+}
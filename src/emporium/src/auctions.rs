@@ -0,0 +1,506 @@
+use crate::update::{
+    add_log, deposit_nft_to_canister, escrow_icp_from_caller, transfer_icp_from_canister,
+    transfer_nft_from_canister, ICP_TRANSFER_FEE,
+};
+use crate::analytics::record_sale;
+use crate::royalties::{distribute_sale_payment, get_sale_split};
+use crate::utils::{call_deduct_marketplace_fee, get_token_owner, is_owner};
+use crate::{
+    not_anon, Currency, Auction, AuctionStatus, Bid, CallerGuard, LogAction, SaleKind, AUCTIONS, OPEN_AUCTION_ENDS,
+    PENDING_BID_REFUNDS,
+};
+use candid::{CandidType, Deserialize, Nat, Principal};
+use ic_cdk::{caller, query, update};
+
+const MIN_AUCTION_DURATION_NS: u64 = 60 * 60 * 1_000_000_000; // 1 hour
+const MAX_AUCTION_DURATION_NS: u64 = 30 * 24 * 60 * 60 * 1_000_000_000; // 30 days
+const MAX_START_DELAY_NS: u64 = 30 * 24 * 60 * 60 * 1_000_000_000; // 30 days
+const ANTI_SNIPING_WINDOW_NS: u64 = 10 * 60 * 1_000_000_000; // bids in the last 10 minutes extend the end to now + 10 minutes
+const SETTLE_BATCH_SIZE: usize = 25;
+const SETTLING_TIMEOUT_NS: u64 = 10 * 60 * 1_000_000_000; // a settlement still Settling after 10 minutes trapped and is resumed
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Auctions {
+    pub auctions: Vec<Auction>,
+    pub total_pages: u64,
+    pub current_page: u64,
+    pub page_size: u64,
+}
+
+/**
+ * Start an English auction for an NFT the caller owns, approved to emporium (icrc37) like for list_nft.
+ * The NFT is escrowed until settlement. A start_time in the past starts the auction right away.
+ * The winning bid is paid out less royalty and platform fee, whose payout ledger fees the seller also covers,
+ * so the reserve price must be high enough to cover those fees.
+ * Returns the auction id.
+ */
+#[update(guard = "not_anon")]
+pub async fn create_auction(
+    token_id: Nat,
+    reserve_price: u64,
+    start_time: u64,
+    end_time: u64,
+    min_increment: u64,
+) -> Result<u64, String> {
+    let _guard: CallerGuard = CallerGuard::new(caller())?;
+    let now = ic_cdk::api::time();
+    let start_time = start_time.max(now);
+
+    if reserve_price < 1 || min_increment < 1 {
+        return Err("Reserve price and minimum increment should be greater than 1 e8s ICP".to_string());
+    }
+    if start_time > now.saturating_add(MAX_START_DELAY_NS) {
+        return Err("Auction must start within 30 days".to_string());
+    }
+    if end_time < start_time.saturating_add(MIN_AUCTION_DURATION_NS)
+        || end_time > start_time.saturating_add(MAX_AUCTION_DURATION_NS)
+    {
+        return Err("Auction must run between 1 hour and 30 days".to_string());
+    }
+
+    match is_owner(caller(), token_id.clone()).await {
+        Ok(true) => {}
+        Ok(false) => return Err("You can't auction this NFT, ownership proof failed!".to_string()),
+        Err(_) => return Err("Something went wrong !".to_string()),
+    };
+    // Every winning bid is at least the reserve, so a reserve that covers the payouts means the sale can be paid out
+    get_sale_split(&token_id, caller(), reserve_price, Currency::ICP)
+        .await?
+        .seller_covers_payout_fees(ICP_TRANSFER_FEE)?;
+    call_deduct_marketplace_fee().await?;
+    deposit_nft_to_canister(token_id.clone()).await?;
+
+    let auction_id = AUCTIONS.with(|auctions| {
+        auctions
+            .borrow()
+            .last_key_value()
+            .map(|(id, _)| id + 1)
+            .unwrap_or(1)
+    });
+    let auction = Auction {
+        auction_id,
+        token_id: token_id.clone(),
+        seller: caller(),
+        reserve_price,
+        min_increment,
+        start_time,
+        end_time,
+        highest_bid: None,
+        bid_count: 0,
+        status: AuctionStatus::Active,
+        settling_since: None,
+    };
+    AUCTIONS.with(|auctions| auctions.borrow_mut().insert(auction_id, auction));
+    OPEN_AUCTION_ENDS.with(|ends| ends.borrow_mut().insert((end_time, auction_id), ()));
+
+    add_log(
+        ic_cdk::api::time(),
+        token_id,
+        caller(),
+        Principal::anonymous(),
+        LogAction::AuctionCreated { auction_id, reserve_price },
+    );
+    Ok(auction_id)
+}
+
+/**
+ * Bid on a running auction. The bid plus one ledger fee is escrowed with icrc2_transfer_from,
+ * so approve amount + 2 * fee to emporium first. The previous top bid is refunded.
+ * Bids may be below the reserve price, the NFT is only sold if the reserve is met.
+ */
+#[update(guard = "not_anon")]
+pub async fn place_bid(auction_id: u64, amount: u64) -> Result<String, String> {
+    let _guard: CallerGuard = CallerGuard::new(caller())?;
+    let bidder = caller();
+
+    let auction = get_auction(auction_id).ok_or("Auction doesn't exists")?;
+    if auction.seller == bidder {
+        return Err("You can't bid on your own auction".to_string());
+    }
+    check_bid(&auction, amount, ic_cdk::api::time())?;
+
+    call_deduct_marketplace_fee().await?;
+    escrow_icp_from_caller(amount + ICP_TRANSFER_FEE).await?;
+
+    // Someone may have outbid us, or the auction ended, while the ICP was being escrowed
+    let now = ic_cdk::api::time();
+    let mut auction = get_auction(auction_id).ok_or("Auction doesn't exists")?;
+    if let Err(err) = check_bid(&auction, amount, now) {
+        refund_bid(auction_id, &auction.token_id, bidder, amount).await;
+        return Err(format!("{}, your ICP was refunded", err));
+    }
+
+    let previous_bid = auction.highest_bid.replace(Bid { bidder, amount, time: now });
+    auction.bid_count += 1;
+    let extended = auction.end_time - now < ANTI_SNIPING_WINDOW_NS;
+    if extended {
+        OPEN_AUCTION_ENDS.with(|ends| {
+            let mut ends = ends.borrow_mut();
+            ends.remove(&(auction.end_time, auction_id));
+            ends.insert((now + ANTI_SNIPING_WINDOW_NS, auction_id), ());
+        });
+        auction.end_time = now + ANTI_SNIPING_WINDOW_NS;
+    }
+    AUCTIONS.with(|auctions| auctions.borrow_mut().insert(auction_id, auction.clone()));
+
+    add_log(
+        now,
+        auction.token_id.clone(),
+        auction.seller,
+        bidder,
+        LogAction::BidPlaced { auction_id, amount },
+    );
+    if extended {
+        add_log(
+            now,
+            auction.token_id.clone(),
+            auction.seller,
+            bidder,
+            LogAction::AuctionExtended { auction_id, end_time: auction.end_time },
+        );
+    }
+
+    if let Some(previous) = previous_bid {
+        refund_bid(auction_id, &auction.token_id, previous.bidder, previous.amount).await;
+    }
+    Ok("Bid placed".to_string())
+}
+
+/**
+ * Cancel an auction that has no bids yet and get the NFT back.
+ */
+#[update(guard = "not_anon")]
+pub async fn cancel_auction(auction_id: u64) -> Result<String, String> {
+    let _guard: CallerGuard = CallerGuard::new(caller())?;
+
+    let auction = get_auction(auction_id).ok_or("Auction doesn't exists")?;
+    if auction.seller != caller() {
+        return Err("Unauthorized !".to_string());
+    }
+    if auction.status != AuctionStatus::Active || auction.highest_bid.is_some() {
+        return Err("Only auctions without bids can be cancelled".to_string());
+    }
+
+    call_deduct_marketplace_fee().await?;
+    // Fee call may have let a bid in
+    let auction = get_auction(auction_id).ok_or("Auction doesn't exists")?;
+    if auction.status != AuctionStatus::Active || auction.highest_bid.is_some() {
+        return Err("Only auctions without bids can be cancelled".to_string());
+    }
+    set_auction_status(auction_id, AuctionStatus::Cancelled);
+
+    if let Err(err) = transfer_nft_from_canister(auction.seller, auction.token_id.clone()).await {
+        set_auction_status(auction_id, AuctionStatus::Active);
+        OPEN_AUCTION_ENDS.with(|ends| ends.borrow_mut().insert((auction.end_time, auction_id), ()));
+        return Err(err);
+    }
+    OPEN_AUCTION_ENDS.with(|ends| ends.borrow_mut().remove(&(auction.end_time, auction_id)));
+
+    add_log(
+        ic_cdk::api::time(),
+        auction.token_id,
+        auction.seller,
+        Principal::anonymous(),
+        LogAction::AuctionCancelled { auction_id },
+    );
+    Ok("Auction cancelled".to_string())
+}
+
+#[query]
+pub fn get_auction(auction_id: u64) -> Option<Auction> {
+    AUCTIONS.with(|auctions| auctions.borrow().get(&auction_id))
+}
+
+/**
+ * Running and upcoming auctions, ending soonest first.
+ */
+#[query]
+pub fn get_auctions(page: Option<u64>, page_size: Option<u64>) -> Auctions {
+    let page = page.unwrap_or(1).max(1);
+    let page_size = page_size.unwrap_or(10).clamp(1, 100);
+    let now = ic_cdk::api::time();
+
+    let auctions: Vec<Auction> = OPEN_AUCTION_ENDS.with(|ends| {
+        ends.borrow()
+            .range((now, 0)..)
+            .filter_map(|((_, auction_id), _)| get_auction(auction_id))
+            .filter(|auction| auction.status == AuctionStatus::Active)
+            .collect()
+    });
+    let total_pages = (auctions.len() as u64).div_ceil(page_size);
+    let auctions = auctions
+        .into_iter()
+        .skip(((page - 1) * page_size) as usize)
+        .take(page_size as usize)
+        .collect();
+
+    Auctions {
+        auctions,
+        total_pages,
+        current_page: page,
+        page_size,
+    }
+}
+
+/// Settles ended auctions, resumes stalled settlements and retries failed payouts and refunds, called from the timer
+pub async fn settle_auctions() {
+    let now = ic_cdk::api::time();
+
+    let due: Vec<u64> = OPEN_AUCTION_ENDS.with(|ends| {
+        ends.borrow()
+            .range(..=(now, u64::MAX))
+            .take(SETTLE_BATCH_SIZE)
+            .map(|((_, auction_id), _)| auction_id)
+            .collect()
+    });
+    for auction_id in due {
+        let Some(auction) = get_auction(auction_id) else {
+            continue;
+        };
+        let finished = match auction.status {
+            AuctionStatus::Active => settle_auction(auction.clone()).await,
+            AuctionStatus::Settling
+                if auction.settling_since.is_none_or(|since| now.saturating_sub(since) >= SETTLING_TIMEOUT_NS) =>
+            {
+                ic_cdk::println!("Auction {} settlement stalled, resuming", auction_id);
+                settle_auction(auction.clone()).await
+            }
+            AuctionStatus::Settling => false,
+            _ => true,
+        };
+        if finished {
+            OPEN_AUCTION_ENDS.with(|ends| ends.borrow_mut().remove(&(auction.end_time, auction_id)));
+        }
+    }
+
+    let refunds: Vec<((u64, Principal), u64)> = PENDING_BID_REFUNDS.with(|refunds| {
+        refunds.borrow().iter().take(SETTLE_BATCH_SIZE).collect()
+    });
+    for ((auction_id, bidder), amount) in refunds {
+        if transfer_icp_from_canister(amount, bidder).await.is_ok() {
+            PENDING_BID_REFUNDS.with(|refunds| refunds.borrow_mut().remove(&(auction_id, bidder)));
+            if let Some(auction) = get_auction(auction_id) {
+                add_log(
+                    ic_cdk::api::time(),
+                    auction.token_id,
+                    auction.seller,
+                    bidder,
+                    LogAction::BidRefunded { auction_id, amount },
+                );
+            }
+        }
+    }
+}
+
+// Returns whether the auction no longer needs the timer.
+// Also resumes a settlement that trapped half way, so every step must be safe to repeat.
+async fn settle_auction(auction: Auction) -> bool {
+    let auction_id = auction.auction_id;
+    let settling_since = ic_cdk::api::time();
+    set_auction_status(auction_id, AuctionStatus::Settling);
+
+    // A sale whose price no longer covers the payout fees, as the royalty settings changed
+    // since the auction was created, ends unsold like one below the reserve
+    let sale = match auction.highest_bid.clone() {
+        Some(bid) if bid.amount >= auction.reserve_price => {
+            let split = get_sale_split(&auction.token_id, auction.seller, bid.amount, Currency::ICP).await;
+            if !is_settling(auction_id, settling_since) {
+                return false;
+            }
+            match split.map(|split| split.seller_covers_payout_fees(ICP_TRANSFER_FEE)) {
+                Ok(Ok(split)) => Some((bid, split)),
+                Ok(Err(err)) => {
+                    ic_cdk::println!("Auction {} can't be paid out, returning the NFT: {}", auction_id, err);
+                    None
+                }
+                Err(err) => {
+                    ic_cdk::println!("Auction {} sale split failed, will retry: {}", auction_id, err);
+                    set_auction_status(auction_id, AuctionStatus::Active);
+                    return false;
+                }
+            }
+        }
+        _ => None,
+    };
+
+    match sale {
+        Some((bid, split)) => {
+            let delivery = release_nft(bid.bidder, auction.token_id.clone()).await;
+            if !is_settling(auction_id, settling_since) {
+                return false;
+            }
+            if let Err(err) = delivery {
+                ic_cdk::println!("Auction {} NFT delivery failed, will retry: {}", auction_id, err);
+                set_auction_status(auction_id, AuctionStatus::Active);
+                return false;
+            }
+            add_log(
+                ic_cdk::api::time(),
+                auction.token_id.clone(),
+                auction.seller,
+                bid.bidder,
+                LogAction::AuctionSold { auction_id, price: bid.amount },
+            );
+            set_auction_status(auction_id, AuctionStatus::Sold);
+            record_sale(
+                ic_cdk::api::time(),
+                &auction.token_id,
+                auction.seller,
+                bid.bidder,
+                bid.amount,
+                Currency::ICP,
+                SaleKind::Auction,
+            );
+            // Failed payouts are queued and retried by the timer
+            distribute_sale_payment(&auction.token_id, auction.seller, &split, ICP_TRANSFER_FEE).await;
+            true
+        }
+        None => {
+            let delivery = release_nft(auction.seller, auction.token_id.clone()).await;
+            if !is_settling(auction_id, settling_since) {
+                return false;
+            }
+            if let Err(err) = delivery {
+                ic_cdk::println!("Auction {} NFT return failed, will retry: {}", auction_id, err);
+                set_auction_status(auction_id, AuctionStatus::Active);
+                return false;
+            }
+            set_auction_status(auction_id, AuctionStatus::Unsold);
+            add_log(
+                ic_cdk::api::time(),
+                auction.token_id.clone(),
+                auction.seller,
+                Principal::anonymous(),
+                LogAction::AuctionUnsold { auction_id },
+            );
+            if let Some(bid) = auction.highest_bid {
+                refund_bid(auction_id, &auction.token_id, bid.bidder, bid.amount).await;
+            }
+            true
+        }
+    }
+}
+
+// Whether the settlement attempt started at settling_since still owns the auction,
+// a stalled one may have been resumed by the timer meanwhile
+fn is_settling(auction_id: u64, settling_since: u64) -> bool {
+    get_auction(auction_id).is_some_and(|auction| {
+        auction.status == AuctionStatus::Settling && auction.settling_since == Some(settling_since)
+    })
+}
+
+// Sends the escrowed NFT. One the recipient already holds counts as sent,
+// a resumed settlement finds it there if the first attempt trapped after the transfer.
+async fn release_nft(to: Principal, token_id: Nat) -> Result<(), String> {
+    match transfer_nft_from_canister(to, token_id.clone()).await {
+        Ok(_) => Ok(()),
+        Err(err) => match get_token_owner(token_id).await {
+            Ok(Some(owner)) if owner == to => Ok(()),
+            _ => Err(err),
+        },
+    }
+}
+
+// Refunds an escrowed bid, queueing it for the timer if the ledger call fails
+async fn refund_bid(auction_id: u64, token_id: &Nat, bidder: Principal, amount: u64) {
+    match transfer_icp_from_canister(amount, bidder).await {
+        Ok(_) => {
+            let seller = get_auction(auction_id).map_or(Principal::anonymous(), |a| a.seller);
+            add_log(
+                ic_cdk::api::time(),
+                token_id.clone(),
+                seller,
+                bidder,
+                LogAction::BidRefunded { auction_id, amount },
+            );
+        }
+        Err(err) => {
+            ic_cdk::println!("Refund of bid on auction {} failed, will retry: {}", auction_id, err);
+            PENDING_BID_REFUNDS.with(|refunds| {
+                let mut refunds = refunds.borrow_mut();
+                let owed = refunds.get(&(auction_id, bidder)).unwrap_or(0);
+                refunds.insert((auction_id, bidder), owed + amount);
+            });
+        }
+    }
+}
+
+fn check_bid(auction: &Auction, amount: u64, now: u64) -> Result<(), String> {
+    if auction.status != AuctionStatus::Active || now >= auction.end_time {
+        return Err("Auction is not running".to_string());
+    }
+    if now < auction.start_time {
+        return Err("Auction hasn't started yet".to_string());
+    }
+    let min_bid = auction
+        .highest_bid
+        .as_ref()
+        .map_or(1, |bid| bid.amount.saturating_add(auction.min_increment));
+    if amount < min_bid {
+        return Err(format!("Bid should be at least {} e8s ICP", min_bid));
+    }
+    Ok(())
+}
+
+fn set_auction_status(auction_id: u64, status: AuctionStatus) {
+    AUCTIONS.with(|auctions| {
+        let mut auctions = auctions.borrow_mut();
+        if let Some(mut auction) = auctions.get(&auction_id) {
+            auction.settling_since = (status == AuctionStatus::Settling).then(ic_cdk::api::time);
+            auction.status = status;
+            auctions.insert(auction_id, auction);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const START: u64 = 1_000;
+    const END: u64 = 2_000;
+
+    fn auction(highest_bid: Option<u64>) -> Auction {
+        Auction {
+            auction_id: 1,
+            token_id: Nat::from(7u64),
+            seller: Principal::anonymous(),
+            reserve_price: 500,
+            min_increment: 10,
+            start_time: START,
+            end_time: END,
+            highest_bid: highest_bid.map(|amount| Bid { bidder: Principal::management_canister(), amount, time: START }),
+            bid_count: highest_bid.map_or(0, |_| 1),
+            status: AuctionStatus::Active,
+            settling_since: None,
+        }
+    }
+
+    #[test]
+    fn first_bid_may_be_below_reserve() {
+        assert!(check_bid(&auction(None), 1, START).is_ok());
+        assert!(check_bid(&auction(None), 0, START).is_err());
+    }
+
+    #[test]
+    fn later_bids_must_beat_the_top_bid_by_the_increment() {
+        let auction = auction(Some(100));
+        assert_eq!(check_bid(&auction, 109, START + 1), Err("Bid should be at least 110 e8s ICP".to_string()));
+        assert!(check_bid(&auction, 110, START + 1).is_ok());
+    }
+
+    #[test]
+    fn bids_only_count_while_running() {
+        let auction = auction(None);
+        assert_eq!(check_bid(&auction, 1, START - 1), Err("Auction hasn't started yet".to_string()));
+        assert!(check_bid(&auction, 1, END - 1).is_ok());
+        assert_eq!(check_bid(&auction, 1, END), Err("Auction is not running".to_string()));
+    }
+
+    #[test]
+    fn settling_auctions_take_no_bids() {
+        let mut auction = auction(None);
+        auction.status = AuctionStatus::Settling;
+        assert_eq!(check_bid(&auction, 1, START), Err("Auction is not running".to_string()));
+    }
+}
@@ -0,0 +1,369 @@
+use crate::analytics::record_bundle_sale;
+use crate::royalties::{distribute_sale_payment, escrow_sale_payment, get_sale_split, refund_sale_payment, SaleSplit};
+use crate::update::{add_log, deposit_nft_to_canister, transfer_held_nfts, transfer_nfts_from_canister};
+use crate::utils::{call_deduct_marketplace_fee, get_principal, get_token_accounts, is_owner, Account, EMPORIUM_CANISTER_ID};
+use crate::{not_anon, Bundle, BundleStatus, CallerGuard, Currency, LogAction, BUNDLES, PENDING_NFT_DELIVERIES};
+use candid::{Nat, Principal};
+use ic_cdk::{caller, query, update};
+use std::collections::BTreeSet;
+
+pub const MAX_BUNDLE_SIZE: usize = 20;
+const DELIVERY_BATCH_SIZE: usize = 25;
+
+/**
+ * List several NFTs as one lot at a single price, in ICP unless another allowed currency is given.
+ * Every NFT is escrowed, so approve each of them to emporium first. If one deposit fails the
+ * NFTs already deposited are sent back and nothing is listed.
+ * Returns the bundle id.
+ */
+#[update(guard = "not_anon")]
+pub async fn list_bundle(token_ids: Vec<Nat>, price: u64, currency: Option<Currency>) -> Result<u64, String> {
+    let _guard: CallerGuard = CallerGuard::new(caller())?;
+    let owner = caller();
+    let currency = currency.unwrap_or_default();
+
+    if price < 1 {
+        return Err("Price should greater than 1 unit".to_string());
+    }
+    if token_ids.len() < 2 || token_ids.len() > MAX_BUNDLE_SIZE {
+        return Err(format!("A bundle holds between 2 and {} NFTs", MAX_BUNDLE_SIZE));
+    }
+    let unique: BTreeSet<String> = token_ids.iter().map(|token_id| token_id.to_string()).collect();
+    if unique.len() != token_ids.len() {
+        return Err("A bundle can't contain the same NFT twice".to_string());
+    }
+    for token_id in &token_ids {
+        match is_owner(owner, token_id.clone()).await {
+            Ok(true) => {}
+            Ok(false) => return Err(format!("You can't list NFT {}, ownership proof failed!", token_id)),
+            Err(_) => return Err("Something went wrong !".to_string()),
+        }
+    }
+
+    call_deduct_marketplace_fee().await?;
+    let mut deposited: Vec<Nat> = Vec::new();
+    for token_id in &token_ids {
+        if let Err(err) = deposit_nft_to_canister(token_id.clone()).await {
+            return_nfts(owner, deposited).await;
+            return Err(format!("Depositing NFT {} failed, bundle not listed: {}", token_id, err));
+        }
+        deposited.push(token_id.clone());
+    }
+
+    let time = ic_cdk::api::time();
+    let bundle_id = BUNDLES.with(|bundles| {
+        let mut bundles = bundles.borrow_mut();
+        let bundle_id = bundles.last_key_value().map(|(id, _)| id + 1).unwrap_or(1);
+        bundles.insert(
+            bundle_id,
+            Bundle {
+                bundle_id,
+                owner,
+                token_ids: token_ids.clone(),
+                price,
+                currency,
+                time,
+                status: BundleStatus::Listed,
+            },
+        );
+        bundle_id
+    });
+    add_log(
+        time,
+        token_ids[0].clone(),
+        owner,
+        Principal::anonymous(),
+        LogAction::BundleListed { bundle_id, token_ids, price, currency },
+    );
+    Ok(bundle_id)
+}
+
+/**
+ * Take a bundle down and get its NFTs back.
+ */
+#[update(guard = "not_anon")]
+pub async fn remove_bundle(bundle_id: u64) -> Result<String, String> {
+    let _guard: CallerGuard = CallerGuard::new(caller())?;
+
+    let bundle = lock_bundle(bundle_id)?;
+    if bundle.owner != caller() {
+        unlock_bundle(bundle_id);
+        return Err("Unauthorized !".to_string());
+    }
+    if let Err(err) = call_deduct_marketplace_fee().await {
+        unlock_bundle(bundle_id);
+        return Err(err);
+    }
+
+    let held = match locate_bundle_nfts(&bundle).await {
+        Ok(held) => held,
+        Err(err) => {
+            unlock_bundle(bundle_id);
+            return Err(err);
+        }
+    };
+    let tokens = bundle.token_ids.iter().cloned().zip(held).collect();
+    let results = match transfer_held_nfts(tokens, Account { owner: bundle.owner, subaccount: None }).await {
+        Ok(results) => results,
+        Err(err) => {
+            unlock_bundle(bundle_id);
+            return Err(err);
+        }
+    };
+    BUNDLES.with(|bundles| bundles.borrow_mut().remove(&bundle_id));
+    // The bundle is gone either way, NFTs that didn't move are delivered by the timer
+    queue_failed_deliveries(bundle.owner, &bundle.token_ids, &results);
+    add_log(
+        ic_cdk::api::time(),
+        bundle.token_ids[0].clone(),
+        bundle.owner,
+        Principal::anonymous(),
+        LogAction::BundleRemoved { bundle_id },
+    );
+    Ok("Successfully cancelled the bundle listing.".to_string())
+}
+
+/**
+ * Buy every NFT of a bundle at the bundle price. The price is escrowed like in buy_nft, so approve
+ * price + (2 + number of minters) * fee to emporium first. The royalty of each NFT is worked out on
+ * an equal share of the price.
+ * Every NFT must still be held by emporium before any payment is taken. The NFTs are then set aside
+ * in the bundle's own emporium subaccount; if any of them can't be moved there, or none of them can
+ * be sent on to the buyer, the payment is refunded (minus one ledger fee) and the bundle goes back on sale.
+ */
+#[update(guard = "not_anon")]
+pub async fn buy_bundle(bundle_id: u64) -> Result<String, String> {
+    let _guard: CallerGuard = CallerGuard::new(caller())?;
+    let buyer = caller();
+
+    let bundle = lock_bundle(bundle_id)?;
+    if bundle.owner == buyer {
+        unlock_bundle(bundle_id);
+        return Err("You can't buy your own bundle".to_string());
+    }
+    let lead_token = bundle.token_ids[0].clone();
+
+    let held = match locate_bundle_nfts(&bundle).await {
+        Ok(held) => held,
+        Err(err) => {
+            unlock_bundle(bundle_id);
+            return Err(err);
+        }
+    };
+    let split = match get_bundle_sale_split(&bundle).await {
+        Ok(split) => split,
+        Err(err) => {
+            unlock_bundle(bundle_id);
+            return Err(err);
+        }
+    };
+    if let Err(err) = call_deduct_marketplace_fee().await {
+        unlock_bundle(bundle_id);
+        return Err(err);
+    }
+    let fee = match escrow_sale_payment(&split).await {
+        Ok(fee) => fee,
+        Err(err) => {
+            unlock_bundle(bundle_id);
+            return Err(err);
+        }
+    };
+
+    // Gather every NFT in the lot first. Emporium keeps hold of them there, so until all of them
+    // made it the purchase can be rolled back; the ones already moved wait in the lot for the next buyer.
+    let lot = bundle_lot(bundle_id);
+    let emporium = get_principal(EMPORIUM_CANISTER_ID);
+    let to_gather: Vec<(Nat, Option<Vec<u8>>)> = bundle
+        .token_ids
+        .iter()
+        .cloned()
+        .zip(held)
+        .filter(|(_, subaccount)| subaccount.as_ref() != Some(&lot))
+        .collect();
+    let gathered = if to_gather.is_empty() {
+        Ok(Vec::new())
+    } else {
+        transfer_held_nfts(to_gather, Account { owner: emporium, subaccount: Some(lot.clone()) }).await
+    };
+    if let Some(reason) = first_failure(&gathered) {
+        refund_sale_payment(&lead_token, buyer, &split, fee).await;
+        unlock_bundle(bundle_id);
+        return Err(format!("Bundle transfer failed, purchase rolled back: {}", reason));
+    }
+
+    let handover = bundle.token_ids.iter().map(|token_id| (token_id.clone(), Some(lot.clone()))).collect();
+    let results = transfer_held_nfts(handover, Account { owner: buyer, subaccount: None }).await;
+    let delivered_any = matches!(&results, Ok(results) if results.iter().any(|result| result.is_ok()));
+    if !delivered_any {
+        refund_sale_payment(&lead_token, buyer, &split, fee).await;
+        unlock_bundle(bundle_id);
+        let reason = first_failure(&results).unwrap_or_default();
+        return Err(format!("Bundle transfer failed, purchase rolled back: {}", reason));
+    }
+
+    BUNDLES.with(|bundles| bundles.borrow_mut().remove(&bundle_id));
+    if let Ok(results) = &results {
+        // Every NFT was gathered in the lot and the batch went through, so a token it didn't move
+        // is still set aside there; the sale stands and the timer delivers it
+        queue_failed_deliveries(buyer, &bundle.token_ids, results);
+    }
+    record_bundle_sale(ic_cdk::api::time(), &bundle, buyer);
+    distribute_sale_payment(&lead_token, bundle.owner, &split, fee).await;
+    add_log(
+        ic_cdk::api::time(),
+        lead_token,
+        bundle.owner,
+        buyer,
+        LogAction::BundleSold {
+            bundle_id,
+            price: bundle.price,
+            royalty: split.royalty,
+            platform_fee: split.platform_fee,
+            currency: bundle.currency,
+        },
+    );
+    Ok("Success".to_string())
+}
+
+#[query]
+pub fn get_bundle(bundle_id: u64) -> Option<Bundle> {
+    BUNDLES.with(|bundles| bundles.borrow().get(&bundle_id))
+}
+
+/// Retries delivering NFTs whose transfer failed, called from the timer.
+///
+/// Each NFT is sent from whichever emporium subaccount holds it, main account or bundle lot.
+pub async fn retry_pending_deliveries() {
+    let pending: Vec<(Nat, Principal)> = PENDING_NFT_DELIVERIES.with(|deliveries| {
+        deliveries
+            .borrow()
+            .iter()
+            .take(DELIVERY_BATCH_SIZE)
+            .filter_map(|(token_key, recipient)| token_key.parse::<Nat>().ok().map(|token_id| (token_id, recipient)))
+            .collect()
+    });
+    if pending.is_empty() {
+        return;
+    }
+    let accounts = match get_token_accounts(pending.iter().map(|(token_id, _)| token_id.clone()).collect()).await {
+        Ok(accounts) => accounts,
+        Err(err) => {
+            ic_cdk::println!("Looking up pending NFT deliveries failed, will retry: {}", err);
+            return;
+        }
+    };
+    let emporium = get_principal(EMPORIUM_CANISTER_ID);
+    for ((token_id, recipient), account) in pending.into_iter().zip(accounts) {
+        let from_subaccount = match account {
+            Some(account) if account.owner == emporium => account.subaccount,
+            _ => None,
+        };
+        let to = Account { owner: recipient, subaccount: None };
+        let delivery = transfer_held_nfts(vec![(token_id.clone(), from_subaccount)], to)
+            .await
+            .and_then(|results| results.into_iter().next().unwrap_or(Err("Unknown transfer error.".to_string())));
+        match delivery {
+            Ok(_) => {
+                PENDING_NFT_DELIVERIES.with(|deliveries| deliveries.borrow_mut().remove(&token_id.to_string()));
+            }
+            Err(err) => ic_cdk::println!("Delivering NFT {} failed, will retry: {}", token_id, err),
+        }
+    }
+}
+
+// Checks emporium still holds every NFT of the bundle, so a paid sale can deliver all of them.
+// Returns the emporium subaccount each one sits in.
+async fn locate_bundle_nfts(bundle: &Bundle) -> Result<Vec<Option<Vec<u8>>>, String> {
+    let emporium = get_principal(EMPORIUM_CANISTER_ID);
+    let accounts = get_token_accounts(bundle.token_ids.clone()).await?;
+    if accounts.len() != bundle.token_ids.len() {
+        return Err("Couldn't check the owners of the bundle NFTs".to_string());
+    }
+    bundle
+        .token_ids
+        .iter()
+        .zip(accounts)
+        .map(|(token_id, account)| match account {
+            Some(account) if account.owner == emporium => Ok(account.subaccount.filter(|s| s.iter().any(|b| *b != 0))),
+            _ => Err(format!("NFT {} of this bundle is no longer held by emporium", token_id)),
+        })
+        .collect()
+}
+
+// Emporium subaccount a bundle's NFTs are gathered in while it is bought, tagged 1 in the first byte
+fn bundle_lot(bundle_id: u64) -> Vec<u8> {
+    let mut subaccount = vec![0u8; 32];
+    subaccount[0] = 1;
+    subaccount[24..].copy_from_slice(&bundle_id.to_be_bytes());
+    subaccount
+}
+
+// First error of a batch transfer, whether the call or one of the transfers failed
+fn first_failure(results: &Result<Vec<Result<(), String>>, String>) -> Option<String> {
+    match results {
+        Err(err) => Some(err.clone()),
+        Ok(results) => results.iter().find_map(|result| result.clone().err()),
+    }
+}
+
+// Marks a listed bundle as Settling so no other call can buy or remove it meanwhile
+fn lock_bundle(bundle_id: u64) -> Result<Bundle, String> {
+    BUNDLES.with(|bundles| {
+        let mut bundles = bundles.borrow_mut();
+        let mut bundle = bundles.get(&bundle_id).ok_or("Bundle doesn't exists")?;
+        if bundle.status != BundleStatus::Listed {
+            return Err("Bundle is being settled, try again later".to_string());
+        }
+        bundle.status = BundleStatus::Settling;
+        bundles.insert(bundle_id, bundle.clone());
+        Ok(bundle)
+    })
+}
+
+fn unlock_bundle(bundle_id: u64) {
+    BUNDLES.with(|bundles| {
+        let mut bundles = bundles.borrow_mut();
+        if let Some(mut bundle) = bundles.get(&bundle_id) {
+            bundle.status = BundleStatus::Listed;
+            bundles.insert(bundle_id, bundle);
+        }
+    });
+}
+
+// Splits the bundle price equally over its NFTs, the remainder goes to the first one
+async fn get_bundle_sale_split(bundle: &Bundle) -> Result<SaleSplit, String> {
+    let count = bundle.token_ids.len() as u64;
+    let share = bundle.price / count;
+    let mut splits = Vec::new();
+    for (i, token_id) in bundle.token_ids.iter().enumerate() {
+        let price = if i == 0 { share + bundle.price % count } else { share };
+        splits.push(get_sale_split(token_id, bundle.owner, price, bundle.currency).await?);
+    }
+    Ok(SaleSplit::combine(bundle.currency, splits))
+}
+
+// Sends back NFTs deposited for a bundle that couldn't be listed
+async fn return_nfts(owner: Principal, token_ids: Vec<Nat>) {
+    if token_ids.is_empty() {
+        return;
+    }
+    let results = match transfer_nfts_from_canister(owner, token_ids.clone()).await {
+        Ok(results) => results,
+        Err(err) => vec![Err(err); token_ids.len()],
+    };
+    queue_failed_deliveries(owner, &token_ids, &results);
+}
+
+fn queue_failed_deliveries(recipient: Principal, token_ids: &[Nat], results: &[Result<(), String>]) {
+    for (token_id, result) in token_ids.iter().zip(results) {
+        if let Err(err) = result {
+            ic_cdk::println!("Delivering NFT {} to {} failed, queued: {}", token_id, recipient, err);
+            queue_nft_delivery(token_id, recipient);
+        }
+    }
+}
+
+/// Queues an NFT held by emporium for delivery by the timer
+pub fn queue_nft_delivery(token_id: &Nat, recipient: Principal) {
+    PENDING_NFT_DELIVERIES.with(|deliveries| deliveries.borrow_mut().insert(token_id.to_string(), recipient));
+}
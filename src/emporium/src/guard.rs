@@ -11,6 +11,15 @@ pub fn not_anon() -> Result<(), String> {
     }
 }
 
+pub fn is_controller() -> Result<(), String> {
+    let caller = ic_cdk::api::caller();
+    if ic_cdk::api::is_controller(&caller) {
+        Ok(())
+    } else {
+        Err("Only canister controllers can call this method.".to_string())
+    }
+}
+
 pub struct CallerGuard {
     principal: Principal,
 }
@@ -12,7 +12,31 @@ mod queries;
 pub use queries::*;
 
 mod listings;
-pub use listings::{ListingsQuery, ListingsResponse, SortBy, SortOrder, get_listings, ListingUserInfo};
+pub use listings::{ListingsQuery, ListingsResponse, BundleListing, SortBy, SortOrder, get_listings, ListingUserInfo};
+
+mod offers;
+pub use offers::{Offers, make_offer, cancel_offer, accept_offer, get_token_offers, get_user_offers};
+
+mod auctions;
+pub use auctions::{Auctions, create_auction, place_bid, cancel_auction, get_auction, get_auctions};
+
+mod royalties;
+pub use royalties::{SaleSplit, set_royalty_settings, get_royalty_settings, get_seller_proceeds};
+
+mod bundles;
+pub use bundles::{list_bundle, remove_bundle, buy_bundle, get_bundle};
+
+mod settlements;
+pub use settlements::{Settlements, get_settlement, get_buyer_settlements};
+
+mod analytics;
+mod listing_index;
+pub use analytics::{StatsRange, MarketStats, SaleHistory, get_market_stats, get_token_price_history, get_seller_price_history};
+
+mod watchlist;
+pub use watchlist::{WatchEntry, AlertInbox, watch, unwatch, get_watchlist, add_alert_rule, remove_alert_rule, get_alert_rules, get_alert_inbox, clear_alerts};
+
+mod timers;
 
 
 mod guard;
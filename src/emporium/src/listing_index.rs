@@ -0,0 +1,284 @@
+use crate::listings::get_username;
+use crate::royalties::get_token_metadata;
+use crate::{
+    Currency, ListingAttrKey, ListingMetadata, ListingPriceKey, ListingTimeKey, Nft, NftStatus,
+    TokenOrigin, LISTED_COUNTS, LISTING, LISTINGS_BY_TIME, LISTING_ATTRS, LISTING_METADATA, LISTING_PRICES, STATE,
+};
+use candid::{Nat, Principal};
+use icrc_ledger_types::icrc::generic_value::Value;
+
+pub const CURRENCIES: [Currency; 4] = [Currency::ICP, Currency::LBRY, Currency::ALEX, Currency::CKUSDC];
+const CONTENT_TYPE_METADATA_KEY: &str = "alexandria:content_type";
+const TAGS_METADATA_KEY: &str = "alexandria:tags";
+const METADATA_BATCH_SIZE: usize = 25;
+
+pub fn owner_attr(owner: Principal) -> String {
+    format!("owner:{}", owner)
+}
+
+pub fn username_attr(username: &str) -> String {
+    format!("user:{}", username.trim().to_lowercase())
+}
+
+pub fn content_type_attr(content_type: &str) -> String {
+    format!("type:{}", content_type.trim().to_lowercase())
+}
+
+pub fn tag_attr(tag: &str) -> String {
+    format!("tag:{}", tag.trim().to_lowercase())
+}
+
+pub fn origin_attr(origin: TokenOrigin) -> String {
+    match origin {
+        TokenOrigin::OG => "origin:og".to_string(),
+        TokenOrigin::Scion => "origin:scion".to_string(),
+    }
+}
+
+/// Adds a listing to the price, time and attribute indexes, call whenever one is put in LISTING
+pub fn index_listing(nft: &Nft) {
+    if nft.status != NftStatus::Listed {
+        return;
+    }
+    let inserted = LISTING_PRICES.with(|index| index.borrow_mut().insert(price_key(nft), ()));
+    if inserted.is_none() {
+        adjust_listed_count(nft.currency, 1);
+    }
+    LISTINGS_BY_TIME.with(|index| index.borrow_mut().insert(time_key(nft), ()));
+    LISTING_ATTRS.with(|index| {
+        let mut index = index.borrow_mut();
+        for key in attr_keys(nft) {
+            index.insert(key, ());
+        }
+    });
+}
+
+/// Drops a listing from the indexes, call whenever one leaves LISTING or changes price
+pub fn unindex_listing(nft: &Nft) {
+    let removed = LISTING_PRICES.with(|index| index.borrow_mut().remove(&price_key(nft)));
+    if removed.is_some() {
+        adjust_listed_count(nft.currency, -1);
+    }
+    LISTINGS_BY_TIME.with(|index| index.borrow_mut().remove(&time_key(nft)));
+    LISTING_ATTRS.with(|index| {
+        let mut index = index.borrow_mut();
+        for key in attr_keys(nft) {
+            index.remove(&key);
+        }
+    });
+}
+
+pub fn get_listing_metadata(token_id: &Nat) -> Option<ListingMetadata> {
+    LISTING_METADATA.with(|metadata| metadata.borrow().get(&token_id.to_string()))
+}
+
+/// Saves the details of a token about to be listed, before index_listing picks them up
+pub fn set_listing_metadata(token_id: &Nat, metadata: Option<ListingMetadata>) {
+    match metadata {
+        Some(metadata) => {
+            LISTING_METADATA.with(|m| m.borrow_mut().insert(token_id.to_string(), metadata));
+        }
+        None => {
+            // Lookup failed, the timer tries again
+            LISTING_METADATA.with(|m| m.borrow_mut().remove(&token_id.to_string()));
+            STATE.with(|state| state.borrow_mut().pending_metadata.insert(token_id.to_string()));
+        }
+    }
+}
+
+/// Looks up content type and tags in the token metadata and the owner's username.
+///
+/// None when either canister can't be reached, a missing username or missing keys are fine.
+pub async fn fetch_listing_metadata(token_id: &Nat, owner: Principal) -> Option<ListingMetadata> {
+    let metadata = get_token_metadata(token_id.clone()).await.ok()?.unwrap_or_default();
+    let owner_username = get_username(owner).await.ok()?;
+
+    let content_type = match metadata.get(CONTENT_TYPE_METADATA_KEY) {
+        Some(Value::Text(text)) if !text.trim().is_empty() => Some(text.trim().to_lowercase()),
+        _ => None,
+    };
+    let mut tags: Vec<String> = match metadata.get(TAGS_METADATA_KEY) {
+        Some(Value::Text(text)) => text.split(',').map(|tag| tag.trim().to_lowercase()).collect(),
+        Some(Value::Array(values)) => values
+            .iter()
+            .filter_map(|value| match value {
+                Value::Text(tag) => Some(tag.trim().to_lowercase()),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+    tags.retain(|tag| !tag.is_empty());
+    tags.sort();
+    tags.dedup();
+
+    Some(ListingMetadata {
+        content_type,
+        tags,
+        owner_username,
+        origin: token_origin(token_id),
+    })
+}
+
+/// Looks up details for listings that don't have them yet, called from the timer
+pub async fn backfill_listing_metadata() {
+    let pending: Vec<String> = STATE.with(|state| {
+        state
+            .borrow()
+            .pending_metadata
+            .iter()
+            .take(METADATA_BATCH_SIZE)
+            .cloned()
+            .collect()
+    });
+    for token_key in pending {
+        let nft = match LISTING.with(|nfts| nfts.borrow().get(&token_key)) {
+            Some(nft) => nft,
+            None => {
+                STATE.with(|state| state.borrow_mut().pending_metadata.remove(&token_key));
+                continue;
+            }
+        };
+        let metadata = match fetch_listing_metadata(&nft.token_id, nft.owner).await {
+            Some(metadata) => metadata,
+            None => continue,
+        };
+        // The listing may have changed during the calls, re-index whatever is there now
+        if let Some(nft) = LISTING.with(|nfts| nfts.borrow().get(&token_key)) {
+            unindex_listing(&nft);
+            LISTING_METADATA.with(|m| m.borrow_mut().insert(token_key.clone(), metadata));
+            index_listing(&nft);
+        }
+        STATE.with(|state| state.borrow_mut().pending_metadata.remove(&token_key));
+    }
+}
+
+/// Rebuilds the indexes from LISTING, run on upgrade so they always match the listings.
+/// Listings without details are queued for backfill_listing_metadata.
+pub fn rebuild_listing_index() {
+    let keys: Vec<ListingPriceKey> = LISTING_PRICES.with(|index| index.borrow().iter().map(|(key, _)| key).collect());
+    LISTING_PRICES.with(|index| {
+        let mut index = index.borrow_mut();
+        for key in keys {
+            index.remove(&key);
+        }
+    });
+    let keys: Vec<ListingTimeKey> = LISTINGS_BY_TIME.with(|index| index.borrow().iter().map(|(key, _)| key).collect());
+    LISTINGS_BY_TIME.with(|index| {
+        let mut index = index.borrow_mut();
+        for key in keys {
+            index.remove(&key);
+        }
+    });
+    let keys: Vec<ListingAttrKey> = LISTING_ATTRS.with(|index| index.borrow().iter().map(|(key, _)| key).collect());
+    LISTING_ATTRS.with(|index| {
+        let mut index = index.borrow_mut();
+        for key in keys {
+            index.remove(&key);
+        }
+    });
+    LISTED_COUNTS.with(|counts| {
+        let mut counts = counts.borrow_mut();
+        for currency in CURRENCIES {
+            counts.remove(&currency);
+        }
+    });
+
+    let listings: Vec<Nft> = LISTING.with(|nfts| nfts.borrow().iter().map(|(_, nft)| nft).collect());
+    for nft in &listings {
+        index_listing(nft);
+        if nft.status == NftStatus::Listed && get_listing_metadata(&nft.token_id).is_none() {
+            STATE.with(|state| state.borrow_mut().pending_metadata.insert(nft.token_id.to_string()));
+        }
+    }
+}
+
+/// LISTING keys of listings with an attribute
+pub fn tokens_with_attr(attr: String) -> Vec<String> {
+    let start = ListingAttrKey { attr: attr.clone(), token_id: String::new() };
+    LISTING_ATTRS.with(|index| {
+        index
+            .borrow()
+            .range(start..)
+            .take_while(|(key, _)| key.attr == attr)
+            .map(|(key, _)| key.token_id)
+            .collect()
+    })
+}
+
+/// LISTING keys of listings priced within [min, max] in a currency
+pub fn tokens_in_price_range(currency: Currency, min: u64, max: u64) -> Vec<String> {
+    let start = ListingPriceKey { currency, price: min, token_id: String::new() };
+    LISTING_PRICES.with(|index| {
+        index
+            .borrow()
+            .range(start..)
+            .take_while(|(key, _)| key.currency == currency && key.price <= max)
+            .map(|(key, _)| key.token_id)
+            .collect()
+    })
+}
+
+/// LISTING keys of listings listed or repriced within [after, before]
+pub fn tokens_listed_between(after: u64, before: u64) -> Vec<String> {
+    let start = ListingTimeKey { time: after, token_id: String::new() };
+    LISTINGS_BY_TIME.with(|index| {
+        index
+            .borrow()
+            .range(start..)
+            .take_while(|(key, _)| key.time <= before)
+            .map(|(key, _)| key.token_id)
+            .collect()
+    })
+}
+
+// Scion ids carry the minter's hash above the 256 bit id of their OG
+pub fn token_origin(token_id: &Nat) -> TokenOrigin {
+    if token_id.0.bits() > 256 {
+        TokenOrigin::Scion
+    } else {
+        TokenOrigin::OG
+    }
+}
+
+fn attr_keys(nft: &Nft) -> Vec<ListingAttrKey> {
+    let mut attrs = vec![owner_attr(nft.owner), origin_attr(token_origin(&nft.token_id))];
+    if let Some(metadata) = get_listing_metadata(&nft.token_id) {
+        if let Some(username) = &metadata.owner_username {
+            attrs.push(username_attr(username));
+        }
+        if let Some(content_type) = &metadata.content_type {
+            attrs.push(content_type_attr(content_type));
+        }
+        attrs.extend(metadata.tags.iter().map(|tag| tag_attr(tag)));
+    }
+    let token_id = nft.token_id.to_string();
+    attrs
+        .into_iter()
+        .map(|attr| ListingAttrKey { attr, token_id: token_id.clone() })
+        .collect()
+}
+
+fn price_key(nft: &Nft) -> ListingPriceKey {
+    ListingPriceKey {
+        currency: nft.currency,
+        price: nft.price,
+        token_id: nft.token_id.to_string(),
+    }
+}
+
+fn time_key(nft: &Nft) -> ListingTimeKey {
+    ListingTimeKey {
+        time: nft.time,
+        token_id: nft.token_id.to_string(),
+    }
+}
+
+fn adjust_listed_count(currency: Currency, delta: i64) {
+    LISTED_COUNTS.with(|counts| {
+        let mut counts = counts.borrow_mut();
+        let count = counts.get(&currency).unwrap_or(0);
+        counts.insert(currency, count.saturating_add_signed(delta));
+    });
+}
+
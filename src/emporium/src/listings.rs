@@ -1,7 +1,12 @@
-use crate::{id_converter, Nft, NftStatus, LISTING};
+use crate::listing_index::{
+    content_type_attr, get_listing_metadata, origin_attr, owner_attr, tag_attr, token_origin, tokens_in_price_range,
+    tokens_listed_between, tokens_with_attr, username_attr, CURRENCIES,
+};
+use crate::{id_converter, Bundle, BundleStatus, Currency, ListingMetadata, Nft, NftStatus, TokenOrigin, BUNDLES, LISTING};
 use candid::{CandidType, Nat, Principal};
 use ic_cdk::{query, update, api::call::CallResult};
 use serde::Deserialize;
+use std::cmp::Reverse;
 use std::collections::HashMap;
 
 #[derive(CandidType, Deserialize, Clone, Debug)]
@@ -12,6 +17,20 @@ pub struct ArweaveNft {
     pub token_id: Nat,
     pub status: NftStatus,
     pub time: u64,
+    pub currency: Currency,
+    pub expires_at: Option<u64>,
+    pub metadata: Option<ListingMetadata>, // None until the details are looked up
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct BundleListing {
+    pub bundle_id: u64,
+    pub owner: Principal,
+    pub token_ids: Vec<Nat>,
+    pub arweave_ids: Vec<String>,
+    pub price: u64,
+    pub currency: Currency,
+    pub time: u64,
 }
 
 #[derive(CandidType, Deserialize, Clone, Debug)]
@@ -22,12 +41,22 @@ pub struct ListingsQuery {
     pub sort_order: SortOrder,
     pub selected_user: Option<Principal>, // User selector filter
     pub search_term: Option<String>,      // Token ID search within filtered set
+    pub currency: Option<Currency>,       // Only listings priced in this currency
+    pub min_price: Option<u64>,           // Inclusive, in the units of the listing's currency
+    pub max_price: Option<u64>,           // Inclusive
+    pub content_type: Option<String>,     // Arweave content type, e.g. "image/png"
+    pub tag: Option<String>,              // Listings whose token carries this tag
+    pub username: Option<String>,         // Owner username, as an alternative to selected_user
+    pub listed_after: Option<u64>,        // Listed or repriced at or after this time (ns)
+    pub listed_before: Option<u64>,       // Listed or repriced at or before this time (ns)
+    pub origin: Option<TokenOrigin>,      // OG tokens or scions only
 }
 
 #[derive(CandidType, Deserialize, Clone, Debug)]
 pub enum SortBy {
     Price, // Sort by NFT price - most useful for marketplace
     Time,  // Sort by listing time - show newest/oldest first
+    Currency, // Group by currency, then by price within each currency
 }
 
 #[derive(CandidType, Deserialize, Clone, Debug)]
@@ -39,6 +68,7 @@ pub enum SortOrder {
 #[derive(CandidType, Deserialize, Clone, Debug)]
 pub struct ListingsResponse {
     pub nfts: Vec<ArweaveNft>,
+    pub bundles: Vec<BundleListing>, // bundles on this page, sorted and counted together with the nfts
     pub total_count: u64,
     pub page: u64,
     pub page_size: u64,
@@ -56,6 +86,44 @@ impl Default for ListingsQuery {
             sort_order: SortOrder::Desc, // Default: newest first
             selected_user: None,         // Default: all users
             search_term: None,           // Default: no search
+            currency: None,              // Default: all currencies
+            min_price: None,
+            max_price: None,
+            content_type: None,
+            tag: None,
+            username: None,
+            listed_after: None,
+            listed_before: None,
+            origin: None,
+        }
+    }
+}
+
+// A single listing or a bundle, so both can be sorted and paginated together
+enum ListingEntry {
+    Single(Nft),
+    Bundle(Bundle),
+}
+
+impl ListingEntry {
+    fn price(&self) -> u64 {
+        match self {
+            ListingEntry::Single(nft) => nft.price,
+            ListingEntry::Bundle(bundle) => bundle.price,
+        }
+    }
+
+    fn time(&self) -> u64 {
+        match self {
+            ListingEntry::Single(nft) => nft.time,
+            ListingEntry::Bundle(bundle) => bundle.time,
+        }
+    }
+
+    fn currency(&self) -> Currency {
+        match self {
+            ListingEntry::Single(nft) => nft.currency,
+            ListingEntry::Bundle(bundle) => bundle.currency,
         }
     }
 }
@@ -64,9 +132,14 @@ impl Default for ListingsQuery {
  * Core query function with filtering funnel approach:
  *
  * Filtering Logic:
- * 1. Start with all listings in storage
- * 2. Apply user filter (if specified) -> narrows down to specific user's listings
- * 3. Apply search filter (if specified) -> searches within the user-filtered results
+ * 1. Pick the candidate listings from the narrowest index the query allows:
+ *    search term > selected user > username > tag > content type > price range > listing age > origin,
+ *    only a query without any of these reads every listing
+ * 2. Apply every filter to the candidates -> user, search, currency, price range, listing age,
+ *    content type, tag, username and origin; expired listings are never shown
+ * 3. Apply the user, search, currency, price and age filters to listed bundles,
+ *    a bundle matches the search when any of its tokens does. Bundles carry no metadata,
+ *    so they are left out whenever a content type, tag, username or origin is asked for
  * 4. Sort the final filtered results
  * 5. Apply pagination
  *
@@ -78,75 +151,110 @@ impl Default for ListingsQuery {
  */
 #[query]
 pub fn get_listings(query: ListingsQuery) -> ListingsResponse {
+    let now = ic_cdk::api::time();
     LISTING.with(|storage| {
         let listings = storage.borrow();
 
-        // STEP 1: Apply user filter first
-        // This creates our "scope" for the search
-        let user_filtered: Vec<Nft> = if let Some(selected_user) = query.selected_user {
-            // Scenario: User is selected -> only show that user's listings
-            listings
-                .iter()
-                .filter(|(_, nft)| nft.owner == selected_user)
-                .map(|(_, nft)| nft.clone())
-                .collect()
-        } else {
-            // Scenario: No user selected -> show all listings from all users
-            listings
-                .iter()
-                .map(|(_, nft)| nft.clone())
-                .collect()
+        // STEP 1: Candidates from the secondary indexes, so only those are read from LISTING
+        let candidates: Vec<Nft> = match candidate_keys(&query) {
+            Some(keys) => keys.iter().filter_map(|key| listings.get(key)).collect(),
+            None => listings.iter().map(|(_, nft)| nft).collect(),
         };
 
-        // STEP 2: Apply search filter within the user-filtered scope
-        let mut search_filtered: Vec<Nft> =
-            if let Some(ref search_term) = query.search_term {
-                // Search for token IDs within the current scope (all users OR specific user)
-                user_filtered
-                    .into_iter()
-                    .filter(|nft| matches_token_search(nft, search_term))
+        // STEP 2: Every filter still applies, the index only narrowed down the scope
+        // Expired listings wait for the timer to return them and are never shown
+        let filtered: Vec<Nft> = candidates
+            .into_iter()
+            .filter(|nft| !nft.is_expired(now) && matches_listing_query(nft, &query))
+            .collect();
+
+        // STEP 3: Bundles go through the user, search, currency, price and age filters
+        let wants_metadata = query.content_type.is_some()
+            || query.tag.is_some()
+            || query.username.is_some()
+            || query.origin.is_some();
+        let bundles: Vec<Bundle> = if wants_metadata {
+            Vec::new()
+        } else {
+            BUNDLES.with(|bundles| {
+                bundles
+                    .borrow()
+                    .iter()
+                    .map(|(_, bundle)| bundle)
+                    .filter(|bundle| bundle.status == BundleStatus::Listed)
+                    .filter(|bundle| query.selected_user.is_none_or(|user| bundle.owner == user))
+                    .filter(|bundle| {
+                        query.search_term.as_ref().is_none_or(|term| {
+                            bundle.token_ids.iter().any(|token_id| matches_token_id_search(token_id, term))
+                        })
+                    })
+                    .filter(|bundle| query.currency.is_none_or(|currency| bundle.currency == currency))
+                    .filter(|bundle| in_price_range(bundle.price, &query) && in_time_range(bundle.time, &query))
                     .collect()
-            } else {
-                // No search term -> use all results from user filter step
-                user_filtered
-            };
+            })
+        };
+        let mut entries: Vec<ListingEntry> = filtered
+            .into_iter()
+            .map(ListingEntry::Single)
+            .chain(bundles.into_iter().map(ListingEntry::Bundle))
+            .collect();
 
-        // STEP 3: Sort the filtered results
-        sort_listings(&mut search_filtered, &query.sort_by, &query.sort_order);
+        // STEP 4: Sort the filtered results
+        sort_listings(&mut entries, &query.sort_by, &query.sort_order);
 
-        // STEP 4: Calculate pagination info
-        let total_count = search_filtered.len() as u64;
+        // STEP 5: Calculate pagination info
+        let total_count = entries.len() as u64;
         let total_pages = if total_count == 0 {
             1
         } else {
             (total_count + query.page_size - 1) / query.page_size // Ceiling division
         };
 
-        // STEP 5: Apply pagination with bounds checking
+        // Apply pagination with bounds checking
         let start_idx = ((query.page - 1) * query.page_size) as usize;
-        let end_idx = (start_idx + query.page_size as usize).min(search_filtered.len());
+        let end_idx = (start_idx + query.page_size as usize).min(entries.len());
 
-        let paginated_nfts = if start_idx < search_filtered.len() {
-            search_filtered[start_idx..end_idx].to_vec()
+        let paginated_entries: Vec<ListingEntry> = if start_idx < entries.len() {
+            entries.drain(start_idx..end_idx).collect()
         } else {
             Vec::new()
         };
 
-        // Convert to ArweaveNft only for the paginated results
-        let nfts: Vec<ArweaveNft> = paginated_nfts
-            .into_iter()
-            .map(|nft| ArweaveNft {
-                arweave_id: id_converter::nat_to_arweave_id(nft.token_id.clone()),
-                owner: nft.owner,
-                price: nft.price,
-                token_id: nft.token_id,
-                status: nft.status,
-                time: nft.time,
-            })
-            .collect();
+        // Convert to ArweaveNft and BundleListing only for the paginated results
+        let mut nfts: Vec<ArweaveNft> = Vec::new();
+        let mut bundles: Vec<BundleListing> = Vec::new();
+        for entry in paginated_entries {
+            match entry {
+                ListingEntry::Single(nft) => nfts.push(ArweaveNft {
+                    arweave_id: id_converter::nat_to_arweave_id(nft.token_id.clone()),
+                    metadata: get_listing_metadata(&nft.token_id),
+                    owner: nft.owner,
+                    price: nft.price,
+                    token_id: nft.token_id,
+                    status: nft.status,
+                    time: nft.time,
+                    currency: nft.currency,
+                    expires_at: nft.expires_at,
+                }),
+                ListingEntry::Bundle(bundle) => bundles.push(BundleListing {
+                    bundle_id: bundle.bundle_id,
+                    owner: bundle.owner,
+                    arweave_ids: bundle
+                        .token_ids
+                        .iter()
+                        .map(|token_id| id_converter::nat_to_arweave_id(token_id.clone()))
+                        .collect(),
+                    token_ids: bundle.token_ids,
+                    price: bundle.price,
+                    currency: bundle.currency,
+                    time: bundle.time,
+                }),
+            }
+        }
 
         ListingsResponse {
             nfts,
+            bundles,
             total_count,
             page: query.page,
             page_size: query.page_size,
@@ -162,15 +270,19 @@ pub fn get_listings(query: ListingsQuery) -> ListingsResponse {
  * Supports both direct Nat token IDs and Arweave ID matching
  */
 fn matches_token_search(nft: &Nft, search_term: &str) -> bool {
+    matches_token_id_search(&nft.token_id, search_term)
+}
+
+fn matches_token_id_search(token_id: &Nat, search_term: &str) -> bool {
     // Try parsing as direct Nat token ID (e.g., "123", "456")
-    if let Ok(token_id) = search_term.parse::<Nat>() {
-        if nft.token_id == token_id {
+    if let Ok(search_id) = search_term.parse::<Nat>() {
+        if *token_id == search_id {
             return true;
         }
     }
 
     // Generate arweave_id from token_id and compare
-    let arweave_id = id_converter::nat_to_arweave_id(nft.token_id.clone());
+    let arweave_id = id_converter::nat_to_arweave_id(token_id.clone());
     if arweave_id == search_term {
         return true;
     }
@@ -178,26 +290,148 @@ fn matches_token_search(nft: &Nft, search_term: &str) -> bool {
     false
 }
 
+/**
+ * LISTING keys worth reading for a query, taken from the most selective index it can use.
+ * None when the query has nothing an index can answer and every listing has to be read.
+ */
+fn candidate_keys(query: &ListingsQuery) -> Option<Vec<String>> {
+    if let Some(ref search_term) = query.search_term {
+        return Some(search_keys(search_term));
+    }
+    if let Some(selected_user) = query.selected_user {
+        return Some(tokens_with_attr(owner_attr(selected_user)));
+    }
+    if let Some(ref username) = query.username {
+        return Some(tokens_with_attr(username_attr(username)));
+    }
+    if let Some(ref tag) = query.tag {
+        return Some(tokens_with_attr(tag_attr(tag)));
+    }
+    if let Some(ref content_type) = query.content_type {
+        return Some(tokens_with_attr(content_type_attr(content_type)));
+    }
+    if query.min_price.is_some() || query.max_price.is_some() {
+        let min = query.min_price.unwrap_or(0);
+        let max = query.max_price.unwrap_or(u64::MAX);
+        let currencies = match query.currency {
+            Some(currency) => vec![currency],
+            None => CURRENCIES.to_vec(),
+        };
+        return Some(
+            currencies
+                .into_iter()
+                .flat_map(|currency| tokens_in_price_range(currency, min, max))
+                .collect(),
+        );
+    }
+    if query.listed_after.is_some() || query.listed_before.is_some() {
+        return Some(tokens_listed_between(
+            query.listed_after.unwrap_or(0),
+            query.listed_before.unwrap_or(u64::MAX),
+        ));
+    }
+    // Origin alone still skips the listings of the other kind
+    query
+        .origin
+        .map(|origin| tokens_with_attr(origin_attr(origin)))
+}
+
+// The search term names a single token, either as its Nat id or its arweave id
+fn search_keys(search_term: &str) -> Vec<String> {
+    let mut keys = Vec::new();
+    if let Ok(token_id) = search_term.parse::<Nat>() {
+        keys.push(token_id.to_string());
+    }
+    if id_converter::is_arweave_id(search_term.to_string()) {
+        keys.push(id_converter::arweave_id_to_nat(search_term.to_string()).to_string());
+    }
+    keys.dedup();
+    keys
+}
+
+fn matches_listing_query(nft: &Nft, query: &ListingsQuery) -> bool {
+    if query.selected_user.is_some_and(|user| nft.owner != user) {
+        return false;
+    }
+    if query.search_term.as_ref().is_some_and(|term| !matches_token_search(nft, term)) {
+        return false;
+    }
+    // Prices in different currencies don't compare
+    if query.currency.is_some_and(|currency| nft.currency != currency) {
+        return false;
+    }
+    if !in_price_range(nft.price, query) || !in_time_range(nft.time, query) {
+        return false;
+    }
+    if query.origin.is_some_and(|origin| token_origin(&nft.token_id) != origin) {
+        return false;
+    }
+    if query.content_type.is_none() && query.tag.is_none() && query.username.is_none() {
+        return true;
+    }
+
+    // Listings whose details haven't been looked up yet can't match a metadata filter
+    let metadata = match get_listing_metadata(&nft.token_id) {
+        Some(metadata) => metadata,
+        None => return false,
+    };
+    if let Some(ref content_type) = query.content_type {
+        if metadata.content_type.as_deref() != Some(content_type.trim().to_lowercase().as_str()) {
+            return false;
+        }
+    }
+    if let Some(ref tag) = query.tag {
+        if !metadata.tags.contains(&tag.trim().to_lowercase()) {
+            return false;
+        }
+    }
+    if let Some(ref username) = query.username {
+        let matches = metadata
+            .owner_username
+            .is_some_and(|owner_username| owner_username.to_lowercase() == username.trim().to_lowercase());
+        if !matches {
+            return false;
+        }
+    }
+    true
+}
+
+fn in_price_range(price: u64, query: &ListingsQuery) -> bool {
+    query.min_price.is_none_or(|min| price >= min) && query.max_price.is_none_or(|max| price <= max)
+}
+
+fn in_time_range(time: u64, query: &ListingsQuery) -> bool {
+    query.listed_after.is_none_or(|after| time >= after) && query.listed_before.is_none_or(|before| time <= before)
+}
+
 /**
  * Efficient sorting using unstable sort for better performance
  * unstable_sort is faster than stable_sort when order of equal elements doesn't matter
  */
-fn sort_listings(items: &mut [Nft], sort_by: &SortBy, sort_order: &SortOrder) {
+fn sort_listings(items: &mut [ListingEntry], sort_by: &SortBy, sort_order: &SortOrder) {
     match (sort_by, sort_order) {
         // Price sorting - useful for finding cheapest/most expensive
         (SortBy::Price, SortOrder::Asc) => {
-            items.sort_unstable_by(|a, b| a.price.cmp(&b.price)); // Lowest price first
+            items.sort_unstable_by_key(|item| item.price()); // Lowest price first
         }
         (SortBy::Price, SortOrder::Desc) => {
-            items.sort_unstable_by(|a, b| b.price.cmp(&a.price)); // Highest price first
+            items.sort_unstable_by_key(|item| Reverse(item.price())); // Highest price first
         }
 
         // Time sorting - useful for finding newest/oldest listings
         (SortBy::Time, SortOrder::Asc) => {
-            items.sort_unstable_by(|a, b| a.time.cmp(&b.time)); // Oldest first
+            items.sort_unstable_by_key(|item| item.time()); // Oldest first
         }
         (SortBy::Time, SortOrder::Desc) => {
-            items.sort_unstable_by(|a, b| b.time.cmp(&a.time)); // Newest first (default)
+            items.sort_unstable_by_key(|item| Reverse(item.time())); // Newest first (default)
+        }
+
+        // Currency sorting - currencies in allow-list order, price order within each
+        (SortBy::Currency, SortOrder::Asc) => {
+            items.sort_unstable_by(|a, b| a.currency().cmp(&b.currency()).then(a.price().cmp(&b.price())));
+        }
+        (SortBy::Currency, SortOrder::Desc) => {
+            items.sort_unstable_by(|a, b| a.currency().cmp(&b.currency()).then(b.price().cmp(&a.price())));
         }
     }
 }
@@ -264,16 +498,43 @@ async fn get_all_users() -> Result<Vec<UserPrincipalInfo>, String> {
     }
 }
 
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct UserProfile {
+    username: String,
+}
+
+/// Fetch one user's username, None when they have no profile
+pub async fn get_username(owner: Principal) -> Result<Option<String>, String> {
+    let result: CallResult<(Result<UserProfile, String>,)> = ic_cdk::api::call::call(
+        user_principal(),
+        "get_user",
+        (owner,),
+    ).await;
+
+    match result {
+        Ok((Ok(user),)) => Ok(Some(user.username)),
+        Ok((Err(_),)) => Ok(None),
+        Err((code, msg)) => Err(format!("Error getting user: {} (rejection code: {:?})", msg, code))
+    }
+}
+
 #[update]
 pub async fn get_listing_users() -> Vec<ListingUserInfo> {
     // Get user listing counts from storage
     let user_listing_counts = LISTING.with(|storage| {
         let listings = storage.borrow();
         let mut counts: HashMap<Principal, u64> = HashMap::new();
+        let now = ic_cdk::api::time();
 
-        for (_, nft) in listings.iter() {
+        for (_, nft) in listings.iter().filter(|(_, nft)| !nft.is_expired(now)) {
             *counts.entry(nft.owner).or_insert(0) += 1;
         }
+        // A bundle counts as one listing
+        BUNDLES.with(|bundles| {
+            for (_, bundle) in bundles.borrow().iter() {
+                *counts.entry(bundle.owner).or_insert(0) += 1;
+            }
+        });
 
         counts
     });
@@ -331,16 +592,17 @@ pub async fn get_listing_users() -> Vec<ListingUserInfo> {
 
 /**
  * Get listed NFTs for multiple token IDs
- * Returns Option<Nft> for each token_id - None if token is not found
+ * Returns Option<Nft> for each token_id - None if token is not found or its listing expired
  * Used for: Frontend price display and listing status in token grids
  */
 #[query]
 pub fn get_listed_tokens(token_ids: Vec<Nat>) -> Vec<Option<Nft>> {
+    let now = ic_cdk::api::time();
     LISTING.with(|storage| {
         let listings = storage.borrow();
         token_ids
             .into_iter()
-            .map(|token_id| listings.get(&token_id.to_string()))
+            .map(|token_id| listings.get(&token_id.to_string()).filter(|nft| !nft.is_expired(now)))
             .collect()
     })
 }
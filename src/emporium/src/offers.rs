@@ -0,0 +1,363 @@
+use crate::update::{
+    add_log, deposit_nft_to_canister, escrow_icp_from_caller, restore_listing, transfer_icp_from_canister,
+    transfer_nft_from_canister, ICP_TRANSFER_FEE,
+};
+use crate::bundles::queue_nft_delivery;
+use crate::analytics::record_sale;
+use crate::royalties::{distribute_sale_payment, get_sale_split};
+use crate::utils::{
+    call_deduct_marketplace_fee, get_principal, get_token_owner, is_owner, remove_nft_from_listing,
+    EMPORIUM_CANISTER_ID,
+};
+use crate::{
+    not_anon, Currency, CallerGuard, LogAction, Nft, Offer, OfferBuyerKey, OfferStatus, SaleKind,
+    OfferTokenKey, LISTING, OFFERS, OFFERS_BY_BUYER, OFFERS_BY_TOKEN, OFFER_EXPIRY,
+};
+use candid::{CandidType, Deserialize, Nat, Principal};
+use ic_cdk::{caller, query, update};
+
+const MIN_OFFER_DURATION_NS: u64 = 60 * 60 * 1_000_000_000; // 1 hour
+const MAX_OFFER_DURATION_NS: u64 = 90 * 24 * 60 * 60 * 1_000_000_000; // 90 days
+const MAX_OPEN_OFFERS_PER_BUYER: usize = 50;
+const SWEEP_BATCH_SIZE: usize = 25;
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Offers {
+    pub offers: Vec<Offer>,
+    pub total_pages: u64,
+    pub current_page: u64,
+    pub page_size: u64,
+}
+
+/**
+ * Place an ICP offer on any NFT of the collection, listed or not.
+ * The offer amount plus one ledger fee (used for the later payout or refund) is pulled into
+ * escrow with icrc2_transfer_from, so approve amount + 2 * fee to emporium first.
+ * Returns the offer id.
+ */
+#[update(guard = "not_anon")]
+pub async fn make_offer(token_id: Nat, amount: u64, expires_at: u64) -> Result<u64, String> {
+    let _guard: CallerGuard = CallerGuard::new(caller())?;
+    let buyer = caller();
+    let now = ic_cdk::api::time();
+
+    if amount < 1 {
+        return Err("Offer should be greater than 1 e8s ICP".to_string());
+    }
+    if expires_at < now.saturating_add(MIN_OFFER_DURATION_NS)
+        || expires_at > now.saturating_add(MAX_OFFER_DURATION_NS)
+    {
+        return Err("Offer must expire between 1 hour and 90 days from now".to_string());
+    }
+
+    let owner = get_token_owner(token_id.clone())
+        .await?
+        .ok_or("NFT doesn't exists")?;
+    // Listed NFTs are held by emporium, the seller is the listing owner
+    let seller = if owner == get_principal(EMPORIUM_CANISTER_ID) {
+        LISTING
+            .with(|nfts| nfts.borrow().get(&token_id.to_string()))
+            .map(|nft| nft.owner)
+            .ok_or("NFT is held in escrow and can't receive offers right now")?
+    } else {
+        owner
+    };
+    if seller == buyer {
+        return Err("You can't make an offer on your own NFT".to_string());
+    }
+
+    let open_offers = get_offers_of_buyer(buyer)
+        .into_iter()
+        .filter(|offer| offer.is_open(now))
+        .collect::<Vec<_>>();
+    if open_offers.iter().any(|offer| offer.token_id == token_id) {
+        return Err("You already have an open offer on this NFT, cancel it first".to_string());
+    }
+    if open_offers.len() >= MAX_OPEN_OFFERS_PER_BUYER {
+        return Err(format!("Maximum of {} open offers reached", MAX_OPEN_OFFERS_PER_BUYER));
+    }
+
+    call_deduct_marketplace_fee().await?;
+    escrow_icp_from_caller(amount + ICP_TRANSFER_FEE).await?;
+
+    let created_at = ic_cdk::api::time();
+    let offer_id = OFFERS.with(|offers| {
+        offers
+            .borrow()
+            .last_key_value()
+            .map(|(id, _)| id + 1)
+            .unwrap_or(1)
+    });
+    let offer = Offer {
+        offer_id,
+        token_id: token_id.clone(),
+        buyer,
+        amount,
+        created_at,
+        expires_at,
+        status: OfferStatus::Active,
+    };
+    OFFERS.with(|offers| offers.borrow_mut().insert(offer_id, offer));
+    OFFERS_BY_TOKEN.with(|index| {
+        index.borrow_mut().insert(
+            OfferTokenKey {
+                token_id: token_id.to_string(),
+                offer_id,
+            },
+            (),
+        )
+    });
+    OFFERS_BY_BUYER.with(|index| {
+        index
+            .borrow_mut()
+            .insert(OfferBuyerKey { buyer, offer_id }, ())
+    });
+    OFFER_EXPIRY.with(|index| index.borrow_mut().insert((expires_at, offer_id), ()));
+
+    add_log(created_at, token_id, seller, buyer, LogAction::OfferMade { offer_id, amount });
+    Ok(offer_id)
+}
+
+/**
+ * Cancel an active offer and refund the escrowed ICP to the buyer.
+ */
+#[update(guard = "not_anon")]
+pub async fn cancel_offer(offer_id: u64) -> Result<String, String> {
+    let _guard: CallerGuard = CallerGuard::new(caller())?;
+
+    let offer = get_offer(offer_id).ok_or("Offer doesn't exists")?;
+    if offer.buyer != caller() {
+        return Err("Unauthorized !".to_string());
+    }
+    if offer.status != OfferStatus::Active {
+        return Err("Offer is no longer active".to_string());
+    }
+
+    refund_offer(offer, OfferStatus::Cancelled).await?;
+    Ok("Offer cancelled and ICP refunded.".to_string())
+}
+
+/**
+ * Accept an offer on an NFT the caller owns, listed or not.
+ * Unlisted NFTs must be approved to emporium (icrc37) like for list_nft.
+ * The NFT goes to the buyer and the offer amount, less royalty and platform fee, to the caller;
+ * the ledger fees of the royalty and platform fee payouts also come out of the caller's share.
+ * If the NFT can't be delivered the offer stays active and the NFT goes back to the caller.
+ */
+#[update(guard = "not_anon")]
+pub async fn accept_offer(offer_id: u64) -> Result<String, String> {
+    let _guard: CallerGuard = CallerGuard::new(caller())?;
+    let seller = caller();
+
+    let offer = get_offer(offer_id).ok_or("Offer doesn't exists")?;
+    if !offer.is_open(ic_cdk::api::time()) {
+        return Err("Offer is no longer active".to_string());
+    }
+    if offer.buyer == seller {
+        return Err("You can't accept your own offer".to_string());
+    }
+    let token_key = offer.token_id.to_string();
+
+    // Lock the offer (and the listing, if any) before the first await so a cancel, another
+    // accept or buy_nft can't race this settlement
+    let listing = LISTING
+        .with(|nfts| nfts.borrow().get(&token_key))
+        .filter(|nft| nft.owner == seller);
+    if listing.is_none() {
+        match is_owner(seller, offer.token_id.clone()).await {
+            Ok(true) => {}
+            Ok(false) => return Err("You can't accept this offer, ownership proof failed!".to_string()),
+            Err(_) => return Err("Something went wrong !".to_string()),
+        };
+        if get_offer(offer_id).is_none_or(|o| o.status != OfferStatus::Active) {
+            return Err("Offer is no longer active".to_string());
+        }
+    } else {
+        remove_nft_from_listing(offer.token_id.clone())?;
+    }
+    set_offer_status(offer_id, OfferStatus::Settling);
+    OFFER_EXPIRY.with(|index| index.borrow_mut().remove(&(offer.expires_at, offer_id)));
+
+    let reopen = |listing: &Option<Nft>| {
+        set_offer_status(offer_id, OfferStatus::Active);
+        OFFER_EXPIRY.with(|index| index.borrow_mut().insert((offer.expires_at, offer_id), ()));
+        if let Some(nft) = listing {
+            restore_listing(nft.clone());
+        }
+    };
+
+    if let Err(err) = call_deduct_marketplace_fee().await {
+        reopen(&listing);
+        return Err(err);
+    }
+    let split = match get_sale_split(&offer.token_id, seller, offer.amount, Currency::ICP)
+        .await
+        .and_then(|split| split.seller_covers_payout_fees(ICP_TRANSFER_FEE))
+    {
+        Ok(split) => split,
+        Err(err) => {
+            reopen(&listing);
+            return Err(err);
+        }
+    };
+    if listing.is_none() {
+        if let Err(err) = deposit_nft_to_canister(offer.token_id.clone()).await {
+            reopen(&listing);
+            return Err(err);
+        }
+    }
+
+    if let Err(err) = transfer_nft_from_canister(offer.buyer, offer.token_id.clone()).await {
+        reopen(&listing);
+        if listing.is_none() {
+            // Give the deposited NFT back, the timer retries if that fails too
+            if transfer_nft_from_canister(seller, offer.token_id.clone()).await.is_err() {
+                queue_nft_delivery(&offer.token_id, seller);
+            }
+        }
+        return Err(format!("Nft transfer failed, offer is still active: {}", err));
+    }
+
+    let time = ic_cdk::api::time();
+    set_offer_status(offer_id, OfferStatus::Accepted { seller, time });
+    record_sale(time, &offer.token_id, seller, offer.buyer, offer.amount, Currency::ICP, SaleKind::Offer);
+    add_log(
+        time,
+        offer.token_id.clone(),
+        seller,
+        offer.buyer,
+        LogAction::OfferAccepted { offer_id, amount: offer.amount },
+    );
+
+    // NFT is already with the buyer, failed payouts are queued and retried by the timer
+    distribute_sale_payment(&offer.token_id, seller, &split, ICP_TRANSFER_FEE).await;
+    Ok("Offer accepted".to_string())
+}
+
+/**
+ * Active, unexpired offers on a token, highest first.
+ */
+#[query]
+pub fn get_token_offers(token_id: Nat) -> Vec<Offer> {
+    let now = ic_cdk::api::time();
+    let token_key = token_id.to_string();
+    let start = OfferTokenKey { token_id: token_key.clone(), offer_id: 0 };
+    let end = OfferTokenKey { token_id: token_key, offer_id: u64::MAX };
+
+    let mut offers: Vec<Offer> = OFFERS_BY_TOKEN.with(|index| {
+        index
+            .borrow()
+            .range(start..=end)
+            .filter_map(|(key, _)| get_offer(key.offer_id))
+            .filter(|offer| offer.is_open(now))
+            .collect()
+    });
+    offers.sort_by(|a, b| b.amount.cmp(&a.amount).then(a.created_at.cmp(&b.created_at)));
+    offers
+}
+
+/**
+ * All offers made by a user in any state, newest first.
+ */
+#[query]
+pub fn get_user_offers(user: Principal, page: Option<u64>, page_size: Option<u64>) -> Offers {
+    let page = page.unwrap_or(1).max(1);
+    let page_size = page_size.unwrap_or(10).clamp(1, 100);
+
+    let mut offers = get_offers_of_buyer(user);
+    offers.reverse();
+    let total_pages = (offers.len() as u64).div_ceil(page_size);
+    let offers = offers
+        .into_iter()
+        .skip(((page - 1) * page_size) as usize)
+        .take(page_size as usize)
+        .collect();
+
+    Offers {
+        offers,
+        total_pages,
+        current_page: page,
+        page_size,
+    }
+}
+
+/// Refunds expired offers, called from the timer
+pub async fn sweep_offers() {
+    let now = ic_cdk::api::time();
+
+    let expired: Vec<u64> = OFFER_EXPIRY.with(|index| {
+        index
+            .borrow()
+            .range(..(now, 0))
+            .take(SWEEP_BATCH_SIZE)
+            .map(|((_, offer_id), _)| offer_id)
+            .collect()
+    });
+    for offer_id in expired {
+        match get_offer(offer_id) {
+            Some(offer) if offer.status == OfferStatus::Active => {
+                if let Err(err) = refund_offer(offer, OfferStatus::Expired).await {
+                    ic_cdk::println!("Refund of expired offer {} failed: {}", offer_id, err);
+                }
+            }
+            Some(offer) => {
+                OFFER_EXPIRY.with(|index| index.borrow_mut().remove(&(offer.expires_at, offer_id)));
+            }
+            None => {}
+        }
+    }
+
+}
+
+// Marks the offer closed before the refund call, and reopens it if the refund fails
+async fn refund_offer(offer: Offer, closed_status: OfferStatus) -> Result<(), String> {
+    let offer_id = offer.offer_id;
+    let action = match closed_status {
+        OfferStatus::Expired => LogAction::OfferExpired { offer_id },
+        _ => LogAction::OfferCancelled { offer_id },
+    };
+    set_offer_status(offer_id, closed_status);
+    OFFER_EXPIRY.with(|index| index.borrow_mut().remove(&(offer.expires_at, offer_id)));
+
+    if let Err(err) = transfer_icp_from_canister(offer.amount, offer.buyer).await {
+        set_offer_status(offer_id, OfferStatus::Active);
+        OFFER_EXPIRY.with(|index| index.borrow_mut().insert((offer.expires_at, offer_id), ()));
+        return Err(err);
+    }
+
+    add_log(
+        ic_cdk::api::time(),
+        offer.token_id,
+        Principal::anonymous(),
+        offer.buyer,
+        action,
+    );
+    Ok(())
+}
+
+fn get_offer(offer_id: u64) -> Option<Offer> {
+    OFFERS.with(|offers| offers.borrow().get(&offer_id))
+}
+
+fn set_offer_status(offer_id: u64, status: OfferStatus) {
+    OFFERS.with(|offers| {
+        let mut offers = offers.borrow_mut();
+        if let Some(mut offer) = offers.get(&offer_id) {
+            offer.status = status;
+            offers.insert(offer_id, offer);
+        }
+    });
+}
+
+// Oldest first
+fn get_offers_of_buyer(buyer: Principal) -> Vec<Offer> {
+    let start = OfferBuyerKey { buyer, offer_id: 0 };
+    let end = OfferBuyerKey { buyer, offer_id: u64::MAX };
+    OFFERS_BY_BUYER.with(|index| {
+        index
+            .borrow()
+            .range(start..=end)
+            .filter_map(|(key, _)| get_offer(key.offer_id))
+            .collect()
+    })
+}
@@ -0,0 +1,353 @@
+use crate::update::{escrow_from_caller, ledger_fee, transfer_from_canister};
+use crate::utils::{get_principal, ALEX_REVSHARE_CANISTER_ID, ICRC7_CANISTER_ID};
+use crate::{
+    is_controller, Currency, PendingPayout, SellerCurrencyKey, RoyaltySettings, SellerProceeds, PENDING_PAYOUTS,
+    ROYALTY_SETTINGS, SELLER_PROCEEDS,
+};
+use candid::{CandidType, Deserialize, Nat, Principal};
+use ic_cdk::api::call::CallResult;
+use ic_cdk::{query, update};
+use icrc_ledger_types::icrc::generic_value::Value;
+use std::collections::BTreeMap;
+
+pub const MAX_ROYALTY_BPS: u64 = 1_000; // 10%
+pub const MAX_PLATFORM_FEE_BPS: u64 = 1_000; // 10%
+const MINTER_METADATA_KEY: &str = "alexandria:minter";
+const ROYALTY_METADATA_KEY: &str = "alexandria:royalty_bps";
+const PAYOUT_BATCH_SIZE: usize = 25;
+
+type TokenMetadata = BTreeMap<String, Value>;
+
+// How a sale price is divided, all in the smallest unit of `currency`
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SaleSplit {
+    pub currency: Currency,
+    pub price: u64,
+    pub royalty: u64,
+    pub royalty_payouts: Vec<(Principal, u64)>, // minters and their share of `royalty`
+    pub platform_fee: u64,
+    pub seller_amount: u64,
+}
+
+impl SaleSplit {
+    // The whole price goes to the seller
+    pub fn direct(currency: Currency, price: u64) -> Self {
+        SaleSplit {
+            currency,
+            price,
+            royalty: 0,
+            royalty_payouts: Vec::new(),
+            platform_fee: 0,
+            seller_amount: price,
+        }
+    }
+
+    // Adds up the splits of the tokens in a bundle, minters of several tokens are paid once
+    pub fn combine(currency: Currency, splits: Vec<SaleSplit>) -> Self {
+        let mut combined = SaleSplit::direct(currency, 0);
+        for split in splits {
+            combined.price += split.price;
+            combined.royalty += split.royalty;
+            combined.platform_fee += split.platform_fee;
+            combined.seller_amount += split.seller_amount;
+            for (minter, amount) in split.royalty_payouts {
+                match combined.royalty_payouts.iter_mut().find(|(m, _)| *m == minter) {
+                    Some((_, total)) => *total += amount,
+                    None => combined.royalty_payouts.push((minter, amount)),
+                }
+            }
+        }
+        combined
+    }
+
+    // For sales paid from an escrow holding a single ledger fee (offers, auction bids):
+    // the fees of the royalty and platform fee payouts come out of the seller's amount
+    pub fn seller_covers_payout_fees(mut self, fee: u64) -> Result<Self, String> {
+        let extra_fees = self.payout_fees(fee).saturating_sub(fee);
+        if self.seller_amount < extra_fees {
+            return Err("Price is too low to cover the royalty and platform fee payouts".to_string());
+        }
+        self.seller_amount -= extra_fees;
+        Ok(self)
+    }
+
+    // Ledger fees the canister pays to distribute this split
+    fn payout_fees(&self, fee: u64) -> u64 {
+        self.payouts(Principal::anonymous()).len() as u64 * fee
+    }
+
+    // Non-zero transfers needed to distribute this split
+    fn payouts(&self, seller: Principal) -> Vec<(Principal, u64)> {
+        let mut payouts = vec![(seller, self.seller_amount)];
+        payouts.extend(self.royalty_payouts.iter().cloned());
+        payouts.push((get_principal(ALEX_REVSHARE_CANISTER_ID), self.platform_fee));
+        payouts.retain(|(_, amount)| *amount > 0);
+        payouts
+    }
+}
+
+/**
+ * Set the platform fee and the default royalty per collection (controllers only).
+ * Both are in basis points, capped at 10%.
+ */
+#[update(guard = "is_controller")]
+pub fn set_royalty_settings(settings: RoyaltySettings) -> Result<String, String> {
+    if settings.platform_fee_bps > MAX_PLATFORM_FEE_BPS {
+        return Err(format!("Platform fee can't exceed {} bps", MAX_PLATFORM_FEE_BPS));
+    }
+    if settings
+        .collection_royalty_bps
+        .iter()
+        .any(|(_, bps)| *bps > MAX_ROYALTY_BPS)
+    {
+        return Err(format!("Royalty can't exceed {} bps", MAX_ROYALTY_BPS));
+    }
+    ROYALTY_SETTINGS
+        .with(|cell| cell.borrow_mut().set(settings))
+        .map_err(|e| format!("Failed to save royalty settings: {:?}", e))?;
+    Ok("Royalty settings updated.".to_string())
+}
+
+#[query]
+pub fn get_royalty_settings() -> RoyaltySettings {
+    ROYALTY_SETTINGS.with(|cell| cell.borrow().get().clone())
+}
+
+/**
+ * Totals of everything a seller has sold through emporium, per currency.
+ */
+#[query]
+pub fn get_seller_proceeds(seller: Principal) -> Vec<(Currency, SellerProceeds)> {
+    let start = SellerCurrencyKey { seller, currency: Currency::ICP };
+    SELLER_PROCEEDS.with(|proceeds| {
+        proceeds
+            .borrow()
+            .range(start..)
+            .take_while(|(key, _)| key.seller == seller)
+            .map(|(key, totals)| (key.currency, totals))
+            .collect()
+    })
+}
+
+/// Works out the royalty and platform fee for a sale.
+///
+/// The royalty goes to the minter recorded in the token metadata, at the token's own rate if it
+/// has one and the collection default otherwise. Sellers reselling their own mint pay no royalty,
+/// and neither do tokens minted before nft_manager recorded the minter.
+pub async fn get_sale_split(
+    token_id: &Nat,
+    seller: Principal,
+    price: u64,
+    currency: Currency,
+) -> Result<SaleSplit, String> {
+    let settings = get_royalty_settings();
+    let collection = get_principal(ICRC7_CANISTER_ID);
+    let metadata = get_token_metadata(token_id.clone()).await?.unwrap_or_default();
+
+    let minter = match metadata.get(MINTER_METADATA_KEY) {
+        Some(Value::Text(text)) => Principal::from_text(text).ok(),
+        _ => None,
+    };
+    let royalty_bps = match metadata.get(ROYALTY_METADATA_KEY) {
+        Some(Value::Nat(bps)) => u64::try_from(&bps.0).unwrap_or(MAX_ROYALTY_BPS),
+        _ => settings
+            .collection_royalty_bps
+            .iter()
+            .find(|(c, _)| *c == collection)
+            .map_or(0, |(_, bps)| *bps),
+    };
+
+    Ok(split_sale(currency, price, seller, minter, royalty_bps, settings.platform_fee_bps))
+}
+
+// Divides a price between the minter's royalty, the platform fee and the seller
+fn split_sale(
+    currency: Currency,
+    price: u64,
+    seller: Principal,
+    minter: Option<Principal>,
+    royalty_bps: u64,
+    platform_fee_bps: u64,
+) -> SaleSplit {
+    let royalty_recipient = minter.filter(|minter| *minter != seller);
+    let royalty = if royalty_recipient.is_some() {
+        bps_of(price, royalty_bps.min(MAX_ROYALTY_BPS))
+    } else {
+        0
+    };
+    let platform_fee = bps_of(price, platform_fee_bps.min(MAX_PLATFORM_FEE_BPS));
+
+    SaleSplit {
+        currency,
+        price,
+        royalty,
+        royalty_payouts: royalty_recipient
+            .filter(|_| royalty > 0)
+            .map(|minter| vec![(minter, royalty)])
+            .unwrap_or_default(),
+        platform_fee,
+        seller_amount: price - royalty - platform_fee,
+    }
+}
+
+/// Escrows the price plus the payout ledger fees from the buyer, returns the ledger fee used
+pub async fn escrow_sale_payment(split: &SaleSplit) -> Result<u64, String> {
+    let fee = ledger_fee(split.currency).await?;
+    escrow_from_caller(split.currency, fee, split.price + split.payout_fees(fee)).await?;
+    Ok(fee)
+}
+
+/// Pays out an escrowed sale to the seller, minters and alex_revshare
+pub async fn distribute_sale_payment(token_id: &Nat, seller: Principal, split: &SaleSplit, fee: u64) {
+    for (to, amount) in split.payouts(seller) {
+        if let Err(err) = transfer_from_canister(split.currency, fee, amount, to).await {
+            ic_cdk::println!("Payout of {} {:?} to {} failed, queued: {}", amount, split.currency, to, err);
+            queue_payout(to, amount, split.currency, token_id.clone());
+        }
+    }
+    record_sale_proceeds(seller, split);
+}
+
+/// Returns an escrowed sale payment to the buyer when the sale is rolled back
+pub async fn refund_sale_payment(token_id: &Nat, buyer: Principal, split: &SaleSplit, fee: u64) {
+    let amount = split.price + split.payout_fees(fee) - fee;
+    if let Err(err) = transfer_from_canister(split.currency, fee, amount, buyer).await {
+        ic_cdk::println!("Refund of {} {:?} to {} failed, queued: {}", amount, split.currency, buyer, err);
+        queue_payout(buyer, amount, split.currency, token_id.clone());
+    }
+}
+
+pub fn record_sale_proceeds(seller: Principal, split: &SaleSplit) {
+    let key = SellerCurrencyKey { seller, currency: split.currency };
+    SELLER_PROCEEDS.with(|proceeds| {
+        let mut proceeds = proceeds.borrow_mut();
+        let mut totals = proceeds.get(&key).unwrap_or_default();
+        totals.sales += 1;
+        totals.gross += split.price;
+        totals.royalties += split.royalty;
+        totals.platform_fees += split.platform_fee;
+        totals.net += split.seller_amount;
+        proceeds.insert(key, totals);
+    });
+}
+
+/// Retries queued sale payouts, called from the timer
+pub async fn retry_pending_payouts() {
+    let pending: Vec<(u64, PendingPayout)> = PENDING_PAYOUTS.with(|payouts| {
+        payouts.borrow().iter().take(PAYOUT_BATCH_SIZE).collect()
+    });
+    for (payout_id, payout) in pending {
+        let transfer = match ledger_fee(payout.currency).await {
+            Ok(fee) => transfer_from_canister(payout.currency, fee, payout.amount, payout.to).await,
+            Err(err) => Err(err),
+        };
+        match transfer {
+            Ok(_) => {
+                PENDING_PAYOUTS.with(|payouts| payouts.borrow_mut().remove(&payout_id));
+            }
+            Err(err) => ic_cdk::println!("Payout {} failed, will retry: {}", payout_id, err),
+        }
+    }
+}
+
+fn queue_payout(to: Principal, amount: u64, currency: Currency, token_id: Nat) {
+    PENDING_PAYOUTS.with(|payouts| {
+        let mut payouts = payouts.borrow_mut();
+        let payout_id = payouts.last_key_value().map(|(id, _)| id + 1).unwrap_or(1);
+        payouts.insert(
+            payout_id,
+            PendingPayout {
+                to,
+                amount,
+                currency,
+                token_id,
+                created_at: ic_cdk::api::time(),
+            },
+        );
+    });
+}
+
+fn bps_of(amount: u64, bps: u64) -> u64 {
+    (amount as u128 * bps as u128 / 10_000) as u64
+}
+
+pub async fn get_token_metadata(token_id: Nat) -> Result<Option<TokenMetadata>, String> {
+    let call_result: CallResult<(Vec<Option<TokenMetadata>>,)> = ic_cdk::call(
+        get_principal(ICRC7_CANISTER_ID),
+        "icrc7_token_metadata",
+        (vec![token_id],),
+    )
+    .await;
+
+    match call_result {
+        Ok((metadata,)) => Ok(metadata.into_iter().next().flatten()),
+        Err((code, msg)) => Err(format!("Error fetching token metadata {}: {}", code as u8, msg)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seller() -> Principal {
+        Principal::from_slice(&[1])
+    }
+
+    fn minter() -> Principal {
+        Principal::from_slice(&[2])
+    }
+
+    #[test]
+    fn bps_of_rounds_down_without_overflow() {
+        assert_eq!(bps_of(10_000, 250), 250);
+        assert_eq!(bps_of(399, 250), 9);
+        assert_eq!(bps_of(u64::MAX, 10_000), u64::MAX);
+    }
+
+    #[test]
+    fn sale_split_pays_minter_platform_and_seller() {
+        let split = split_sale(Currency::ICP, 100_000, seller(), Some(minter()), 500, 200);
+        assert_eq!(split.royalty, 5_000);
+        assert_eq!(split.royalty_payouts, vec![(minter(), 5_000)]);
+        assert_eq!(split.platform_fee, 2_000);
+        assert_eq!(split.seller_amount, 93_000);
+    }
+
+    #[test]
+    fn reselling_own_mint_pays_no_royalty() {
+        let split = split_sale(Currency::ICP, 100_000, seller(), Some(seller()), 500, 200);
+        assert_eq!(split.royalty, 0);
+        assert!(split.royalty_payouts.is_empty());
+        assert_eq!(split.seller_amount, 98_000);
+
+        let split = split_sale(Currency::ICP, 100_000, seller(), None, 500, 200);
+        assert_eq!(split.royalty, 0);
+    }
+
+    #[test]
+    fn rates_are_capped() {
+        let split = split_sale(Currency::ICP, 100_000, seller(), Some(minter()), 9_000, 9_000);
+        assert_eq!(split.royalty, 10_000);
+        assert_eq!(split.platform_fee, 10_000);
+        assert_eq!(split.seller_amount, 80_000);
+    }
+
+    #[test]
+    fn seller_covers_the_extra_payout_fees() {
+        // Seller, minter and platform payouts, the escrow holds one of the three fees
+        let split = split_sale(Currency::ICP, 100_000, seller(), Some(minter()), 500, 200)
+            .seller_covers_payout_fees(10_000)
+            .unwrap();
+        assert_eq!(split.seller_amount, 73_000);
+
+        // Zero payouts need no fee
+        let split = SaleSplit::direct(Currency::ICP, 100_000).seller_covers_payout_fees(10_000).unwrap();
+        assert_eq!(split.seller_amount, 100_000);
+    }
+
+    #[test]
+    fn price_too_low_for_payout_fees_is_rejected() {
+        let split = split_sale(Currency::ICP, 10_000, seller(), Some(minter()), 1_000, 1_000);
+        assert!(split.seller_covers_payout_fees(10_000).is_err());
+    }
+}
@@ -0,0 +1,230 @@
+use crate::analytics::record_sale;
+use crate::royalties::{distribute_sale_payment, SaleSplit};
+use crate::update::{add_log, restore_listing, transfer_nft_from_canister};
+use crate::utils::remove_nft_from_listing;
+use crate::{
+    CallerGuard, LogAction, Nft, SaleKind, Settlement, SettlementStage, LISTING, OPEN_SETTLEMENTS, SETTLEMENTS,
+    SETTLEMENTS_BY_BUYER,
+};
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::query;
+
+const SETTLEMENT_BATCH_SIZE: usize = 25;
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Settlements {
+    pub settlements: Vec<Settlement>,
+    pub total_pages: u64,
+    pub current_page: u64,
+    pub page_size: u64,
+}
+
+#[query]
+pub fn get_settlement(settlement_id: u64) -> Option<Settlement> {
+    SETTLEMENTS.with(|settlements| settlements.borrow().get(&settlement_id))
+}
+
+/**
+ * Purchases made by a buyer with buy_nft, newest first, with the stage each one has reached.
+ */
+#[query]
+pub fn get_buyer_settlements(buyer: Principal, page: Option<u64>, page_size: Option<u64>) -> Settlements {
+    let page = page.unwrap_or(1).max(1);
+    let page_size = page_size.unwrap_or(10).clamp(1, 100);
+
+    let mut settlements: Vec<Settlement> = SETTLEMENTS_BY_BUYER.with(|index| {
+        index
+            .borrow()
+            .range((buyer, 0)..=(buyer, u64::MAX))
+            .filter_map(|((_, settlement_id), _)| get_settlement(settlement_id))
+            .collect()
+    });
+    settlements.reverse();
+    let total_pages = (settlements.len() as u64).div_ceil(page_size);
+    let settlements = settlements
+        .into_iter()
+        .skip(((page - 1) * page_size) as usize)
+        .take(page_size as usize)
+        .collect();
+
+    Settlements {
+        settlements,
+        total_pages,
+        current_page: page,
+        page_size,
+    }
+}
+
+/// Starts the settlement of a purchase once the marketplace fee is paid.
+///
+/// The listing is taken down so nobody else can buy it meanwhile; it has to be unchanged
+/// since the buyer read it.
+pub fn open_settlement(buyer: Principal, listing: &Nft, split: SaleSplit) -> Result<u64, String> {
+    let token_key = listing.token_id.to_string();
+    let unchanged = LISTING.with(|nfts| nfts.borrow().get(&token_key)).is_some_and(|current| {
+        current.owner == listing.owner
+            && current.price == listing.price
+            && current.currency == listing.currency
+            && current.time == listing.time
+    });
+    if !unchanged {
+        return Err("Listing changed while buying, please try again".to_string());
+    }
+    remove_nft_from_listing(listing.token_id.clone())?;
+
+    let now = ic_cdk::api::time();
+    let settlement_id = SETTLEMENTS.with(|settlements| {
+        let mut settlements = settlements.borrow_mut();
+        let settlement_id = settlements.last_key_value().map(|(id, _)| id + 1).unwrap_or(1);
+        settlements.insert(
+            settlement_id,
+            Settlement {
+                settlement_id,
+                buyer,
+                listing: listing.clone(),
+                split,
+                ledger_fee: 0,
+                stage: SettlementStage::FeePaid,
+                created_at: now,
+                updated_at: now,
+                attempts: 0,
+                last_error: None,
+            },
+        );
+        settlement_id
+    });
+    SETTLEMENTS_BY_BUYER.with(|index| index.borrow_mut().insert((buyer, settlement_id), ()));
+    OPEN_SETTLEMENTS.with(|open| open.borrow_mut().insert(settlement_id, ()));
+    Ok(settlement_id)
+}
+
+/// Records that the price is in escrow
+pub fn mark_payment_taken(settlement_id: u64, ledger_fee: u64) {
+    update_settlement(settlement_id, |settlement| {
+        settlement.ledger_fee = ledger_fee;
+        settlement.stage = SettlementStage::PaymentTaken;
+    });
+}
+
+/// Ends a purchase whose payment couldn't be taken and puts the listing back
+pub fn cancel_settlement(settlement_id: u64, reason: String) {
+    if let Some(settlement) = get_settlement(settlement_id) {
+        restore_listing(settlement.listing);
+    }
+    update_settlement(settlement_id, |settlement| {
+        settlement.stage = SettlementStage::Cancelled;
+        settlement.last_error = Some(reason);
+    });
+}
+
+/// Moves a paid purchase as far as it can go: NFT to the buyer, then the payouts.
+///
+/// A failed delivery leaves the settlement at PaymentTaken for the timer to retry.
+pub async fn advance_settlement(settlement_id: u64) -> Option<Settlement> {
+    loop {
+        let settlement = get_settlement(settlement_id)?;
+        match settlement.stage {
+            SettlementStage::PaymentTaken => {
+                match transfer_nft_from_canister(settlement.buyer, settlement.listing.token_id.clone()).await {
+                    Ok(_) => update_settlement(settlement_id, |settlement| {
+                        settlement.stage = SettlementStage::NftDelivered;
+                    }),
+                    Err(err) => {
+                        update_settlement(settlement_id, |settlement| {
+                            settlement.attempts += 1;
+                            settlement.last_error = Some(err);
+                        });
+                        return get_settlement(settlement_id);
+                    }
+                }
+            }
+            SettlementStage::NftDelivered => {
+                let listing = &settlement.listing;
+                // Completed before paying out, so a trap half way can't pay anyone twice;
+                // payouts that fail are queued with the other pending payouts
+                update_settlement(settlement_id, |settlement| {
+                    settlement.stage = SettlementStage::Completed;
+                });
+                add_log(
+                    ic_cdk::api::time(),
+                    listing.token_id.clone(),
+                    listing.owner,
+                    settlement.buyer,
+                    LogAction::Sold {
+                        price: listing.price,
+                        royalty: Some(settlement.split.royalty),
+                        platform_fee: Some(settlement.split.platform_fee),
+                        currency: Some(listing.currency),
+                    },
+                );
+                record_sale(
+                    ic_cdk::api::time(),
+                    &listing.token_id,
+                    listing.owner,
+                    settlement.buyer,
+                    listing.price,
+                    listing.currency,
+                    SaleKind::Listing,
+                );
+                distribute_sale_payment(&listing.token_id, listing.owner, &settlement.split, settlement.ledger_fee)
+                    .await;
+            }
+            _ => return Some(settlement),
+        }
+    }
+}
+
+/// Drives stuck purchases forward, called from the timer.
+///
+/// Settlements whose buyer has a call in flight are left alone, that call is still working on them.
+/// One stuck at FeePaid had its buy_nft call trap before the payment went through, so it's cancelled.
+pub async fn drive_settlements() {
+    let open: Vec<u64> = OPEN_SETTLEMENTS.with(|open| {
+        open.borrow()
+            .iter()
+            .take(SETTLEMENT_BATCH_SIZE)
+            .map(|(settlement_id, _)| settlement_id)
+            .collect()
+    });
+    for settlement_id in open {
+        let settlement = match get_settlement(settlement_id) {
+            Some(settlement) => settlement,
+            None => {
+                OPEN_SETTLEMENTS.with(|open| open.borrow_mut().remove(&settlement_id));
+                continue;
+            }
+        };
+        let _guard = match CallerGuard::new(settlement.buyer) {
+            Ok(guard) => guard,
+            Err(_) => continue,
+        };
+        if settlement.stage == SettlementStage::FeePaid {
+            cancel_settlement(settlement_id, "Payment was never taken".to_string());
+            continue;
+        }
+        if let Some(settlement) = advance_settlement(settlement_id).await {
+            if settlement.stage == SettlementStage::PaymentTaken {
+                ic_cdk::println!(
+                    "Settlement {} still can't deliver NFT {}, will retry: {:?}",
+                    settlement_id,
+                    settlement.listing.token_id,
+                    settlement.last_error
+                );
+            }
+        }
+    }
+}
+
+fn update_settlement(settlement_id: u64, apply: impl FnOnce(&mut Settlement)) {
+    SETTLEMENTS.with(|settlements| {
+        let mut settlements = settlements.borrow_mut();
+        if let Some(mut settlement) = settlements.get(&settlement_id) {
+            apply(&mut settlement);
+            settlement.updated_at = ic_cdk::api::time();
+            if !settlement.is_open() {
+                OPEN_SETTLEMENTS.with(|open| open.borrow_mut().remove(&settlement_id));
+            }
+            settlements.insert(settlement_id, settlement);
+        }
+    });
+}
@@ -4,9 +4,11 @@ use std::borrow::Cow;
 use std::cell::RefCell;
 use std::collections::BTreeSet;
 
+use crate::royalties::SaleSplit;
+
 use ic_stable_structures::memory_manager::VirtualMemory;
 use ic_stable_structures::storable::Bound;
-use ic_stable_structures::{DefaultMemoryImpl, Storable};
+use ic_stable_structures::{DefaultMemoryImpl, StableCell, Storable};
 type Memory = VirtualMemory<DefaultMemoryImpl>;
 use ic_stable_structures::{
     memory_manager::{MemoryId, MemoryManager},
@@ -14,13 +16,43 @@ use ic_stable_structures::{
 };
 pub const LISTING_MEM_ID: MemoryId = MemoryId::new(0);
 pub const LOGS_MEM_ID: MemoryId = MemoryId::new(1);
+pub const OFFERS_MEM_ID: MemoryId = MemoryId::new(2);
+pub const OFFERS_BY_TOKEN_MEM_ID: MemoryId = MemoryId::new(3);
+pub const OFFERS_BY_BUYER_MEM_ID: MemoryId = MemoryId::new(4);
+pub const OFFER_EXPIRY_MEM_ID: MemoryId = MemoryId::new(5);
+pub const AUCTIONS_MEM_ID: MemoryId = MemoryId::new(7);
+pub const OPEN_AUCTION_ENDS_MEM_ID: MemoryId = MemoryId::new(8);
+pub const PENDING_BID_REFUNDS_MEM_ID: MemoryId = MemoryId::new(9);
+pub const ROYALTY_SETTINGS_MEM_ID: MemoryId = MemoryId::new(10);
+pub const PENDING_PAYOUTS_MEM_ID: MemoryId = MemoryId::new(11);
+pub const SELLER_PROCEEDS_MEM_ID: MemoryId = MemoryId::new(12);
+pub const LISTING_EXPIRY_MEM_ID: MemoryId = MemoryId::new(13);
+pub const BUNDLES_MEM_ID: MemoryId = MemoryId::new(14);
+pub const PENDING_NFT_DELIVERIES_MEM_ID: MemoryId = MemoryId::new(15);
+pub const SETTLEMENTS_MEM_ID: MemoryId = MemoryId::new(16);
+pub const SETTLEMENTS_BY_BUYER_MEM_ID: MemoryId = MemoryId::new(17);
+pub const OPEN_SETTLEMENTS_MEM_ID: MemoryId = MemoryId::new(18);
+pub const LISTING_PRICES_MEM_ID: MemoryId = MemoryId::new(19);
+pub const LISTED_COUNTS_MEM_ID: MemoryId = MemoryId::new(20);
+pub const HOURLY_VOLUME_MEM_ID: MemoryId = MemoryId::new(21);
+pub const TOKEN_SALES_MEM_ID: MemoryId = MemoryId::new(22);
+pub const SELLER_SALES_MEM_ID: MemoryId = MemoryId::new(23);
+pub const LISTING_METADATA_MEM_ID: MemoryId = MemoryId::new(24);
+pub const LISTING_ATTRS_MEM_ID: MemoryId = MemoryId::new(25);
+pub const LISTINGS_BY_TIME_MEM_ID: MemoryId = MemoryId::new(26);
+pub const WATCHLIST_MEM_ID: MemoryId = MemoryId::new(27);
+pub const ALERT_RULES_MEM_ID: MemoryId = MemoryId::new(28);
+pub const ALERT_RULES_BY_OWNER_MEM_ID: MemoryId = MemoryId::new(29);
+pub const ALERT_RULES_BY_TARGET_MEM_ID: MemoryId = MemoryId::new(30);
+pub const ALERT_INBOX_MEM_ID: MemoryId = MemoryId::new(31);
+pub const SALES_BACKFILL_MEM_ID: MemoryId = MemoryId::new(32);
 
 thread_local! {
 
     static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> = RefCell::new(
         MemoryManager::init(DefaultMemoryImpl::default())
     );
-    pub static STATE: RefCell<State> = RefCell::new(State{pending_requests: BTreeSet::new()});
+    pub static STATE: RefCell<State> = const { RefCell::new(State{pending_requests: BTreeSet::new(), pending_metadata: BTreeSet::new()}) };
 
     pub static LISTING: RefCell<StableBTreeMap<String, Nft, Memory>> = RefCell::new(
         StableBTreeMap::init(
@@ -32,17 +64,227 @@ thread_local! {
             MEMORY_MANAGER.with(|m| m.borrow().get(LOGS_MEM_ID))
         )
     );
+    pub static OFFERS: RefCell<StableBTreeMap<u64, Offer, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(OFFERS_MEM_ID))
+        )
+    );
+    // Indexes into OFFERS, so per-token and per-buyer lookups are range scans
+    pub static OFFERS_BY_TOKEN: RefCell<StableBTreeMap<OfferTokenKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(OFFERS_BY_TOKEN_MEM_ID))
+        )
+    );
+    pub static OFFERS_BY_BUYER: RefCell<StableBTreeMap<OfferBuyerKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(OFFERS_BY_BUYER_MEM_ID))
+        )
+    );
+    // (expires_at, offer_id) for Active offers only, so the sweep doesn't scan history
+    pub static OFFER_EXPIRY: RefCell<StableBTreeMap<(u64, u64), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(OFFER_EXPIRY_MEM_ID))
+        )
+    );
+    pub static AUCTIONS: RefCell<StableBTreeMap<u64, Auction, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(AUCTIONS_MEM_ID))
+        )
+    );
+    // (end_time, auction_id) for auctions that still hold an NFT or ICP, removed once settled
+    pub static OPEN_AUCTION_ENDS: RefCell<StableBTreeMap<(u64, u64), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(OPEN_AUCTION_ENDS_MEM_ID))
+        )
+    );
+    // (auction_id, bidder) -> e8s of outbid bids whose refund failed, retried by the timer
+    pub static PENDING_BID_REFUNDS: RefCell<StableBTreeMap<(u64, Principal), u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(PENDING_BID_REFUNDS_MEM_ID))
+        )
+    );
+    pub static ROYALTY_SETTINGS: RefCell<StableCell<RoyaltySettings, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(ROYALTY_SETTINGS_MEM_ID)),
+            RoyaltySettings::default(),
+        ).expect("Failed to init royalty settings")
+    );
+    // Sale proceeds (royalties, platform fees) whose transfer failed, retried by the timer
+    pub static PENDING_PAYOUTS: RefCell<StableBTreeMap<u64, PendingPayout, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(PENDING_PAYOUTS_MEM_ID))
+        )
+    );
+    pub static SELLER_PROCEEDS: RefCell<StableBTreeMap<SellerCurrencyKey, SellerProceeds, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(SELLER_PROCEEDS_MEM_ID))
+        )
+    );
+    // Listings with an expiry, swept by the timer
+    pub static LISTING_EXPIRY: RefCell<StableBTreeMap<ListingExpiryKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(LISTING_EXPIRY_MEM_ID))
+        )
+    );
+    // Listed bundles, removed once sold or taken down
+    pub static BUNDLES: RefCell<StableBTreeMap<u64, Bundle, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(BUNDLES_MEM_ID))
+        )
+    );
+    // token_id -> recipient of NFTs held by emporium whose delivery failed, retried by the timer
+    pub static PENDING_NFT_DELIVERIES: RefCell<StableBTreeMap<String, Principal, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(PENDING_NFT_DELIVERIES_MEM_ID))
+        )
+    );
+    pub static SETTLEMENTS: RefCell<StableBTreeMap<u64, Settlement, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(SETTLEMENTS_MEM_ID))
+        )
+    );
+    // (buyer, settlement_id) index into SETTLEMENTS
+    pub static SETTLEMENTS_BY_BUYER: RefCell<StableBTreeMap<(Principal, u64), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(SETTLEMENTS_BY_BUYER_MEM_ID))
+        )
+    );
+    // Settlements not yet Completed or Cancelled, driven forward by the timer
+    pub static OPEN_SETTLEMENTS: RefCell<StableBTreeMap<u64, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(OPEN_SETTLEMENTS_MEM_ID))
+        )
+    );
+    // Single listings ordered by currency and price, for the floor price
+    pub static LISTING_PRICES: RefCell<StableBTreeMap<ListingPriceKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(LISTING_PRICES_MEM_ID))
+        )
+    );
+    pub static LISTED_COUNTS: RefCell<StableBTreeMap<Currency, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(LISTED_COUNTS_MEM_ID))
+        )
+    );
+    // Sales per currency and hour, summed for the trailing volume
+    pub static HOURLY_VOLUME: RefCell<StableBTreeMap<VolumeKey, VolumeBucket, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(HOURLY_VOLUME_MEM_ID))
+        )
+    );
+    pub static TOKEN_SALES: RefCell<StableBTreeMap<TokenSaleKey, SaleRecord, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(TOKEN_SALES_MEM_ID))
+        )
+    );
+    pub static SELLER_SALES: RefCell<StableBTreeMap<SellerSaleKey, SaleRecord, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(SELLER_SALES_MEM_ID))
+        )
+    );
+    // Details looked up when a token is listed, kept after delisting so a restored listing finds them
+    pub static LISTING_METADATA: RefCell<StableBTreeMap<String, ListingMetadata, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(LISTING_METADATA_MEM_ID))
+        )
+    );
+    // Single listings by attribute ("owner:", "user:", "type:", "tag:", "origin:"), for get_listings filters
+    pub static LISTING_ATTRS: RefCell<StableBTreeMap<ListingAttrKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(LISTING_ATTRS_MEM_ID))
+        )
+    );
+    pub static LISTINGS_BY_TIME: RefCell<StableBTreeMap<ListingTimeKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(LISTINGS_BY_TIME_MEM_ID))
+        )
+    );
+    // Tokens and sellers each principal follows, with the time they were added
+    pub static WATCHLIST: RefCell<StableBTreeMap<WatchKey, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(WATCHLIST_MEM_ID))
+        )
+    );
+    pub static ALERT_RULES: RefCell<StableBTreeMap<u64, AlertRule, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(ALERT_RULES_MEM_ID))
+        )
+    );
+    pub static ALERT_RULES_BY_OWNER: RefCell<StableBTreeMap<(Principal, u64), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(ALERT_RULES_BY_OWNER_MEM_ID))
+        )
+    );
+    // Rules by the token or seller they watch, so listing only reads the rules it can fire
+    pub static ALERT_RULES_BY_TARGET: RefCell<StableBTreeMap<AlertTargetKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(ALERT_RULES_BY_TARGET_MEM_ID))
+        )
+    );
+    // Fired alerts per principal, waiting to be pulled with get_alert_inbox
+    pub static ALERT_INBOX: RefCell<StableBTreeMap<(Principal, u64), AlertEvent, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(ALERT_INBOX_MEM_ID))
+        )
+    );
+    // Progress of replaying the logs into the sales history, one batch per timer tick
+    pub static SALES_BACKFILL: RefCell<StableCell<SalesBackfill, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(SALES_BACKFILL_MEM_ID)),
+            SalesBackfill::default(),
+        ).expect("Failed to init sales backfill")
+    );
 
 }
 const MAX_VALUE_SIZE: u32 = 300;
 
+// Allow-list of ICRC-1/2 ledgers listings can be priced in
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Currency {
+    #[default]
+    ICP,
+    LBRY,
+    ALEX,
+    CKUSDC,
+}
+
+impl Storable for Currency {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
 #[derive(CandidType, Deserialize, Clone, Debug)]
 pub struct Nft {
     pub owner: Principal,
-    pub price: u64,
+    pub price: u64, // smallest unit of `currency`
     pub token_id: Nat,
     pub status: NftStatus, // Replaced String with NftStatus enum
     pub time: u64,
+    pub currency: Currency,
+    pub expires_at: Option<u64>,
+}
+
+impl Nft {
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| now >= expires_at)
+    }
+}
+
+// Listings stored before multi-currency pricing, all priced in ICP
+#[derive(CandidType, Deserialize)]
+struct LegacyNft {
+    owner: Principal,
+    price: u64,
+    token_id: Nat,
+    status: NftStatus,
+    time: u64,
 }
 
 #[derive(CandidType, Deserialize, Clone, Debug)]
@@ -53,18 +295,38 @@ pub struct Listing {
     pub page_size: u64,
 }
 
-#[derive(CandidType, Deserialize, Clone, Debug)]
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
 pub enum NftStatus {
     Listed,
-    Reimbursed,
+    Reimbursed, // no longer set, failed deliveries are retried by the purchase's settlement
 }
 #[derive(CandidType, Deserialize, Clone, Debug)]
 pub enum LogAction {
     PriceUpdate { old_price: u64, new_price: u64 },
-    Sold { price: u64 },
+    Sold { price: u64, royalty: Option<u64>, platform_fee: Option<u64>, currency: Option<Currency> }, // None on older sales (ICP, no royalties)
     ReimbursedToBuyer,
-    Listed { price: u64 },
-    Removed,
+    Listed { price: u64, currency: Option<Currency> }, // None on older listings (ICP)
+    Removed(Option<RemovalReason>), // None on older logs
+    OfferMade { offer_id: u64, amount: u64 },
+    OfferCancelled { offer_id: u64 },
+    OfferExpired { offer_id: u64 },
+    OfferAccepted { offer_id: u64, amount: u64 },
+    AuctionCreated { auction_id: u64, reserve_price: u64 },
+    BidPlaced { auction_id: u64, amount: u64 },
+    AuctionExtended { auction_id: u64, end_time: u64 },
+    BidRefunded { auction_id: u64, amount: u64 },
+    AuctionSold { auction_id: u64, price: u64 },
+    AuctionUnsold { auction_id: u64 },
+    AuctionCancelled { auction_id: u64 },
+    // Bundle logs are filed under the bundle's first token
+    BundleListed { bundle_id: u64, token_ids: Vec<Nat>, price: u64, currency: Currency },
+    BundleSold { bundle_id: u64, price: u64, royalty: u64, platform_fee: u64, currency: Currency },
+    BundleRemoved { bundle_id: u64 },
+}
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum RemovalReason {
+    ByOwner,
+    Expired,
 }
 #[derive(CandidType, Deserialize, Clone, Debug)]
 pub struct LogEntry {
@@ -89,7 +351,18 @@ impl Storable for Nft {
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+        Decode!(bytes.as_ref(), Self).unwrap_or_else(|_| {
+            let legacy = Decode!(bytes.as_ref(), LegacyNft).unwrap();
+            Nft {
+                owner: legacy.owner,
+                price: legacy.price,
+                token_id: legacy.token_id,
+                status: legacy.status,
+                time: legacy.time,
+                currency: Currency::ICP,
+                expires_at: None,
+            }
+        })
     }
 
     const BOUND: Bound = Bound::Bounded {
@@ -97,6 +370,8 @@ impl Storable for Nft {
         is_fixed_size: false,
     };
 }
+// Unbounded since the candid type table grows with every LogAction variant,
+// existing bounded entries are still readable after the switch.
 impl Storable for LogEntry {
     fn to_bytes(&self) -> Cow<[u8]> {
         Cow::Owned(Encode!(self).unwrap())
@@ -106,11 +381,622 @@ impl Storable for LogEntry {
         Decode!(bytes.as_ref(), Self).unwrap()
     }
 
-    const BOUND: Bound = Bound::Bounded {
-        max_size: MAX_VALUE_SIZE,
-        is_fixed_size: false,
-    };
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum OfferStatus {
+    Active,
+    Settling, // accept_offer in progress
+    Accepted { seller: Principal, time: u64 },
+    Cancelled,
+    Expired,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Offer {
+    pub offer_id: u64,
+    pub token_id: Nat,
+    pub buyer: Principal,
+    pub amount: u64, // e8s ICP paid to the seller, the ledger fee for the payout is escrowed on top
+    pub created_at: u64,
+    pub expires_at: u64,
+    pub status: OfferStatus,
+}
+
+impl Offer {
+    pub fn is_open(&self, now: u64) -> bool {
+        self.status == OfferStatus::Active && now < self.expires_at
+    }
+}
+
+impl Storable for Offer {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum AuctionStatus {
+    Active,   // also covers scheduled auctions, see start_time
+    Settling, // settlement call in progress
+    Sold,
+    Unsold, // reserve not met, NFT returned to seller
+    Cancelled,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Bid {
+    pub bidder: Principal,
+    pub amount: u64,
+    pub time: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Auction {
+    pub auction_id: u64,
+    pub token_id: Nat,
+    pub seller: Principal,
+    pub reserve_price: u64,
+    pub min_increment: u64,
+    pub start_time: u64,
+    pub end_time: u64, // pushed back by late bids
+    pub highest_bid: Option<Bid>,
+    pub bid_count: u64,
+    pub status: AuctionStatus,
+    pub settling_since: Option<u64>, // when the current settlement attempt started, while Settling
+}
+
+impl Storable for Auction {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct RoyaltySettings {
+    pub platform_fee_bps: u64, // share of every sale sent to alex_revshare
+    pub collection_royalty_bps: Vec<(Principal, u64)>, // default per ICRC-7 collection, token metadata overrides it
+}
+
+impl Storable for RoyaltySettings {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct PendingPayout {
+    pub to: Principal,
+    pub amount: u64,
+    pub currency: Currency,
+    pub token_id: Nat,
+    pub created_at: u64,
+}
+
+impl Storable for PendingPayout {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SellerCurrencyKey {
+    pub seller: Principal,
+    pub currency: Currency,
+}
+
+impl Storable for SellerCurrencyKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// Running totals per seller and currency
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct SellerProceeds {
+    pub sales: u64,
+    pub gross: u64,
+    pub royalties: u64,
+    pub platform_fees: u64,
+    pub net: u64,
+}
+
+impl Storable for SellerProceeds {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ListingExpiryKey {
+    pub expires_at: u64,
+    pub token_id: String, // LISTING key
+}
+
+impl Storable for ListingExpiryKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum BundleStatus {
+    Listed,
+    Settling, // buy_bundle or remove_bundle in progress
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Bundle {
+    pub bundle_id: u64,
+    pub owner: Principal,
+    pub token_ids: Vec<Nat>,
+    pub price: u64, // smallest unit of `currency`, for the whole bundle
+    pub currency: Currency,
+    pub time: u64,
+    pub status: BundleStatus,
+}
+
+impl Storable for Bundle {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// Stages of a buy_nft purchase, in order; Cancelled means nothing was taken but the marketplace fee
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum SettlementStage {
+    FeePaid,
+    PaymentTaken, // price in escrow, NFT not yet delivered
+    NftDelivered, // seller, royalty and platform fee not yet paid out
+    Completed,
+    Cancelled,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Settlement {
+    pub settlement_id: u64,
+    pub buyer: Principal,
+    pub listing: Nft, // as it was when bought, put back if the purchase is cancelled
+    pub split: SaleSplit,
+    pub ledger_fee: u64, // fee of the currency's ledger when the payment was escrowed
+    pub stage: SettlementStage,
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub attempts: u64, // failed attempts to move to the next stage
+    pub last_error: Option<String>,
+}
+
+impl Settlement {
+    pub fn is_open(&self) -> bool {
+        !matches!(self.stage, SettlementStage::Completed | SettlementStage::Cancelled)
+    }
+}
+
+impl Storable for Settlement {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ListingPriceKey {
+    pub currency: Currency,
+    pub price: u64,
+    pub token_id: String, // LISTING key
+}
+
+impl Storable for ListingPriceKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct VolumeKey {
+    pub currency: Currency,
+    pub hour: u64, // hours since the epoch
+}
+
+impl Storable for VolumeKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct VolumeBucket {
+    pub sales: u64,
+    pub volume: u64,
+}
+
+impl Storable for VolumeBucket {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum SaleKind {
+    Listing,
+    Offer,
+    Auction,
+    Bundle { bundle_id: u64 }, // price is the token's equal share of the bundle price
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Default, PartialEq)]
+pub enum SalesBackfill {
+    #[default]
+    NotStarted,
+    Running { next_log: u64, last_log: u64 }, // LOGS keys, logs after last_log were recorded live
+    Done,
+}
+
+impl Storable for SalesBackfill {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SaleRecord {
+    pub token_id: Nat,
+    pub seller: Principal,
+    pub buyer: Principal,
+    pub price: u64,
+    pub currency: Currency,
+    pub time: u64,
+    pub kind: SaleKind,
+}
+
+impl Storable for SaleRecord {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TokenSaleKey {
+    pub token_id: String, // same format as the LISTING keys
+    pub time: u64,
+}
+
+impl Storable for TokenSaleKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SellerSaleKey {
+    pub seller: Principal,
+    pub time: u64,
+    pub token_id: String,
+}
+
+impl Storable for SellerSaleKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenOrigin {
+    OG,
+    Scion,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ListingMetadata {
+    pub content_type: Option<String>, // lowercased
+    pub tags: Vec<String>,            // lowercased
+    pub owner_username: Option<String>,
+    pub origin: TokenOrigin,
+}
+
+impl Storable for ListingMetadata {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ListingAttrKey {
+    pub attr: String,
+    pub token_id: String, // LISTING key
+}
+
+impl Storable for ListingAttrKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ListingTimeKey {
+    pub time: u64,
+    pub token_id: String, // LISTING key
+}
+
+impl Storable for ListingTimeKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct OfferTokenKey {
+    pub token_id: String, // same format as the LISTING keys
+    pub offer_id: u64,
+}
+
+impl Storable for OfferTokenKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct OfferBuyerKey {
+    pub buyer: Principal,
+    pub offer_id: u64,
+}
+
+impl Storable for OfferBuyerKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum WatchTarget {
+    Token(Nat),
+    Seller(Principal),
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct WatchKey {
+    pub watcher: Principal,
+    pub target: WatchTarget,
+}
+
+impl Storable for WatchKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum AlertCondition {
+    TokenListedBelow { token_id: Nat, price: u64, currency: Currency }, // listed or repriced under price
+    SellerLists { seller: Principal },                                  // any new listing by the seller
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct AlertRule {
+    pub rule_id: u64,
+    pub owner: Principal,
+    pub condition: AlertCondition,
+    pub created_at: u64,
+}
+
+impl Storable for AlertRule {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AlertTargetKey {
+    pub target: String, // "token:<LISTING key>" or "seller:<principal>"
+    pub rule_id: u64,
+}
+
+impl Storable for AlertTargetKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AlertTrigger {
+    Listed,
+    PriceUpdated,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct AlertEvent {
+    pub event_id: u64, // increasing per principal
+    pub rule_id: u64,
+    pub token_id: Nat,
+    pub seller: Principal,
+    pub price: u64,
+    pub currency: Currency,
+    pub trigger: AlertTrigger,
+    pub time: u64,
+}
+
+impl Storable for AlertEvent {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
 }
 pub struct State {
     pub pending_requests: BTreeSet<Principal>,
+    pub pending_metadata: BTreeSet<String>, // listings waiting for their ListingMetadata, rebuilt on upgrade
+}
+
+// Rewrites listings stored before multi-currency pricing so they carry Currency::ICP explicitly
+pub fn migrate_listing_currencies() {
+    LISTING.with(|nfts| {
+        let mut nfts = nfts.borrow_mut();
+        let listings: Vec<(String, Nft)> = nfts.iter().collect();
+        for (key, nft) in listings {
+            nfts.insert(key, nft);
+        }
+    });
+}
+
+// Hands NFTs of listings left Reimbursed by the old buy_nft to the delivery retrier, their buyers already paid
+pub fn migrate_reimbursed_listings() {
+    let reimbursed: Vec<(String, Nft)> = LISTING.with(|nfts| {
+        nfts.borrow()
+            .iter()
+            .filter(|(_, nft)| nft.status == NftStatus::Reimbursed)
+            .collect()
+    });
+    for (key, nft) in reimbursed {
+        LISTING.with(|nfts| nfts.borrow_mut().remove(&key));
+        PENDING_NFT_DELIVERIES.with(|deliveries| deliveries.borrow_mut().insert(key, nft.owner));
+    }
 }
@@ -0,0 +1,51 @@
+use ic_cdk::{init, post_upgrade};
+use ic_cdk_timers::set_timer_interval;
+use std::time::Duration;
+
+use crate::analytics::{backfill_sales_from_logs, start_sales_backfill};
+use crate::auctions::settle_auctions;
+use crate::bundles::retry_pending_deliveries;
+use crate::listing_index::{backfill_listing_metadata, rebuild_listing_index};
+use crate::offers::sweep_offers;
+use crate::royalties::retry_pending_payouts;
+use crate::settlements::drive_settlements;
+use crate::update::sweep_expired_listings;
+use crate::{migrate_listing_currencies, migrate_reimbursed_listings, CallerGuard};
+
+// Short enough that ended auctions settle promptly
+const BACKGROUND_JOBS_INTERVAL: Duration = Duration::from_secs(60);
+
+#[init]
+fn init() {
+    start_timers();
+}
+
+#[post_upgrade]
+fn post_upgrade() {
+    migrate_listing_currencies();
+    migrate_reimbursed_listings();
+    rebuild_listing_index();
+    start_sales_backfill();
+    start_timers();
+}
+
+// Timers don't survive upgrades, so they're set up again on every install
+fn start_timers() {
+    set_timer_interval(BACKGROUND_JOBS_INTERVAL, || ic_cdk::spawn(run_background_jobs()));
+}
+
+async fn run_background_jobs() {
+    // Keyed on the canister's own principal so a slow run is never overlapped by the next tick
+    let _guard = match CallerGuard::new(ic_cdk::id()) {
+        Ok(guard) => guard,
+        Err(_) => return,
+    };
+    drive_settlements().await;
+    sweep_expired_listings().await;
+    sweep_offers().await;
+    settle_auctions().await;
+    retry_pending_payouts().await;
+    retry_pending_deliveries().await;
+    backfill_listing_metadata().await;
+    backfill_sales_from_logs();
+}
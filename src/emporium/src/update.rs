@@ -1,45 +1,73 @@
 use crate::utils::call_deduct_marketplace_fee;
+use crate::listing_index::{fetch_listing_metadata, index_listing, set_listing_metadata, unindex_listing};
+use crate::royalties::{escrow_sale_payment, get_sale_split};
+use crate::settlements::{advance_settlement, cancel_settlement, mark_payment_taken, open_settlement};
+use crate::watchlist::check_alerts;
 use crate::{not_anon, CallerGuard};
 use crate::{
     id_converter,
     utils::{
-        get_principal, is_owner, remove_nft_from_listing, Account, TransferArg, TransferError,
+        currency_ledger, get_principal, is_owner, remove_nft_from_listing, Account, TransferArg, TransferError,
         TransferFromArg, TransferFromError, TransferFromResult, TransferResult,
         EMPORIUM_CANISTER_ID, ICRC7_CANISTER_ID,
     },
-    Nft, NftStatus, LISTING,
+    AlertTrigger, Currency, ListingExpiryKey, Nft, NftStatus, RemovalReason, SettlementStage, LISTING, LISTING_EXPIRY,
 };
 use candid::{Nat, Principal};
 use ic_cdk::api::call;
 use ic_cdk::{api::call::CallResult, caller, update};
 use icrc_ledger_types::icrc1::account::Account as AccountIcrc;
-use icrc_ledger_types::icrc1::transfer::BlockIndex;
+use icrc_ledger_types::icrc1::transfer::{
+    BlockIndex, TransferArg as TransferArgIcrc, TransferError as TransferErrorIcrc,
+};
 use icrc_ledger_types::icrc2::transfer_from::{
     TransferFromArgs as TransferFromArgsIcrc, TransferFromError as TransferFromErrorIcrc,
 };
 
 use crate::{LogAction, LogEntry, LOGS};
-use ic_ledger_types::MAINNET_LEDGER_CANISTER_ID;
 
+const MIN_LISTING_DURATION_NS: u64 = 60 * 60 * 1_000_000_000; // 1 hour
+// Expired listings are left alone this long so a purchase already in flight can finish
+const EXPIRY_SWEEP_GRACE_NS: u64 = 5 * 60 * 1_000_000_000;
+const EXPIRY_SWEEP_BATCH_SIZE: usize = 25;
+
+/**
+ * List an NFT at a fixed price, in ICP unless another allowed currency is given.
+ * The price is in the currency's smallest unit (e8s for ICP).
+ * With expires_at the listing is taken down and the NFT returned once that time passes.
+ */
 #[update(guard = "not_anon")]
-pub async fn list_nft(token_id: Nat, icp_amount: u64) -> Result<String, String> {
+pub async fn list_nft(
+    token_id: Nat,
+    price: u64,
+    currency: Option<Currency>,
+    expires_at: Option<u64>,
+) -> Result<String, String> {
     //check ownership
     //desposit nft to canister
     //add record to listing
     let _guard: CallerGuard = CallerGuard::new(ic_cdk::caller())?;
-    if icp_amount < 1 {
-        return Err("Price should greater than 1 e8s ICP".to_string());
+    let currency = currency.unwrap_or_default();
+    if price < 1 {
+        return Err("Price should greater than 1 unit".to_string());
+    }
+    if let Some(expires_at) = expires_at {
+        if expires_at < ic_cdk::api::time().saturating_add(MIN_LISTING_DURATION_NS) {
+            return Err("Listing must stay up for at least 1 hour".to_string());
+        }
     }
     match is_owner(caller(), token_id.clone()).await {
         Ok(true) => {}
         Ok(false) => return Err("You can't list this NFT, ownership proof failed!".to_string()),
         Err(_) => return Err("Something went wrong !".to_string()),
     };
+    // Content type, tags and username for the get_listings filters
+    let metadata = fetch_listing_metadata(&token_id, caller()).await;
     //Deducting Lbry from subaccount
     call_deduct_marketplace_fee().await?;
     deposit_nft_to_canister(token_id.clone()).await?;
     let timestamp = ic_cdk::api::time();
-    LISTING.with(|nfts| -> Result<(), String> {
+    let listed = LISTING.with(|nfts| -> Result<Nft, String> {
         let mut nft_map = nfts.borrow_mut();
         let nft = match nft_map.get(&token_id.to_string()) {
             Some(_existing_nft_sale) => {
@@ -47,17 +75,33 @@ pub async fn list_nft(token_id: Nat, icp_amount: u64) -> Result<String, String>
             }
             None => Nft {
                 owner: caller(),
-                price: icp_amount,
+                price,
                 token_id: token_id.clone(),
                 status: NftStatus::Listed,
                 time: timestamp,
+                currency,
+                expires_at,
             },
         };
 
-        nft_map.insert(token_id.clone().to_string(), nft);
-        Ok(())
+        set_listing_metadata(&token_id, metadata);
+        index_listing(&nft);
+        nft_map.insert(token_id.clone().to_string(), nft.clone());
+        Ok(nft)
     })?;
-    add_log(timestamp, token_id, caller(),Principal::anonymous(), LogAction::Listed{price:icp_amount});
+    if let Some(expires_at) = expires_at {
+        LISTING_EXPIRY.with(|index| {
+            index.borrow_mut().insert(
+                ListingExpiryKey {
+                    expires_at,
+                    token_id: token_id.to_string(),
+                },
+                (),
+            )
+        });
+    }
+    add_log(timestamp, token_id, caller(),Principal::anonymous(), LogAction::Listed{price, currency: Some(currency)});
+    check_alerts(&listed, AlertTrigger::Listed);
     Ok("NFT added for sale".to_string())
 }
 #[update(guard = "not_anon")]
@@ -73,21 +117,31 @@ pub async fn remove_nft_listing(token_id: Nat) -> Result<String, String> {
         .ok_or("NFT doesn't exists")?;
     if current_nft.owner == caller() {
         call_deduct_marketplace_fee().await?;
-        // Transfer the NFT back to the owner
-        transfer_nft_from_canister(caller(), token_id.clone()).await?;
+        // Take the listing down before the transfer so the expiry sweep can't return it twice
         remove_nft_from_listing(token_id.clone())?;
-        add_log(ic_cdk::api::time(), token_id, caller(),Principal::anonymous(), LogAction::Removed);
+        // Transfer the NFT back to the owner
+        if let Err(err) = transfer_nft_from_canister(caller(), token_id.clone()).await {
+            restore_listing(current_nft);
+            return Err(err);
+        }
+        add_log(ic_cdk::api::time(), token_id, caller(),Principal::anonymous(), LogAction::Removed(Some(RemovalReason::ByOwner)));
     } else {
         return Err("Unauthorized !".to_string());
     }
     Ok("Successfully cancelled the NFT listing.".to_string())
 }
+/**
+ * Buy a listed NFT. The price is escrowed with icrc2_transfer_from on the listing currency's ledger and
+ * split between the seller, the minter's royalty and the platform fee, so approve price + 4 * fee to emporium first.
+ * Each purchase is tracked as a settlement (see get_buyer_settlements); once the price is escrowed the
+ * purchase always completes, an NFT that can't be delivered right away is retried by the timer.
+ */
 #[update(guard = "not_anon")]
 pub async fn buy_nft(token_id: Nat) -> Result<String, String> {
     // deduct fee in LBRY
-    // transfer ICP from caller to seller through tranfer approve
-    // transfer NFT
-    // delete record from sale
+    // open a settlement, taking the listing down
+    // escrow the price from the caller through tranfer approve
+    // transfer NFT, then pay out seller, minter and alex_revshare
     let _guard: CallerGuard = CallerGuard::new(ic_cdk::caller())?;
 
     let current_nft = LISTING
@@ -96,67 +150,40 @@ pub async fn buy_nft(token_id: Nat) -> Result<String, String> {
             nft_map.get(&token_id.clone().to_string())
         })
         .ok_or("NFT doesn't exists")?;
-    let seller = current_nft.owner.clone();
+    if current_nft.is_expired(ic_cdk::api::time()) {
+        return Err("Listing has expired".to_string());
+    }
+    let split = get_sale_split(&token_id, current_nft.owner, current_nft.price, current_nft.currency).await?;
     call_deduct_marketplace_fee().await?;
-    transfer_icp_to_seller(current_nft.price, current_nft.owner).await?;
-    match transfer_nft_from_canister(caller(), token_id.clone()).await {
-        Ok(ok) => {
-            add_log(
-                ic_cdk::api::time(),
-                token_id.clone(),
-                seller,caller(),
-                LogAction::Sold{price:current_nft.price.clone()} ,
-            ); //seller
-           
-        }
+    let settlement_id = open_settlement(caller(), &current_nft, split.clone())?;
+    match escrow_sale_payment(&split).await {
+        Ok(fee) => mark_payment_taken(settlement_id, fee),
         Err(err) => {
-            //incase of failure change the owner to caller
-            LISTING.with(|nfts| -> Result<(), String> {
-                let mut nft_map = nfts.borrow_mut();
-
-                // Retrieve the existing NFT
-                let updated_nft = match nft_map.get(&token_id.clone().to_string()) {
-                    Some(existing_nft) => {
-                        let mut updated = existing_nft.clone();
-                        updated.owner = caller();
-                        updated.status = NftStatus::Reimbursed;
-                        updated.time = ic_cdk::api::time();
-                        updated
-                    }
-                    None => return Err("NFT not listed for sale.".to_string()),
-                };
-
-                // Updated
-                nft_map.insert(token_id.clone().to_string(), updated_nft);
-                add_log(
-                    ic_cdk::api::time(),
-                    token_id.clone(),
-                    seller,caller(),
-                    LogAction::ReimbursedToBuyer ,
-                ); // for seller
-               
-
-                return Err("Nft transfer failed, ownership transfered.".to_string());
-            })?;
+            cancel_settlement(settlement_id, err.clone());
+            return Err(err);
         }
     }
 
-    remove_nft_from_listing(token_id)?;
-
-    Ok("Success".to_string())
+    match advance_settlement(settlement_id).await {
+        Some(settlement) if settlement.stage == SettlementStage::Completed => Ok("Success".to_string()),
+        _ => Ok(format!(
+            "Payment received, the NFT will be delivered shortly (settlement {}).",
+            settlement_id
+        )),
+    }
 }
 #[update(guard = "not_anon")]
 pub async fn update_nft_price(token_id: Nat, new_price: u64) -> Result<String, String> {
     let _guard: CallerGuard = CallerGuard::new(ic_cdk::caller())?;
 
     if new_price < 1 {
-        return Err("Price should greater than 1 e8s ICP".to_string());
+        return Err("Price should greater than 1 unit".to_string());
     }
     call_deduct_marketplace_fee().await?;
     let current_time: u64 = ic_cdk::api::time();
     let mut old_price:u64=0;
 
-    LISTING.with(|nfts| -> Result<(), String> {
+    let repriced = LISTING.with(|nfts| -> Result<Nft, String> {
         let mut nft_map = nfts.borrow_mut();
         // Retrieve the existing NFT
         let updated_nft = match nft_map.get(&token_id.clone().to_string()) {
@@ -164,6 +191,9 @@ pub async fn update_nft_price(token_id: Nat, new_price: u64) -> Result<String, S
                 if existing_nft.owner != caller() {
                     return Err("Only the owner of the NFT can update its price.".to_string());
                 }
+                if existing_nft.is_expired(current_time) {
+                    return Err("Listing has expired".to_string());
+                }
                 let mut updated = existing_nft.clone();
                  old_price=updated.price.clone(); //old price 
                 updated.price = new_price;
@@ -173,9 +203,13 @@ pub async fn update_nft_price(token_id: Nat, new_price: u64) -> Result<String, S
             None => return Err("NFT not listed for sale.".to_string()),
         };
 
-        // Updated
-        nft_map.insert(token_id.clone().to_string(), updated_nft);
-        Ok(())
+        // Updated, re-indexed under the new price
+        if let Some(existing_nft) = nft_map.get(&token_id.to_string()) {
+            unindex_listing(&existing_nft);
+        }
+        index_listing(&updated_nft);
+        nft_map.insert(token_id.clone().to_string(), updated_nft.clone());
+        Ok(updated_nft)
     })?;
     add_log(
         current_time,
@@ -184,6 +218,7 @@ pub async fn update_nft_price(token_id: Nat, new_price: u64) -> Result<String, S
         Principal::anonymous(),
         LogAction::PriceUpdate { old_price: (old_price), new_price: (new_price) } 
     ); 
+    check_alerts(&repriced, AlertTrigger::PriceUpdated);
 
 
     Ok(format!(
@@ -192,8 +227,87 @@ pub async fn update_nft_price(token_id: Nat, new_price: u64) -> Result<String, S
     ))
 }
 
-async fn transfer_icp_to_seller(amount: u64, destination: Principal) -> Result<BlockIndex, String> {
-    pub const ICP_TRANSFER_FEE: u64 = 10_000;
+/// Returns NFTs of expired listings to their owners, called from the timer
+pub async fn sweep_expired_listings() {
+    let cutoff = ic_cdk::api::time().saturating_sub(EXPIRY_SWEEP_GRACE_NS);
+    let end = ListingExpiryKey {
+        expires_at: cutoff,
+        token_id: String::new(),
+    };
+    let due: Vec<ListingExpiryKey> = LISTING_EXPIRY.with(|index| {
+        index
+            .borrow()
+            .range(..end)
+            .take(EXPIRY_SWEEP_BATCH_SIZE)
+            .map(|(key, _)| key)
+            .collect()
+    });
+
+    for key in due {
+        // The listing may have been sold, removed or relisted since
+        let nft = match LISTING.with(|nfts| nfts.borrow().get(&key.token_id)) {
+            Some(nft) if nft.expires_at == Some(key.expires_at) => nft,
+            _ => {
+                LISTING_EXPIRY.with(|index| index.borrow_mut().remove(&key));
+                continue;
+            }
+        };
+        if remove_nft_from_listing(nft.token_id.clone()).is_err() {
+            continue;
+        }
+        match transfer_nft_from_canister(nft.owner, nft.token_id.clone()).await {
+            Ok(_) => add_log(
+                ic_cdk::api::time(),
+                nft.token_id,
+                nft.owner,
+                Principal::anonymous(),
+                LogAction::Removed(Some(RemovalReason::Expired)),
+            ),
+            Err(err) => {
+                ic_cdk::println!("Returning expired listing {} failed, will retry: {}", key.token_id, err);
+                restore_listing(nft);
+            }
+        }
+    }
+}
+
+// Puts a listing taken down for a transfer back, e.g. when the transfer failed
+pub fn restore_listing(nft: Nft) {
+    let token_key = nft.token_id.to_string();
+    if let Some(expires_at) = nft.expires_at {
+        LISTING_EXPIRY.with(|index| {
+            index.borrow_mut().insert(
+                ListingExpiryKey {
+                    expires_at,
+                    token_id: token_key.clone(),
+                },
+                (),
+            )
+        });
+    }
+    index_listing(&nft);
+    LISTING.with(|nfts| nfts.borrow_mut().insert(token_key, nft));
+}
+
+pub const ICP_TRANSFER_FEE: u64 = 10_000;
+
+// Transfer fee of a currency's ledger, ICP's is fixed so it isn't looked up
+pub async fn ledger_fee(currency: Currency) -> Result<u64, String> {
+    if currency == Currency::ICP {
+        return Ok(ICP_TRANSFER_FEE);
+    }
+    let (fee,): (Nat,) = ic_cdk::call(currency_ledger(currency), "icrc1_fee", ())
+        .await
+        .map_err(|e| format!("failed to call ledger: {:?}", e))?;
+    u64::try_from(&fee.0).map_err(|_| "ledger fee doesn't fit in u64".to_string())
+}
+
+async fn transfer_to_seller(
+    currency: Currency,
+    fee: u64,
+    amount: u64,
+    destination: Principal,
+) -> Result<BlockIndex, String> {
     let caller = ic_cdk::caller();
 
     let transfer_args = TransferFromArgsIcrc {
@@ -206,14 +320,14 @@ async fn transfer_icp_to_seller(amount: u64, destination: Principal) -> Result<B
             subaccount: None,
         },
         amount: amount.into(),
-        fee: Some(Nat::from(ICP_TRANSFER_FEE)),
+        fee: Some(Nat::from(fee)),
         memo: None,
         created_at_time: None,
         spender_subaccount: None,
     };
 
     ic_cdk::call::<(TransferFromArgsIcrc,), (Result<BlockIndex, TransferFromErrorIcrc>,)>(
-        MAINNET_LEDGER_CANISTER_ID,
+        currency_ledger(currency),
         "icrc2_transfer_from",
         (transfer_args,),
     )
@@ -223,6 +337,49 @@ async fn transfer_icp_to_seller(amount: u64, destination: Principal) -> Result<B
     .map_err(|e: TransferFromErrorIcrc| format!("ledger transfer error {:?}", e))
 }
 
+// Pulls `amount` from the caller into the emporium canister (needs an ICRC-2 approval)
+pub async fn escrow_from_caller(currency: Currency, fee: u64, amount: u64) -> Result<BlockIndex, String> {
+    transfer_to_seller(currency, fee, amount, get_principal(EMPORIUM_CANISTER_ID)).await
+}
+
+pub async fn escrow_icp_from_caller(amount: u64) -> Result<BlockIndex, String> {
+    escrow_from_caller(Currency::ICP, ICP_TRANSFER_FEE, amount).await
+}
+
+// Pays out tokens held by the emporium canister, the ledger fee comes out of the canister balance
+pub async fn transfer_from_canister(
+    currency: Currency,
+    fee: u64,
+    amount: u64,
+    destination: Principal,
+) -> Result<BlockIndex, String> {
+    let transfer_args = TransferArgIcrc {
+        from_subaccount: None,
+        to: AccountIcrc {
+            owner: destination,
+            subaccount: None,
+        },
+        amount: amount.into(),
+        fee: Some(Nat::from(fee)),
+        memo: None,
+        created_at_time: None,
+    };
+
+    ic_cdk::call::<(TransferArgIcrc,), (Result<BlockIndex, TransferErrorIcrc>,)>(
+        currency_ledger(currency),
+        "icrc1_transfer",
+        (transfer_args,),
+    )
+    .await
+    .map_err(|e| format!("failed to call ledger: {:?}", e))?
+    .0
+    .map_err(|e: TransferErrorIcrc| format!("ledger transfer error {:?}", e))
+}
+
+pub async fn transfer_icp_from_canister(amount: u64, destination: Principal) -> Result<BlockIndex, String> {
+    transfer_from_canister(Currency::ICP, ICP_TRANSFER_FEE, amount, destination).await
+}
+
 pub async fn deposit_nft_to_canister(token_id: Nat) -> Result<String, String> {
     let nft_canister: Principal = get_principal(ICRC7_CANISTER_ID);
 
@@ -378,6 +535,56 @@ pub async fn transfer_nft_from_canister(
     }
 }
 
+/// Transfers several NFTs held by emporium's main account in one icrc7_transfer batch.
+///
+/// Err means the call itself failed and nothing moved; otherwise there is one result per token.
+pub async fn transfer_nfts_from_canister(
+    destination: Principal,
+    token_ids: Vec<Nat>,
+) -> Result<Vec<Result<(), String>>, String> {
+    let tokens = token_ids.into_iter().map(|token_id| (token_id, None)).collect();
+    transfer_held_nfts(tokens, Account { owner: destination, subaccount: None }).await
+}
+
+/// Transfers NFTs held by emporium, each from the subaccount it sits in, in one icrc7_transfer batch.
+///
+/// Err means the call itself failed and nothing moved; otherwise there is one result per token.
+pub async fn transfer_held_nfts(
+    tokens: Vec<(Nat, Option<Vec<u8>>)>,
+    to: Account,
+) -> Result<Vec<Result<(), String>>, String> {
+    let nft_canister: Principal = get_principal(ICRC7_CANISTER_ID);
+    let created_at_time = ic_cdk::api::time();
+
+    let transfer_args: Vec<TransferArg> = tokens
+        .iter()
+        .map(|(token_id, from_subaccount)| TransferArg {
+            from_subaccount: from_subaccount.clone(),
+            to: to.clone(),
+            token_id: token_id.clone(),
+            memo: None,
+            created_at_time: Some(created_at_time),
+        })
+        .collect();
+
+    let call_result: CallResult<(Vec<Option<TransferResult>>,)> =
+        ic_cdk::call(nft_canister, "icrc7_transfer", (transfer_args,)).await;
+
+    match call_result {
+        Ok((response,)) => Ok((0..tokens.len())
+            .map(|i| match response.get(i) {
+                Some(Some(TransferResult::Ok(_))) => Ok(()),
+                Some(Some(TransferResult::Err(error))) => Err(format!("Transfer failed: {:?}", error)),
+                _ => Err("Unknown transfer error.".to_string()),
+            })
+            .collect()),
+        Err((code, msg)) => Err(format!(
+            "Canister call failed with code {}: {}.",
+            code as u8, msg
+        )),
+    }
+}
+
 pub fn add_log(time: u64, token_id: Nat, seller: Principal,buyer:Principal, action: LogAction) {
     let log_entry = LogEntry {
         timestamp: time,
@@ -387,7 +594,13 @@ pub fn add_log(time: u64, token_id: Nat, seller: Principal,buyer:Principal, acti
         action,
     };
 
+    // Logs are keyed by time, bump the key so several logs in one call don't overwrite each other
     LOGS.with(|logs| {
-        logs.borrow_mut().insert(time, log_entry);
+        let mut logs = logs.borrow_mut();
+        let mut key = time;
+        while logs.contains_key(&key) {
+            key += 1;
+        }
+        logs.insert(key, log_entry);
     });
 }
@@ -3,11 +3,25 @@ use ic_cdk::api::call::CallResult;
 use ic_cdk::{call, caller};
 use serde::Deserialize;
 
-use crate::LISTING;
+use crate::listing_index::unindex_listing;
+use crate::{Currency, ListingExpiryKey, LISTING, LISTING_EXPIRY};
 pub const ICRC7_CANISTER_ID: &str = "53ewn-qqaaa-aaaap-qkmqq-cai";
 pub const EMPORIUM_CANISTER_ID: &str = "zdcg2-dqaaa-aaaap-qpnha-cai";
 pub const NFT_MANAGER_CANISTER_ID: &str = "5sh5r-gyaaa-aaaap-qkmra-cai";
 pub const LBRY_CANISTER_ID: &str = "y33wz-myaaa-aaaap-qkmna-cai";
+pub const ALEX_REVSHARE_CANISTER_ID: &str = "e454q-riaaa-aaaap-qqcyq-cai";
+pub const ICP_LEDGER_CANISTER_ID: &str = "ryjl3-tyaaa-aaaaa-aaaba-cai";
+pub const ALEX_CANISTER_ID: &str = "ysy5f-2qaaa-aaaap-qkmmq-cai";
+pub const CKUSDC_LEDGER_CANISTER_ID: &str = "xevnm-gaaaa-aaaar-qafnq-cai";
+
+pub fn currency_ledger(currency: Currency) -> Principal {
+    get_principal(match currency {
+        Currency::ICP => ICP_LEDGER_CANISTER_ID,
+        Currency::LBRY => LBRY_CANISTER_ID,
+        Currency::ALEX => ALEX_CANISTER_ID,
+        Currency::CKUSDC => CKUSDC_LEDGER_CANISTER_ID,
+    })
+}
 
 
 #[derive(CandidType, Deserialize, Debug)]
@@ -36,14 +50,50 @@ pub async fn is_owner(principal: Principal, token_id: Nat) -> Result<bool, Strin
         }
     }
 }
+// Current owner of a token, None if it doesn't exist
+pub async fn get_token_owner(token_id: Nat) -> Result<Option<Principal>, String> {
+    let nft_canister = get_principal(ICRC7_CANISTER_ID);
+
+    let call_result: CallResult<(Vec<Option<OwnerInfo>>,)> =
+        ic_cdk::call(nft_canister, "icrc7_owner_of", (vec![token_id],)).await;
+
+    match call_result {
+        Ok((owners,)) => Ok(owners.into_iter().next().flatten().map(|info| info.owner)),
+        Err((code, msg)) => Err(format!("Error {}: {}", code as u8, msg)),
+    }
+}
+// Accounts currently holding several tokens, in the same order
+pub async fn get_token_accounts(token_ids: Vec<Nat>) -> Result<Vec<Option<Account>>, String> {
+    let nft_canister = get_principal(ICRC7_CANISTER_ID);
+
+    let call_result: CallResult<(Vec<Option<OwnerInfo>>,)> =
+        ic_cdk::call(nft_canister, "icrc7_owner_of", (token_ids,)).await;
+
+    match call_result {
+        Ok((owners,)) => Ok(owners
+            .into_iter()
+            .map(|info| info.map(|info| Account { owner: info.owner, subaccount: info.subaccount }))
+            .collect()),
+        Err((code, msg)) => Err(format!("Error {}: {}", code as u8, msg)),
+    }
+}
 pub fn remove_nft_from_listing(token_id: Nat) -> Result<String, String> {
     LISTING.with(|nfts| -> Result<(), String> {
         let mut nft_map = nfts.borrow_mut();
 
         // Check exists
         match nft_map.get(&token_id.to_string()) {
-            Some(_existing_nft_sale) => {
+            Some(existing_nft_sale) => {
                 nft_map.remove(&token_id.to_string());
+                unindex_listing(&existing_nft_sale);
+                if let Some(expires_at) = existing_nft_sale.expires_at {
+                    LISTING_EXPIRY.with(|index| {
+                        index.borrow_mut().remove(&ListingExpiryKey {
+                            expires_at,
+                            token_id: token_id.to_string(),
+                        })
+                    });
+                }
                 ic_cdk::println!("NFT with token_id {} removed from the listing.", token_id);
                 Ok(())
             }
@@ -0,0 +1,319 @@
+use crate::listing_index::{owner_attr, tokens_with_attr};
+use crate::{
+    not_anon, AlertCondition, AlertEvent, AlertRule, AlertTargetKey, AlertTrigger, Nft, WatchKey, WatchTarget,
+    ALERT_INBOX, ALERT_RULES, ALERT_RULES_BY_OWNER, ALERT_RULES_BY_TARGET, LISTING, WATCHLIST,
+};
+use candid::{CandidType, Deserialize, Nat, Principal};
+use ic_cdk::{caller, query, update};
+
+pub const MAX_WATCHLIST_SIZE: usize = 100;
+pub const MAX_ALERT_RULES: usize = 50;
+// Bounds the rules a single list_nft or update_nft_price has to fire
+pub const MAX_RULES_PER_TARGET: usize = 100;
+pub const MAX_INBOX_SIZE: usize = 200;
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct WatchEntry {
+    pub target: WatchTarget,
+    pub added_at: u64,
+    pub listings: Vec<Nft>, // what the token or seller has on sale right now
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct AlertInbox {
+    pub events: Vec<AlertEvent>,
+    pub total_pages: u64,
+    pub current_page: u64,
+    pub page_size: u64,
+}
+
+/**
+ * Follow a token or a seller. get_watchlist shows what each of them currently has on sale.
+ */
+#[update(guard = "not_anon")]
+pub fn watch(target: WatchTarget) -> Result<String, String> {
+    let watcher = caller();
+    if target == WatchTarget::Seller(watcher) {
+        return Err("You can't watch yourself".to_string());
+    }
+    let key = WatchKey { watcher, target };
+    if WATCHLIST.with(|watchlist| watchlist.borrow().contains_key(&key)) {
+        return Err("Already on your watchlist".to_string());
+    }
+    if watched(watcher).len() >= MAX_WATCHLIST_SIZE {
+        return Err(format!("A watchlist holds at most {} entries", MAX_WATCHLIST_SIZE));
+    }
+    WATCHLIST.with(|watchlist| watchlist.borrow_mut().insert(key, ic_cdk::api::time()));
+    Ok("Added to your watchlist".to_string())
+}
+
+#[update(guard = "not_anon")]
+pub fn unwatch(target: WatchTarget) -> Result<String, String> {
+    let key = WatchKey { watcher: caller(), target };
+    WATCHLIST
+        .with(|watchlist| watchlist.borrow_mut().remove(&key))
+        .ok_or("Not on your watchlist")?;
+    Ok("Removed from your watchlist".to_string())
+}
+
+/**
+ * The caller's watchlist, oldest entry first, with the unexpired listings of every token and seller on it.
+ */
+#[query]
+pub fn get_watchlist() -> Vec<WatchEntry> {
+    let now = ic_cdk::api::time();
+    watched(caller())
+        .into_iter()
+        .map(|(target, added_at)| {
+            let keys = match &target {
+                WatchTarget::Token(token_id) => vec![token_id.to_string()],
+                WatchTarget::Seller(seller) => tokens_with_attr(owner_attr(*seller)),
+            };
+            let listings = LISTING.with(|nfts| {
+                let nfts = nfts.borrow();
+                keys.iter()
+                    .filter_map(|key| nfts.get(key))
+                    .filter(|nft| !nft.is_expired(now))
+                    .collect()
+            });
+            WatchEntry { target, added_at, listings }
+        })
+        .collect()
+}
+
+/**
+ * Get an alert in the inbox when a token is listed or repriced below a price, or when a seller lists.
+ * Prices only compare within the currency given in the rule. A token or seller can be watched by
+ * at most 100 rules.
+ * Returns the rule id.
+ */
+#[update(guard = "not_anon")]
+pub fn add_alert_rule(condition: AlertCondition) -> Result<u64, String> {
+    let owner = caller();
+    match &condition {
+        AlertCondition::TokenListedBelow { price, .. } if *price < 1 => {
+            return Err("Price should greater than 1 unit".to_string());
+        }
+        AlertCondition::SellerLists { seller } if *seller == owner => {
+            return Err("You can't set an alert on your own listings".to_string());
+        }
+        _ => {}
+    }
+    if rules_of(owner).len() >= MAX_ALERT_RULES {
+        return Err(format!("You can have at most {} alert rules", MAX_ALERT_RULES));
+    }
+
+    let target = rule_target(&condition);
+    if rules_watching(target.clone()).len() >= MAX_RULES_PER_TARGET {
+        return Err(format!("A token or seller can be watched by at most {} alert rules", MAX_RULES_PER_TARGET));
+    }
+    let rule_id = ALERT_RULES.with(|rules| {
+        let mut rules = rules.borrow_mut();
+        let rule_id = rules.last_key_value().map(|(id, _)| id + 1).unwrap_or(1);
+        rules.insert(
+            rule_id,
+            AlertRule {
+                rule_id,
+                owner,
+                condition,
+                created_at: ic_cdk::api::time(),
+            },
+        );
+        rule_id
+    });
+    ALERT_RULES_BY_OWNER.with(|index| index.borrow_mut().insert((owner, rule_id), ()));
+    ALERT_RULES_BY_TARGET.with(|index| index.borrow_mut().insert(AlertTargetKey { target, rule_id }, ()));
+    Ok(rule_id)
+}
+
+#[update(guard = "not_anon")]
+pub fn remove_alert_rule(rule_id: u64) -> Result<String, String> {
+    let rule = ALERT_RULES
+        .with(|rules| rules.borrow().get(&rule_id))
+        .ok_or("Alert rule doesn't exists")?;
+    if rule.owner != caller() {
+        return Err("Unauthorized !".to_string());
+    }
+    ALERT_RULES.with(|rules| rules.borrow_mut().remove(&rule_id));
+    ALERT_RULES_BY_OWNER.with(|index| index.borrow_mut().remove(&(rule.owner, rule_id)));
+    ALERT_RULES_BY_TARGET.with(|index| {
+        index.borrow_mut().remove(&AlertTargetKey {
+            target: rule_target(&rule.condition),
+            rule_id,
+        })
+    });
+    Ok("Alert rule removed".to_string())
+}
+
+#[query]
+pub fn get_alert_rules() -> Vec<AlertRule> {
+    rules_of(caller())
+}
+
+/**
+ * Alerts fired for the caller, newest first. The inbox keeps the latest 200, older ones are dropped.
+ */
+#[query]
+pub fn get_alert_inbox(page: Option<u64>, page_size: Option<u64>) -> AlertInbox {
+    let page = page.unwrap_or(1).max(1);
+    let page_size = page_size.unwrap_or(10).clamp(1, 100);
+    let owner = caller();
+
+    let mut events: Vec<AlertEvent> = ALERT_INBOX.with(|inbox| {
+        inbox
+            .borrow()
+            .range((owner, 0)..=(owner, u64::MAX))
+            .map(|(_, event)| event)
+            .collect()
+    });
+    events.reverse();
+    let total_pages = (events.len() as u64).div_ceil(page_size);
+    let events = events
+        .into_iter()
+        .skip(((page - 1) * page_size) as usize)
+        .take(page_size as usize)
+        .collect();
+
+    AlertInbox {
+        events,
+        total_pages,
+        current_page: page,
+        page_size,
+    }
+}
+
+/**
+ * Delete the caller's alerts up to and including an event id, once they have been seen.
+ */
+#[update(guard = "not_anon")]
+pub fn clear_alerts(up_to_event_id: u64) -> Result<String, String> {
+    let cleared = remove_events(caller(), up_to_event_id);
+    Ok(format!("Cleared {} alerts", cleared))
+}
+
+/// Fires the alert rules watching a listing, call after a listing is created or repriced.
+///
+/// Seller rules only fire for new listings, a price change isn't news about the seller.
+pub fn check_alerts(nft: &Nft, trigger: AlertTrigger) {
+    let mut rule_ids = rules_watching(token_target(&nft.token_id));
+    if trigger == AlertTrigger::Listed {
+        rule_ids.extend(rules_watching(seller_target(nft.owner)));
+    }
+    let time = ic_cdk::api::time();
+    for rule_id in rule_ids {
+        let rule = match ALERT_RULES.with(|rules| rules.borrow().get(&rule_id)) {
+            Some(rule) => rule,
+            None => continue,
+        };
+        if rule.owner == nft.owner {
+            continue;
+        }
+        let fires = match &rule.condition {
+            AlertCondition::TokenListedBelow { price, currency, .. } => {
+                nft.currency == *currency && nft.price < *price
+            }
+            AlertCondition::SellerLists { .. } => true,
+        };
+        if fires {
+            push_event(
+                rule.owner,
+                AlertEvent {
+                    event_id: 0,
+                    rule_id,
+                    token_id: nft.token_id.clone(),
+                    seller: nft.owner,
+                    price: nft.price,
+                    currency: nft.currency,
+                    trigger,
+                    time,
+                },
+            );
+        }
+    }
+}
+
+fn watched(watcher: Principal) -> Vec<(WatchTarget, u64)> {
+    // Tokens order before sellers, token 0 is the first possible target
+    let start = WatchKey {
+        watcher,
+        target: WatchTarget::Token(Nat::from(0u64)),
+    };
+    WATCHLIST.with(|watchlist| {
+        watchlist
+            .borrow()
+            .range(start..)
+            .take_while(|(key, _)| key.watcher == watcher)
+            .map(|(key, added_at)| (key.target, added_at))
+            .collect()
+    })
+}
+
+fn rules_of(owner: Principal) -> Vec<AlertRule> {
+    ALERT_RULES_BY_OWNER.with(|index| {
+        index
+            .borrow()
+            .range((owner, 0)..=(owner, u64::MAX))
+            .filter_map(|((_, rule_id), _)| ALERT_RULES.with(|rules| rules.borrow().get(&rule_id)))
+            .collect()
+    })
+}
+
+fn rules_watching(target: String) -> Vec<u64> {
+    let start = AlertTargetKey { target: target.clone(), rule_id: 0 };
+    ALERT_RULES_BY_TARGET.with(|index| {
+        index
+            .borrow()
+            .range(start..)
+            .take_while(|(key, _)| key.target == target)
+            .take(MAX_RULES_PER_TARGET)
+            .map(|(key, _)| key.rule_id)
+            .collect()
+    })
+}
+
+fn rule_target(condition: &AlertCondition) -> String {
+    match condition {
+        AlertCondition::TokenListedBelow { token_id, .. } => token_target(token_id),
+        AlertCondition::SellerLists { seller } => seller_target(*seller),
+    }
+}
+
+fn token_target(token_id: &Nat) -> String {
+    format!("token:{}", token_id)
+}
+
+fn seller_target(seller: Principal) -> String {
+    format!("seller:{}", seller)
+}
+
+// Appends to the owner's inbox, dropping the oldest alerts past MAX_INBOX_SIZE
+fn push_event(owner: Principal, mut event: AlertEvent) {
+    ALERT_INBOX.with(|inbox| {
+        let mut inbox = inbox.borrow_mut();
+        let events: Vec<u64> = inbox
+            .range((owner, 0)..=(owner, u64::MAX))
+            .map(|((_, event_id), _)| event_id)
+            .collect();
+        event.event_id = events.last().map(|id| id + 1).unwrap_or(1);
+        inbox.insert((owner, event.event_id), event);
+        if events.len() >= MAX_INBOX_SIZE {
+            for event_id in &events[..=events.len() - MAX_INBOX_SIZE] {
+                inbox.remove(&(owner, *event_id));
+            }
+        }
+    });
+}
+
+fn remove_events(owner: Principal, up_to_event_id: u64) -> usize {
+    ALERT_INBOX.with(|inbox| {
+        let mut inbox = inbox.borrow_mut();
+        let events: Vec<(Principal, u64)> = inbox
+            .range((owner, 0)..=(owner, up_to_event_id))
+            .map(|(key, _)| key)
+            .collect();
+        for key in &events {
+            inbox.remove(key);
+        }
+        events.len()
+    })
+}
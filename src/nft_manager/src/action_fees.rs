@@ -1,7 +1,7 @@
 use candid::{Principal, Nat};
 use ic_cdk::{update, caller};
 use crate::guard::not_anon;
-use crate::{emporium_principal, icp_swap_principal};
+use crate::{emporium_principal, icp_swap_principal, perpetua_principal};
 use crate::coordinate_mint::verify_lbry_payment;
 
 // Make constants public so they can be used in other modules
@@ -52,6 +52,27 @@ pub async fn deduct_shelf_creation_fee(user_principal: Principal) -> Result<Stri
     Ok("Shelf creation fee successfully deducted.".to_string())
 }
 
+// Only Perpetua can call this. Moves a shelf's one-time access fee from the
+// user's topup account to the shelf curator.
+#[update(guard = "not_anon")]
+pub async fn pay_shelf_access_fee(user_principal: Principal, curator: Principal, amount_e8s: u64) -> Result<String, String> {
+    let caller = caller();
+
+    if caller != perpetua_principal() {
+        return Err("Only perpetua can call this function.".to_string());
+    }
+    if amount_e8s == 0 {
+        return Err("Access fee must be greater than zero.".to_string());
+    }
+
+    let payment_result = verify_lbry_payment(user_principal, curator, None, Nat::from(amount_e8s)).await;
+    if let Err(e) = payment_result {
+        return Err(format!("Not enough balance to unlock this shelf: {}", e));
+    }
+
+    Ok("Shelf access fee successfully paid.".to_string())
+}
+
 #[update(guard = "not_anon")]
 pub async fn deduct_asset_canister_creation_fee(user_principal: Principal) -> Result<String, String> {
     let burn_result = burn_lbry(user_principal, Nat::from(LBRY_ASSET_CANISTER_CREATION_COST_E8S)).await;
@@ -14,6 +14,7 @@ pub const FRONTEND_CANISTER_ID: &str = "yj5ba-aiaaa-aaaap-qkmoa-cai";
 pub const ICP_SWAP: &str = "54fqz-5iaaa-aaaap-qkmqa-cai";
 pub const NFT_MANAGER: &str = "5sh5r-gyaaa-aaaap-qkmra-cai";
 pub const EMPORIUM: &str = "zdcg2-dqaaa-aaaap-qpnha-cai";
+pub const PERPETUA: &str = "ya6k4-waaaa-aaaap-qkmpq-cai";
 
 pub fn get_principal(id: &str) -> Principal {
     Principal::from_text(id).expect(&format!("Invalid principal: {}", id))
@@ -52,6 +53,10 @@ pub fn emporium_principal() -> Principal {
     get_principal(EMPORIUM)
 }
 
+pub fn perpetua_principal() -> Principal {
+    get_principal(PERPETUA)
+}
+
 mod init;
 pub use init::*;
 
@@ -29,11 +29,11 @@ pub async fn mint_nft(minting_number: Nat, description: Option<String>) -> Resul
             value: CandyShared::Text(description.unwrap_or_default()),
             immutable: true,
         },
-        // PropertyShared {
-        //     name: "alexandria:minter".to_string(),
-        //     value: CandyShared::Text(owner.to_string()),
-        //     immutable: true,
-        // },
+        PropertyShared {
+            name: "alexandria:minter".to_string(),
+            value: CandyShared::Text(owner.to_string()),
+            immutable: true,
+        },
     ];
 
     let nft_request = SetNFTItemRequest {
@@ -82,6 +82,11 @@ pub async fn mint_scion_nft(minting_number: Nat, description: Option<String>) ->
             value: CandyShared::Text(description.unwrap_or_default()),
             immutable: true,
         },
+        PropertyShared {
+            name: "alexandria:minter".to_string(),
+            value: CandyShared::Text(owner.to_string()),
+            immutable: true,
+        },
     ];
 
     let nft_request = SetNFTItemRequest {
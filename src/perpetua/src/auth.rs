@@ -1,6 +1,6 @@
 use candid::Principal;
 // Import New Types
-use crate::storage::{ShelfData, ShelfMetadata, ShelfContent, SHELF_DATA};
+use crate::storage::{ShelfData, ShelfMetadata, ShelfContent, SHELF_DATA, push_notification, NotificationKind};
 use ic_stable_structures::{memory_manager::VirtualMemory};
 use ic_stable_structures::DefaultMemoryImpl;
 
@@ -144,11 +144,15 @@ where
 
     // Update the timestamp on metadata
     shelf_data.metadata.updated_at = ic_cdk::api::time();
+    let shelf_owner = shelf_data.metadata.owner;
 
     // Save the updated ShelfData
     SHELF_DATA.with(|map_ref| {
         map_ref.borrow_mut().insert(shelf_id.to_string(), shelf_data);
     });
 
+    // Let the owner know when a collaborator edited their public shelf
+    push_notification(shelf_owner, NotificationKind::ShelfEdited, *principal, Some(shelf_id.to_string()), None);
+
     Ok(result)
 } 
\ No newline at end of file
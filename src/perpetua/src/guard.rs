@@ -1,4 +1,13 @@
 use candid::Principal;
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+
+use crate::storage::is_restore_in_progress;
+
+thread_local! {
+    // Callers with an update in flight across an inter-canister call (heap only, cleared on upgrade)
+    static PENDING_REQUESTS: RefCell<BTreeSet<Principal>> = const { RefCell::new(BTreeSet::new()) };
+}
 
 pub fn not_anon() -> Result<(), String> {
     let caller = ic_cdk::api::caller();
@@ -8,3 +17,49 @@ pub fn not_anon() -> Result<(), String> {
         Err("Anonymous principal not allowed to make calls.".to_string())
     }
 }
+
+pub fn is_controller() -> Result<(), String> {
+    let caller = ic_cdk::api::caller();
+    if ic_cdk::api::is_controller(&caller) {
+        Ok(())
+    } else {
+        Err("Only canister controllers can call this method.".to_string())
+    }
+}
+
+/// `not_anon` for endpoints that write user data; rejected while a backup restore is in progress.
+pub fn not_anon_writable() -> Result<(), String> {
+    not_anon()?;
+    if is_restore_in_progress() {
+        return Err("A backup restore is in progress; writes are temporarily disabled.".to_string());
+    }
+    Ok(())
+}
+
+/// Rejects a second request from the same caller while the first is awaiting another canister.
+/// The caller is released when the guard is dropped.
+pub struct CallerGuard {
+    principal: Principal,
+}
+
+impl CallerGuard {
+    pub fn new(principal: Principal) -> Result<Self, String> {
+        PENDING_REQUESTS.with(|pending| {
+            if !pending.borrow_mut().insert(principal) {
+                return Err(format!(
+                    "Already processing a request for principal {:?}",
+                    principal.to_string()
+                ));
+            }
+            Ok(Self { principal })
+        })
+    }
+}
+
+impl Drop for CallerGuard {
+    fn drop(&mut self) {
+        PENDING_REQUESTS.with(|pending| {
+            pending.borrow_mut().remove(&self.principal);
+        })
+    }
+}
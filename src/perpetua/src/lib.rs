@@ -13,31 +13,51 @@ pub mod ordering;
 pub mod update {
     pub mod shelf;
     pub mod item;
+    pub mod batch;
     pub mod access;
     pub mod utils;
     pub mod profile;
     pub mod tags;
+    pub mod tag_moderation;
     pub mod follow;
     pub mod debug;
+    pub mod search;
+    pub mod trending;
+    pub mod notifications;
+    pub mod backup;
+    pub mod audit;
+    pub mod stats;
 }
 pub mod query {
     pub mod shelves;
     pub mod follows;
+    pub mod search;
+    pub mod trending;
+    pub mod recommendations;
+    pub mod notifications;
+    pub mod backup;
+    pub mod audit;
+    pub mod http;
+    pub mod stats;
 }
 pub mod utils;
 pub mod types;
 
-pub use storage::{Item, Shelf, ShelfId, NormalizedTag, ItemId, ShelfPublic, ShelfBackupData, TagShelfCreationTimelineKey};
-pub use types::{TagPopularityKey, /* TagShelfAssociationKey, */ GlobalTimelineBackupChunk, ShelvesEssentialBackupChunk, BackupPaginationInput};
-pub use update::shelf::{store_shelf, update_shelf_metadata};
+pub use storage::{Item, ItemContent, Shelf, ShelfId, NormalizedTag, ItemId, ShelfPublic, ShelfBackupData, TagShelfCreationTimelineKey};
+pub use types::{TagPopularityKey, /* TagShelfAssociationKey, */ GlobalTimelineBackupChunk, ShelvesEssentialBackupChunk, BackupPaginationInput, BackupMap, BackupData, BackupChunk, RestoreReport, RankMigrationCursor, RankMigrationProgress, TagMergeCursor, TagMergeProgress};
+pub use update::shelf::{store_shelf, fork_shelf, update_shelf_metadata};
 pub use update::item::{
     AddItemInput, add_item_to_shelf, remove_item_from_shelf, 
 };
-pub use update::profile::{reorder_profile_shelf, reset_profile_order};
+pub use update::batch::{ShelfOp, apply_shelf_operations};
+pub use update::profile::{reorder_profile_shelf, reset_profile_order, migrate_position_ranks};
 pub use update::tags::{TagOperationInput, add_tag_to_shelf, remove_tag_from_shelf};
+pub use update::tag_moderation::{set_tag_alias, remove_tag_alias, merge_tags, block_tag, unblock_tag, rebuild_tag_cooccurrence};
 pub use query::follows::{
     get_tag_shelf_count, get_popular_tags, get_tags_with_prefix,
+    get_tag_aliases, get_blocked_tags,
     get_my_followed_tags, get_my_followed_users,
+    get_followers, get_tag_followers, get_follower_count, get_tag_follower_count, get_follow_limits,
     QueryResult, QueryError,
     OffsetPaginationInput,
     CursorPaginationInput,
@@ -45,14 +65,49 @@ pub use query::follows::{
     CursorPaginatedResult,
 };
 pub use query::shelves::{
-    get_shelf, get_shelf_items, get_shelf_position_metrics, get_shelves_by_tag,
-    get_nft_shelf_appearances, NFTAppearancesResult,
+    get_shelf, get_shelf_items, get_shelf_forks, get_shelf_position_metrics, get_shelves_by_tag,
+    get_nft_shelf_appearances, get_item_shelf_appearances, NFTAppearancesResult,
     get_user_shelves, get_recent_shelves, get_shuffled_by_hour_feed,
     get_followed_users_feed, get_followed_tags_feed,
     ShelfPositionMetrics
 };
+pub use query::search::search_shelves;
+pub use update::search::rebuild_search_index;
+pub use query::trending::{get_trending_tags, get_trending_shelves};
+pub use update::trending::refresh_trending;
+pub use query::recommendations::{get_recommended_shelves, RecommendationCursor};
+pub use query::notifications::{get_notifications, get_unread_notification_count, get_muted_notification_kinds};
+pub use update::notifications::{mark_notifications_read, set_notification_muted};
+pub use storage::{Notification, NotificationKind};
+pub use storage::{TrendingWindow, TrendingScoreKey};
+pub use storage::FollowLimits;
+pub use query::backup::export_backup_chunk;
+pub use update::backup::{begin_restore, import_backup_chunk, rebuild_restored_indexes, finish_restore};
+pub use query::audit::{get_audit_status, get_audit_findings};
+pub use update::audit::{start_consistency_audit, set_audit_repair_mode, run_consistency_audit_step};
+pub use storage::{AuditState, AuditPhase, AuditFinding, AuditIssueKind};
+pub use query::http::{http_request, HttpRequest, HttpResponse};
+pub use query::stats::{get_shelf_stats, get_most_viewed_shelves, StatsRange, ShelfStats, ItemOpenCount};
+pub use update::stats::{record_shelf_view, record_item_open};
+pub use storage::{StatsGranularity, ShelfStatsBucket};
+pub use update::access::{set_shelf_access_policy, unlock_shelf, get_my_shelf_access};
+pub use storage::{ShelfAccessPolicy, TokenGate, AccessGrant, AccessGrantKind, ShelfAccessKey};
 pub use update::follow::*;
 
+#[ic_cdk::init]
+fn init() {
+    query::http::init_http_certification();
+    update::trending::setup_trending_timer();
+    update::audit::setup_audit_timer();
+}
+
+#[ic_cdk::post_upgrade]
+fn post_upgrade() {
+    query::http::init_http_certification();
+    update::trending::setup_trending_timer();
+    update::audit::setup_audit_timer();
+}
+
 pub fn get_principal(id: &str) -> Principal {
     Principal::from_text(id).expect(&format!("Invalid principal: {}", id))
 }
@@ -1,49 +1,124 @@
 use std::collections::BTreeMap;
-use std::cmp::Ordering;
 use std::borrow::Borrow; // Needed for BTreeMap key flexibility
 
-/// The minimum floating-point gap allowed between adjacent positions before triggering a rebalance.
-/// Set significantly larger than f64 epsilon (~2.2e-16) to prevent clustering.
-const REBALANCE_MIN_GAP_THRESHOLD: f64 = 1e-9;
-
-// --- OrderedFloat Wrapper ---
-// Wrapper to allow f64 to be used as a BTreeMap key.
-// NOTE: Assumes NaNs will not be inserted. Behavior with NaN is undefined by Ord.
-#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
-pub struct OrderedFloat(pub f64);
-
-impl Eq for OrderedFloat {}
-
-impl Ord for OrderedFloat {
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.0.partial_cmp(&other.0).unwrap_or_else(|| {
-            // Handle NaN comparison if it ever occurs, though it shouldn't with our logic.
-            // Decide on a consistent ordering for NaNs, e.g., treat them as less than everything.
-             if self.0.is_nan() && other.0.is_nan() {
-                 Ordering::Equal
-             } else if self.0.is_nan() {
-                 Ordering::Less // Or Greater, be consistent
-             } else if other.0.is_nan() {
-                 Ordering::Greater // Or Less, be consistent
-             } else {
-                 // Should not happen if partial_cmp only fails for NaN
-                 Ordering::Equal 
-             }
-         })
+// --- Rank keys (fractional indexing) ---
+// Positions are strings over RANK_DIGITS compared lexicographically. A key never ends in the
+// smallest digit, so a new key strictly between any two existing keys can always be generated
+// (at worst by making it one digit longer). No global rebalance is ever needed.
+
+/// Rank digits in ascending ASCII order, so byte order equals rank order.
+const RANK_DIGITS: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+const RANK_BASE: usize = 62;
+
+fn digit_value(byte: u8) -> usize {
+    RANK_DIGITS.iter().position(|&d| d == byte).unwrap_or(0)
+}
+
+fn digit_char(value: usize) -> char {
+    RANK_DIGITS[value] as char
+}
+
+/// Returns whether `rank` is a valid key: non-empty, only rank digits, not ending in '0'.
+pub fn is_valid_rank(rank: &str) -> bool {
+    !rank.is_empty()
+        && rank.bytes().all(|b| RANK_DIGITS.contains(&b))
+        && !rank.ends_with(RANK_DIGITS[0] as char)
+}
+
+/// Generates a key strictly between `lower` and `upper` (`""` / `None` mean unbounded).
+/// Both bounds must be valid keys with `lower < upper`.
+fn midpoint_rank(lower: &str, upper: Option<&str>) -> String {
+    let lower_bytes = lower.as_bytes();
+
+    // Shared prefix: keep it and recurse on the remainder.
+    if let Some(upper) = upper {
+        let upper_bytes = upper.as_bytes();
+        let mut n = 0;
+        while n < upper_bytes.len() && lower_bytes.get(n).copied().unwrap_or(RANK_DIGITS[0]) == upper_bytes[n] {
+            n += 1;
+        }
+        if n > 0 {
+            let lower_rest = if n < lower.len() { &lower[n..] } else { "" };
+            return format!("{}{}", &upper[..n], midpoint_rank(lower_rest, Some(&upper[n..])));
+        }
+    }
+
+    let digit_lower = lower_bytes.first().map(|&b| digit_value(b)).unwrap_or(0);
+    let digit_upper = upper.and_then(|u| u.as_bytes().first().map(|&b| digit_value(b))).unwrap_or(RANK_BASE);
+
+    if digit_upper - digit_lower > 1 {
+        // Room for a single digit in between.
+        digit_char((digit_lower + digit_upper).div_ceil(2)).to_string()
+    } else if let Some(upper) = upper.filter(|u| u.len() > 1) {
+        // upper's first digit alone already sorts between lower and upper.
+        upper[..1].to_string()
+    } else {
+        let lower_rest = if lower.len() > 1 { &lower[1..] } else { "" };
+        format!("{}{}", digit_char(digit_lower), midpoint_rank(lower_rest, None))
+    }
+}
+
+/// Generates a key after `last`, bumping the first digit that can grow so appends stay short.
+fn rank_after(last: &str) -> String {
+    let bytes = last.as_bytes();
+    for (i, &b) in bytes.iter().enumerate() {
+        let value = digit_value(b);
+        if value + 1 < RANK_BASE {
+            return format!("{}{}", &last[..i], digit_char(value + 1));
+        }
+    }
+    midpoint_rank(last, None)
+}
+
+/// Generates a key before `first`, lowering the first digit that can shrink so prepends stay short.
+fn rank_before(first: &str) -> String {
+    let bytes = first.as_bytes();
+    for (i, &b) in bytes.iter().enumerate() {
+        let value = digit_value(b);
+        if value > 1 {
+            return format!("{}{}", &first[..i], digit_char(value - 1));
+        }
+    }
+    midpoint_rank("", Some(first))
+}
+
+/// Generates `count` evenly spaced keys in ascending order, each as short as the count allows.
+pub fn evenly_spaced_ranks(count: usize) -> Vec<String> {
+    if count == 0 {
+        return Vec::new();
     }
+    // Smallest width leaving at least one free digit's worth of room between neighbours.
+    let slots = (count as u128 + 1) * RANK_BASE as u128;
+    let mut width: u32 = 1;
+    while (RANK_BASE as u128).pow(width) < slots {
+        width += 1;
+    }
+    let space = (RANK_BASE as u128).pow(width);
+    let step = space / (count as u128 + 1);
+
+    (1..=count as u128)
+        .map(|i| {
+            let mut value = i * step;
+            let mut digits = vec![b'0'; width as usize];
+            for d in digits.iter_mut().rev() {
+                *d = RANK_DIGITS[(value % RANK_BASE as u128) as usize];
+                value /= RANK_BASE as u128;
+            }
+            let rank = String::from_utf8(digits).expect("rank digits are ASCII");
+            rank.trim_end_matches(RANK_DIGITS[0] as char).to_string()
+        })
+        .collect()
 }
 
 
 // --- PositionTracker ---
-// Manages positions using two maps for efficient key and position lookups.
+// Manages rank keys using two maps for efficient key and position lookups.
 #[derive(Debug, Clone)]
 pub struct PositionTracker<K: Ord + Clone> {
-    positions_by_key: BTreeMap<K, f64>,
-    keys_by_position: BTreeMap<OrderedFloat, Vec<K>>,
-    // Note: Using BTreeMap<OrderedFloat, K> assumes positions are unique. 
-    // If exact position collisions are possible and need to be handled (e.g., multiple items at 0.0), 
-    // value should be BTreeSet<K>. For our midpoint logic, collisions should be extremely rare except transiently during rebalance.
-    // Let's stick with K for now and refine if needed.
+    positions_by_key: BTreeMap<K, String>,
+    keys_by_position: BTreeMap<String, Vec<K>>,
+    // Note: calculate_position always returns a rank not held by any other key, so each Vec
+    // normally holds a single key. It stays a Vec so imported data with duplicate ranks keeps every key.
 }
 
 // Default implementation needed for Candid derivation later if used directly in structs
@@ -62,6 +137,43 @@ impl<K: Ord + Clone + std::fmt::Debug> PositionTracker<K> {
         }
     }
 
+    /// Builds a tracker holding `keys` in the given order with evenly spaced ranks.
+    pub fn from_ordered_keys(keys: impl IntoIterator<Item = K>) -> Self {
+        let keys: Vec<K> = keys.into_iter().collect();
+        let ranks = evenly_spaced_ranks(keys.len());
+        let mut tracker = Self::new();
+        for (key, rank) in keys.into_iter().zip(ranks) {
+            tracker.insert(key, rank);
+        }
+        tracker
+    }
+
+    /// Builds a tracker from stored (key, rank) entries. Invalid ranks are re-ranked at the end.
+    pub fn from_ranked_entries(entries: Vec<(K, String)>) -> Self {
+        let mut tracker = Self::new();
+        let mut unranked: Vec<K> = Vec::new();
+        for (key, rank) in entries {
+            if is_valid_rank(&rank) {
+                tracker.insert(key, rank);
+            } else {
+                unranked.push(key);
+            }
+        }
+        for key in unranked {
+            let rank = tracker.rank_at_end();
+            tracker.insert(key, rank);
+        }
+        tracker
+    }
+
+    /// Migrates legacy `f64` positions: keeps their order (ties broken by key) and assigns fresh ranks.
+    pub fn from_legacy_positions(mut entries: Vec<(K, f64)>) -> Self {
+        entries.sort_by(|(key_a, pos_a), (key_b, pos_b)| {
+            pos_a.total_cmp(pos_b).then_with(|| key_a.cmp(key_b))
+        });
+        Self::from_ordered_keys(entries.into_iter().map(|(key, _)| key))
+    }
+
     pub fn len(&self) -> usize {
         self.positions_by_key.len()
     }
@@ -70,10 +182,10 @@ impl<K: Ord + Clone + std::fmt::Debug> PositionTracker<K> {
         self.positions_by_key.is_empty()
     }
 
-    pub fn get_position<Q: ?Sized>(&self, key: &Q) -> Option<f64>
+    pub fn get_position<Q>(&self, key: &Q) -> Option<String>
     where
         K: Borrow<Q>,
-        Q: Ord + Eq,
+        Q: Ord + Eq + ?Sized,
     {
         self.positions_by_key.get(key).cloned()
     }
@@ -90,59 +202,50 @@ impl<K: Ord + Clone + std::fmt::Debug> PositionTracker<K> {
         self.keys_by_position.values().flat_map(|keys_vec| keys_vec.iter().cloned()).collect()
     }
 
-    /// Returns the keys and their positions, ordered by position.
-    pub fn get_ordered_entries(&self) -> Vec<(K, f64)> {
+    /// Returns the keys and their ranks, ordered by rank.
+    pub fn get_ordered_entries(&self) -> Vec<(K, String)> {
         self.keys_by_position.iter()
-            .flat_map(|(pos_ord, keys_vec)| {
-                keys_vec.iter().map(move |key| (key.clone(), pos_ord.0))
+            .flat_map(|(rank, keys_vec)| {
+                keys_vec.iter().map(move |key| (key.clone(), rank.clone()))
             })
             .collect()
     }
 
     // --- Core Mutation Methods ---
 
-    /// Inserts or updates the position for a key, maintaining both maps.
-    pub fn insert(&mut self, key_to_insert: K, new_position: f64) {
-        // Remove key_to_insert from its old position in keys_by_position if its position changes.
-        if let Some(old_position_float) = self.positions_by_key.insert(key_to_insert.clone(), new_position) {
-            if old_position_float != new_position { // Position actually changed.
-                let old_ord_float = OrderedFloat(old_position_float);
-                if let Some(keys_at_old_pos) = self.keys_by_position.get_mut(&old_ord_float) {
-                    keys_at_old_pos.retain(|k| k != &key_to_insert); // K needs PartialEq, which Ord implies.
-                    if keys_at_old_pos.is_empty() {
-                        self.keys_by_position.remove(&old_ord_float);
-                    }
+    /// Inserts or updates the rank for a key, maintaining both maps.
+    pub fn insert(&mut self, key_to_insert: K, new_position: String) {
+        // Remove key_to_insert from its old rank in keys_by_position if its rank changes.
+        if let Some(old_position) = self.positions_by_key.insert(key_to_insert.clone(), new_position.clone()) {
+            if let Some(keys_at_old_pos) = self.keys_by_position.get_mut(&old_position) {
+                keys_at_old_pos.retain(|k| k != &key_to_insert);
+                if keys_at_old_pos.is_empty() {
+                    self.keys_by_position.remove(&old_position);
                 }
             }
         }
-        
-        // Add key_to_insert to the Vec at new_position in keys_by_position.
+
         self.keys_by_position
-            .entry(OrderedFloat(new_position))
+            .entry(new_position)
             .or_insert_with(Vec::new)
             .push(key_to_insert);
     }
 
-    /// Removes a key and its position from both maps. Returns the position if the key existed.
-    pub fn remove<Q: ?Sized>(&mut self, key_to_remove: &Q) -> Option<f64>
+    /// Removes a key and its rank from both maps. Returns the rank if the key existed.
+    pub fn remove<Q>(&mut self, key_to_remove: &Q) -> Option<String>
     where
-        K: Borrow<Q> + Clone, 
-        Q: Ord + Eq, // Removed Debug constraint as it's not used in this function body for Q
+        K: Borrow<Q> + Clone,
+        Q: Ord + Eq + ?Sized,
     {
-        // Remove from positions_by_key first to get the position
-        if let Some(removed_position_float) = self.positions_by_key.remove(key_to_remove) {
-            let ord_float = OrderedFloat(removed_position_float);
-            if let Some(keys_at_pos) = self.keys_by_position.get_mut(&ord_float) {
-                // K: Borrow<Q> allows us to compare &K with &Q
-                keys_at_pos.retain(|k_in_vec| k_in_vec.borrow() != key_to_remove);
-                if keys_at_pos.is_empty() {
-                    self.keys_by_position.remove(&ord_float);
-                }
+        let removed_position = self.positions_by_key.remove(key_to_remove)?;
+        if let Some(keys_at_pos) = self.keys_by_position.get_mut(&removed_position) {
+            // K: Borrow<Q> allows us to compare &K with &Q
+            keys_at_pos.retain(|k_in_vec| k_in_vec.borrow() != key_to_remove);
+            if keys_at_pos.is_empty() {
+                self.keys_by_position.remove(&removed_position);
             }
-            Some(removed_position_float)
-        } else {
-            None
         }
+        Some(removed_position)
     }
 
     /// Clears all positions.
@@ -151,409 +254,255 @@ impl<K: Ord + Clone + std::fmt::Debug> PositionTracker<K> {
         self.keys_by_position.clear();
     }
 
-    /// Finds the positions immediately before and after the target position. O(log N).
-    fn find_neighbors(&self, target_pos: f64) -> (Option<f64>, Option<f64>) {
-        let target_ord = OrderedFloat(target_pos);
-
-        // Find the largest position strictly less than target_pos
+    /// Finds the ranks immediately before and after `target`. O(log N).
+    fn find_neighbors(&self, target: &str) -> (Option<String>, Option<String>) {
         let prev = self.keys_by_position
-            .range(..target_ord) // Range of positions less than target
-            .next_back()          // Get the last (largest) one in that range
-            .map(|(pos, _key)| pos.0); // Extract the f64 position
+            .range::<str, _>((std::ops::Bound::Unbounded, std::ops::Bound::Excluded(target)))
+            .next_back()
+            .map(|(rank, _)| rank.clone());
 
-        // Find the smallest position strictly greater than target_pos
-        // Use range with Excluded bound for correctness if target_pos itself exists in the map
         let next = self.keys_by_position
-            .range((std::ops::Bound::Excluded(target_ord), std::ops::Bound::Unbounded))
-            .next() // Get the first item in the range > target_pos
-            .map(|(pos, _key)| pos.0); // Extract the f64 position
+            .range::<str, _>((std::ops::Bound::Excluded(target), std::ops::Bound::Unbounded))
+            .next()
+            .map(|(rank, _)| rank.clone());
 
         (prev, next)
     }
 
-    /// Calculates a position based on reference item and placement preference. O(log N).
-    /// Takes &mut self because it might trigger an internal rebalance.
-    pub fn calculate_position(
-        &mut self, 
-        reference_key: Option<&K>, 
-        before: bool, 
-        step_size: f64
-    ) -> Result<f64, String> {
-        // Loop allows for one retry attempt after a rebalance.
-        for attempt in 0..2 {
-            // --- Determine Neighbors of the Target Insertion Spot ---
-            // These are the positions immediately bounding where the new item *should* go.
-            let (neighbor_before_spot, neighbor_after_spot) = match reference_key {
-                 Some(ref_key) => {
-                     // --- Get current reference position --- O(log N)
-                     let reference_pos = self.get_position(ref_key)
-                         .ok_or_else(|| {
-                             if attempt > 0 {
-                                 format!("Reference item (key: {:?}) lost after rebalance", ref_key)
-                             } else {
-                                 "Reference item not found".to_string()
-                             }
-                         })?;
-
-                     // Find neighbors relative to the reference position itself. O(log N)
-                     let (prev_neighbor, next_neighbor) = self.find_neighbors(reference_pos);
-
-                     if before {
-                         // Target spot is just *before* reference_pos.
-                         // Neighbor before target spot: prev_neighbor (largest pos < reference_pos)
-                         // Neighbor after target spot: reference_pos itself.
-                         (prev_neighbor, Some(reference_pos))
-                     } else {
-                         // Target spot is just *after* reference_pos.
-                         // Neighbor before target spot: reference_pos itself.
-                         // Neighbor after target spot: next_neighbor (smallest pos > reference_pos)
-                         (Some(reference_pos), next_neighbor)
-                     }
-                 }
-                 None => { // No reference key: Place at the absolute start or end.
-                     if self.is_empty() {
-                         return Ok(0.0); // First item ever.
-                     }
-                     // Find absolute min/max using the ordered map - O(log N)
-                     let min_pos_opt = self.keys_by_position.first_key_value().map(|(p, _)| p.0);
-                     let max_pos_opt = self.keys_by_position.last_key_value().map(|(p, _)| p.0);
-
-                     if before {
-                         // Target spot is before the first item.
-                         // Neighbor before: None
-                         // Neighbor after: Minimum existing position
-                         (None, min_pos_opt) 
-                     } else {
-                          // Target spot is after the last item.
-                         // Neighbor before: Maximum existing position
-                         // Neighbor after: None
-                         (max_pos_opt, None) 
-                     }
-                 }
-            };
-            
-            // --- Calculate potential new position ---
-            let new_pos = match (neighbor_before_spot, neighbor_after_spot) {
-                (Some(prev), Some(next)) => (prev + next) / 2.0, // Between two items
-                (None, Some(next)) => next - step_size,         // Before the first item
-                (Some(prev), None) => prev + step_size,         // After the last item
-                (None, None) => {
-                     // This case should only happen if the map was empty and we are adding the first item without a reference key.
-                     // It's handled by the early return `if self.is_empty()`.
-                     return Err("Internal error: Unreachable state in position calculation (None, None)".to_string());
-                }
-            };
-
-            // --- Check if rebalance is needed ---
-            let mut needs_rebalance = false;
-            // Check only needed when inserting BETWEEN two existing positions.
-            if let (Some(prev), Some(next)) = (neighbor_before_spot, neighbor_after_spot) {
-                 let gap_before = new_pos - prev;
-                 let gap_after = next - new_pos;
-                // Trigger rebalance if EITHER gap is too small OR if precision loss occurred.
-                if gap_before < REBALANCE_MIN_GAP_THRESHOLD 
-                   || gap_after < REBALANCE_MIN_GAP_THRESHOLD 
-                   || new_pos <= prev // Precision loss check (use <= just in case)
-                   || new_pos >= next // Precision loss check (use >= just in case)
-                {
-                    needs_rebalance = true;
-                }
-            } 
-            // No gap check needed when adding at the absolute start/end,
-            // but we still need the precision loss check against the single neighbor.
-            else if let (None, Some(next)) = (neighbor_before_spot, neighbor_after_spot) { // Before first
-                if new_pos >= next { needs_rebalance = true; }
-            } else if let (Some(prev), None) = (neighbor_before_spot, neighbor_after_spot) { // After last
-                if new_pos <= prev { needs_rebalance = true; }
-            }
-             // Case (None, None) is unreachable.
+    fn rank_at_end(&self) -> String {
+        match self.keys_by_position.last_key_value() {
+            Some((last, _)) => rank_after(last),
+            None => midpoint_rank("", None),
+        }
+    }
 
-            // --- Perform rebalance or return position ---
-            if needs_rebalance {
-                if attempt == 0 {
-                    self.rebalance_positions(step_size);
-                    continue; // Go to the next iteration to recalculate with new positions
+    /// Calculates a rank based on reference item and placement preference. O(log N).
+    ///
+    /// The result sorts strictly between the neighbours of the target spot, however many
+    /// times the same spot has been used before.
+    pub fn calculate_position(
+        &self,
+        reference_key: Option<&K>,
+        before: bool,
+    ) -> Result<String, String> {
+        let (neighbor_before_spot, neighbor_after_spot) = match reference_key {
+            Some(ref_key) => {
+                let reference_pos = self.get_position(ref_key)
+                    .ok_or_else(|| "Reference item not found".to_string())?;
+                let (prev_neighbor, next_neighbor) = self.find_neighbors(&reference_pos);
+
+                if before {
+                    // Target spot is between the previous neighbour and the reference.
+                    (prev_neighbor, Some(reference_pos))
                 } else {
-                    // Rebalance needed even on the second attempt. Log and return potentially suboptimal position.
-                    ic_cdk::println!("WARN: Rebalance triggered twice for reference_key: {:?}, before: {}. Proceeding with potentially suboptimal position.", reference_key, before);
-                    // Return the position calculated *after* the first rebalance (which is `new_pos` from this iteration)
-                    return Ok(new_pos); 
-                    // Alternative: return Err("Failed to find suitable position even after rebalancing.".to_string());
+                    // Target spot is between the reference and the next neighbour.
+                    (Some(reference_pos), next_neighbor)
                 }
-            } else {
-                // No rebalance needed, return the calculated position
-                return Ok(new_pos);
             }
-        } // End of loop
+            None => {
+                let min_pos_opt = self.keys_by_position.first_key_value().map(|(p, _)| p.clone());
+                let max_pos_opt = self.keys_by_position.last_key_value().map(|(p, _)| p.clone());
+                if before { (None, min_pos_opt) } else { (max_pos_opt, None) }
+            }
+        };
+
+        let new_pos = match (neighbor_before_spot, neighbor_after_spot) {
+            (Some(prev), Some(next)) => midpoint_rank(&prev, Some(&next)), // Between two items
+            (None, Some(next)) => rank_before(&next),                      // Before the first item
+            (Some(prev), None) => rank_after(&prev),                       // After the last item
+            (None, None) => midpoint_rank("", None),                       // First item
+        };
+        Ok(new_pos)
+    }
+
+    /// Reassigns short, evenly spaced ranks keeping the current order. O(N log N).
+    ///
+    /// Never needed for correctness; it only shortens keys that grew from repeated
+    /// inserts at the same spot.
+    pub fn rebalance_positions(&mut self) {
+        let ordered_keys = self.get_ordered_keys();
+        *self = Self::from_ordered_keys(ordered_keys);
+    }
 
-         // Should be unreachable if logic is correct.
-         Err("Internal error: Failed to calculate position after rebalance logic.".to_string())
+    /// Returns an iterator over keys ordered by their rank.
+    pub fn iter_keys_ordered(&self) -> impl Iterator<Item = &K> {
+        self.keys_by_position.values().flat_map(|keys_vec| keys_vec.iter())
     }
 
-    /// Rebalances all positions to be evenly spaced using the provided step_size. O(N log N).
-    pub fn rebalance_positions(&mut self, step_size: f64) {
-        if self.is_empty() {
-            return;
+    /// Returns an iterator over (key, rank) ordered by rank.
+    pub fn iter_ordered(&self) -> impl Iterator<Item = (&K, &str)> {
+        self.keys_by_position.iter().flat_map(|(rank, keys_vec)| {
+            keys_vec.iter().map(move |key| (key, rank.as_str()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_between(rank: &str, lower: &str, upper: Option<&str>) {
+        assert!(is_valid_rank(rank), "{rank:?} is not a valid rank");
+        assert!(rank > lower, "{rank:?} should sort after {lower:?}");
+        if let Some(upper) = upper {
+            assert!(rank < upper, "{rank:?} should sort before {upper:?}");
         }
+    }
 
-        // Get currently ordered keys (ordered by position)
-        let ordered_keys: Vec<K> = self.keys_by_position
-            .values()
-            .flat_map(|vec_k| vec_k.iter().cloned())
-            .collect();
+    #[test]
+    fn midpoint_sorts_strictly_between_bounds() {
+        let cases: &[(&str, Option<&str>)] = &[
+            ("", None),
+            ("", Some("1")),
+            ("1", Some("2")),
+            ("1", Some("3")),
+            ("y", Some("z")),
+            ("z", None),
+            ("a", Some("a1")),
+            ("a1", Some("a2")),
+            ("U", Some("V01")),
+            ("zzz", None),
+        ];
+        for &(lower, upper) in cases {
+            assert_between(&midpoint_rank(lower, upper), lower, upper);
+        }
+    }
 
-        // We need to rebuild both maps. Clear them first.
-        self.positions_by_key.clear();
-        self.keys_by_position.clear(); 
-
-        // Reset positions to be evenly spaced
-        let mut current_pos = step_size; // Start from step_size
-        for key in ordered_keys {
-            // Insert updates both maps correctly
-            self.insert(key, current_pos); 
-            current_pos += step_size;
+    #[test]
+    fn repeated_midpoints_at_one_spot_never_collide() {
+        let lower = "U".to_string();
+        let mut upper = "V".to_string();
+        for _ in 0..500 {
+            let rank = midpoint_rank(&lower, Some(&upper));
+            assert_between(&rank, &lower, Some(&upper));
+            upper = rank;
+        }
+
+        let upper = "V".to_string();
+        let mut lower = "U".to_string();
+        for _ in 0..500 {
+            let rank = midpoint_rank(&lower, Some(&upper));
+            assert_between(&rank, &lower, Some(&upper));
+            lower = rank;
         }
     }
-    
-    /// Returns an iterator over keys ordered by their position.
-    pub fn iter_keys_ordered(&self) -> impl Iterator<Item = &K> {
-        self.keys_by_position.values().flat_map(|keys_vec| keys_vec.iter())
+
+    #[test]
+    fn rank_after_and_before_extend_past_the_last_digit() {
+        for rank in ["1", "U", "z", "zz", "az", "1z"] {
+            assert_between(&rank_after(rank), rank, None);
+        }
+        for rank in ["2", "U", "1", "11", "01", "z"] {
+            let before = rank_before(rank);
+            assert!(is_valid_rank(&before), "{before:?} is not a valid rank");
+            assert!(before.as_str() < rank, "{before:?} should sort before {rank:?}");
+        }
     }
 
-    /// Returns an iterator over (key, position) ordered by position.
-    pub fn iter_ordered(&self) -> impl Iterator<Item = (&K, f64)> {
-        self.keys_by_position.iter().flat_map(|(pos_ord, keys_vec)| {
-            keys_vec.iter().map(move |key| (key, pos_ord.0))
-        })
+    #[test]
+    fn evenly_spaced_ranks_are_ascending_and_valid() {
+        for count in [0, 1, 2, 61, 62, 63, 1_000, 5_000] {
+            let ranks = evenly_spaced_ranks(count);
+            assert_eq!(ranks.len(), count);
+            assert!(ranks.iter().all(|rank| is_valid_rank(rank)));
+            assert!(ranks.windows(2).all(|pair| pair[0] < pair[1]));
+        }
+        assert!(evenly_spaced_ranks(10).iter().all(|rank| rank.len() <= 2));
     }
-}
 
+    #[test]
+    fn is_valid_rank_rejects_empty_trailing_zero_and_foreign_bytes() {
+        assert!(is_valid_rank("a1"));
+        assert!(!is_valid_rank(""));
+        assert!(!is_valid_rank("a0"));
+        assert!(!is_valid_rank("a-b"));
+    }
+
+    #[test]
+    fn calculate_position_places_keys_around_the_reference() {
+        let mut tracker = PositionTracker::from_ordered_keys([1u32, 2, 3]);
+
+        let rank = tracker.calculate_position(Some(&2), true).unwrap();
+        tracker.insert(4, rank);
+        let rank = tracker.calculate_position(Some(&2), false).unwrap();
+        tracker.insert(5, rank);
+        let rank = tracker.calculate_position(None, true).unwrap();
+        tracker.insert(6, rank);
+        let rank = tracker.calculate_position(None, false).unwrap();
+        tracker.insert(7, rank);
+
+        assert_eq!(tracker.get_ordered_keys(), vec![6, 1, 4, 2, 5, 3, 7]);
+        assert!(tracker.calculate_position(Some(&99), true).is_err());
+    }
+
+    #[test]
+    fn inserting_before_the_same_key_keeps_the_order() {
+        let mut tracker = PositionTracker::from_ordered_keys([0u32, 1]);
+        for key in 2..300u32 {
+            let rank = tracker.calculate_position(Some(&1), true).unwrap();
+            tracker.insert(key, rank);
+        }
+        let ordered = tracker.get_ordered_keys();
+        assert_eq!(ordered.len(), 300);
+        assert_eq!(ordered.first(), Some(&0));
+        assert_eq!(ordered.last(), Some(&1));
+        assert_eq!(&ordered[1..299], &(2..300).collect::<Vec<u32>>()[..]);
+    }
+
+    #[test]
+    fn rebalance_keeps_the_order_and_shortens_ranks() {
+        let mut tracker = PositionTracker::from_ordered_keys([0u32, 1]);
+        for key in 2..200u32 {
+            let rank = tracker.calculate_position(Some(&1), true).unwrap();
+            tracker.insert(key, rank);
+        }
+        let before = tracker.get_ordered_keys();
+        let longest_before = tracker.iter_ordered().map(|(_, rank)| rank.len()).max().unwrap();
+
+        tracker.rebalance_positions();
 
-// --- Remove old PositionedOrdering trait, BTreeMap implementation, and get_ordered_by_position helper ---
-
-// pub trait PositionedOrdering<K> { ... }
-// impl<K: Ord + Clone> PositionedOrdering<K> for BTreeMap<K, f64> { ... }
-// pub fn get_ordered_by_position<K: Clone + Ord, V: Clone>(...) -> Vec<V> { ... }
-
-// Keep the threshold constant defined at the top.
-// Keep the OrderedFloat struct defined at the top.
-
-// pub trait PositionedOrdering<K> {
-//     /// Finds a position that would place an item before the target position
-//     fn find_position_before(&self, target_pos: f64, default_step: f64) -> f64;
-//     
-//     /// Finds a position that would place an item after the target position
-//     fn find_position_after(&self, target_pos: f64, default_step: f64) -> f64;
-//     
-//     /// Gets a map of all positions
-//     fn get_positions(&self) -> &BTreeMap<K, f64>;
-//     
-//     /// Gets a mutable reference to the positions map
-//     fn get_positions_mut(&mut self) -> &mut BTreeMap<K, f64>;
-//     
-//     /// Calculates a position based on reference item and placement preference.
-//     /// Takes &mut self because it might trigger an internal rebalance.
-//     /// Takes step_size to be used during rebalancing if needed.
-//     fn calculate_position(&mut self, reference_key: Option<&K>, before: bool, step_size: f64) -> Result<f64, String>
-//     where K: Clone + Ord + std::fmt::Debug;
-//     
-//     /// Rebalances all positions to be evenly spaced using the provided step_size
-//     fn rebalance_positions(&mut self, step_size: f64);
-// }
-
-// /// Implementation for any BTreeMap-based positioned ordering
-// impl<K: Ord + Clone> PositionedOrdering<K> for BTreeMap<K, f64> {
-//     fn find_position_before(&self, target_pos: f64, default_step: f64) -> f64 {
-//         let prev = self.values()
-//             .filter(|&&pos| pos < target_pos)
-//             .max_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
-//             
-//         match prev {
-//             Some(prev_pos) => (prev_pos + target_pos) / 2.0,
-//             None => target_pos - default_step // Use default_step
-//         }
-//     }
-
-//     fn find_position_after(&self, target_pos: f64, default_step: f64) -> f64 {
-//         let next = self.values()
-//             .filter(|&&pos| pos > target_pos)
-//             .min_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
-//             
-//         match next {
-//             Some(next_pos) => (target_pos + next_pos) / 2.0,
-//             None => target_pos + default_step // Use default_step
-//         }
-//     }
-//     
-//     fn get_positions(&self) -> &BTreeMap<K, f64> {
-//         self
-//     }
-//     
-//     fn get_positions_mut(&mut self) -> &mut BTreeMap<K, f64> {
-//         self
-//     }
-//     
-//     // calculate_position now contains the full logic, including proactive rebalancing check
-//     fn calculate_position(&mut self, reference_key: Option<&K>, before: bool, step_size: f64) -> Result<f64, String>
-//     where K: Clone + Ord + std::fmt::Debug { // Added Debug constraint for logging
-//         
-//         // Loop allows for one retry attempt after a rebalance.
-//         for attempt in 0..2 {
-//             match reference_key {
-//                 Some(ref_key) => {
-//                     // --- Get current reference position ---
-//                     // Ensure reference key exists in the current state of the map
-//                     let reference_pos = *self.get(ref_key)
-//                         .ok_or_else(|| {
-//                             // If the key disappeared after a rebalance, it's an issue.
-//                             if attempt > 0 {
-//                                 format!("Reference item (key: {:?}) lost after rebalance", ref_key) // TODO: Improve key display if possible
-//                             } else {
-//                                 "Reference item not found".to_string()
-//                             }
-//                         })?;
-
-//                     // --- Determine immediate neighbours based on 'before' flag ---
-//                     // We find the positions immediately adjacent to the *target insertion spot*.
-//                     let (prev_pos_opt, next_pos_opt) = if before {
-//                         // Target spot is just *before* reference_pos.
-//                         // Neighbour before target spot: The item with the largest position < reference_pos.
-//                         // Neighbour after target spot: The reference item itself.
-//                         let prev = self.values()
-//                             .filter(|&&pos| pos < reference_pos)
-//                             .max_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal))
-//                             .cloned(); // Clone value in case we need mutable borrow later for rebalance
-//                         (prev, Some(reference_pos))
-//                     } else {
-//                         // Target spot is just *after* reference_pos.
-//                         // Neighbour before target spot: The reference item itself.
-//                         // Neighbour after target spot: The item with the smallest position > reference_pos.
-//                         let next = self.values()
-//                             .filter(|&&pos| pos > reference_pos)
-//                             .min_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal))
-//                             .cloned(); // Clone value
-//                         (Some(reference_pos), next)
-//                     };
-
-//                     // --- Calculate potential new position ---
-//                     let new_pos = match (prev_pos_opt, next_pos_opt) {
-//                         (Some(prev), Some(next)) => (prev + next) / 2.0, // Between two items
-//                         (None, Some(next)) => next - step_size,         // Before the first item (relative to ref)
-//                         (Some(prev), None) => prev + step_size,         // After the last item (relative to ref)
-//                         (None, None) => {
-//                             // This case implies the reference key is the *only* item in the map.
-//                             // Calculate position relative to the single reference item.
-//                             if before { reference_pos - step_size } else { reference_pos + step_size }
-//                         }
-//                     };
-
-//                     // --- Check if rebalance is needed ---
-//                     let mut needs_rebalance = false;
-//                     if let (Some(prev), Some(next)) = (prev_pos_opt, next_pos_opt) {
-//                         // Check only when inserting BETWEEN two existing positions.
-//                         let gap_before = new_pos - prev;
-//                         let gap_after = next - new_pos;
-
-//                         // Trigger rebalance if EITHER gap is too small OR if floating point precision loss occurred.
-//                         if gap_before < REBALANCE_MIN_GAP_THRESHOLD 
-//                            || gap_after < REBALANCE_MIN_GAP_THRESHOLD 
-//                            || new_pos == prev // Precision loss check
-//                            || new_pos == next // Precision loss check
-//                         {
-//                             needs_rebalance = true;
-//                         }
-//                     } 
-//                     // No gap check needed when adding at the absolute start/end relative to the reference key,
-//                     // but we still need the precision loss check in those cases.
-//                     else if let (None, Some(next)) = (prev_pos_opt, next_pos_opt) { // Before first relative to ref
-//                         if new_pos == next { needs_rebalance = true; }
-//                     } else if let (Some(prev), None) = (prev_pos_opt, next_pos_opt) { // After last relative to ref
-//                         if new_pos == prev { needs_rebalance = true; }
-//                     }
-//                     // Case (None, None) means only one item exists, no gaps/precision loss to check yet.
-
-//                     // --- Perform rebalance or return position ---
-//                     if needs_rebalance {
-//                         if attempt == 0 {
-//                             self.rebalance_positions(step_size);
-//                             continue; // Go to the next iteration to recalculate with new positions
-//                         } else {
-//                             // If rebalance was needed even on the second attempt, it indicates a potential issue.
-//                             // This could happen if step_size is too small for the number of items, causing
-//                             // rebalanced gaps to *still* be below the threshold.
-//                             // Use ic_cdk::println for logging on ICP.
-//                             ic_cdk::println!("WARN: Rebalance triggered twice for reference_key: {:?}, before: {}. Proceeding with potentially suboptimal position.", reference_key, before);
-//                             // Proceeding with the potentially suboptimal position calculated after the first rebalance attempt.
-//                             // A hard error might be too disruptive. Log and return the current `new_pos`.
-//                              return Ok(new_pos); 
-//                             // Alternative: return Err("Failed to find suitable position even after rebalancing... L".to_string());
-//                         }
-//                     } else {
-//                         // No rebalance needed, return the calculated position
-//                         return Ok(new_pos);
-//                     }
-//                 }
-//                 None => { // No reference key: Place at the absolute start or end of the list.
-//                     if self.is_empty() {
-//                         return Ok(0.0); // First item ever.
-//                     }
-//                     
-//                     // No gap checks needed here, as we are extending the range.
-//                     if before {
-//                         // Find the minimum position in the current state.
-//                         let min_pos = self.values().cloned().fold(f64::INFINITY, f64::min);
-//                         return Ok(min_pos - step_size);
-//                     } else {
-//                         // Find the maximum position in the current state.
-//                         let max_pos = self.values().cloned().fold(f64::NEG_INFINITY, f64::max);
-//                         return Ok(max_pos + step_size);
-//                     }
-//                 }
-//             }
-//         } // End of loop
-
-//         // Should be unreachable if logic is correct, but needed for compiler.
-//         // This path is taken only if the loop completes without returning, 
-//         // which implies the rebalance -> continue path was taken on attempt 0, 
-//         // and then the code somehow exited the match without returning Ok or Err on attempt 1.
-//         Err("Internal error: Failed to calculate position after rebalance logic.".to_string())
-//     }
-//     
-//     fn rebalance_positions(&mut self, step_size: f64) {
-//         if self.is_empty() {
-//             return;
-//         }
-//         
-//         // Get ordered keys by collecting and copying data to avoid borrow issues
-//         let mut ordered_pairs: Vec<(K, f64)> = Vec::new();
-//         for (key, &value) in self.iter() {
-//             ordered_pairs.push((key.clone(), value));
-//         }
-//         
-//         // Sort the pairs by position value
-//         ordered_pairs.sort_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(Ordering::Equal));
-//         
-//         // Reset positions to be evenly spaced
-//         // Start from step_size to avoid 0.0, making calculations near the start consistent
-//         let mut current_pos = step_size; 
-//         for (key, _) in ordered_pairs {
-//             self.insert(key, current_pos);
-//             current_pos += step_size;
-//         }
-//     }
-// }
-
-// Helper functions that can be used by both Shelf and Profile ordering
-
-// /// Helper for getting ordered items based on their positions
-// pub fn get_ordered_by_position<K: Clone + Ord, V: Clone>(
-//     items: &BTreeMap<K, V>,
-//     positions: &BTreeMap<K, f64>
-// ) -> Vec<V> {
-//     let mut ordered: Vec<_> = positions.iter().collect();
-//     ordered.sort_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(Ordering::Equal));
-//     
-//     ordered.into_iter()
-//         .filter_map(|(key, _)| items.get(key).map(|item| item.clone()))
-//         .collect()
-// } 
\ No newline at end of file
+        assert_eq!(tracker.get_ordered_keys(), before);
+        let longest_after = tracker.iter_ordered().map(|(_, rank)| rank.len()).max().unwrap();
+        assert!(longest_after <= 3); // 200 keys fit in three digits with room between each
+        assert!(longest_after < longest_before);
+    }
+
+    #[test]
+    fn legacy_positions_migrate_in_order_with_ties_broken_by_key() {
+        let tracker = PositionTracker::from_legacy_positions(vec![
+            (3u32, 2.0),
+            (1, 1.0),
+            (4, 1.0),
+            (2, -5.5),
+            (5, 1e-12),
+        ]);
+        assert_eq!(tracker.get_ordered_keys(), vec![2, 5, 1, 4, 3]);
+        assert!(tracker.iter_ordered().all(|(_, rank)| is_valid_rank(rank)));
+    }
+
+    #[test]
+    fn invalid_stored_ranks_are_moved_to_the_end() {
+        let tracker = PositionTracker::from_ranked_entries(vec![
+            (1u32, "V".to_string()),
+            (2, "bad-rank".to_string()),
+            (3, "U".to_string()),
+            (4, "".to_string()),
+        ]);
+        let ordered = tracker.get_ordered_keys();
+        assert_eq!(&ordered[..2], &[3, 1]);
+        assert_eq!(ordered.len(), 4);
+        assert!(tracker.iter_ordered().all(|(_, rank)| is_valid_rank(rank)));
+    }
+
+    #[test]
+    fn remove_drops_the_key_from_both_maps() {
+        let mut tracker = PositionTracker::from_ordered_keys([1u32, 2, 3]);
+        let rank = tracker.remove(&2).unwrap();
+        assert!(!tracker.contains_key(&2));
+        assert_eq!(tracker.get_ordered_keys(), vec![1, 3]);
+        assert!(tracker.remove(&2).is_none());
+        // The freed rank can be reused by another key
+        tracker.insert(9, rank);
+        assert_eq!(tracker.get_ordered_keys(), vec![1, 9, 3]);
+    }
+}
@@ -0,0 +1,51 @@
+use ic_cdk;
+use std::ops::Bound;
+
+use crate::storage::{AUDIT_FINDINGS, AuditFinding, AuditState, get_audit_state};
+use crate::guard::is_controller;
+use super::follows::{
+    CursorPaginationInput, CursorPaginatedResult,
+    QueryResult,
+};
+
+/// Get the state of the consistency audit (controllers only).
+#[ic_cdk::query(guard = "is_controller")]
+pub fn get_audit_status() -> AuditState {
+    get_audit_state()
+}
+
+/// Get findings from the current (or last) audit pass, oldest first (controllers only - Paginated).
+/// The cursor is the `finding_id` of the last finding returned on the previous page.
+#[ic_cdk::query(guard = "is_controller")]
+pub fn get_audit_findings(
+    pagination: CursorPaginationInput<u64>
+) -> QueryResult<CursorPaginatedResult<AuditFinding, u64>> {
+    let limit = pagination.get_limit();
+    let limit_plus_one = limit + 1;
+
+    let start_bound = match pagination.cursor {
+        Some(cursor_id) => Bound::Excluded(cursor_id),
+        None => Bound::Unbounded,
+    };
+
+    let mut items: Vec<AuditFinding> = AUDIT_FINDINGS.with(|findings_ref| {
+        findings_ref.borrow()
+            .range((start_bound, Bound::Unbounded))
+            .take(limit_plus_one)
+            .map(|(_, finding)| finding)
+            .collect()
+    });
+
+    let next_cursor = if items.len() == limit_plus_one {
+        items.pop();
+        items.last().map(|finding| finding.finding_id)
+    } else {
+        None
+    };
+
+    Ok(CursorPaginatedResult {
+        items,
+        next_cursor,
+        limit: pagination.limit,
+    })
+}
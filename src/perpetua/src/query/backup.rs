@@ -0,0 +1,199 @@
+use ic_cdk;
+use ic_stable_structures::{StableBTreeMap, Storable};
+use std::cell::RefCell;
+use std::thread::LocalKey;
+
+use crate::storage::{
+    Memory,
+    SHELF_DATA, GLOBAL_TIMELINE, SHELF_FORKS, SHELF_FORK_COUNTS,
+    USER_SHELVES, USER_PROFILE_ORDER, NFT_SHELVES, RANDOM_SHELF_CANDIDATES,
+    TAG_METADATA, TAG_SHELF_ASSOCIATIONS, SHELF_TAG_ASSOCIATIONS, TAG_POPULARITY_INDEX,
+    TAG_LEXICAL_INDEX, TAG_SHELF_CREATION_TIMELINE_INDEX, TAG_ALIASES, TAG_BLOCKLIST, TAG_COOCCURRENCE,
+    FOLLOWED_USERS, FOLLOWED_TAGS, USER_FOLLOWERS, TAG_FOLLOWERS, USER_FOLLOWER_COUNTS, TAG_FOLLOWER_COUNTS,
+    SEARCH_INDEX, SHELF_SEARCH_TERMS, ACTIVITY_LOG, TRENDING_TAGS, TRENDING_SHELVES,
+    NOTIFICATIONS, MUTED_NOTIFICATION_KINDS, SHELF_ACCESS_GRANTS,
+    SHELF_STATS, DAILY_SHELF_VIEWS, ITEM_OPEN_COUNTS, MOST_VIEWED_SHELVES,
+    UserProfileOrderSerializable, get_follow_limits,
+};
+use crate::types::{BackupMap, BackupData, BackupChunk, BackupPaginationInput};
+use crate::guard::is_controller;
+
+const MAX_BACKUP_CHUNK_SIZE: u64 = 1_000;
+
+/// Reads `pagination.limit` entries (in key order) from a stable map starting at `pagination.offset`,
+/// along with the map's total size.
+fn export_page<K, V, T>(
+    map: &'static LocalKey<RefCell<StableBTreeMap<K, V, Memory>>>,
+    pagination: &BackupPaginationInput,
+    convert: impl Fn(K, V) -> T,
+) -> (Vec<T>, u64)
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+{
+    let limit = pagination.limit.min(MAX_BACKUP_CHUNK_SIZE) as usize;
+    map.with(|map_ref| {
+        let map = map_ref.borrow();
+        let data = map.iter()
+            .skip(pagination.offset as usize)
+            .take(limit)
+            .map(|(key, value)| convert(key, value))
+            .collect();
+        (data, map.len())
+    })
+}
+
+/// Exports one page of a stable structure for backup (controllers only).
+///
+/// Every map can be exported. Only the source data variants can be imported again with
+/// `import_backup_chunk`; derived indexes are exported for inspection and are rebuilt on restore.
+/// Call with increasing offsets until `offset >= total_count`.
+#[ic_cdk::query(guard = "is_controller")]
+pub fn export_backup_chunk(map: BackupMap, pagination: BackupPaginationInput) -> Result<BackupChunk, String> {
+    let p = &pagination;
+    let (data, total_count) = match map {
+        BackupMap::Shelves => {
+            let (d, t) = export_page(&SHELF_DATA, p, |_, v| v);
+            (BackupData::Shelves(d), t)
+        }
+        BackupMap::UserProfileOrders => {
+            let (d, t) = export_page(&USER_PROFILE_ORDER, p, |k, v| (k, UserProfileOrderSerializable::from_uop(&v)));
+            (BackupData::UserProfileOrders(d), t)
+        }
+        BackupMap::FollowedUsers => {
+            let (d, t) = export_page(&FOLLOWED_USERS, p, |k, v| (k, v));
+            (BackupData::FollowedUsers(d), t)
+        }
+        BackupMap::FollowedTags => {
+            let (d, t) = export_page(&FOLLOWED_TAGS, p, |k, v| (k, v));
+            (BackupData::FollowedTags(d), t)
+        }
+        BackupMap::FollowLimits => (BackupData::FollowLimits(get_follow_limits()), 1),
+        BackupMap::TagAliases => {
+            let (d, t) = export_page(&TAG_ALIASES, p, |k, v| (k, v));
+            (BackupData::TagAliases(d), t)
+        }
+        BackupMap::TagBlocklist => {
+            let (d, t) = export_page(&TAG_BLOCKLIST, p, |k, _| k);
+            (BackupData::TagBlocklist(d), t)
+        }
+        BackupMap::ActivityLog => {
+            let (d, t) = export_page(&ACTIVITY_LOG, p, |k, v| (k, v));
+            (BackupData::ActivityLog(d), t)
+        }
+        BackupMap::Notifications => {
+            let (d, t) = export_page(&NOTIFICATIONS, p, |k, v| (k.recipient, v));
+            (BackupData::Notifications(d), t)
+        }
+        BackupMap::MutedNotificationKinds => {
+            let (d, t) = export_page(&MUTED_NOTIFICATION_KINDS, p, |k, v| (k, v));
+            (BackupData::MutedNotificationKinds(d), t)
+        }
+        BackupMap::ShelfAccessGrants => {
+            let (d, t) = export_page(&SHELF_ACCESS_GRANTS, p, |k, v| (k, v));
+            (BackupData::ShelfAccessGrants(d), t)
+        }
+        BackupMap::ShelfStats => {
+            let (d, t) = export_page(&SHELF_STATS, p, |k, v| (k, v));
+            (BackupData::ShelfStats(d), t)
+        }
+        BackupMap::DailyShelfViews => {
+            let (d, t) = export_page(&DAILY_SHELF_VIEWS, p, |k, v| (k, v));
+            (BackupData::DailyShelfViews(d), t)
+        }
+        BackupMap::ItemOpenCounts => {
+            let (d, t) = export_page(&ITEM_OPEN_COUNTS, p, |k, v| (k, v));
+            (BackupData::ItemOpenCounts(d), t)
+        }
+        BackupMap::GlobalTimeline => {
+            let (d, t) = export_page(&GLOBAL_TIMELINE, p, |k, v| (k, v));
+            (BackupData::GlobalTimeline(d), t)
+        }
+        BackupMap::UserShelves => {
+            let (d, t) = export_page(&USER_SHELVES, p, |k, v| (k, v));
+            (BackupData::UserShelves(d), t)
+        }
+        BackupMap::NftShelves => {
+            let (d, t) = export_page(&NFT_SHELVES, p, |k, v| (k, v));
+            (BackupData::NftShelves(d), t)
+        }
+        BackupMap::RandomShelfCandidates => {
+            let (d, t) = export_page(&RANDOM_SHELF_CANDIDATES, p, |k, v| (k, v));
+            (BackupData::RandomShelfCandidates(d), t)
+        }
+        BackupMap::ShelfForks => {
+            let (d, t) = export_page(&SHELF_FORKS, p, |k, _| k);
+            (BackupData::ShelfForks(d), t)
+        }
+        BackupMap::ShelfForkCounts => {
+            let (d, t) = export_page(&SHELF_FORK_COUNTS, p, |k, v| (k, v));
+            (BackupData::ShelfForkCounts(d), t)
+        }
+        BackupMap::TagMetadata => {
+            let (d, t) = export_page(&TAG_METADATA, p, |k, v| (k, v));
+            (BackupData::TagMetadata(d), t)
+        }
+        BackupMap::TagShelfAssociations => {
+            let (d, t) = export_page(&TAG_SHELF_ASSOCIATIONS, p, |k, _| k);
+            (BackupData::TagShelfAssociations(d), t)
+        }
+        BackupMap::ShelfTagAssociations => {
+            let (d, t) = export_page(&SHELF_TAG_ASSOCIATIONS, p, |k, _| k);
+            (BackupData::ShelfTagAssociations(d), t)
+        }
+        BackupMap::TagPopularityIndex => {
+            let (d, t) = export_page(&TAG_POPULARITY_INDEX, p, |k, _| k);
+            (BackupData::TagPopularityIndex(d), t)
+        }
+        BackupMap::TagLexicalIndex => {
+            let (d, t) = export_page(&TAG_LEXICAL_INDEX, p, |k, _| k);
+            (BackupData::TagLexicalIndex(d), t)
+        }
+        BackupMap::TagShelfCreationTimeline => {
+            let (d, t) = export_page(&TAG_SHELF_CREATION_TIMELINE_INDEX, p, |k, _| k);
+            (BackupData::TagShelfCreationTimeline(d), t)
+        }
+        BackupMap::TagCooccurrence => {
+            let (d, t) = export_page(&TAG_COOCCURRENCE, p, |k, v| (k, v));
+            (BackupData::TagCooccurrence(d), t)
+        }
+        BackupMap::UserFollowers => {
+            let (d, t) = export_page(&USER_FOLLOWERS, p, |k, _| k);
+            (BackupData::UserFollowers(d), t)
+        }
+        BackupMap::TagFollowers => {
+            let (d, t) = export_page(&TAG_FOLLOWERS, p, |k, _| k);
+            (BackupData::TagFollowers(d), t)
+        }
+        BackupMap::UserFollowerCounts => {
+            let (d, t) = export_page(&USER_FOLLOWER_COUNTS, p, |k, v| (k, v));
+            (BackupData::UserFollowerCounts(d), t)
+        }
+        BackupMap::TagFollowerCounts => {
+            let (d, t) = export_page(&TAG_FOLLOWER_COUNTS, p, |k, v| (k, v));
+            (BackupData::TagFollowerCounts(d), t)
+        }
+        BackupMap::SearchIndex => {
+            let (d, t) = export_page(&SEARCH_INDEX, p, |k, v| (k, v));
+            (BackupData::SearchIndex(d), t)
+        }
+        BackupMap::ShelfSearchTerms => {
+            let (d, t) = export_page(&SHELF_SEARCH_TERMS, p, |k, v| (k, v));
+            (BackupData::ShelfSearchTerms(d), t)
+        }
+        BackupMap::TrendingTags => {
+            let (d, t) = export_page(&TRENDING_TAGS, p, |k, _| k);
+            (BackupData::TrendingTags(d), t)
+        }
+        BackupMap::TrendingShelves => {
+            let (d, t) = export_page(&TRENDING_SHELVES, p, |k, _| k);
+            (BackupData::TrendingShelves(d), t)
+        }
+        BackupMap::MostViewedShelves => {
+            let (d, t) = export_page(&MOST_VIEWED_SHELVES, p, |k, _| k);
+            (BackupData::MostViewedShelves(d), t)
+        }
+    };
+
+    Ok(BackupChunk { data, total_count })
+}
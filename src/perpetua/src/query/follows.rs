@@ -12,12 +12,17 @@ use crate::storage::{
     ShelfContent as StorageShelfContent,   // Alias to avoid name clash if needed
     Shelf as StorageShelf, // For the existing ShelfPublic::from
     Shelf, Item, ShelfId, NormalizedTag, // ItemId is no longer used directly
+    TAG_ALIASES, TAG_BLOCKLIST,
+    resolve_tag, is_tag_blocked, get_fork_count,
+    USER_FOLLOWERS, TAG_FOLLOWERS, USER_FOLLOWER_COUNTS, TAG_FOLLOWER_COUNTS,
+    UserFollowerKey, TagFollowerKey, FollowLimits,
+    ShelfAccessPolicy, visible_item_order,
 };
 // Remove UserProfileOrder import
 
 use crate::types::TagPopularityKey; // TagShelfAssociationKey is no longer used
 use crate::utils::normalize_tag; // Keep
-use crate::guard::not_anon; // Keep
+use crate::guard::{not_anon, is_controller}; // Keep
 
 // --- Pagination Defaults ---
 pub(super) const DEFAULT_PAGE_LIMIT: usize = 20; // Keep pub(super) for now
@@ -33,6 +38,7 @@ pub enum QueryError {
     InvalidCursor,
     InvalidTimeRange,
     ShelfContentNotFound, // ADDED
+    Unauthorized,
     // Any other existing variants
 }
 
@@ -46,29 +52,39 @@ pub struct ShelfPublic {
     pub description: Option<String>,
     pub owner: Principal,
     pub items: BTreeMap<u32, Item>, 
-    pub item_positions: Vec<(u32, f64)>,
+    pub item_order: Vec<u32>, // Item IDs in display order
     pub created_at: u64,
     pub updated_at: u64,
     pub appears_in: Vec<ShelfId>,
     pub tags: Vec<NormalizedTag>, 
     pub public_editing: bool,
+    pub forked_from: Option<ShelfId>,
+    pub fork_count: u64,
+    pub access_policy: ShelfAccessPolicy,
+    pub is_preview: bool, // Only the first items are included; the caller has not unlocked the shelf
 }
 
 impl ShelfPublic {
     // ADDED: New constructor from ShelfMetadata and ShelfContent
+    // Gated shelves the caller has not unlocked only include the preview items.
     pub fn from_parts(metadata: &StorageShelfMetadata, content: &StorageShelfContent) -> Self {
+        let (item_order, is_preview) = visible_item_order(metadata, content, ic_cdk::caller());
         Self {
             shelf_id: metadata.shelf_id.clone(),
             title: metadata.title.clone(),
             description: metadata.description.clone(),
             owner: metadata.owner.clone(),
-            items: content.items.clone(),
-            item_positions: content.item_positions.get_ordered_entries(),
+            items: item_order.iter().filter_map(|id| content.items.get(id).map(|item| (*id, item.clone()))).collect(),
+            item_order,
             created_at: metadata.created_at,
             updated_at: metadata.updated_at,
             appears_in: metadata.appears_in.clone(),
             tags: metadata.tags.clone(),
             public_editing: metadata.public_editing,
+            forked_from: metadata.forked_from.clone(),
+            fork_count: get_fork_count(&metadata.shelf_id),
+            access_policy: metadata.access_policy(),
+            is_preview,
         }
     }
 
@@ -80,12 +96,16 @@ impl ShelfPublic {
              description: internal_shelf.description.clone(),
              owner: internal_shelf.owner.clone(),
              items: internal_shelf.items.clone(),
-             item_positions: internal_shelf.item_positions.get_ordered_entries(),
+             item_order: internal_shelf.item_positions.get_ordered_keys(),
              created_at: internal_shelf.created_at,
              updated_at: internal_shelf.updated_at,
              appears_in: internal_shelf.appears_in.clone(),
              tags: internal_shelf.tags.clone(),
              public_editing: internal_shelf.public_editing,
+             forked_from: None,
+             fork_count: get_fork_count(&internal_shelf.shelf_id),
+             access_policy: ShelfAccessPolicy::Public,
+             is_preview: false,
          }
     }
 }
@@ -93,7 +113,7 @@ impl ShelfPublic {
 /// Get the number of shelves associated with a specific tag.
 #[ic_cdk::query]
 pub fn get_tag_shelf_count(tag: String) -> u64 {
-     let normalized_tag = normalize_tag(&tag);
+     let normalized_tag = resolve_tag(&tag);
      
      crate::storage::TAG_METADATA.with(|meta| {
          meta.borrow()
@@ -127,6 +147,7 @@ pub fn get_popular_tags(
                 Bound::Unbounded => false,
                 _ => unreachable!(), // Should only be Excluded or Unbounded
             })
+            .filter(|(k, _)| !is_tag_blocked(&k.1)) // Hide moderated tags
             .take(limit_plus_one) 
         {
             result_keys.push(key.clone());
@@ -192,6 +213,11 @@ pub fn get_tags_with_prefix(
                 break;
             }
 
+            // Skip moderated tags
+            if is_tag_blocked(&tag) {
+                continue;
+            }
+
             matching_tags.push(tag.clone());
 
             // Stop if we have fetched enough tags for pagination
@@ -219,6 +245,18 @@ pub fn get_tags_with_prefix(
     })
 }
 
+/// Get all tag aliases as (alias, canonical tag) pairs.
+#[ic_cdk::query]
+pub fn get_tag_aliases() -> Vec<(NormalizedTag, NormalizedTag)> {
+    TAG_ALIASES.with(|aliases| aliases.borrow().iter().collect())
+}
+
+/// Get the tag blocklist (controllers only).
+#[ic_cdk::query(guard = "is_controller")]
+pub fn get_blocked_tags() -> Vec<NormalizedTag> {
+    TAG_BLOCKLIST.with(|blocklist| blocklist.borrow().iter().map(|(tag, _)| tag).collect())
+}
+
 // --- Follow System Queries ---
 
 /// Query to get the list of tags followed by the caller.
@@ -252,6 +290,102 @@ pub fn get_my_followed_users() -> QueryResult<Vec<Principal>> {
     })
 }
 
+/// Get the followers of a user (ordered by principal - Paginated).
+/// The cursor is the last follower returned on the previous page.
+#[ic_cdk::query]
+pub fn get_followers(
+    user: Principal,
+    pagination: CursorPaginationInput<Principal>
+) -> QueryResult<CursorPaginatedResult<Principal, Principal>> {
+    let limit = pagination.get_limit();
+    let limit_plus_one = limit + 1;
+
+    let start_bound = match pagination.cursor {
+        Some(follower) => Bound::Excluded(UserFollowerKey { followed: user, follower }),
+        None => Bound::Included(UserFollowerKey { followed: user, follower: Principal::management_canister() }),
+    };
+
+    let mut followers: Vec<Principal> = USER_FOLLOWERS.with(|map_ref| {
+        map_ref.borrow()
+            .range((start_bound, Bound::Unbounded))
+            .take_while(|(key, _)| key.followed == user)
+            .take(limit_plus_one)
+            .map(|(key, _)| key.follower)
+            .collect()
+    });
+
+    let next_cursor = if followers.len() == limit_plus_one {
+        followers.pop();
+        followers.last().cloned()
+    } else {
+        None
+    };
+
+    Ok(CursorPaginatedResult {
+        items: followers,
+        next_cursor,
+        limit: pagination.limit,
+    })
+}
+
+/// Get the followers of a tag (ordered by principal - Paginated).
+/// Aliases resolve to their canonical tag.
+#[ic_cdk::query]
+pub fn get_tag_followers(
+    tag: String,
+    pagination: CursorPaginationInput<Principal>
+) -> QueryResult<CursorPaginatedResult<Principal, Principal>> {
+    let normalized_tag = resolve_tag(&tag);
+    let limit = pagination.get_limit();
+    let limit_plus_one = limit + 1;
+
+    let start_bound = match pagination.cursor {
+        Some(follower) => Bound::Excluded(TagFollowerKey { tag: normalized_tag.clone(), follower }),
+        None => Bound::Included(TagFollowerKey { tag: normalized_tag.clone(), follower: Principal::management_canister() }),
+    };
+
+    let mut followers: Vec<Principal> = TAG_FOLLOWERS.with(|map_ref| {
+        map_ref.borrow()
+            .range((start_bound, Bound::Unbounded))
+            .take_while(|(key, _)| key.tag == normalized_tag)
+            .take(limit_plus_one)
+            .map(|(key, _)| key.follower)
+            .collect()
+    });
+
+    let next_cursor = if followers.len() == limit_plus_one {
+        followers.pop();
+        followers.last().cloned()
+    } else {
+        None
+    };
+
+    Ok(CursorPaginatedResult {
+        items: followers,
+        next_cursor,
+        limit: pagination.limit,
+    })
+}
+
+/// Get the number of principals following a user.
+#[ic_cdk::query]
+pub fn get_follower_count(user: Principal) -> u64 {
+    USER_FOLLOWER_COUNTS.with(|counts| counts.borrow().get(&user).unwrap_or(0))
+}
+
+/// Get the number of principals following a tag.
+#[ic_cdk::query]
+pub fn get_tag_follower_count(tag: String) -> u64 {
+    let normalized_tag = resolve_tag(&tag);
+    TAG_FOLLOWER_COUNTS.with(|counts| counts.borrow().get(&normalized_tag).unwrap_or(0))
+}
+
+/// Get the current follow caps applied by `follow_user` / `follow_tag`.
+#[ic_cdk::query]
+pub fn get_follow_limits() -> FollowLimits {
+    crate::storage::get_follow_limits()
+}
+
 #[derive(CandidType, Deserialize, Debug, Clone)]
 pub struct OffsetPaginationInput {
     pub offset: Nat,
@@ -0,0 +1,499 @@
+use candid::{CandidType, Deserialize, Nat, Principal};
+use ic_cdk;
+use ic_http_certification::utils::{add_skip_certification_header, skip_certification_certified_data};
+use serde::Serialize;
+
+use crate::storage::{ItemContent, TagShelfCreationTimelineKey, resolve_tag};
+use super::follows::{
+    OffsetPaginationInput, CursorPaginationInput,
+    QueryError, ShelfPublic,
+};
+use super::shelves::{get_shelf, get_user_shelves, get_shelves_by_tag};
+
+// --- Constants ---
+const FRONTEND_BASE_URL: &str = "https://lbry.app";
+const PROVIDER_NAME: &str = "Alexandria";
+const SHELF_CACHE_MAX_AGE_SECS: u64 = 60;   // Single shelves change rarely between views
+const LIST_CACHE_MAX_AGE_SECS: u64 = 30;    // Lists pick up new shelves sooner
+const DEFAULT_EMBED_WIDTH: u32 = 600;
+const DEFAULT_EMBED_HEIGHT: u32 = 400;
+const MAX_EMBED_DIMENSION: u32 = 2_000;
+
+// --- HTTP gateway types ---
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct HttpResponse {
+    pub status_code: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+// --- JSON response bodies ---
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum JsonItemContent {
+    Nft { nft_id: String },
+    Markdown { markdown: String },
+    Shelf { shelf_id: String },
+    Arweave { tx_id: String },
+    Link { url: String, title: Option<String> },
+    Icrc7Token { collection: String, token_id: String },
+}
+
+#[derive(Serialize)]
+struct JsonItem {
+    id: u32,
+    #[serde(flatten)]
+    content: JsonItemContent,
+}
+
+#[derive(Serialize)]
+struct JsonShelf {
+    shelf_id: String,
+    title: String,
+    description: Option<String>,
+    owner: String,
+    tags: Vec<String>,
+    created_at: u64,
+    updated_at: u64,
+    forked_from: Option<String>,
+    fork_count: u64,
+    url: String,
+    is_preview: bool,     // Gated shelf: only the preview items are included
+    items: Vec<JsonItem>, // In display order
+}
+
+#[derive(Serialize)]
+struct JsonShelfSummary {
+    shelf_id: String,
+    title: String,
+    description: Option<String>,
+    owner: String,
+    tags: Vec<String>,
+    created_at: u64,
+    item_count: usize,
+    url: String,
+}
+
+#[derive(Serialize)]
+struct JsonShelfList {
+    shelves: Vec<JsonShelfSummary>,
+    total_count: Option<u64>,     // Offset-paginated lists only
+    next_cursor: Option<String>,  // Cursor-paginated lists only
+}
+
+#[derive(Serialize)]
+struct OEmbedResponse {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    version: &'static str,
+    title: String,
+    author_name: Option<String>,
+    author_url: Option<String>,
+    provider_name: &'static str,
+    provider_url: &'static str,
+    cache_age: u64,
+    html: String,
+    width: u32,
+    height: u32,
+}
+
+#[derive(Serialize)]
+struct JsonError {
+    error: String,
+}
+
+// --- Request parsing ---
+
+/// Decodes `%XX` escapes (and `+` as space in query values).
+fn percent_decode(input: &str, plus_as_space: bool) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => {
+                let hex = |b: u8| (b as char).to_digit(16);
+                match (hex(bytes[i + 1]), hex(bytes[i + 2])) {
+                    (Some(high), Some(low)) => {
+                        decoded.push((high * 16 + low) as u8);
+                        i += 3;
+                        continue;
+                    }
+                    _ => decoded.push(b'%'),
+                }
+            }
+            b'+' if plus_as_space => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn url_encode(input: &str) -> String {
+    input.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn html_escape(input: &str) -> String {
+    input.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+struct ParsedUrl {
+    segments: Vec<String>,
+    params: Vec<(String, String)>,
+}
+
+impl ParsedUrl {
+    fn parse(url: &str) -> Self {
+        let (path, query) = url.split_once('?').unwrap_or((url, ""));
+        let segments = path.split('/')
+            .filter(|s| !s.is_empty())
+            .map(|s| percent_decode(s, false))
+            .collect();
+        let params = query.split('&')
+            .filter(|p| !p.is_empty())
+            .map(|p| {
+                let (key, value) = p.split_once('=').unwrap_or((p, ""));
+                (percent_decode(key, true), percent_decode(value, true))
+            })
+            .collect();
+        Self { segments, params }
+    }
+
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+
+    fn param_u64(&self, name: &str) -> Option<u64> {
+        self.param(name).and_then(|value| value.parse().ok())
+    }
+}
+
+/// Response format and embed size requested through `format`, `maxwidth` and `maxheight`.
+struct OutputFormat {
+    oembed: bool,
+    width: u32,
+    height: u32,
+}
+
+impl OutputFormat {
+    fn from_url(url: &ParsedUrl) -> Self {
+        let dimension = |name: &str, default: u32| {
+            url.param_u64(name).map_or(default, |v| (v as u32).clamp(1, MAX_EMBED_DIMENSION))
+        };
+        Self {
+            oembed: url.param("format") == Some("oembed"),
+            width: dimension("maxwidth", DEFAULT_EMBED_WIDTH),
+            height: dimension("maxheight", DEFAULT_EMBED_HEIGHT),
+        }
+    }
+}
+
+// --- Response helpers ---
+
+fn cors_headers() -> Vec<(String, String)> {
+    vec![
+        ("Access-Control-Allow-Origin".to_string(), "*".to_string()),
+        ("Access-Control-Allow-Methods".to_string(), "GET, HEAD, OPTIONS".to_string()),
+        ("Access-Control-Allow-Headers".to_string(), "Content-Type".to_string()),
+    ]
+}
+
+fn json_response<T: Serialize>(status_code: u16, body: &T, max_age_secs: u64) -> HttpResponse {
+    let json_body = serde_json::to_string(body).unwrap_or_else(|_|
+        r#"{"error": "Failed to serialize response"}"#.to_string()
+    );
+    let cache_control = if max_age_secs > 0 {
+        format!("public, max-age={}", max_age_secs)
+    } else {
+        "no-store".to_string()
+    };
+    let mut headers = vec![
+        ("Content-Type".to_string(), "application/json; charset=utf-8".to_string()),
+        ("Cache-Control".to_string(), cache_control),
+    ];
+    headers.extend(cors_headers());
+    HttpResponse {
+        status_code,
+        headers,
+        body: json_body.into_bytes(),
+    }
+}
+
+fn error_response(status_code: u16, message: &str) -> HttpResponse {
+    json_response(status_code, &JsonError { error: message.to_string() }, 0)
+}
+
+// --- Conversions ---
+
+fn shelf_url(shelf_id: &str) -> String {
+    format!("{}/app/perpetua/shelf/{}", FRONTEND_BASE_URL, url_encode(shelf_id))
+}
+
+fn user_url(user: &Principal) -> String {
+    format!("{}/app/perpetua/user/{}", FRONTEND_BASE_URL, user.to_text())
+}
+
+fn tag_url(tag: &str) -> String {
+    format!("{}/app/perpetua?tag={}", FRONTEND_BASE_URL, url_encode(tag))
+}
+
+fn nat_to_string(n: &Nat) -> String {
+    n.0.to_string() // Nat's Display inserts '_' separators
+}
+
+fn json_item_content(content: &ItemContent) -> JsonItemContent {
+    match content {
+        ItemContent::Nft(nft_id) => JsonItemContent::Nft { nft_id: nft_id.clone() },
+        ItemContent::Markdown(markdown) => JsonItemContent::Markdown { markdown: markdown.clone() },
+        ItemContent::Shelf(shelf_id) => JsonItemContent::Shelf { shelf_id: shelf_id.clone() },
+        ItemContent::Arweave(tx_id) => JsonItemContent::Arweave { tx_id: tx_id.clone() },
+        ItemContent::Link { url, title } => JsonItemContent::Link { url: url.clone(), title: title.clone() },
+        ItemContent::Icrc7Token { collection, token_id } => JsonItemContent::Icrc7Token {
+            collection: collection.to_text(),
+            token_id: nat_to_string(token_id),
+        },
+    }
+}
+
+fn json_shelf(shelf: &ShelfPublic) -> JsonShelf {
+    JsonShelf {
+        shelf_id: shelf.shelf_id.clone(),
+        title: shelf.title.clone(),
+        description: shelf.description.clone(),
+        owner: shelf.owner.to_text(),
+        tags: shelf.tags.clone(),
+        created_at: shelf.created_at,
+        updated_at: shelf.updated_at,
+        forked_from: shelf.forked_from.clone(),
+        fork_count: shelf.fork_count,
+        url: shelf_url(&shelf.shelf_id),
+        is_preview: shelf.is_preview,
+        items: shelf.item_order.iter()
+            .filter_map(|item_id| shelf.items.get(item_id))
+            .map(|item| JsonItem { id: item.id, content: json_item_content(&item.content) })
+            .collect(),
+    }
+}
+
+fn json_shelf_summary(shelf: &ShelfPublic) -> JsonShelfSummary {
+    JsonShelfSummary {
+        shelf_id: shelf.shelf_id.clone(),
+        title: shelf.title.clone(),
+        description: shelf.description.clone(),
+        owner: shelf.owner.to_text(),
+        tags: shelf.tags.clone(),
+        created_at: shelf.created_at,
+        item_count: shelf.items.len(),
+        url: shelf_url(&shelf.shelf_id),
+    }
+}
+
+fn oembed_response(title: String, author: Option<&Principal>, page_url: &str, format: &OutputFormat, max_age_secs: u64) -> HttpResponse {
+    let embed_src = if page_url.contains('?') {
+        format!("{}&embed=1", page_url)
+    } else {
+        format!("{}?embed=1", page_url)
+    };
+    let html = format!(
+        r#"<iframe src="{}" width="{}" height="{}" title="{}" style="border:0" loading="lazy" allowfullscreen></iframe>"#,
+        html_escape(&embed_src), format.width, format.height, html_escape(&title)
+    );
+    let body = OEmbedResponse {
+        kind: "rich",
+        version: "1.0",
+        title,
+        author_name: author.map(|p| p.to_text()),
+        author_url: author.map(user_url),
+        provider_name: PROVIDER_NAME,
+        provider_url: FRONTEND_BASE_URL,
+        cache_age: max_age_secs,
+        html,
+        width: format.width,
+        height: format.height,
+    };
+    json_response(200, &body, max_age_secs)
+}
+
+// --- Routes ---
+
+fn serve_shelf(shelf_id: &str, format: &OutputFormat) -> HttpResponse {
+    let shelf = match get_shelf(shelf_id.to_string()) {
+        Ok(shelf) => shelf,
+        Err(_) => return error_response(404, "Shelf not found"),
+    };
+    if format.oembed {
+        return oembed_response(shelf.title.clone(), Some(&shelf.owner), &shelf_url(&shelf.shelf_id), format, SHELF_CACHE_MAX_AGE_SECS);
+    }
+    json_response(200, &json_shelf(&shelf), SHELF_CACHE_MAX_AGE_SECS)
+}
+
+fn serve_user_shelves(user_text: &str, url: &ParsedUrl, format: &OutputFormat) -> HttpResponse {
+    let user = match Principal::from_text(user_text) {
+        Ok(user) => user,
+        Err(_) => return error_response(400, "Invalid principal"),
+    };
+    if format.oembed {
+        return oembed_response(format!("Shelves by {}", user.to_text()), Some(&user), &user_url(&user), format, LIST_CACHE_MAX_AGE_SECS);
+    }
+
+    let pagination = OffsetPaginationInput {
+        offset: Nat::from(url.param_u64("offset").unwrap_or(0)),
+        limit: url.param_u64("limit").unwrap_or(0),
+    };
+    match get_user_shelves(user, pagination) {
+        Ok(page) => {
+            let body = JsonShelfList {
+                shelves: page.items.iter().map(json_shelf_summary).collect(),
+                total_count: page.total_count.0.try_into().ok(),
+                next_cursor: None,
+            };
+            json_response(200, &body, LIST_CACHE_MAX_AGE_SECS)
+        }
+        Err(QueryError::UserNotFound) => error_response(404, "User has no shelves"),
+        Err(_) => error_response(500, "Failed to load shelves"),
+    }
+}
+
+/// Tag cursors are `{reversed_created_at}.{shelf_id}` (shelf IDs are base58, so never contain '.').
+fn parse_tag_cursor(tag: &str, cursor: &str) -> Option<TagShelfCreationTimelineKey> {
+    let (reversed_created_at, shelf_id) = cursor.split_once('.')?;
+    Some(TagShelfCreationTimelineKey {
+        tag: tag.to_string(),
+        reversed_created_at: reversed_created_at.parse().ok()?,
+        shelf_id: shelf_id.to_string(),
+    })
+}
+
+fn serve_tag_shelves(tag: &str, url: &ParsedUrl, format: &OutputFormat) -> HttpResponse {
+    let normalized_tag = resolve_tag(tag);
+    if normalized_tag.is_empty() {
+        return error_response(400, "Invalid tag");
+    }
+    if format.oembed {
+        return oembed_response(format!("Shelves tagged #{}", normalized_tag), None, &tag_url(&normalized_tag), format, LIST_CACHE_MAX_AGE_SECS);
+    }
+
+    let cursor = match url.param("cursor") {
+        Some(raw) => match parse_tag_cursor(&normalized_tag, raw) {
+            Some(key) => Some(key),
+            None => return error_response(400, "Invalid cursor"),
+        },
+        None => None,
+    };
+    let pagination = CursorPaginationInput {
+        cursor,
+        limit: url.param_u64("limit").unwrap_or(0),
+    };
+    match get_shelves_by_tag(normalized_tag, pagination) {
+        Ok(page) => {
+            let body = JsonShelfList {
+                shelves: page.items.iter().map(json_shelf_summary).collect(),
+                total_count: None,
+                next_cursor: page.next_cursor.map(|key| format!("{}.{}", key.reversed_created_at, key.shelf_id)),
+            };
+            json_response(200, &body, LIST_CACHE_MAX_AGE_SECS)
+        }
+        Err(QueryError::InvalidCursor) => error_response(400, "Invalid cursor"),
+        Err(_) => error_response(500, "Failed to load shelves"),
+    }
+}
+
+/// Maps a frontend page URL (as passed to an oEmbed endpoint) onto the matching route.
+fn serve_oembed_for_page(url: &ParsedUrl, format: &OutputFormat) -> HttpResponse {
+    let page_url = match url.param("url") {
+        Some(page_url) => page_url,
+        None => return error_response(400, "Missing url parameter"),
+    };
+    let format = OutputFormat { oembed: true, ..*format };
+    let page = ParsedUrl::parse(page_url.split_once("://").map_or(page_url, |(_, rest)| rest));
+    // segments[0] is the host
+    let path: Vec<&str> = page.segments.iter().skip(1).map(|s| s.as_str()).collect();
+    match path.as_slice() {
+        ["app", "perpetua", "shelf", shelf_id] => serve_shelf(shelf_id, &format),
+        ["app", "perpetua", "user", _, "shelf", shelf_id] => serve_shelf(shelf_id, &format),
+        ["app", "perpetua", "user", user] => serve_user_shelves(user, &page, &format),
+        ["app", "perpetua"] => match page.param("tag") {
+            Some(tag) => serve_tag_shelves(tag, &page, &format),
+            None => error_response(404, "No embeddable content at this URL"),
+        },
+        _ => error_response(404, "No embeddable content at this URL"),
+    }
+}
+
+/// Sets the certified data that lets HTTP gateways skip response verification for `http_request`.
+///
+/// Responses are built per request from live shelf data, so they are not certified individually.
+/// Certified data does not survive upgrades, so this runs from both `init` and `post_upgrade`.
+pub fn init_http_certification() {
+    ic_cdk::api::set_certified_data(&skip_certification_certified_data());
+}
+
+/// Adds the `IC-Certificate` headers telling HTTP gateways that this canister skips certification.
+fn with_skip_certification(mut response: HttpResponse) -> HttpResponse {
+    let mut certified = ic_http_certification::HttpResponse::builder().build();
+    add_skip_certification_header(ic_cdk::api::data_certificate().unwrap_or_default(), &mut certified);
+    response.headers.extend(certified.headers().iter().cloned());
+    response
+}
+
+/// Serves public shelves over HTTP so other sites can embed them without an IC agent.
+///
+/// Served from `https://ya6k4-waaaa-aaaap-qkmpq-cai.icp0.io` (the perpetua canister), e.g.
+/// `https://ya6k4-waaaa-aaaap-qkmpq-cai.icp0.io/shelf/{shelf_id}`. Responses use the HTTP
+/// gateway's skip-certification mode (see `init_http_certification`), so the regular
+/// `icp0.io` domain works and the `raw` domain is not needed.
+///
+/// Routes (GET / HEAD):
+/// - `/shelf/{shelf_id}`: the shelf with its items in display order
+/// - `/user/{principal}/shelves`: the user's shelves in profile order (`offset`, `limit`)
+/// - `/tag/{tag}`: shelves with the tag, newest first (`cursor`, `limit`)
+/// - `/oembed?url={page}`: oEmbed discovery endpoint for shelf, profile and tag pages
+///
+/// Add `format=oembed` (with optional `maxwidth` / `maxheight`) to any route for an oEmbed
+/// `rich` response instead of JSON. Responses carry cache headers and allow any origin.
+#[ic_cdk::query]
+pub fn http_request(req: HttpRequest) -> HttpResponse {
+    let method = req.method.to_uppercase();
+    if method == "OPTIONS" {
+        let mut headers = cors_headers();
+        headers.push(("Access-Control-Max-Age".to_string(), "86400".to_string()));
+        return with_skip_certification(HttpResponse { status_code: 204, headers, body: Vec::new() });
+    }
+    if method != "GET" && method != "HEAD" {
+        return with_skip_certification(error_response(405, "Method not allowed"));
+    }
+
+    let url = ParsedUrl::parse(&req.url);
+    let format = OutputFormat::from_url(&url);
+    let segments: Vec<&str> = url.segments.iter().map(|s| s.as_str()).collect();
+    let mut response = match segments.as_slice() {
+        ["shelf", shelf_id] => serve_shelf(shelf_id, &format),
+        ["user", user, "shelves"] => serve_user_shelves(user, &url, &format),
+        ["tag", tag] => serve_tag_shelves(tag, &url, &format),
+        ["oembed"] => serve_oembed_for_page(&url, &format),
+        _ => error_response(404, "Not found"),
+    };
+
+    if method == "HEAD" {
+        response.body.clear();
+    }
+    with_skip_certification(response)
+}
@@ -0,0 +1,77 @@
+use ic_cdk;
+use std::ops::Bound;
+
+use crate::storage::{
+    NOTIFICATIONS, MUTED_NOTIFICATION_KINDS,
+    Notification, NotificationKey, NotificationKind, recipient_key_range,
+};
+use crate::guard::not_anon;
+use super::follows::{
+    CursorPaginationInput, CursorPaginatedResult,
+    QueryResult,
+};
+
+/// Get the caller's notifications (newest first - Paginated).
+/// The cursor is the `notification_id` of the last notification returned on the previous page.
+#[ic_cdk::query(guard = "not_anon")]
+pub fn get_notifications(
+    pagination: CursorPaginationInput<u64>
+) -> QueryResult<CursorPaginatedResult<Notification, u64>> {
+    let caller = ic_cdk::caller();
+    let limit = pagination.get_limit();
+    let limit_plus_one = limit + 1;
+
+    let (start_key, _) = recipient_key_range(caller);
+    let end_bound = match pagination.cursor {
+        Some(cursor_id) => Bound::Excluded(NotificationKey { recipient: caller, notification_id: cursor_id }),
+        None => Bound::Included(NotificationKey { recipient: caller, notification_id: u64::MAX }),
+    };
+
+    let mut items: Vec<Notification> = NOTIFICATIONS.with(|notifications_ref| {
+        notifications_ref.borrow()
+            .range((Bound::Included(start_key), end_bound))
+            .rev()
+            .take(limit_plus_one)
+            .map(|(_, notification)| notification)
+            .collect()
+    });
+
+    let next_cursor = if items.len() == limit_plus_one {
+        items.pop();
+        items.last().map(|notification| notification.notification_id)
+    } else {
+        None
+    };
+
+    Ok(CursorPaginatedResult {
+        items,
+        next_cursor,
+        limit: pagination.limit,
+    })
+}
+
+/// Get the number of unread notifications for the caller.
+#[ic_cdk::query(guard = "not_anon")]
+pub fn get_unread_notification_count() -> u64 {
+    let caller = ic_cdk::caller();
+    let (start_key, end_key) = recipient_key_range(caller);
+
+    NOTIFICATIONS.with(|notifications_ref| {
+        notifications_ref.borrow()
+            .range(start_key..=end_key)
+            .filter(|(_, notification)| !notification.read)
+            .count() as u64
+    })
+}
+
+/// Get the notification types the caller has muted.
+#[ic_cdk::query(guard = "not_anon")]
+pub fn get_muted_notification_kinds() -> Vec<NotificationKind> {
+    let caller = ic_cdk::caller();
+    MUTED_NOTIFICATION_KINDS.with(|muted_ref| {
+        muted_ref.borrow()
+            .get(&caller)
+            .map(|kinds| kinds.0.into_iter().collect())
+            .unwrap_or_default()
+    })
+}
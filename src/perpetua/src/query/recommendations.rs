@@ -0,0 +1,236 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk;
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::storage::{
+    SHELF_DATA, USER_SHELVES, FOLLOWED_USERS, FOLLOWED_TAGS, NFT_SHELVES,
+    TAG_COOCCURRENCE, TAG_SHELF_CREATION_TIMELINE_INDEX,
+    TagCooccurrenceKey, TagShelfCreationTimelineKey,
+    ShelfId, NormalizedTag, is_tag_blocked,
+};
+use crate::utils::id_conversion;
+use crate::guard::not_anon;
+use super::follows::{
+    CursorPaginationInput, CursorPaginatedResult,
+    QueryResult,
+    ShelfPublic,
+};
+
+// --- Scoring weights ---
+const INTEREST_TAG_WEIGHT: u64 = 3;   // Tag on a shelf the caller owns, or a followed tag
+const RELATED_TAG_WEIGHT: u64 = 1;    // Tag that co-occurs with one of the caller's interest tags
+const SHARED_ITEM_WEIGHT: u64 = 4;    // Shelf sharing an NFT / tracked item with one of the caller's shelves
+const FOLLOW_OF_FOLLOW_WEIGHT: u64 = 2; // Shelf owned by someone the caller's followees follow
+
+// --- Fan-out limits (bound instruction usage per query) ---
+const MAX_SEED_TAGS: usize = 20;
+const MAX_RELATED_TAGS_PER_TAG: usize = 5;
+const MAX_COOCCURRENCE_SCAN_PER_TAG: usize = 200;
+const MAX_SHELVES_PER_TAG: usize = 50;
+const MAX_OWN_SHELVES_SCANNED: usize = 50;
+const MAX_FOLLOWEES_SCANNED: usize = 50;
+const MAX_SHELVES_PER_USER: usize = 10;
+const MAX_ITEMS_SCANNED: usize = 200;       // Items across all of the caller's scanned shelves
+const MAX_SHELVES_PER_ITEM: usize = 50;
+const MAX_FOLLOWS_PER_FOLLOWEE: usize = 50;
+const MAX_CANDIDATES: usize = 500;          // Shelves scored per query, later sources only add to these
+
+/// Cursor for `get_recommended_shelves`: the rank key of the last shelf returned.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RecommendationCursor {
+    pub reversed_score: u64,
+    pub shelf_id: ShelfId,
+}
+
+/// Adds `weight` to every tag in `tags`.
+fn add_tag_weights(tag_weights: &mut BTreeMap<NormalizedTag, u64>, tags: impl IntoIterator<Item = NormalizedTag>, weight: u64) {
+    for tag in tags {
+        *tag_weights.entry(tag).or_insert(0) += weight;
+    }
+}
+
+/// Adds `weight` to a shelf's score, admitting new shelves only while there is room for them.
+fn add_score(scores: &mut BTreeMap<ShelfId, u64>, shelf_id: ShelfId, weight: u64) {
+    if let Some(score) = scores.get_mut(&shelf_id) {
+        *score += weight;
+    } else if scores.len() < MAX_CANDIDATES {
+        scores.insert(shelf_id, weight);
+    }
+}
+
+/// Most frequently co-occurring tags for `tag`.
+fn top_related_tags(tag: &NormalizedTag) -> Vec<NormalizedTag> {
+    let mut related: Vec<(NormalizedTag, u64)> = TAG_COOCCURRENCE.with(|map_ref| {
+        let start_key = TagCooccurrenceKey { tag: tag.clone(), related_tag: String::new() };
+        map_ref.borrow()
+            .range(start_key..)
+            .take_while(|(key, _)| &key.tag == tag)
+            .take(MAX_COOCCURRENCE_SCAN_PER_TAG)
+            .map(|(key, count)| (key.related_tag, count))
+            .collect()
+    });
+    related.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    related.into_iter().take(MAX_RELATED_TAGS_PER_TAG).map(|(related_tag, _)| related_tag).collect()
+}
+
+/// Newest shelves carrying `tag`.
+fn newest_shelves_for_tag(tag: &NormalizedTag) -> Vec<ShelfId> {
+    TAG_SHELF_CREATION_TIMELINE_INDEX.with(|map_ref| {
+        let start_key = TagShelfCreationTimelineKey { tag: tag.clone(), reversed_created_at: 0, shelf_id: String::new() };
+        map_ref.borrow()
+            .range(start_key..)
+            .take_while(|(key, _)| &key.tag == tag)
+            .take(MAX_SHELVES_PER_TAG)
+            .map(|(key, _)| key.shelf_id)
+            .collect()
+    })
+}
+
+/// Newest shelves owned by `user`.
+fn newest_shelves_for_user(user: &Principal, max: usize) -> Vec<ShelfId> {
+    USER_SHELVES.with(|map_ref| {
+        map_ref.borrow()
+            .get(user)
+            .map(|shelves| shelves.0.iter().rev().take(max).map(|(_, id)| id.clone()).collect())
+            .unwrap_or_default()
+    })
+}
+
+/// Scores candidate shelves for `caller` from tag co-occurrence, shared items and follows-of-follows.
+///
+/// Every source is read through a fixed fan-out limit and at most `MAX_CANDIDATES` shelves are
+/// scored, so the cost of a query doesn't grow with the size of the index.
+fn score_candidates(caller: &Principal) -> BTreeMap<ShelfId, u64> {
+    let mut scores: BTreeMap<ShelfId, u64> = BTreeMap::new();
+
+    let own_shelf_ids = newest_shelves_for_user(caller, MAX_OWN_SHELVES_SCANNED);
+    let followed_users = FOLLOWED_USERS.with(|fu| fu.borrow().get(caller).unwrap_or_default());
+    let followed_tags = FOLLOWED_TAGS.with(|ft| ft.borrow().get(caller).unwrap_or_default());
+
+    // --- 1. Tag interests and their co-occurring tags ---
+    let mut interest_tags: BTreeMap<NormalizedTag, u64> = BTreeMap::new();
+    add_tag_weights(&mut interest_tags, followed_tags.0.iter().cloned(), INTEREST_TAG_WEIGHT);
+
+    let mut items_scanned = 0;
+    SHELF_DATA.with(|sds_ref| {
+        let sds = sds_ref.borrow();
+        for shelf_id in &own_shelf_ids {
+            if let Some(shelf_data) = sds.get(shelf_id) {
+                add_tag_weights(&mut interest_tags, shelf_data.metadata.tags, INTEREST_TAG_WEIGHT);
+
+                // --- 2. Shelves sharing items with the caller's shelves ---
+                for item in shelf_data.content.items.values() {
+                    if items_scanned >= MAX_ITEMS_SCANNED {
+                        break;
+                    }
+                    items_scanned += 1;
+                    let Some(appearance_key) = id_conversion::get_appearance_key_for_storage(&item.content) else {
+                        continue;
+                    };
+                    if let Some(shelves) = NFT_SHELVES.with(|map_ref| map_ref.borrow().get(&appearance_key)) {
+                        for other_shelf_id in shelves.0.into_iter().take(MAX_SHELVES_PER_ITEM) {
+                            add_score(&mut scores, other_shelf_id, SHARED_ITEM_WEIGHT);
+                        }
+                    }
+                }
+            }
+        }
+    });
+
+    let mut seed_tags: Vec<(NormalizedTag, u64)> = interest_tags.into_iter().collect();
+    seed_tags.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    seed_tags.truncate(MAX_SEED_TAGS);
+
+    let mut tag_weights: BTreeMap<NormalizedTag, u64> = BTreeMap::new();
+    for (tag, weight) in &seed_tags {
+        *tag_weights.entry(tag.clone()).or_insert(0) += weight;
+        add_tag_weights(&mut tag_weights, top_related_tags(tag), RELATED_TAG_WEIGHT);
+    }
+
+    for (tag, weight) in &tag_weights {
+        if is_tag_blocked(tag) {
+            continue;
+        }
+        for shelf_id in newest_shelves_for_tag(tag) {
+            add_score(&mut scores, shelf_id, *weight);
+        }
+    }
+
+    // --- 3. Follows-of-follows ---
+    let mut second_degree_users: BTreeSet<Principal> = BTreeSet::new();
+    FOLLOWED_USERS.with(|fu_ref| {
+        let fu = fu_ref.borrow();
+        for followee in followed_users.0.iter().take(MAX_FOLLOWEES_SCANNED) {
+            if let Some(their_follows) = fu.get(followee) {
+                second_degree_users.extend(
+                    their_follows.0.into_iter()
+                        .take(MAX_FOLLOWS_PER_FOLLOWEE)
+                        .filter(|p| p != caller && !followed_users.0.contains(p))
+                );
+            }
+        }
+    });
+
+    for user in second_degree_users.iter().take(MAX_FOLLOWEES_SCANNED) {
+        for shelf_id in newest_shelves_for_user(user, MAX_SHELVES_PER_USER) {
+            add_score(&mut scores, shelf_id, FOLLOW_OF_FOLLOW_WEIGHT);
+        }
+    }
+
+    // --- 4. Drop shelves the caller already sees: their own and those of followed users ---
+    SHELF_DATA.with(|sds_ref| {
+        let sds = sds_ref.borrow();
+        scores.retain(|shelf_id, _| match sds.get(shelf_id) {
+            Some(shelf_data) => {
+                shelf_data.metadata.owner != *caller && !followed_users.0.contains(&shelf_data.metadata.owner)
+            }
+            None => false,
+        });
+    });
+
+    scores
+}
+
+/// Get recommended shelves for the caller (highest score first - Paginated).
+///
+/// Candidates come from tags that co-occur with the caller's own and followed tags,
+/// shelves sharing NFTs or other tracked items with the caller's shelves, and shelves
+/// of users followed by the people the caller follows. The caller's own shelves and
+/// those of users they already follow are excluded (see `get_storyline_feed`).
+#[ic_cdk::query(guard = "not_anon")]
+pub fn get_recommended_shelves(
+    pagination: CursorPaginationInput<RecommendationCursor>
+) -> QueryResult<CursorPaginatedResult<ShelfPublic, RecommendationCursor>> {
+    let caller = ic_cdk::caller();
+    let limit = pagination.get_limit();
+    let limit_plus_one = limit + 1;
+
+    let mut ranked: Vec<RecommendationCursor> = score_candidates(&caller)
+        .into_iter()
+        .map(|(shelf_id, score)| RecommendationCursor { reversed_score: u64::MAX - score, shelf_id })
+        .filter(|key| pagination.cursor.as_ref().is_none_or(|cursor| key > cursor))
+        .collect();
+    ranked.sort();
+    ranked.truncate(limit_plus_one);
+
+    let next_cursor = if ranked.len() == limit_plus_one {
+        ranked.pop();
+        ranked.last().cloned()
+    } else {
+        None
+    };
+
+    let items: Vec<ShelfPublic> = SHELF_DATA.with(|sds_ref| {
+        let sds = sds_ref.borrow();
+        ranked.iter()
+            .filter_map(|key| sds.get(&key.shelf_id))
+            .map(|shelf_data| ShelfPublic::from_parts(&shelf_data.metadata, &shelf_data.content))
+            .collect()
+    });
+
+    Ok(CursorPaginatedResult {
+        items,
+        next_cursor,
+        limit: pagination.limit,
+    })
+}
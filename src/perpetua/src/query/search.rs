@@ -0,0 +1,88 @@
+use candid::Nat;
+use ic_cdk;
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use crate::storage::{SEARCH_INDEX, SHELF_DATA, SearchTermKey, ShelfId, tokenize_for_search};
+use super::follows::{
+    OffsetPaginationInput, OffsetPaginatedResult,
+    QueryResult,
+    ShelfPublic,
+};
+
+// --- Constants ---
+const MAX_QUERY_TERMS: usize = 8;
+const MAX_MATCHES_PER_TERM: usize = 5_000; // Bounds instruction usage for very common terms
+
+/// Full-text search over shelf titles, descriptions and markdown items (Paginated).
+///
+/// The query is tokenised the same way shelves are indexed (lowercased, stop words removed).
+/// Shelves matching more query terms rank first, then by weighted term frequency
+/// (title > description > markdown). Shelves that no longer exist are skipped.
+#[ic_cdk::query]
+pub fn search_shelves(
+    query: String,
+    pagination: OffsetPaginationInput
+) -> QueryResult<OffsetPaginatedResult<ShelfPublic>> {
+    let limit = pagination.get_limit();
+    let offset = pagination.get_offset();
+
+    let mut query_terms: Vec<String> = Vec::new();
+    for term in tokenize_for_search(&query) {
+        if !query_terms.contains(&term) {
+            query_terms.push(term);
+        }
+        if query_terms.len() >= MAX_QUERY_TERMS {
+            break;
+        }
+    }
+
+    if query_terms.is_empty() {
+        return Ok(OffsetPaginatedResult { items: Vec::new(), total_count: Nat::from(0u64), limit: limit as u64, offset: Nat::from(offset) });
+    }
+
+    // shelf_id -> (matched term count, summed weight)
+    let mut scores: BTreeMap<ShelfId, (u32, u64)> = BTreeMap::new();
+
+    SEARCH_INDEX.with(|index_ref| {
+        let index = index_ref.borrow();
+        for term in &query_terms {
+            let start_key = SearchTermKey { term: term.clone(), shelf_id: String::new() };
+            for (key, weight) in index.range((Bound::Included(start_key), Bound::Unbounded)).take(MAX_MATCHES_PER_TERM) {
+                if &key.term != term {
+                    break;
+                }
+                let entry = scores.entry(key.shelf_id).or_insert((0, 0));
+                entry.0 += 1;
+                entry.1 += weight as u64;
+            }
+        }
+    });
+
+    let mut ranked: Vec<(ShelfId, (u32, u64))> = scores.into_iter().collect();
+    ranked.sort_by(|a, b| {
+        b.1.0.cmp(&a.1.0)
+            .then_with(|| b.1.1.cmp(&a.1.1))
+            .then_with(|| a.0.cmp(&b.0))
+    });
+
+    let visible_ids: Vec<ShelfId> = SHELF_DATA.with(|sds_map_ref| {
+        let sds_map = sds_map_ref.borrow();
+        ranked.into_iter()
+            .map(|(shelf_id, _)| shelf_id)
+            .filter(|shelf_id| sds_map.contains_key(shelf_id))
+            .collect()
+    });
+
+    let total_count = visible_ids.len();
+    let items: Vec<ShelfPublic> = SHELF_DATA.with(|sds_map_ref| {
+        let sds_map = sds_map_ref.borrow();
+        visible_ids.iter()
+            .skip(offset)
+            .take(limit)
+            .filter_map(|id| sds_map.get(id).map(|sd| ShelfPublic::from_parts(&sd.metadata, &sd.content)))
+            .collect()
+    });
+
+    Ok(OffsetPaginatedResult { items, total_count: Nat::from(total_count), limit: limit as u64, offset: Nat::from(offset) })
+}
@@ -11,7 +11,7 @@ use rand_core::SeedableRng;
 use crate::storage::{
     SHELF_DATA, ShelfData, // Replaced SHELVES, SHELF_METADATA
     NFT_SHELVES, 
-    Item, ShelfId, ItemId, 
+    Item, ItemContent, ShelfId, ItemId, 
     USER_SHELVES, GLOBAL_TIMELINE, USER_PROFILE_ORDER, TimestampedShelves, // Added TimestampedShelves
     FOLLOWED_USERS, FOLLOWED_TAGS, PrincipalSet, NormalizedTagSet, // Added PrincipalSet, NormalizedTagSet
     UserProfileOrder, 
@@ -19,11 +19,13 @@ use crate::storage::{
     TagShelfCreationTimelineKey, TAG_SHELF_CREATION_TIMELINE_INDEX, 
     ShelfMetadata, ShelfContent, // Still useful for type hints if ShelfData is deconstructed
     StringVec, // Ensure StringVec is imported if not already
+    SHELF_FORKS, ShelfForkKey,
+    visible_item_order, has_shelf_access,
 };
 // Import necessary types from types module
 // use crate::types::TagShelfAssociationKey; // Comment out, no longer primary key for this query
 // Import utils
-use crate::utils::normalize_tag;
+use crate::storage::resolve_tag;
 use crate::utils::id_conversion;
 // Import types/functions from the parent query module
 use super::follows::{ 
@@ -42,9 +44,8 @@ use crate::guard::not_anon;
 #[derive(CandidType, Debug)]
 pub struct ShelfPositionMetrics {
     pub item_count: usize,
-    pub min_gap: f64,
-    pub avg_gap: f64,
-    pub max_gap: f64,
+    pub max_rank_length: usize,
+    pub avg_rank_length: f64,
 }
 
 #[derive(CandidType, Deserialize)]
@@ -56,6 +57,8 @@ pub struct NFTAppearancesResult {
 
 // --- Moved Query Functions ---
 
+/// Get a shelf by ID.
+/// Callers without access to a gated shelf get a preview with only the first items (`is_preview`).
 #[ic_cdk::query]
 pub fn get_shelf(shelf_id: ShelfId) -> QueryResult<ShelfPublic> {
     SHELF_DATA.with(|sds_map_ref| {
@@ -65,6 +68,58 @@ pub fn get_shelf(shelf_id: ShelfId) -> QueryResult<ShelfPublic> {
     })
 }
 
+/// Get the forks of a shelf (Paginated, ordered by fork shelf ID).
+/// The cursor is the ID of the last fork returned on the previous page.
+#[ic_cdk::query]
+pub fn get_shelf_forks(
+    shelf_id: ShelfId,
+    pagination: CursorPaginationInput<ShelfId>
+) -> QueryResult<CursorPaginatedResult<ShelfPublic, ShelfId>> {
+    let limit = pagination.get_limit();
+    let limit_plus_one = limit + 1;
+
+    if !SHELF_DATA.with(|sds_map| sds_map.borrow().contains_key(&shelf_id)) {
+        return Err(QueryError::ShelfNotFound);
+    }
+
+    let start_bound = match pagination.cursor {
+        Some(cursor_id) => Bound::Excluded(ShelfForkKey { source_shelf_id: shelf_id.clone(), fork_shelf_id: cursor_id }),
+        None => Bound::Included(ShelfForkKey { source_shelf_id: shelf_id.clone(), fork_shelf_id: String::new() }),
+    };
+
+    let mut fork_ids: Vec<ShelfId> = SHELF_FORKS.with(|forks_ref| {
+        forks_ref.borrow()
+            .range((start_bound, Bound::Unbounded))
+            .take_while(|(key, _)| key.source_shelf_id == shelf_id)
+            .take(limit_plus_one)
+            .map(|(key, _)| key.fork_shelf_id)
+            .collect()
+    });
+
+    let next_cursor = if fork_ids.len() == limit_plus_one {
+        fork_ids.pop();
+        fork_ids.last().cloned()
+    } else {
+        None
+    };
+
+    let items: Vec<ShelfPublic> = SHELF_DATA.with(|sds_map_ref| {
+        let sds_map = sds_map_ref.borrow();
+        fork_ids.iter()
+            .filter_map(|id| sds_map.get(id))
+            .map(|shelf_data| ShelfPublic::from_parts(&shelf_data.metadata, &shelf_data.content))
+            .collect()
+    });
+
+    Ok(CursorPaginatedResult {
+        items,
+        next_cursor,
+        limit: pagination.limit,
+    })
+}
+
+/// Get a shelf's items in display order (Paginated).
+/// Callers without access to a gated shelf only get the preview items.
 #[ic_cdk::query]
 pub fn get_shelf_items(
     shelf_id: ShelfId,
@@ -75,7 +130,7 @@ pub fn get_shelf_items(
     let shelf_data = SHELF_DATA.with(|sds_map| sds_map.borrow().get(&shelf_id).map(|sd_ref| sd_ref.clone()))
         .ok_or(QueryError::ShelfNotFound)?;
 
-    let ordered_ids: Vec<u32> = shelf_data.content.item_positions.iter_keys_ordered().cloned().collect();
+    let (ordered_ids, _) = visible_item_order(&shelf_data.metadata, &shelf_data.content, ic_cdk::caller());
     let total_items = ordered_ids.len();
 
     if total_items == 0 {
@@ -105,31 +160,21 @@ pub fn get_shelf_items(
     })
 }
 
-/// Get optimization metrics for a shelf's positions
-/// This helps frontend clients identify when a shelf needs rebalancing
+/// Get metrics for a shelf's rank keys
+/// Keys grow longer when items are repeatedly inserted at the same spot; ordering never needs a rebalance.
 #[ic_cdk::query]
 pub fn get_shelf_position_metrics(shelf_id: ShelfId) -> Result<ShelfPositionMetrics, String> {
     SHELF_DATA.with(|sds_map_ref| {
         let sds_map = sds_map_ref.borrow();
         if let Some(shelf_data) = sds_map.get(&shelf_id) { // No clone needed if only accessing .content
             let position_count = shelf_data.content.item_positions.len();
-            if position_count < 2 {
-                return Ok(ShelfPositionMetrics { item_count: position_count, min_gap: 0.0, avg_gap: 0.0, max_gap: 0.0 });
-            }
-            let mut positions: Vec<f64> = shelf_data.content.item_positions.iter_ordered().map(|(_, pos)| pos).collect();
-            positions.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
-            let mut min_gap = f64::MAX;
-            let mut max_gap = 0.0;
-            let mut sum_gap = 0.0;
-            for i in 1..positions.len() {
-                let gap = positions[i] - positions[i-1];
-                if gap < 0.0 { continue; }
-                min_gap = f64::min(min_gap, gap);
-                max_gap = f64::max(max_gap, gap);
-                sum_gap += gap;
+            if position_count == 0 {
+                return Ok(ShelfPositionMetrics { item_count: 0, max_rank_length: 0, avg_rank_length: 0.0 });
             }
-            let avg_gap = if positions.len() > 1 { sum_gap / (positions.len() - 1) as f64 } else { 0.0 };
-            Ok(ShelfPositionMetrics { item_count: position_count, min_gap, avg_gap, max_gap })
+            let rank_lengths: Vec<usize> = shelf_data.content.item_positions.iter_ordered().map(|(_, rank)| rank.len()).collect();
+            let max_rank_length = rank_lengths.iter().copied().max().unwrap_or(0);
+            let avg_rank_length = rank_lengths.iter().sum::<usize>() as f64 / position_count as f64;
+            Ok(ShelfPositionMetrics { item_count: position_count, max_rank_length, avg_rank_length })
         } else {
             Err(format!("Shelf with ID '{}' not found", shelf_id))
         }
@@ -153,8 +198,7 @@ pub fn get_user_shelves(
                 let has_custom_order = user_profile_opt.as_ref().map_or(false, |order| order.is_customized);
                 let combined_ids: Vec<ShelfId> = if has_custom_order {
                     let user_order = user_profile_opt.unwrap();
-                    let ordered_positions: Vec<(ShelfId, f64)> = user_order.shelf_positions.get_ordered_entries();
-                    let ordered_ids: Vec<ShelfId> = ordered_positions.into_iter().map(|(id, _)| id).collect();
+                    let ordered_ids: Vec<ShelfId> = user_order.shelf_positions.get_ordered_keys();
                     let ordered_id_set: std::collections::HashSet<ShelfId> = ordered_ids.iter().cloned().collect();
                     let mut timestamp_ordered_ids: Vec<(u64, ShelfId)> = timestamped.0.iter().filter(|(_, id)| !ordered_id_set.contains(id)).cloned().collect();
                     timestamp_ordered_ids.sort_by(|a, b| b.0.cmp(&a.0));
@@ -506,7 +550,7 @@ pub fn get_shelves_by_tag(
     tag: String,
     pagination: CursorPaginationInput<TagShelfCreationTimelineKey>
 ) -> QueryResult<CursorPaginatedResult<ShelfPublic, TagShelfCreationTimelineKey>> {
-    let normalized_tag = normalize_tag(&tag);
+    let normalized_tag = resolve_tag(&tag);
     if normalized_tag.is_empty() {
         return Ok(CursorPaginatedResult {
             items: Vec::new(),
@@ -585,7 +629,7 @@ pub fn get_shelves_by_tag(
 /// Returns an empty list if the tag is not found or no public shelves are associated.
 #[ic_cdk::query]
 pub fn get_public_shelves_by_tag(tag: String) -> QueryResult<Vec<ShelfPublic>> {
-    let normalized_tag = normalize_tag(&tag);
+    let normalized_tag = resolve_tag(&tag);
     if normalized_tag.is_empty() {
         return Ok(Vec::new());
     }
@@ -648,7 +692,7 @@ pub fn get_user_publicly_editable_shelves(
 
                 let ordered_shelf_ids: Vec<ShelfId> = if has_custom_order {
                     let user_order = user_profile_opt.unwrap();
-                    let custom_ordered_ids: Vec<ShelfId> = user_order.shelf_positions.get_ordered_entries().into_iter().map(|(id, _)| id).collect();
+                    let custom_ordered_ids: Vec<ShelfId> = user_order.shelf_positions.get_ordered_keys();
                     let custom_ordered_id_set: std::collections::HashSet<ShelfId> = custom_ordered_ids.iter().cloned().collect();
                     
                     let mut timestamp_fallback_ids: Vec<(u64, ShelfId)> = timestamped_shelf_ids.0.iter().filter(|(_, id)| !custom_ordered_id_set.contains(id)).cloned().collect();
@@ -678,6 +722,23 @@ pub fn get_user_publicly_editable_shelves(
     })
 }
 
+/// Keeps the shelves whose full contents `principal` can see, so appearance lookups
+/// don't reveal what a gated shelf holds.
+fn accessible_shelves(shelf_ids: Vec<ShelfId>, principal: Principal) -> Vec<ShelfId> {
+    SHELF_DATA.with(|sds| {
+        let sds = sds.borrow();
+        shelf_ids.into_iter()
+            .filter(|shelf_id| {
+                sds.get(shelf_id).is_some_and(|shelf_data| has_shelf_access(&shelf_data.metadata, principal))
+            })
+            .collect()
+    })
+}
+
+/// Get the shelves an NFT appears in.
+/// Also accepts the raw NFT_SHELVES key of other tracked items (Arweave transaction ID, link URL,
+/// or `<collection>:<token_id>` for external ICRC-7 tokens); see `get_item_shelf_appearances`.
+/// Gated shelves are only listed for callers who have unlocked them.
 #[ic_cdk::query]
 pub fn get_nft_shelf_appearances(user_provided_id: String) -> Result<NFTAppearancesResult, String> {
     ic_cdk::println!("[get_nft_shelf_appearances] Received user_provided_id: {}", user_provided_id);
@@ -687,7 +748,7 @@ pub fn get_nft_shelf_appearances(user_provided_id: String) -> Result<NFTAppearan
     NFT_SHELVES.with(|nft_shelves_map_ref| {
         match nft_shelves_map_ref.borrow().get(&key_to_query) {
             Some(string_vec) => Ok(NFTAppearancesResult {
-                shelves: string_vec.0.clone(),
+                shelves: accessible_shelves(string_vec.0, ic_cdk::caller()),
                 original_id_used: key_to_query.clone(),
             }),
             None => Ok(NFTAppearancesResult {
@@ -696,4 +757,25 @@ pub fn get_nft_shelf_appearances(user_provided_id: String) -> Result<NFTAppearan
             }),
         }
     })
-}
\ No newline at end of file
+}
+
+/// Get the shelves an item's content appears in.
+/// Supports NFTs, Arweave transactions, links and ICRC-7 tokens from other collections.
+/// Nested shelves track this in their own `appears_in` list instead.
+/// Gated shelves are only listed for callers who have unlocked them.
+#[ic_cdk::query]
+pub fn get_item_shelf_appearances(content: ItemContent) -> Result<NFTAppearancesResult, String> {
+    let key_to_query = id_conversion::get_appearance_key_for_storage(&content)
+        .ok_or_else(|| "Appearances are not tracked for this item type".to_string())?;
+
+    NFT_SHELVES.with(|nft_shelves_map_ref| {
+        let shelves = nft_shelves_map_ref.borrow()
+            .get(&key_to_query)
+            .map(|string_vec| string_vec.0)
+            .unwrap_or_default();
+        Ok(NFTAppearancesResult {
+            shelves: accessible_shelves(shelves, ic_cdk::caller()),
+            original_id_used: key_to_query.clone(),
+        })
+    })
+}
@@ -0,0 +1,117 @@
+use candid::{CandidType, Deserialize};
+use ic_cdk;
+
+use crate::storage::{
+    SHELF_DATA, SHELF_STATS, ITEM_OPEN_COUNTS, MOST_VIEWED_SHELVES,
+    ShelfId, ItemId, ShelfStatsKey, ShelfStatsBucket, ShelfItemKey, StatsGranularity,
+    TrendingScoreKey, TrendingWindow,
+};
+use crate::guard::not_anon;
+use super::follows::{
+    CursorPaginationInput, CursorPaginatedResult,
+    QueryError, QueryResult,
+    ShelfPublic,
+};
+use super::trending::read_trending_page;
+
+const MAX_TOP_ITEMS: usize = 10;
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct StatsRange {
+    pub granularity: StatsGranularity,
+    pub start_time: u64, // Inclusive, nanoseconds
+    pub end_time: u64,   // Inclusive, nanoseconds
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ItemOpenCount {
+    pub item_id: ItemId,
+    pub opens: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ShelfStats {
+    pub shelf_id: ShelfId,
+    pub granularity: StatsGranularity,
+    pub buckets: Vec<ShelfStatsBucket>, // Oldest first; buckets without activity are omitted
+    pub total_views: u64,               // Over the requested range
+    pub total_item_opens: u64,          // Over the requested range
+    pub top_items: Vec<ItemOpenCount>,  // All time, most opened first
+}
+
+/// Get view and item-open statistics for a shelf (shelf owner only).
+///
+/// Hourly buckets are kept for a week and daily buckets for a year. Unique visitors are
+/// distinct within each bucket, so they are not summed across buckets.
+#[ic_cdk::query(guard = "not_anon")]
+pub fn get_shelf_stats(shelf_id: ShelfId, range: StatsRange) -> QueryResult<ShelfStats> {
+    let caller = ic_cdk::caller();
+
+    let shelf_data = SHELF_DATA.with(|sds| sds.borrow().get(&shelf_id))
+        .ok_or(QueryError::ShelfNotFound)?;
+    if shelf_data.metadata.owner != caller {
+        return Err(QueryError::Unauthorized);
+    }
+
+    let granularity = range.granularity;
+    if range.start_time > range.end_time {
+        return Err(QueryError::InvalidTimeRange);
+    }
+    let first_bucket = granularity.bucket_start(range.start_time);
+    let last_bucket = granularity.bucket_start(range.end_time);
+    if (last_bucket - first_bucket) / granularity.bucket_ns() >= granularity.retention_buckets() {
+        return Err(QueryError::InvalidTimeRange);
+    }
+
+    let start_key = ShelfStatsKey { shelf_id: shelf_id.clone(), granularity, bucket_start: first_bucket };
+    let end_key = ShelfStatsKey { shelf_id: shelf_id.clone(), granularity, bucket_start: last_bucket };
+    let buckets: Vec<ShelfStatsBucket> = SHELF_STATS.with(|stats| {
+        stats.borrow().range(start_key..=end_key).map(|(_, bucket)| bucket).collect()
+    });
+
+    let mut top_items: Vec<ItemOpenCount> = ITEM_OPEN_COUNTS.with(|counts| {
+        let start = ShelfItemKey { shelf_id: shelf_id.clone(), item_id: 0 };
+        let end = ShelfItemKey { shelf_id: shelf_id.clone(), item_id: ItemId::MAX };
+        counts.borrow().range(start..=end)
+            .filter(|(key, _)| shelf_data.content.items.contains_key(&key.item_id)) // Skip removed items
+            .map(|(key, opens)| ItemOpenCount { item_id: key.item_id, opens })
+            .collect()
+    });
+    top_items.sort_by(|a, b| b.opens.cmp(&a.opens).then_with(|| a.item_id.cmp(&b.item_id)));
+    top_items.truncate(MAX_TOP_ITEMS);
+
+    Ok(ShelfStats {
+        shelf_id,
+        granularity,
+        total_views: buckets.iter().map(|b| b.views).sum(),
+        total_item_opens: buckets.iter().map(|b| b.item_opens).sum(),
+        buckets,
+        top_items,
+    })
+}
+
+/// Get the most viewed shelves for a time window (most views first - Paginated).
+///
+/// Rankings are recomputed periodically alongside trending scores; shelves deleted since are skipped.
+#[ic_cdk::query]
+pub fn get_most_viewed_shelves(
+    window: TrendingWindow,
+    pagination: CursorPaginationInput<TrendingScoreKey>
+) -> QueryResult<CursorPaginatedResult<ShelfPublic, TrendingScoreKey>> {
+    let limit = pagination.limit;
+    let (keys, next_cursor) = read_trending_page(&MOST_VIEWED_SHELVES, window, pagination)?;
+
+    let items: Vec<ShelfPublic> = SHELF_DATA.with(|sds_rc| {
+        let sds_map = sds_rc.borrow();
+        keys.iter()
+            .filter_map(|key| sds_map.get(&key.id))
+            .map(|shelf_data| ShelfPublic::from_parts(&shelf_data.metadata, &shelf_data.content))
+            .collect()
+    });
+
+    Ok(CursorPaginatedResult {
+        items,
+        next_cursor,
+        limit,
+    })
+}
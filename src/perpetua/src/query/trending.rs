@@ -0,0 +1,99 @@
+use ic_cdk;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+use std::ops::Bound;
+use std::thread::LocalKey;
+
+use crate::storage::{
+    Memory, TRENDING_TAGS, TRENDING_SHELVES, SHELF_DATA,
+    TrendingScoreKey, TrendingWindow, NormalizedTag,
+};
+use super::follows::{
+    CursorPaginationInput, CursorPaginatedResult,
+    QueryError, QueryResult,
+    ShelfPublic,
+};
+
+/// Reads one page of ids (highest score first) from a trending index for the given window.
+/// The cursor is the key of the last item returned on the previous page.
+pub(super) fn read_trending_page(
+    index: &'static LocalKey<RefCell<StableBTreeMap<TrendingScoreKey, (), Memory>>>,
+    window: TrendingWindow,
+    pagination: CursorPaginationInput<TrendingScoreKey>,
+) -> QueryResult<(Vec<TrendingScoreKey>, Option<TrendingScoreKey>)> {
+    let limit = pagination.get_limit();
+    let limit_plus_one = limit + 1;
+
+    let start_bound = match pagination.cursor {
+        Some(cursor_key) => {
+            if cursor_key.window != window {
+                return Err(QueryError::InvalidCursor);
+            }
+            Bound::Excluded(cursor_key)
+        },
+        None => Bound::Included(TrendingScoreKey::window_start(window)),
+    };
+
+    let mut result_keys: Vec<TrendingScoreKey> = index.with(|index_ref| {
+        index_ref.borrow()
+            .range((start_bound, Bound::Unbounded))
+            .take_while(|(key, _)| key.window == window)
+            .take(limit_plus_one)
+            .map(|(key, _)| key)
+            .collect()
+    });
+
+    let next_cursor = if result_keys.len() == limit_plus_one {
+        result_keys.pop();
+        result_keys.last().cloned()
+    } else {
+        None
+    };
+
+    Ok((result_keys, next_cursor))
+}
+
+/// Get trending tags for a time window (highest decayed activity first - Paginated).
+///
+/// Scores weight recent shelf creation, item additions and follows with exponential decay,
+/// and are recomputed periodically rather than on every call.
+#[ic_cdk::query]
+pub fn get_trending_tags(
+    window: TrendingWindow,
+    pagination: CursorPaginationInput<TrendingScoreKey>
+) -> QueryResult<CursorPaginatedResult<NormalizedTag, TrendingScoreKey>> {
+    let limit = pagination.limit;
+    let (keys, next_cursor) = read_trending_page(&TRENDING_TAGS, window, pagination)?;
+
+    Ok(CursorPaginatedResult {
+        items: keys.into_iter().map(|key| key.id).collect(),
+        next_cursor,
+        limit,
+    })
+}
+
+/// Get trending shelves for a time window (highest decayed activity first - Paginated).
+///
+/// Shelves deleted since the last recompute are skipped.
+#[ic_cdk::query]
+pub fn get_trending_shelves(
+    window: TrendingWindow,
+    pagination: CursorPaginationInput<TrendingScoreKey>
+) -> QueryResult<CursorPaginatedResult<ShelfPublic, TrendingScoreKey>> {
+    let limit = pagination.limit;
+    let (keys, next_cursor) = read_trending_page(&TRENDING_SHELVES, window, pagination)?;
+
+    let items: Vec<ShelfPublic> = SHELF_DATA.with(|sds_rc| {
+        let sds_map = sds_rc.borrow();
+        keys.iter()
+            .filter_map(|key| sds_map.get(&key.id))
+            .map(|shelf_data| ShelfPublic::from_parts(&shelf_data.metadata, &shelf_data.content))
+            .collect()
+    });
+
+    Ok(CursorPaginatedResult {
+        items,
+        next_cursor,
+        limit,
+    })
+}
@@ -0,0 +1,157 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Nat, Principal};
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::cell::RefCell; // Required for MAP.with, etc.
+
+// Imports from parent storage module
+use super::{MEMORY_MANAGER, Memory, MemoryId};
+
+// Import common types from sibling module
+use super::common_types::{ShelfId, ItemId};
+
+// Imports from sibling storage modules
+use super::shelf_storage::{ShelfMetadata, ShelfContent};
+
+// --- Constants ---
+pub const PREVIEW_ITEM_COUNT: usize = 3;                        // Items shown to callers without access
+pub const MAX_ACCESS_PRICE_E8S: u64 = 10_000 * 100_000_000;     // 10,000 LBRY
+pub const TOKEN_ACCESS_GRANT_TTL_NS: u64 = 24 * 60 * 60 * 1_000_000_000; // Token holdings are re-checked daily
+
+// --- ShelfAccessPolicy ---
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum TokenGate {
+    AnyTokenInCollection(Principal),                   // Hold any NFT from the ICRC-7 collection
+    SpecificToken { collection: Principal, token_id: Nat }, // Hold this exact token
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ShelfAccessPolicy {
+    #[default]
+    Public,
+    TokenGated(TokenGate),
+    PayPerAccess { price_e8s: u64 }, // One-time LBRY fee paid to the owner through nft_manager
+}
+
+impl ShelfAccessPolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            ShelfAccessPolicy::PayPerAccess { price_e8s } => {
+                if *price_e8s == 0 {
+                    return Err("Access price must be greater than zero".to_string());
+                }
+                if *price_e8s > MAX_ACCESS_PRICE_E8S {
+                    return Err(format!("Access price cannot exceed {} e8s", MAX_ACCESS_PRICE_E8S));
+                }
+                Ok(())
+            }
+            ShelfAccessPolicy::TokenGated(TokenGate::AnyTokenInCollection(collection))
+            | ShelfAccessPolicy::TokenGated(TokenGate::SpecificToken { collection, .. }) => {
+                if *collection == Principal::anonymous() || *collection == Principal::management_canister() {
+                    return Err("Invalid ICRC-7 collection".to_string());
+                }
+                Ok(())
+            }
+            ShelfAccessPolicy::Public => Ok(()),
+        }
+    }
+}
+
+// --- AccessGrant (SHELF_ACCESS_GRANTS value) ---
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum AccessGrantKind {
+    TokenHolder,
+    Paid { price_e8s: u64 },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct AccessGrant {
+    pub kind: AccessGrantKind,
+    pub granted_at: u64,
+    pub expires_at: Option<u64>, // None for paid access, which never expires
+}
+
+impl AccessGrant {
+    pub fn is_active(&self, now: u64) -> bool {
+        self.expires_at.is_none_or(|expires_at| now < expires_at)
+    }
+}
+
+impl Storable for AccessGrant {
+    fn to_bytes(&self) -> Cow<'_, [u8]> { Cow::Owned(Encode!(self).unwrap()) }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self { Decode!(bytes.as_ref(), Self).unwrap() }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// --- ShelfAccessKey (shelf_id, principal) for SHELF_ACCESS_GRANTS ---
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ShelfAccessKey {
+    pub shelf_id: ShelfId,
+    pub principal: Principal,
+}
+
+impl Storable for ShelfAccessKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> { Cow::Owned(Encode!(&self.shelf_id, &self.principal).unwrap()) }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let (shelf_id, principal) = Decode!(bytes.as_ref(), ShelfId, Principal).unwrap();
+        Self { shelf_id, principal }
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+impl PartialOrd for ShelfAccessKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+impl Ord for ShelfAccessKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.shelf_id.cmp(&other.shelf_id)
+            .then_with(|| self.principal.cmp(&other.principal))
+    }
+}
+
+// Memory IDs
+pub(crate) const SHELF_ACCESS_GRANTS_MEM_ID: MemoryId = MemoryId::new(47);
+
+thread_local! {
+    // K: (shelf_id, principal), V: cached unlock for a gated shelf
+    pub static SHELF_ACCESS_GRANTS: RefCell<StableBTreeMap<ShelfAccessKey, AccessGrant, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(SHELF_ACCESS_GRANTS_MEM_ID)))
+    );
+}
+
+/// Returns the caller's unexpired grant for a shelf, if any.
+pub fn get_active_access_grant(shelf_id: &ShelfId, principal: Principal) -> Option<AccessGrant> {
+    let now = ic_cdk::api::time();
+    SHELF_ACCESS_GRANTS.with(|grants| {
+        grants.borrow().get(&ShelfAccessKey { shelf_id: shelf_id.clone(), principal })
+    }).filter(|grant| grant.is_active(now))
+}
+
+/// Whether `principal` can see a shelf's full contents.
+///
+/// Owners and controllers always have access; otherwise gated shelves need an active grant
+/// (paid grants stay valid if the owner later switches the shelf to token gating, and vice versa).
+pub fn has_shelf_access(metadata: &ShelfMetadata, principal: Principal) -> bool {
+    match metadata.access_policy() {
+        ShelfAccessPolicy::Public => true,
+        _ => {
+            metadata.owner == principal
+                || ic_cdk::api::is_controller(&principal)
+                || get_active_access_grant(&metadata.shelf_id, principal).is_some()
+        }
+    }
+}
+
+pub fn store_access_grant(shelf_id: &ShelfId, principal: Principal, grant: AccessGrant) {
+    SHELF_ACCESS_GRANTS.with(|grants| {
+        grants.borrow_mut().insert(ShelfAccessKey { shelf_id: shelf_id.clone(), principal }, grant);
+    });
+}
+
+/// Item IDs in display order that `principal` may see, and whether that is only the preview.
+pub fn visible_item_order(metadata: &ShelfMetadata, content: &ShelfContent, principal: Principal) -> (Vec<ItemId>, bool) {
+    if has_shelf_access(metadata, principal) {
+        return (content.item_positions.get_ordered_keys(), false);
+    }
+    let preview = content.item_positions.iter_keys_ordered().take(PREVIEW_ITEM_COUNT).cloned().collect();
+    (preview, true)
+}
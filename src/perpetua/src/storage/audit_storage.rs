@@ -0,0 +1,153 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{storable::Bound, StableBTreeMap, StableCell, Storable};
+use std::borrow::Cow;
+use std::cell::RefCell; // Required for MAP.with, etc.
+
+// Imports from parent storage module
+use super::{MEMORY_MANAGER, Memory, MemoryId};
+
+// Import common types from sibling module
+use super::common_types::{ShelfId, NormalizedTag};
+
+// --- Constants ---
+pub const MAX_AUDIT_FINDINGS: u64 = 1_000; // Oldest findings are evicted beyond this
+
+// --- AuditPhase (what the next audit step scans) ---
+// Each phase walks one map in key order; `after` is the last key processed.
+#[derive(CandidType, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub enum AuditPhase {
+    #[default]
+    Idle,
+    Shelves { after: Option<ShelfId> },        // SHELF_DATA -> timeline, user shelves, tags, NFT_SHELVES, appears_in
+    Timeline { after: Option<u64> },           // GLOBAL_TIMELINE entries without a matching shelf
+    UserShelves { after: Option<Principal> },  // USER_SHELVES entries without a matching shelf
+    Tags { after: Option<NormalizedTag> },     // TAG_METADATA counts, popularity, lexical and co-occurrence indexes, dangling associations
+    NftShelves { after: Option<String> },      // NFT_SHELVES entries for shelves that no longer hold the item
+}
+
+// --- AuditState ---
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct AuditState {
+    pub phase: AuditPhase,
+    pub repair_enabled: bool,      // Opt-in: rebuild derived data from SHELF_DATA when drift is found
+    pub pass_started_at: u64,
+    pub last_completed_at: u64,
+    pub entries_checked: u64,      // In the current (or last) pass
+    pub issues_found: u64,         // In the current (or last) pass
+    pub issues_repaired: u64,      // In the current (or last) pass
+    pub next_finding_id: u64,
+}
+
+impl Storable for AuditState {
+    fn to_bytes(&self) -> Cow<'_, [u8]> { Cow::Owned(Encode!(self).unwrap()) }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self { Decode!(bytes.as_ref(), Self).unwrap() }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// --- AuditFinding (AUDIT_FINDINGS value) ---
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuditIssueKind {
+    MissingTimelineEntry,
+    StaleTimelineEntry,
+    MissingUserShelfEntry,
+    StaleUserShelfEntry,
+    MissingTagAssociation,
+    StaleTagAssociation,
+    TagCountMismatch,
+    MissingNftReference,
+    StaleNftReference,
+    MissingAppearsIn,
+    StaleAppearsIn,
+    DanglingNestedShelf,
+    TagCooccurrenceMismatch,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct AuditFinding {
+    pub finding_id: u64,
+    pub kind: AuditIssueKind,
+    pub shelf_id: Option<ShelfId>,
+    pub detail: String,
+    pub found_at: u64,
+    pub repaired: bool,
+}
+
+impl Storable for AuditFinding {
+    fn to_bytes(&self) -> Cow<'_, [u8]> { Cow::Owned(Encode!(self).unwrap()) }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self { Decode!(bytes.as_ref(), Self).unwrap() }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// Memory IDs
+pub(crate) const AUDIT_STATE_MEM_ID: MemoryId = MemoryId::new(40);
+pub(crate) const AUDIT_FINDINGS_MEM_ID: MemoryId = MemoryId::new(41);
+
+thread_local! {
+    pub static AUDIT_STATE: RefCell<StableCell<AuditState, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(AUDIT_STATE_MEM_ID)),
+            AuditState::default()
+        ).expect("Failed to initialize AUDIT_STATE")
+    );
+    // K: finding_id, V: finding (cleared at the start of each pass)
+    pub static AUDIT_FINDINGS: RefCell<StableBTreeMap<u64, AuditFinding, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(AUDIT_FINDINGS_MEM_ID)))
+    );
+}
+
+pub fn get_audit_state() -> AuditState {
+    AUDIT_STATE.with(|state| state.borrow().get().clone())
+}
+
+/// Applies `update` to the audit state and stores the result.
+pub fn update_audit_state(update: impl FnOnce(&mut AuditState)) {
+    AUDIT_STATE.with(|state_ref| {
+        let mut cell = state_ref.borrow_mut();
+        let mut state = cell.get().clone();
+        update(&mut state);
+        cell.set(state).expect("Failed to store AUDIT_STATE");
+    });
+}
+
+/// Records a finding for the current pass, evicting the oldest beyond `MAX_AUDIT_FINDINGS`.
+pub fn record_audit_finding(kind: AuditIssueKind, shelf_id: Option<ShelfId>, detail: String, repaired: bool) {
+    let mut finding_id = 0;
+    update_audit_state(|state| {
+        finding_id = state.next_finding_id;
+        state.next_finding_id += 1;
+        state.issues_found += 1;
+        if repaired {
+            state.issues_repaired += 1;
+        }
+    });
+
+    AUDIT_FINDINGS.with(|findings_ref| {
+        let mut findings = findings_ref.borrow_mut();
+        findings.insert(finding_id, AuditFinding {
+            finding_id,
+            kind,
+            shelf_id,
+            detail,
+            found_at: ic_cdk::api::time(),
+            repaired,
+        });
+        while findings.len() > MAX_AUDIT_FINDINGS {
+            let oldest_id = findings.iter().next().map(|(id, _)| id);
+            match oldest_id {
+                Some(oldest_id) => { findings.remove(&oldest_id); }
+                None => break,
+            }
+        }
+    });
+}
+
+/// Removes every finding (called when a new pass starts).
+pub fn clear_audit_findings() {
+    AUDIT_FINDINGS.with(|findings_ref| {
+        let mut findings = findings_ref.borrow_mut();
+        let ids: Vec<u64> = findings.iter().map(|(id, _)| id).collect();
+        for id in ids {
+            findings.remove(&id);
+        }
+    });
+}
@@ -0,0 +1,54 @@
+use candid::{CandidType, Decode, Deserialize, Encode};
+use ic_stable_structures::{storable::Bound, StableCell, Storable};
+use std::borrow::Cow;
+use std::cell::RefCell; // Required for MAP.with, etc.
+
+// Imports from parent storage module
+use super::{MEMORY_MANAGER, Memory, MemoryId};
+
+// --- RestoreState (controller import mode) ---
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct RestoreState {
+    pub in_progress: bool,
+    pub started_at: u64,
+    pub imported_shelves: u64,
+    pub expected_tag_associations: u64, // Distinct tags across imported shelves, checked after the rebuild
+}
+
+impl Storable for RestoreState {
+    fn to_bytes(&self) -> Cow<'_, [u8]> { Cow::Owned(Encode!(self).unwrap()) }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self { Decode!(bytes.as_ref(), Self).unwrap() }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// Memory IDs
+pub(crate) const RESTORE_STATE_MEM_ID: MemoryId = MemoryId::new(39);
+
+thread_local! {
+    pub static RESTORE_STATE: RefCell<StableCell<RestoreState, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(RESTORE_STATE_MEM_ID)),
+            RestoreState::default()
+        ).expect("Failed to initialize RESTORE_STATE")
+    );
+}
+
+pub fn get_restore_state() -> RestoreState {
+    RESTORE_STATE.with(|state| state.borrow().get().clone())
+}
+
+pub fn is_restore_in_progress() -> bool {
+    RESTORE_STATE.with(|state| state.borrow().get().in_progress)
+}
+
+/// Applies `update` to the restore state and stores the result.
+pub fn update_restore_state(update: impl FnOnce(&mut RestoreState)) -> Result<(), String> {
+    RESTORE_STATE.with(|state_ref| {
+        let mut cell = state_ref.borrow_mut();
+        let mut state = cell.get().clone();
+        update(&mut state);
+        cell.set(state)
+            .map(|_| ())
+            .map_err(|e| format!("Failed to store restore state: {:?}", e))
+    })
+}
@@ -8,4 +8,6 @@ pub const MAX_TAGS_PER_SHELF: usize = 3;
 pub const MAX_ITEMS_PER_SHELF: usize = 500;
 pub const MAX_APPEARS_IN_COUNT: usize = 100;
 pub const MAX_MARKDOWN_LENGTH: usize = 1_000;
-pub const SHELF_ITEM_STEP_SIZE: f64 = 1000.0; 
\ No newline at end of file
+pub const ARWEAVE_TX_ID_LENGTH: usize = 43;
+pub const MAX_LINK_URL_LENGTH: usize = 2_048;
+pub const MAX_LINK_TITLE_LENGTH: usize = 100; 
\ No newline at end of file
@@ -1,6 +1,7 @@
 use candid::{CandidType, Decode, Deserialize, Encode, Principal};
-use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use ic_stable_structures::{storable::Bound, StableBTreeMap, StableCell, Storable};
 use std::borrow::Cow;
+use std::cmp::Ordering;
 use std::collections::BTreeSet;
 use std::cell::RefCell; // Required for MAP.with, etc.
 
@@ -36,9 +37,89 @@ impl Storable for NormalizedTagSet {
     const BOUND: Bound = Bound::Unbounded;
 }
 
+// --- UserFollowerKey (followed, follower) for USER_FOLLOWERS ---
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct UserFollowerKey {
+    pub followed: Principal,
+    pub follower: Principal,
+}
+
+impl Storable for UserFollowerKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> { Cow::Owned(Encode!(&self.followed, &self.follower).unwrap()) }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let (followed, follower) = Decode!(bytes.as_ref(), Principal, Principal).unwrap();
+        Self { followed, follower }
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+impl PartialOrd for UserFollowerKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+impl Ord for UserFollowerKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match self.followed.cmp(&other.followed) {
+            Ordering::Equal => self.follower.cmp(&other.follower),
+            other_cmp => other_cmp,
+        }
+    }
+}
+
+// --- TagFollowerKey (tag, follower) for TAG_FOLLOWERS ---
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TagFollowerKey {
+    pub tag: NormalizedTag,
+    pub follower: Principal,
+}
+
+impl Storable for TagFollowerKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> { Cow::Owned(Encode!(&self.tag, &self.follower).unwrap()) }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let (tag, follower) = Decode!(bytes.as_ref(), NormalizedTag, Principal).unwrap();
+        Self { tag, follower }
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+impl PartialOrd for TagFollowerKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+impl Ord for TagFollowerKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match self.tag.cmp(&other.tag) {
+            Ordering::Equal => self.follower.cmp(&other.follower),
+            other_cmp => other_cmp,
+        }
+    }
+}
+
+// --- FollowLimits (controller-configurable caps) ---
+pub const MAX_FOLLOW_LIMIT: u64 = 1_000; // Upper bound for either cap; follow sets are stored as single values
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FollowLimits {
+    pub max_followed_users: u64,
+    pub max_followed_tags: u64,
+}
+
+impl Default for FollowLimits {
+    fn default() -> Self {
+        Self { max_followed_users: 100, max_followed_tags: 100 }
+    }
+}
+
+impl Storable for FollowLimits {
+    fn to_bytes(&self) -> Cow<'_, [u8]> { Cow::Owned(Encode!(self).unwrap()) }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self { Decode!(bytes.as_ref(), Self).unwrap() }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
 // Memory IDs
 pub(crate) const FOLLOWED_USERS_MEM_ID: MemoryId = MemoryId::new(16);
 pub(crate) const FOLLOWED_TAGS_MEM_ID: MemoryId = MemoryId::new(17);
+pub(crate) const USER_FOLLOWERS_MEM_ID: MemoryId = MemoryId::new(34);
+pub(crate) const TAG_FOLLOWERS_MEM_ID: MemoryId = MemoryId::new(35);
+pub(crate) const USER_FOLLOWER_COUNTS_MEM_ID: MemoryId = MemoryId::new(36);
+pub(crate) const TAG_FOLLOWER_COUNTS_MEM_ID: MemoryId = MemoryId::new(37);
+pub(crate) const FOLLOW_LIMITS_MEM_ID: MemoryId = MemoryId::new(38);
 
 thread_local! {
     // K: Follower Principal, V: Set of followed Principals
@@ -49,4 +130,80 @@ thread_local! {
     pub static FOLLOWED_TAGS: RefCell<StableBTreeMap<Principal, NormalizedTagSet, Memory>> = RefCell::new(
         StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(FOLLOWED_TAGS_MEM_ID)))
     );
-} 
\ No newline at end of file
+    // Reverse index of FOLLOWED_USERS. K: (followed, follower)
+    pub static USER_FOLLOWERS: RefCell<StableBTreeMap<UserFollowerKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(USER_FOLLOWERS_MEM_ID)))
+    );
+    // Reverse index of FOLLOWED_TAGS. K: (tag, follower)
+    pub static TAG_FOLLOWERS: RefCell<StableBTreeMap<TagFollowerKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(TAG_FOLLOWERS_MEM_ID)))
+    );
+    // K: followed Principal, V: number of followers
+    pub static USER_FOLLOWER_COUNTS: RefCell<StableBTreeMap<Principal, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(USER_FOLLOWER_COUNTS_MEM_ID)))
+    );
+    // K: tag, V: number of followers
+    pub static TAG_FOLLOWER_COUNTS: RefCell<StableBTreeMap<NormalizedTag, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(TAG_FOLLOWER_COUNTS_MEM_ID)))
+    );
+    pub static FOLLOW_LIMITS: RefCell<StableCell<FollowLimits, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(FOLLOW_LIMITS_MEM_ID)),
+            FollowLimits::default()
+        ).expect("Failed to initialize FOLLOW_LIMITS")
+    );
+}
+
+pub fn get_follow_limits() -> FollowLimits {
+    FOLLOW_LIMITS.with(|limits| limits.borrow().get().clone())
+}
+
+/// Adds `delta` (+1 / -1) to a follower count, removing the entry when it reaches zero.
+fn adjust_follower_count<K: Storable + Ord + Clone>(
+    counts: &'static std::thread::LocalKey<RefCell<StableBTreeMap<K, u64, Memory>>>,
+    key: &K,
+    increment: bool,
+) {
+    counts.with(|counts_ref| {
+        let mut map = counts_ref.borrow_mut();
+        let current = map.get(key).unwrap_or(0);
+        let updated = if increment { current + 1 } else { current.saturating_sub(1) };
+        if updated == 0 {
+            map.remove(key);
+        } else {
+            map.insert(key.clone(), updated);
+        }
+    });
+}
+
+/// Records `follower` in the reverse index of `followed`. Idempotent.
+pub fn add_user_follower(followed: Principal, follower: Principal) {
+    let inserted = USER_FOLLOWERS.with(|map| map.borrow_mut().insert(UserFollowerKey { followed, follower }, ()).is_none());
+    if inserted {
+        adjust_follower_count(&USER_FOLLOWER_COUNTS, &followed, true);
+    }
+}
+
+/// Removes `follower` from the reverse index of `followed`. Idempotent.
+pub fn remove_user_follower(followed: Principal, follower: Principal) {
+    let removed = USER_FOLLOWERS.with(|map| map.borrow_mut().remove(&UserFollowerKey { followed, follower }).is_some());
+    if removed {
+        adjust_follower_count(&USER_FOLLOWER_COUNTS, &followed, false);
+    }
+}
+
+/// Records `follower` in the reverse index of `tag`. Idempotent.
+pub fn add_tag_follower(tag: &NormalizedTag, follower: Principal) {
+    let inserted = TAG_FOLLOWERS.with(|map| map.borrow_mut().insert(TagFollowerKey { tag: tag.clone(), follower }, ()).is_none());
+    if inserted {
+        adjust_follower_count(&TAG_FOLLOWER_COUNTS, tag, true);
+    }
+}
+
+/// Removes `follower` from the reverse index of `tag`. Idempotent.
+pub fn remove_tag_follower(tag: &NormalizedTag, follower: Principal) {
+    let removed = TAG_FOLLOWERS.with(|map| map.borrow_mut().remove(&TagFollowerKey { tag: tag.clone(), follower }).is_some());
+    if removed {
+        adjust_follower_count(&TAG_FOLLOWER_COUNTS, tag, false);
+    }
+}
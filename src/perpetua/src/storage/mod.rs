@@ -25,6 +25,13 @@ pub mod user_storage;
 pub mod nft_storage;
 pub mod follow_storage;
 pub mod random_feed_storage;
+pub mod search_storage;
+pub mod trending_storage;
+pub mod notification_storage;
+pub mod backup_storage;
+pub mod audit_storage;
+pub mod stats_storage;
+pub mod access_storage;
 
 // Re-export key types/structs for easier access from outside crate::storage
 pub use common_types::{
@@ -35,17 +42,21 @@ pub use common_types::{
     MAX_ITEMS_PER_SHELF,
     MAX_APPEARS_IN_COUNT,
     MAX_MARKDOWN_LENGTH,
-    SHELF_ITEM_STEP_SIZE
+    ARWEAVE_TX_ID_LENGTH,
+    MAX_LINK_URL_LENGTH,
+    MAX_LINK_TITLE_LENGTH
 };
 
 pub use shelf_storage::{
     // Statics (Maps)
-    SHELF_DATA, GLOBAL_TIMELINE,
+    SHELF_DATA, GLOBAL_TIMELINE, SHELF_FORKS, SHELF_FORK_COUNTS,
     // Structs
     Shelf, ShelfData, ShelfMetadata, ShelfContent, ShelfContentSerializable, Item, ItemContent,
-    GlobalTimelineItemValue, ShelfPublic, ShelfBackupData,
+    GlobalTimelineItemValue, ShelfPublic, ShelfBackupData, ShelfForkKey,
     // Functions
-    create_shelf,
+    create_shelf, validate_arweave_tx_id, validate_link, validate_icrc7_token,
+    validate_nft_id, validate_markdown, validate_item_content,
+    get_fork_count, record_shelf_fork,
     // Constants - Removed as they are now in common_types
     // Memory IDs (made pub(crate) in their modules, re-export if needed publicly)
 };
@@ -55,10 +66,13 @@ pub use tag_storage::{
     TAG_METADATA, TAG_SHELF_ASSOCIATIONS, SHELF_TAG_ASSOCIATIONS,
     TAG_POPULARITY_INDEX, TAG_LEXICAL_INDEX,
     TAG_SHELF_CREATION_TIMELINE_INDEX,
+    TAG_ALIASES, TAG_BLOCKLIST, TAG_COOCCURRENCE,
     // Structs
-    TagMetadata, ShelfTagAssociationKey, TagShelfCreationTimelineKey,
+    TagMetadata, ShelfTagAssociationKey, TagShelfCreationTimelineKey, TagCooccurrenceKey,
     // Functions
-    validate_tag_format,
+    validate_tag_format, resolve_tag_alias, resolve_tag, is_tag_blocked, canonicalize_tag,
+    adjust_tag_cooccurrence, shelf_associated_tags,
+    stored_tag_cooccurrence, expected_tag_cooccurrence, replace_tag_cooccurrence,
     // Constants
     MAX_TAG_LENGTH, // MAX_TAGS_PER_SHELF removed, now in common_types
 };
@@ -81,8 +95,13 @@ pub use nft_storage::{
 pub use follow_storage::{
     // Statics (Maps)
     FOLLOWED_USERS, FOLLOWED_TAGS,
+    USER_FOLLOWERS, TAG_FOLLOWERS, USER_FOLLOWER_COUNTS, TAG_FOLLOWER_COUNTS, FOLLOW_LIMITS,
     // Structs
-    PrincipalSet, NormalizedTagSet,
+    PrincipalSet, NormalizedTagSet, UserFollowerKey, TagFollowerKey, FollowLimits,
+    // Functions
+    get_follow_limits, add_user_follower, remove_user_follower, add_tag_follower, remove_tag_follower,
+    // Constants
+    MAX_FOLLOW_LIMIT,
 };
 
 pub use random_feed_storage::{
@@ -92,6 +111,76 @@ pub use random_feed_storage::{
     refresh_random_shelf_candidates,
 };
 
+pub use search_storage::{
+    // Statics (Maps)
+    SEARCH_INDEX, SHELF_SEARCH_TERMS,
+    // Structs
+    SearchTermKey, SearchTermSet,
+    // Functions
+    tokenize_for_search, index_shelf_for_search, remove_shelf_from_search_index,
+};
+
+pub use trending_storage::{
+    // Statics (Maps)
+    ACTIVITY_LOG, TRENDING_TAGS, TRENDING_SHELVES,
+    // Structs
+    TrendingWindow, TrendingScoreKey, ActivityKind, ActivityEvent,
+    // Functions
+    record_activity, recompute_trending_scores,
+};
+
+pub use notification_storage::{
+    // Statics (Maps)
+    NOTIFICATIONS, MUTED_NOTIFICATION_KINDS,
+    // Structs
+    Notification, NotificationKey, NotificationKind, NotificationKindSet,
+    // Functions
+    push_notification, recipient_key_range,
+    // Constants
+    MAX_NOTIFICATIONS_PER_USER,
+};
+
+pub use backup_storage::{
+    // Statics (Cells)
+    RESTORE_STATE,
+    // Structs
+    RestoreState,
+    // Functions
+    get_restore_state, is_restore_in_progress, update_restore_state,
+};
+
+pub use audit_storage::{
+    // Statics (Maps / Cells)
+    AUDIT_STATE, AUDIT_FINDINGS,
+    // Structs
+    AuditState, AuditPhase, AuditFinding, AuditIssueKind,
+    // Functions
+    get_audit_state, update_audit_state, record_audit_finding, clear_audit_findings,
+    // Constants
+    MAX_AUDIT_FINDINGS,
+};
+
+pub use stats_storage::{
+    // Statics (Maps)
+    SHELF_STATS, ENGAGEMENT_DEDUP, DAILY_SHELF_VIEWS, MOST_VIEWED_SHELVES, ITEM_OPEN_COUNTS,
+    // Structs
+    StatsGranularity, ShelfStatsBucket, ShelfStatsKey, EngagementEvent, EngagementDedupKey,
+    DailyShelfViewsKey, ShelfItemKey,
+    // Functions
+    record_shelf_view_event, record_item_open_event, recompute_most_viewed_shelves,
+};
+
+pub use access_storage::{
+    // Statics (Maps)
+    SHELF_ACCESS_GRANTS,
+    // Structs
+    ShelfAccessPolicy, TokenGate, AccessGrant, AccessGrantKind, ShelfAccessKey,
+    // Functions
+    get_active_access_grant, has_shelf_access, store_access_grant, visible_item_order,
+    // Constants
+    PREVIEW_ITEM_COUNT, TOKEN_ACCESS_GRANT_TTL_NS,
+};
+
 // Re-export MemoryId constants if they need to be accessed from outside the storage module directly.
 // Generally, it's cleaner if only the maps/functions are the public API.
-// For now, MemoryId constants are pub(crate) within their respective modules. 
\ No newline at end of file
+// For now, MemoryId constants are pub(crate) within their respective modules. 
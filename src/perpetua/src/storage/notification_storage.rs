@@ -0,0 +1,156 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+use std::cell::RefCell; // Required for MAP.with, etc.
+
+// Imports from parent storage module
+use super::{MEMORY_MANAGER, Memory, MemoryId};
+
+// Import common types from sibling module
+use super::common_types::ShelfId;
+
+// --- Constants ---
+pub const MAX_NOTIFICATIONS_PER_USER: usize = 200; // Oldest are evicted beyond this
+
+// --- NotificationKind ---
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum NotificationKind {
+    NewFollower,      // actor followed the recipient
+    ItemAddedToShelf, // actor added an NFT or token owned by the recipient to a shelf
+    ShelfEdited,      // actor edited the recipient's public shelf (metadata, tags, removals, order)
+    ShelfForked,      // actor forked the recipient's shelf
+}
+
+// --- Notification (NOTIFICATIONS value) ---
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Notification {
+    pub notification_id: u64,
+    pub kind: NotificationKind,
+    pub actor: Principal,
+    pub shelf_id: Option<ShelfId>,         // The shelf involved, if any (the recipient's own except for ItemAddedToShelf)
+    pub related_shelf_id: Option<ShelfId>, // e.g. the fork created from `shelf_id`
+    pub created_at: u64,
+    pub read: bool,
+}
+
+impl Storable for Notification {
+    fn to_bytes(&self) -> Cow<'_, [u8]> { Cow::Owned(Encode!(self).unwrap()) }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self { Decode!(bytes.as_ref(), Self).unwrap() }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// --- NotificationKey (recipient, notification_id) for NOTIFICATIONS ---
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct NotificationKey {
+    pub recipient: Principal,
+    pub notification_id: u64, // Creation timestamp, bumped on collision; newest is largest
+}
+
+impl Storable for NotificationKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> { Cow::Owned(Encode!(&self.recipient, &self.notification_id).unwrap()) }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let (recipient, notification_id) = Decode!(bytes.as_ref(), Principal, u64).unwrap();
+        Self { recipient, notification_id }
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+impl PartialOrd for NotificationKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+impl Ord for NotificationKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match self.recipient.cmp(&other.recipient) {
+            Ordering::Equal => self.notification_id.cmp(&other.notification_id),
+            other_cmp => other_cmp,
+        }
+    }
+}
+
+// --- NotificationKindSet (muted kinds per principal) ---
+#[derive(CandidType, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct NotificationKindSet(pub BTreeSet<NotificationKind>);
+
+impl Storable for NotificationKindSet {
+    fn to_bytes(&self) -> Cow<'_, [u8]> { Cow::Owned(Encode!(&self.0).unwrap()) }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let set = Decode!(bytes.as_ref(), BTreeSet<NotificationKind>).unwrap();
+        Self(set)
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// Memory IDs
+pub(crate) const NOTIFICATIONS_MEM_ID: MemoryId = MemoryId::new(32);
+pub(crate) const MUTED_NOTIFICATION_KINDS_MEM_ID: MemoryId = MemoryId::new(33);
+
+thread_local! {
+    // K: (recipient, notification_id), V: notification
+    pub static NOTIFICATIONS: RefCell<StableBTreeMap<NotificationKey, Notification, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(NOTIFICATIONS_MEM_ID)))
+    );
+    // K: principal, V: notification kinds they do not want to receive
+    pub static MUTED_NOTIFICATION_KINDS: RefCell<StableBTreeMap<Principal, NotificationKindSet, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MUTED_NOTIFICATION_KINDS_MEM_ID)))
+    );
+}
+
+/// Smallest and largest keys for a recipient, for range scans.
+pub fn recipient_key_range(recipient: Principal) -> (NotificationKey, NotificationKey) {
+    (
+        NotificationKey { recipient, notification_id: 0 },
+        NotificationKey { recipient, notification_id: u64::MAX },
+    )
+}
+
+/// Stores a notification for `recipient`, unless the actor is the recipient or the kind is muted.
+/// Evicts the recipient's oldest notifications beyond `MAX_NOTIFICATIONS_PER_USER`.
+pub fn push_notification(
+    recipient: Principal,
+    kind: NotificationKind,
+    actor: Principal,
+    shelf_id: Option<ShelfId>,
+    related_shelf_id: Option<ShelfId>,
+) {
+    if recipient == actor {
+        return;
+    }
+    let muted = MUTED_NOTIFICATION_KINDS.with(|muted_ref| {
+        muted_ref.borrow().get(&recipient).is_some_and(|kinds| kinds.0.contains(&kind))
+    });
+    if muted {
+        return;
+    }
+
+    let now = ic_cdk::api::time();
+    let (start_key, end_key) = recipient_key_range(recipient);
+
+    NOTIFICATIONS.with(|notifications_ref| {
+        let mut notifications = notifications_ref.borrow_mut();
+
+        let mut key = NotificationKey { recipient, notification_id: now };
+        while notifications.contains_key(&key) {
+            key.notification_id += 1; // Several notifications within one message share the same timestamp
+        }
+        let notification = Notification {
+            notification_id: key.notification_id,
+            kind,
+            actor,
+            shelf_id,
+            related_shelf_id,
+            created_at: now,
+            read: false,
+        };
+        notifications.insert(key, notification);
+
+        let existing: Vec<NotificationKey> = notifications
+            .range(start_key..=end_key)
+            .map(|(k, _)| k)
+            .collect();
+        let excess = existing.len().saturating_sub(MAX_NOTIFICATIONS_PER_USER);
+        for old_key in existing.into_iter().take(excess) {
+            notifications.remove(&old_key);
+        }
+    });
+}
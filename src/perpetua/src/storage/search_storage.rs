@@ -0,0 +1,181 @@
+use candid::{CandidType, Decode, Deserialize, Encode};
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::cell::RefCell; // Required for MAP.with, etc.
+
+// Imports from parent storage module
+use super::{MEMORY_MANAGER, Memory, MemoryId};
+
+// Import common types from sibling module
+use super::common_types::ShelfId;
+
+// Imports from sibling storage modules
+use super::shelf_storage::{ShelfData, ItemContent};
+use super::access_storage::{ShelfAccessPolicy, PREVIEW_ITEM_COUNT};
+
+// --- Constants ---
+pub const MIN_SEARCH_TERM_LENGTH: usize = 2;
+pub const MAX_SEARCH_TERM_LENGTH: usize = 32;
+pub const MAX_SEARCH_TERMS_PER_SHELF: usize = 500;
+
+// Field weights: a term found in the title counts more than one found in an item.
+const TITLE_TERM_WEIGHT: u32 = 5;
+const DESCRIPTION_TERM_WEIGHT: u32 = 3;
+const MARKDOWN_TERM_WEIGHT: u32 = 1;
+
+/// Common English words (and URL fragments) that carry no search value.
+pub const SEARCH_STOP_WORDS: &[&str] = &[
+    "a", "about", "an", "and", "are", "as", "at", "be", "but", "by", "com", "for", "from",
+    "has", "have", "he", "her", "his", "how", "http", "https", "i", "if", "in", "into", "is",
+    "it", "its", "me", "my", "no", "not", "of", "on", "or", "our", "she", "so", "than", "that",
+    "the", "their", "them", "then", "there", "these", "they", "this", "to", "was", "we", "were",
+    "what", "when", "which", "who", "why", "will", "with", "www", "you", "your",
+];
+
+// --- SearchTermKey (term, shelf_id) for SEARCH_INDEX ---
+#[derive(CandidType, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SearchTermKey {
+    pub term: String,
+    pub shelf_id: ShelfId,
+}
+
+impl Storable for SearchTermKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> { Cow::Owned(Encode!(&self.term, &self.shelf_id).unwrap()) }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let (term, shelf_id) = Decode!(bytes.as_ref(), String, ShelfId).unwrap();
+        Self { term, shelf_id }
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+impl PartialOrd for SearchTermKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+impl Ord for SearchTermKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match self.term.cmp(&other.term) {
+            Ordering::Equal => self.shelf_id.cmp(&other.shelf_id),
+            other_cmp => other_cmp,
+        }
+    }
+}
+
+// --- SearchTermSet (forward index value) ---
+#[derive(CandidType, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SearchTermSet(pub BTreeSet<String>);
+
+impl Storable for SearchTermSet {
+    fn to_bytes(&self) -> Cow<'_, [u8]> { Cow::Owned(Encode!(&self.0).unwrap()) }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let set = Decode!(bytes.as_ref(), BTreeSet<String>).unwrap();
+        Self(set)
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// Memory IDs
+pub(crate) const SEARCH_INDEX_MEM_ID: MemoryId = MemoryId::new(22);
+pub(crate) const SHELF_SEARCH_TERMS_MEM_ID: MemoryId = MemoryId::new(23);
+
+thread_local! {
+    // K: (term, shelf_id), V: weighted term frequency for that shelf
+    pub static SEARCH_INDEX: RefCell<StableBTreeMap<SearchTermKey, u32, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(SEARCH_INDEX_MEM_ID)))
+    );
+    // K: shelf_id, V: terms currently indexed for the shelf (used to clear stale entries on re-index)
+    pub static SHELF_SEARCH_TERMS: RefCell<StableBTreeMap<ShelfId, SearchTermSet, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(SHELF_SEARCH_TERMS_MEM_ID)))
+    );
+}
+
+/// Splits text into normalised search terms: lowercased alphanumeric runs,
+/// dropping stop words and terms outside the allowed length range.
+pub fn tokenize_for_search(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .map(|token| token.to_lowercase())
+        .filter(|token| {
+            let char_count = token.chars().count();
+            (MIN_SEARCH_TERM_LENGTH..=MAX_SEARCH_TERM_LENGTH).contains(&char_count)
+                && !SEARCH_STOP_WORDS.contains(&token.as_str())
+        })
+        .collect()
+}
+
+/// Computes the weighted terms for a shelf from its title, description and markdown items.
+/// Gated shelves only contribute the markdown of their preview items, so search results
+/// never surface text that callers without access can't read.
+fn collect_weighted_terms(shelf_data: &ShelfData) -> BTreeMap<String, u32> {
+    let mut weighted_terms: BTreeMap<String, u32> = BTreeMap::new();
+    let mut add_terms = |text: &str, weight: u32| {
+        for term in tokenize_for_search(text) {
+            let entry = weighted_terms.entry(term).or_insert(0);
+            *entry = entry.saturating_add(weight);
+        }
+    };
+
+    add_terms(&shelf_data.metadata.title, TITLE_TERM_WEIGHT);
+    if let Some(description) = &shelf_data.metadata.description {
+        add_terms(description, DESCRIPTION_TERM_WEIGHT);
+    }
+    let indexed_item_count = match shelf_data.metadata.access_policy() {
+        ShelfAccessPolicy::Public => usize::MAX,
+        _ => PREVIEW_ITEM_COUNT,
+    };
+    let indexed_items = shelf_data.content.item_positions.iter_keys_ordered()
+        .take(indexed_item_count)
+        .filter_map(|item_id| shelf_data.content.items.get(item_id));
+    for item in indexed_items {
+        if let ItemContent::Markdown(markdown) = &item.content {
+            add_terms(markdown, MARKDOWN_TERM_WEIGHT);
+        }
+    }
+
+    if weighted_terms.len() > MAX_SEARCH_TERMS_PER_SHELF {
+        // Keep the highest weighted terms (ties broken alphabetically for determinism).
+        let mut ranked: Vec<(String, u32)> = weighted_terms.into_iter().collect();
+        ranked.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        ranked.truncate(MAX_SEARCH_TERMS_PER_SHELF);
+        weighted_terms = ranked.into_iter().collect();
+    }
+
+    weighted_terms
+}
+
+/// (Re)indexes a shelf for full-text search, replacing any previously indexed terms.
+/// Call this whenever the title, description or markdown items of a shelf change, and when the
+/// access policy or item order of a shelf changes (both decide which items are indexed).
+pub fn index_shelf_for_search(shelf_id: &ShelfId, shelf_data: &ShelfData) {
+    let weighted_terms = collect_weighted_terms(shelf_data);
+    remove_shelf_from_search_index(shelf_id);
+
+    SEARCH_INDEX.with(|index_ref| {
+        let mut index = index_ref.borrow_mut();
+        for (term, weight) in &weighted_terms {
+            index.insert(SearchTermKey { term: term.clone(), shelf_id: shelf_id.clone() }, *weight);
+        }
+    });
+
+    if !weighted_terms.is_empty() {
+        SHELF_SEARCH_TERMS.with(|terms_ref| {
+            terms_ref.borrow_mut().insert(
+                shelf_id.clone(),
+                SearchTermSet(weighted_terms.into_keys().collect()),
+            );
+        });
+    }
+}
+
+/// Removes every search index entry for a shelf.
+pub fn remove_shelf_from_search_index(shelf_id: &ShelfId) {
+    let previous_terms = SHELF_SEARCH_TERMS.with(|terms_ref| terms_ref.borrow_mut().remove(shelf_id));
+
+    if let Some(terms) = previous_terms {
+        SEARCH_INDEX.with(|index_ref| {
+            let mut index = index_ref.borrow_mut();
+            for term in terms.0 {
+                index.remove(&SearchTermKey { term, shelf_id: shelf_id.clone() });
+            }
+        });
+    }
+}
@@ -1,6 +1,7 @@
-use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use candid::{CandidType, Decode, Deserialize, Encode, Nat, Principal};
 use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
 use std::borrow::Cow;
+use std::cmp::Ordering;
 use std::collections::{BTreeMap, BTreeSet};
 use std::cell::RefCell; // Required for SHELVES.with, etc.
 
@@ -8,17 +9,17 @@ use std::cell::RefCell; // Required for SHELVES.with, etc.
 use super::{MEMORY_MANAGER, Memory, MemoryId};
 
 // Import common types from sibling module
-use super::common_types::{ShelfId, ItemId, NormalizedTag, MAX_TAGS_PER_SHELF, MAX_NFT_ID_LENGTH, MAX_ITEMS_PER_SHELF, MAX_MARKDOWN_LENGTH, SHELF_ITEM_STEP_SIZE, MAX_APPEARS_IN_COUNT};
+use super::common_types::{ShelfId, ItemId, NormalizedTag, MAX_TAGS_PER_SHELF, MAX_NFT_ID_LENGTH, MAX_ITEMS_PER_SHELF, MAX_MARKDOWN_LENGTH, ARWEAVE_TX_ID_LENGTH, MAX_LINK_URL_LENGTH, MAX_LINK_TITLE_LENGTH};
 
 // Imports from other parts of the crate
 use crate::ordering::PositionTracker;
+use super::access_storage::{ShelfAccessPolicy, visible_item_order};
 // create_shelf specific imports
-use crate::utils::normalize_tag; // For create_shelf via Shelf::with_tags
 use sha2::{Sha256, Digest};
 use bs58;
 
 // Import re-exported items from storage module for tag constants/validation
-use crate::storage::{validate_tag_format};
+use crate::storage::{canonicalize_tag};
 
 // --- Define ShelfMetadata ---
 #[derive(CandidType, Deserialize, Clone, Debug)]
@@ -32,6 +33,14 @@ pub struct ShelfMetadata {
     pub appears_in: Vec<ShelfId>,
     pub tags: Vec<NormalizedTag>,
     pub public_editing: bool,
+    pub forked_from: Option<ShelfId>, // Source shelf if created with fork_shelf
+    pub access_policy: Option<ShelfAccessPolicy>, // None for shelves stored before access policies (public)
+}
+
+impl ShelfMetadata {
+    pub fn access_policy(&self) -> ShelfAccessPolicy {
+        self.access_policy.clone().unwrap_or_default()
+    }
 }
 
 impl Storable for ShelfMetadata {
@@ -61,7 +70,8 @@ impl Storable for ShelfMetadata {
 #[derive(CandidType, Deserialize, Clone, Debug)]
 pub struct ShelfContentSerializable {
     pub items: BTreeMap<u32, Item>,
-    pub item_positions: Vec<(u32, f64)>,
+    pub item_positions: Vec<(u32, f64)>,           // Legacy f64 positions, only read to migrate old entries
+    pub item_ranks: Option<Vec<(u32, String)>>,    // Rank keys in order (None for entries written before ranks)
 }
 
 #[derive(Clone, Debug)]
@@ -70,25 +80,36 @@ pub struct ShelfContent {
     pub item_positions: PositionTracker<u32>,
 }
 
-impl Storable for ShelfContent {
-    fn to_bytes(&self) -> Cow<[u8]> {
-        let serializable = ShelfContentSerializable {
+impl ShelfContent {
+    fn to_serializable(&self) -> ShelfContentSerializable {
+        ShelfContentSerializable {
             items: self.items.clone(),
-            item_positions: self.item_positions.get_ordered_entries(),
-        };
-        Cow::Owned(Encode!(&serializable).unwrap())
-    }
-    fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        let serializable: ShelfContentSerializable = Decode!(bytes.as_ref(), ShelfContentSerializable).unwrap();
-        let mut item_positions = PositionTracker::<u32>::new();
-        for (key, pos) in serializable.item_positions {
-            item_positions.insert(key, pos);
+            item_positions: Vec::new(),
+            item_ranks: Some(self.item_positions.get_ordered_entries()),
         }
+    }
+
+    /// Rebuilds the tracker, migrating legacy f64 positions to rank keys when no ranks are stored.
+    fn from_serializable(serializable: ShelfContentSerializable) -> Self {
+        let item_positions = match serializable.item_ranks {
+            Some(ranks) => PositionTracker::from_ranked_entries(ranks),
+            None => PositionTracker::from_legacy_positions(serializable.item_positions),
+        };
         Self {
             items: serializable.items,
             item_positions,
         }
     }
+}
+
+impl Storable for ShelfContent {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(&self.to_serializable()).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let serializable: ShelfContentSerializable = Decode!(bytes.as_ref(), ShelfContentSerializable).unwrap();
+        Self::from_serializable(serializable)
+    }
     const BOUND: Bound = Bound::Unbounded;
 }
 
@@ -102,11 +123,7 @@ impl candid::CandidType for ShelfContent {
     where
         S: candid::types::Serializer,
     {
-        let serializable = ShelfContentSerializable {
-            items: self.items.clone(),
-            item_positions: self.item_positions.get_ordered_entries(),
-        };
-        serializable.idl_serialize(serializer)
+        self.to_serializable().idl_serialize(serializer)
     }
 }
 
@@ -117,16 +134,7 @@ impl<'de> serde::Deserialize<'de> for ShelfContent {
         D: serde::Deserializer<'de>,
     {
         let serializable = ShelfContentSerializable::deserialize(deserializer)?;
-        let mut item_positions = PositionTracker::<u32>::new();
-        for (key, pos) in serializable.item_positions {
-            // It's possible insert could fail if PositionTracker has such logic,
-            // but assuming it's straightforward for deserialization context.
-            item_positions.insert(key, pos);
-        }
-        Ok(Self {
-            items: serializable.items,
-            item_positions,
-        })
+        Ok(Self::from_serializable(serializable))
     }
 }
 
@@ -149,6 +157,90 @@ pub enum ItemContent {
     Nft(String), // NFT ID
     Markdown(String), // Markdown text
     Shelf(ShelfId), // Shelf ID - allows nesting shelves
+    // New variants are appended so existing stored items and clients keep decoding.
+    Arweave(String), // Raw Arweave transaction ID
+    Link { url: String, title: Option<String> }, // External http(s) link
+    Icrc7Token { collection: Principal, token_id: Nat }, // Token from any ICRC-7 collection
+}
+
+/// Validates an NFT ID: decimal digits only, within the stored key length.
+pub fn validate_nft_id(nft_id: &str) -> Result<(), String> {
+    if nft_id.chars().any(|c| !c.is_ascii_digit()) {
+        return Err("Invalid NFT ID: Contains non-digit characters.".to_string());
+    }
+    if nft_id.len() > MAX_NFT_ID_LENGTH {
+        return Err(format!("NFT ID exceeds maximum length of {} characters", MAX_NFT_ID_LENGTH));
+    }
+    Ok(())
+}
+
+/// Validates the length of a markdown item.
+pub fn validate_markdown(markdown: &str) -> Result<(), String> {
+    if markdown.len() > MAX_MARKDOWN_LENGTH {
+        return Err(format!("Markdown content exceeds maximum length of {} characters", MAX_MARKDOWN_LENGTH));
+    }
+    Ok(())
+}
+
+/// Stateless validation of item content being added to a shelf.
+/// Nested shelf existence, cycles and token ownership are checked separately by the caller.
+pub fn validate_item_content(content: &ItemContent) -> Result<(), String> {
+    match content {
+        ItemContent::Nft(nft_id) => validate_nft_id(nft_id),
+        ItemContent::Markdown(markdown) => validate_markdown(markdown),
+        ItemContent::Shelf(_) => Ok(()),
+        ItemContent::Arweave(tx_id) => validate_arweave_tx_id(tx_id),
+        ItemContent::Link { url, title } => validate_link(url, title),
+        ItemContent::Icrc7Token { collection, token_id } => validate_icrc7_token(collection, token_id),
+    }
+}
+
+/// Validates a raw Arweave transaction ID (43 base64url characters).
+pub fn validate_arweave_tx_id(tx_id: &str) -> Result<(), String> {
+    if tx_id.len() != ARWEAVE_TX_ID_LENGTH {
+        return Err(format!("Invalid Arweave transaction ID: must be exactly {} characters", ARWEAVE_TX_ID_LENGTH));
+    }
+    if !tx_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err("Invalid Arweave transaction ID: Contains non-base64url characters.".to_string());
+    }
+    Ok(())
+}
+
+/// Validates an external link item: an absolute http(s) URL with a host and an optional short title.
+pub fn validate_link(url: &str, title: &Option<String>) -> Result<(), String> {
+    if url.len() > MAX_LINK_URL_LENGTH {
+        return Err(format!("URL exceeds maximum length of {} characters", MAX_LINK_URL_LENGTH));
+    }
+    if url.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err("Invalid URL: Contains whitespace or control characters.".to_string());
+    }
+    let rest = url.strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+        .ok_or_else(|| "Invalid URL: Only http:// and https:// links are supported.".to_string())?;
+    let host = rest.split(['/', '?', '#']).next().unwrap_or("");
+    if host.is_empty() || host.contains('@') {
+        return Err("Invalid URL: Missing or invalid host.".to_string());
+    }
+    if let Some(link_title) = title {
+        if link_title.trim().is_empty() {
+            return Err("Link title cannot be empty when provided".to_string());
+        }
+        if link_title.len() > MAX_LINK_TITLE_LENGTH {
+            return Err(format!("Link title exceeds maximum length of {} characters", MAX_LINK_TITLE_LENGTH));
+        }
+    }
+    Ok(())
+}
+
+/// Validates the static parts of an ICRC-7 token reference. Ownership is checked against the collection separately.
+pub fn validate_icrc7_token(collection: &Principal, token_id: &Nat) -> Result<(), String> {
+    if *collection == Principal::anonymous() || *collection == Principal::management_canister() {
+        return Err("Invalid ICRC-7 collection principal".to_string());
+    }
+    if token_id.0.to_string().len() > MAX_NFT_ID_LENGTH {
+        return Err(format!("Token ID exceeds maximum length of {} characters", MAX_NFT_ID_LENGTH));
+    }
+    Ok(())
 }
 
 #[derive(CandidType, Deserialize, Clone, Debug)]
@@ -190,12 +282,16 @@ pub struct ShelfPublic {
     pub description: Option<String>,
     pub owner: Principal,
     pub items: BTreeMap<u32, Item>,
-    pub item_positions: Vec<(u32, f64)>,
+    pub item_order: Vec<ItemId>, // Item IDs in display order
     pub created_at: u64,
     pub updated_at: u64,
     pub appears_in: Vec<ShelfId>,
     pub tags: Vec<NormalizedTag>,
     pub public_editing: bool,
+    pub forked_from: Option<ShelfId>,
+    pub fork_count: u64,
+    pub access_policy: ShelfAccessPolicy,
+    pub is_preview: bool, // Only the first items are included; the caller has not unlocked the shelf
 }
 
 impl ShelfPublic {
@@ -206,31 +302,67 @@ impl ShelfPublic {
             description: shelf.description.clone(),
             owner: shelf.owner.clone(),
             items: shelf.items.clone(),
-            item_positions: shelf.item_positions.get_ordered_entries(),
+            item_order: shelf.item_positions.get_ordered_keys(),
             created_at: shelf.created_at,
             updated_at: shelf.updated_at,
             appears_in: shelf.appears_in.clone(),
             tags: shelf.tags.clone(),
             public_editing: shelf.public_editing,
+            forked_from: None,
+            fork_count: get_fork_count(&shelf.shelf_id),
+            access_policy: ShelfAccessPolicy::Public,
+            is_preview: false,
         }
     }
+    /// Builds the view of a shelf for the current caller; gated shelves they have not unlocked
+    /// only include the preview items.
     pub fn from_parts(metadata: &ShelfMetadata, content: &ShelfContent) -> Self {
+        let (item_order, is_preview) = visible_item_order(metadata, content, ic_cdk::caller());
         Self {
             shelf_id: metadata.shelf_id.clone(),
             title: metadata.title.clone(),
             description: metadata.description.clone(),
             owner: metadata.owner.clone(),
-            items: content.items.clone(),
-            item_positions: content.item_positions.get_ordered_entries(),
+            items: item_order.iter().filter_map(|id| content.items.get(id).map(|item| (*id, item.clone()))).collect(),
+            item_order,
             created_at: metadata.created_at,
             updated_at: metadata.updated_at,
             appears_in: metadata.appears_in.clone(),
             tags: metadata.tags.clone(),
             public_editing: metadata.public_editing,
+            forked_from: metadata.forked_from.clone(),
+            fork_count: get_fork_count(&metadata.shelf_id),
+            access_policy: metadata.access_policy(),
+            is_preview,
         }
     }
 }
 
+// --- ShelfForkKey (source_shelf_id, fork_shelf_id) for SHELF_FORKS ---
+#[derive(CandidType, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ShelfForkKey {
+    pub source_shelf_id: ShelfId,
+    pub fork_shelf_id: ShelfId,
+}
+
+impl Storable for ShelfForkKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> { Cow::Owned(Encode!(&self.source_shelf_id, &self.fork_shelf_id).unwrap()) }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let (source_shelf_id, fork_shelf_id) = Decode!(bytes.as_ref(), ShelfId, ShelfId).unwrap();
+        Self { source_shelf_id, fork_shelf_id }
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+impl PartialOrd for ShelfForkKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+impl Ord for ShelfForkKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.source_shelf_id.cmp(&other.source_shelf_id)
+            .then_with(|| self.fork_shelf_id.cmp(&other.fork_shelf_id))
+    }
+}
+
 // --- Main Shelf struct ---
 #[derive(Clone, Debug)]
 pub struct Shelf {
@@ -254,6 +386,8 @@ pub struct Shelf {
 // These IDs are now available for future use.
 pub(crate) const SHELF_DATA_MEM_ID: MemoryId = MemoryId::new(21);
 pub(crate) const GLOBAL_TIMELINE_MEM_ID: MemoryId = MemoryId::new(3);
+pub(crate) const SHELF_FORKS_MEM_ID: MemoryId = MemoryId::new(30);
+pub(crate) const SHELF_FORK_COUNTS_MEM_ID: MemoryId = MemoryId::new(31);
 
 thread_local! {
     pub static SHELF_DATA: RefCell<StableBTreeMap<ShelfId, ShelfData, Memory>> = RefCell::new(
@@ -267,6 +401,19 @@ thread_local! {
         )
     );
 
+    // K: (source_shelf_id, fork_shelf_id)
+    pub static SHELF_FORKS: RefCell<StableBTreeMap<ShelfForkKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(SHELF_FORKS_MEM_ID))
+        )
+    );
+    // K: source_shelf_id, V: number of forks (kept alongside SHELF_FORKS so ShelfPublic needs no range scan)
+    pub static SHELF_FORK_COUNTS: RefCell<StableBTreeMap<ShelfId, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(SHELF_FORK_COUNTS_MEM_ID))
+        )
+    );
+
     // Temporary: Old storage structures for migration
     // pub(crate) static SHELVES_OLD: RefCell<StableBTreeMap<ShelfId, ShelfContent, Memory>> = RefCell::new(
     //     StableBTreeMap::init(
@@ -281,6 +428,27 @@ thread_local! {
     // );
 }
 
+pub fn get_fork_count(shelf_id: &ShelfId) -> u64 {
+    SHELF_FORK_COUNTS.with(|counts| counts.borrow().get(shelf_id).unwrap_or(0))
+}
+
+/// Records `fork_shelf_id` as a fork of `source_shelf_id`.
+pub fn record_shelf_fork(source_shelf_id: &ShelfId, fork_shelf_id: &ShelfId) {
+    let inserted = SHELF_FORKS.with(|forks| {
+        forks.borrow_mut().insert(
+            ShelfForkKey { source_shelf_id: source_shelf_id.clone(), fork_shelf_id: fork_shelf_id.clone() },
+            (),
+        ).is_none()
+    });
+    if inserted {
+        SHELF_FORK_COUNTS.with(|counts_ref| {
+            let mut counts = counts_ref.borrow_mut();
+            let count = counts.get(source_shelf_id).unwrap_or(0);
+            counts.insert(source_shelf_id.clone(), count + 1);
+        });
+    }
+}
+
 // --- Shelf Impl ---
 impl Shelf {
     pub fn new(shelf_id: ShelfId, title: String, owner: Principal) -> Self {
@@ -316,19 +484,8 @@ impl Shelf {
             return Err(format!("Maximum item limit reached ({})", MAX_ITEMS_PER_SHELF));
         }
         match &item.content {
-            ItemContent::Nft(nft_id) => {
-                if nft_id.chars().any(|c| !c.is_digit(10)) {
-                     return Err("Invalid NFT ID: Contains non-digit characters.".to_string());
-                }
-                if nft_id.len() > MAX_NFT_ID_LENGTH {
-                    return Err(format!("NFT ID exceeds maximum length of {} characters", MAX_NFT_ID_LENGTH));
-                }
-            }
-            ItemContent::Markdown(markdown) => {
-                if markdown.len() > MAX_MARKDOWN_LENGTH {
-                     return Err(format!("Markdown content exceeds maximum length of {} characters", MAX_MARKDOWN_LENGTH));
-                }
-            }
+            ItemContent::Nft(nft_id) => validate_nft_id(nft_id)?,
+            ItemContent::Markdown(markdown) => validate_markdown(markdown)?,
             ItemContent::Shelf(nested_shelf_id) => {
                 if nested_shelf_id == &self.shelf_id {
                     return Err("Circular reference: A shelf cannot contain itself".to_string());
@@ -347,13 +504,12 @@ impl Shelf {
                     ));
                 }
             }
+            ItemContent::Arweave(tx_id) => validate_arweave_tx_id(tx_id)?,
+            ItemContent::Link { url, title } => validate_link(url, title)?,
+            ItemContent::Icrc7Token { collection, token_id } => validate_icrc7_token(collection, token_id)?,
         }
         let item_id = item.id;
-        let new_position = self.item_positions.calculate_position(
-            None,
-            false,
-            SHELF_ITEM_STEP_SIZE
-        )?;
+        let new_position = self.item_positions.calculate_position(None, false)?;
         self.item_positions.insert(item_id, new_position);
         self.items.insert(item_id, item);
         Ok(())
@@ -372,11 +528,7 @@ impl Shelf {
                  return Err("Reference item not found".to_string());
              }
         }
-        let new_position = self.item_positions.calculate_position(
-            reference_item_id.as_ref(),
-            before,
-            SHELF_ITEM_STEP_SIZE
-        )?;
+        let new_position = self.item_positions.calculate_position(reference_item_id.as_ref(), before)?;
         self.item_positions.insert(item_id, new_position);
         Ok(())
     }
@@ -389,7 +541,7 @@ impl Shelf {
     }
     
     pub fn rebalance_positions(&mut self) {
-        self.item_positions.rebalance_positions(SHELF_ITEM_STEP_SIZE);
+        self.item_positions.rebalance_positions();
     }
 }
 
@@ -401,7 +553,7 @@ pub struct ShelfBackupData {
     pub description: Option<String>,
     pub owner: Principal,
     pub items: BTreeMap<u32, Item>,
-    pub item_positions: Vec<(u32, f64)>,
+    pub item_positions: Vec<(u32, String)>,
     pub tags: Vec<NormalizedTag>,
     pub public_editing: bool,
 }
@@ -449,8 +601,7 @@ pub async fn create_shelf(
         
         let mut unique_normalized = BTreeSet::new(); // Use std::collections::BTreeSet
         for tag_str in tag_list {
-            let normalized = normalize_tag(&tag_str); // from crate::utils
-            validate_tag_format(&normalized)?; // Use imported function
+            let normalized = canonicalize_tag(&tag_str)?; // Resolves aliases, rejects blocked tags
             unique_normalized.insert(normalized);
         }
         normalized_tags_for_shelf = unique_normalized.into_iter().collect();
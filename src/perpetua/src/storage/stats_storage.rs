@@ -0,0 +1,382 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::cell::RefCell; // Required for MAP.with, etc.
+
+// Imports from parent storage module
+use super::{MEMORY_MANAGER, Memory, MemoryId};
+
+// Import common types from sibling module
+use super::common_types::{ShelfId, ItemId};
+
+// Imports from sibling storage modules
+use super::shelf_storage::SHELF_DATA;
+use super::trending_storage::{TrendingWindow, TrendingScoreKey, MAX_TRENDING_ENTRIES};
+
+// --- Constants ---
+pub const NANOS_PER_HOUR: u64 = 60 * 60 * 1_000_000_000;
+pub const NANOS_PER_DAY: u64 = 24 * NANOS_PER_HOUR;
+pub const HOURLY_STATS_RETENTION_HOURS: u64 = 7 * 24; // Hourly buckets older than a week are dropped
+pub const DAILY_STATS_RETENTION_DAYS: u64 = 365;
+const DEDUP_RETENTION_NS: u64 = 2 * NANOS_PER_DAY;    // Must cover the longest dedup bucket (one day)
+const MAX_PRUNED_PER_WRITE: usize = 50;                // Bounds the cleanup done by a single record call
+
+// --- StatsGranularity ---
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum StatsGranularity {
+    Hour,
+    Day,
+}
+
+impl StatsGranularity {
+    pub fn bucket_ns(&self) -> u64 {
+        match self {
+            StatsGranularity::Hour => NANOS_PER_HOUR,
+            StatsGranularity::Day => NANOS_PER_DAY,
+        }
+    }
+
+    pub fn retention_buckets(&self) -> u64 {
+        match self {
+            StatsGranularity::Hour => HOURLY_STATS_RETENTION_HOURS,
+            StatsGranularity::Day => DAILY_STATS_RETENTION_DAYS,
+        }
+    }
+
+    /// Start of the bucket containing `timestamp`.
+    pub fn bucket_start(&self, timestamp: u64) -> u64 {
+        timestamp - timestamp % self.bucket_ns()
+    }
+}
+
+// --- ShelfStatsBucket (SHELF_STATS value) ---
+#[derive(CandidType, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ShelfStatsBucket {
+    pub bucket_start: u64,
+    pub views: u64,           // At most one per visitor per hour
+    pub unique_visitors: u64, // Distinct visitors within this bucket
+    pub item_opens: u64,      // At most one per visitor, item and hour
+}
+
+impl Storable for ShelfStatsBucket {
+    fn to_bytes(&self) -> Cow<'_, [u8]> { Cow::Owned(Encode!(self).unwrap()) }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self { Decode!(bytes.as_ref(), Self).unwrap() }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// --- ShelfStatsKey (shelf_id, granularity, bucket_start) for SHELF_STATS ---
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ShelfStatsKey {
+    pub shelf_id: ShelfId,
+    pub granularity: StatsGranularity,
+    pub bucket_start: u64,
+}
+
+impl Storable for ShelfStatsKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> { Cow::Owned(Encode!(self).unwrap()) }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self { Decode!(bytes.as_ref(), Self).unwrap() }
+    const BOUND: Bound = Bound::Unbounded;
+}
+impl PartialOrd for ShelfStatsKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+impl Ord for ShelfStatsKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.shelf_id.cmp(&other.shelf_id)
+            .then_with(|| self.granularity.cmp(&other.granularity))
+            .then_with(|| self.bucket_start.cmp(&other.bucket_start))
+    }
+}
+
+// --- EngagementDedupKey (bucket_start, shelf_id, principal, event) for ENGAGEMENT_DEDUP ---
+// Ordered by bucket first so expired entries form a prefix.
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EngagementEvent {
+    ShelfView,        // Hourly bucket
+    ItemOpen(ItemId), // Hourly bucket
+    DailyVisit,       // Daily bucket, drives daily unique visitors
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct EngagementDedupKey {
+    pub bucket_start: u64,
+    pub shelf_id: ShelfId,
+    pub principal: Principal,
+    pub event: EngagementEvent,
+}
+
+impl Storable for EngagementDedupKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> { Cow::Owned(Encode!(self).unwrap()) }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self { Decode!(bytes.as_ref(), Self).unwrap() }
+    const BOUND: Bound = Bound::Unbounded;
+}
+impl PartialOrd for EngagementDedupKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+impl Ord for EngagementDedupKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.bucket_start.cmp(&other.bucket_start)
+            .then_with(|| self.shelf_id.cmp(&other.shelf_id))
+            .then_with(|| self.principal.cmp(&other.principal))
+            .then_with(|| self.event.cmp(&other.event))
+    }
+}
+
+// --- DailyShelfViewsKey (day_start, shelf_id) for DAILY_SHELF_VIEWS ---
+// Time-ordered copy of daily view counts, so "most viewed" does not scan every shelf.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DailyShelfViewsKey {
+    pub day_start: u64,
+    pub shelf_id: ShelfId,
+}
+
+impl Storable for DailyShelfViewsKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> { Cow::Owned(Encode!(&self.day_start, &self.shelf_id).unwrap()) }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let (day_start, shelf_id) = Decode!(bytes.as_ref(), u64, ShelfId).unwrap();
+        Self { day_start, shelf_id }
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+impl PartialOrd for DailyShelfViewsKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+impl Ord for DailyShelfViewsKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.day_start.cmp(&other.day_start)
+            .then_with(|| self.shelf_id.cmp(&other.shelf_id))
+    }
+}
+
+// --- ShelfItemKey (shelf_id, item_id) for ITEM_OPEN_COUNTS ---
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ShelfItemKey {
+    pub shelf_id: ShelfId,
+    pub item_id: ItemId,
+}
+
+impl Storable for ShelfItemKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> { Cow::Owned(Encode!(&self.shelf_id, &self.item_id).unwrap()) }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let (shelf_id, item_id) = Decode!(bytes.as_ref(), ShelfId, ItemId).unwrap();
+        Self { shelf_id, item_id }
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+impl PartialOrd for ShelfItemKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+impl Ord for ShelfItemKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.shelf_id.cmp(&other.shelf_id)
+            .then_with(|| self.item_id.cmp(&other.item_id))
+    }
+}
+
+// Memory IDs
+pub(crate) const SHELF_STATS_MEM_ID: MemoryId = MemoryId::new(42);
+pub(crate) const ENGAGEMENT_DEDUP_MEM_ID: MemoryId = MemoryId::new(43);
+pub(crate) const DAILY_SHELF_VIEWS_MEM_ID: MemoryId = MemoryId::new(44);
+pub(crate) const MOST_VIEWED_SHELVES_MEM_ID: MemoryId = MemoryId::new(45);
+pub(crate) const ITEM_OPEN_COUNTS_MEM_ID: MemoryId = MemoryId::new(46);
+
+thread_local! {
+    // K: (shelf_id, granularity, bucket_start), V: aggregated counts for that bucket
+    pub static SHELF_STATS: RefCell<StableBTreeMap<ShelfStatsKey, ShelfStatsBucket, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(SHELF_STATS_MEM_ID)))
+    );
+    // K: (bucket_start, shelf_id, principal, event), present once the event was counted
+    pub static ENGAGEMENT_DEDUP: RefCell<StableBTreeMap<EngagementDedupKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(ENGAGEMENT_DEDUP_MEM_ID)))
+    );
+    // K: (day_start, shelf_id), V: views that day
+    pub static DAILY_SHELF_VIEWS: RefCell<StableBTreeMap<DailyShelfViewsKey, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(DAILY_SHELF_VIEWS_MEM_ID)))
+    );
+    // K: (window, reversed views, shelf_id), recomputed alongside trending scores
+    pub static MOST_VIEWED_SHELVES: RefCell<StableBTreeMap<TrendingScoreKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MOST_VIEWED_SHELVES_MEM_ID)))
+    );
+    // K: (shelf_id, item_id), V: all-time opens of the item
+    pub static ITEM_OPEN_COUNTS: RefCell<StableBTreeMap<ShelfItemKey, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(ITEM_OPEN_COUNTS_MEM_ID)))
+    );
+}
+
+/// Marks an event as counted for its bucket. Returns false if it was already counted.
+fn claim_dedup_slot(key: EngagementDedupKey) -> bool {
+    ENGAGEMENT_DEDUP.with(|dedup_ref| {
+        let mut dedup = dedup_ref.borrow_mut();
+        if dedup.contains_key(&key) {
+            return false;
+        }
+        dedup.insert(key, ());
+        true
+    })
+}
+
+/// Adds to the hourly and daily buckets of a shelf, dropping that shelf's expired buckets.
+fn bump_shelf_buckets(shelf_id: &ShelfId, now: u64, apply: impl Fn(&mut ShelfStatsBucket, StatsGranularity)) {
+    SHELF_STATS.with(|stats_ref| {
+        let mut stats = stats_ref.borrow_mut();
+        for granularity in [StatsGranularity::Hour, StatsGranularity::Day] {
+            let bucket_start = granularity.bucket_start(now);
+            let oldest_kept = bucket_start.saturating_sub(granularity.bucket_ns() * granularity.retention_buckets());
+            let range_start = ShelfStatsKey { shelf_id: shelf_id.clone(), granularity, bucket_start: 0 };
+            let range_end = ShelfStatsKey { shelf_id: shelf_id.clone(), granularity, bucket_start: oldest_kept };
+            let expired: Vec<ShelfStatsKey> = stats.range(range_start..range_end)
+                .take(MAX_PRUNED_PER_WRITE)
+                .map(|(k, _)| k)
+                .collect();
+            for key in expired {
+                stats.remove(&key);
+            }
+
+            let key = ShelfStatsKey { shelf_id: shelf_id.clone(), granularity, bucket_start };
+            let mut bucket = stats.get(&key).unwrap_or(ShelfStatsBucket { bucket_start, ..Default::default() });
+            apply(&mut bucket, granularity);
+            stats.insert(key, bucket);
+        }
+    });
+}
+
+/// Drops a bounded number of expired dedup entries and daily view rows.
+fn prune_expired_engagement(now: u64) {
+    let dedup_cutoff = now.saturating_sub(DEDUP_RETENTION_NS);
+    ENGAGEMENT_DEDUP.with(|dedup_ref| {
+        let mut dedup = dedup_ref.borrow_mut();
+        let expired: Vec<EngagementDedupKey> = dedup.iter()
+            .take_while(|(k, _)| k.bucket_start < dedup_cutoff)
+            .take(MAX_PRUNED_PER_WRITE)
+            .map(|(k, _)| k)
+            .collect();
+        for key in expired {
+            dedup.remove(&key);
+        }
+    });
+
+    let max_lookback = TrendingWindow::ALL.iter().map(|w| w.lookback_ns()).max().unwrap_or(0);
+    let views_cutoff = StatsGranularity::Day.bucket_start(now).saturating_sub(max_lookback);
+    DAILY_SHELF_VIEWS.with(|views_ref| {
+        let mut views = views_ref.borrow_mut();
+        let expired: Vec<DailyShelfViewsKey> = views.iter()
+            .take_while(|(k, _)| k.day_start < views_cutoff)
+            .take(MAX_PRUNED_PER_WRITE)
+            .map(|(k, _)| k)
+            .collect();
+        for key in expired {
+            views.remove(&key);
+        }
+    });
+}
+
+/// Counts a shelf view by `visitor`, at most once per visitor per hour.
+/// Returns whether the view was counted.
+pub fn record_shelf_view_event(shelf_id: &ShelfId, visitor: Principal) -> bool {
+    let now = ic_cdk::api::time();
+    prune_expired_engagement(now);
+
+    let counted = claim_dedup_slot(EngagementDedupKey {
+        bucket_start: StatsGranularity::Hour.bucket_start(now),
+        shelf_id: shelf_id.clone(),
+        principal: visitor,
+        event: EngagementEvent::ShelfView,
+    });
+    if !counted {
+        return false;
+    }
+    let new_daily_visitor = claim_dedup_slot(EngagementDedupKey {
+        bucket_start: StatsGranularity::Day.bucket_start(now),
+        shelf_id: shelf_id.clone(),
+        principal: visitor,
+        event: EngagementEvent::DailyVisit,
+    });
+
+    bump_shelf_buckets(shelf_id, now, |bucket, granularity| {
+        bucket.views += 1;
+        // Hourly views are already one per visitor
+        if granularity == StatsGranularity::Hour || new_daily_visitor {
+            bucket.unique_visitors += 1;
+        }
+    });
+
+    DAILY_SHELF_VIEWS.with(|views_ref| {
+        let mut views = views_ref.borrow_mut();
+        let key = DailyShelfViewsKey { day_start: StatsGranularity::Day.bucket_start(now), shelf_id: shelf_id.clone() };
+        let count = views.get(&key).unwrap_or(0);
+        views.insert(key, count + 1);
+    });
+    true
+}
+
+/// Counts an item open by `visitor`, at most once per visitor and item per hour.
+/// Returns whether the open was counted.
+pub fn record_item_open_event(shelf_id: &ShelfId, item_id: ItemId, visitor: Principal) -> bool {
+    let now = ic_cdk::api::time();
+    prune_expired_engagement(now);
+
+    let counted = claim_dedup_slot(EngagementDedupKey {
+        bucket_start: StatsGranularity::Hour.bucket_start(now),
+        shelf_id: shelf_id.clone(),
+        principal: visitor,
+        event: EngagementEvent::ItemOpen(item_id),
+    });
+    if !counted {
+        return false;
+    }
+
+    bump_shelf_buckets(shelf_id, now, |bucket, _| bucket.item_opens += 1);
+
+    ITEM_OPEN_COUNTS.with(|counts_ref| {
+        let mut counts = counts_ref.borrow_mut();
+        let key = ShelfItemKey { shelf_id: shelf_id.clone(), item_id };
+        let count = counts.get(&key).unwrap_or(0);
+        counts.insert(key, count + 1);
+    });
+    true
+}
+
+/// Recomputes the most viewed shelves for every trending window from the daily view counts.
+/// Deleted shelves are skipped.
+pub fn recompute_most_viewed_shelves() {
+    let today = StatsGranularity::Day.bucket_start(ic_cdk::api::time());
+
+    for window in TrendingWindow::ALL {
+        // Whole days only, counting today
+        let window_days = (window.lookback_ns() / NANOS_PER_DAY).max(1);
+        let first_day = today.saturating_sub((window_days - 1) * NANOS_PER_DAY);
+        let range_start = DailyShelfViewsKey { day_start: first_day, shelf_id: String::new() };
+
+        let mut view_totals: BTreeMap<ShelfId, u64> = BTreeMap::new();
+        DAILY_SHELF_VIEWS.with(|views_ref| {
+            for (key, views) in views_ref.borrow().range(range_start..) {
+                *view_totals.entry(key.shelf_id).or_insert(0) += views;
+            }
+        });
+        SHELF_DATA.with(|sds_ref| {
+            let sds = sds_ref.borrow();
+            view_totals.retain(|shelf_id, _| sds.contains_key(shelf_id));
+        });
+
+        let mut ranked: Vec<(ShelfId, u64)> = view_totals.into_iter().collect();
+        ranked.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        ranked.truncate(MAX_TRENDING_ENTRIES);
+
+        MOST_VIEWED_SHELVES.with(|index_ref| {
+            let mut index = index_ref.borrow_mut();
+            let stale_keys: Vec<TrendingScoreKey> = index
+                .range(TrendingScoreKey::window_start(window)..)
+                .take_while(|(key, _)| key.window == window)
+                .map(|(key, _)| key)
+                .collect();
+            for key in stale_keys {
+                index.remove(&key);
+            }
+            for (shelf_id, views) in ranked {
+                index.insert(TrendingScoreKey { window, reversed_score: u64::MAX - views, id: shelf_id }, ());
+            }
+        });
+    }
+}
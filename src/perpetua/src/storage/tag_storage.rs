@@ -2,6 +2,7 @@ use candid::{CandidType, Decode, Deserialize, Encode};
 use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
 use std::borrow::Cow;
 use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::cell::RefCell; // Required for MAP.with, etc.
 
 // Imports from parent storage module
@@ -12,6 +13,7 @@ use super::common_types::{ShelfId, NormalizedTag};
 
 // Imports from main types module in the crate
 use crate::types::{TagPopularityKey, TagShelfAssociationKey as CrateTagShelfAssociationKey}; // Renaming to avoid conflict
+use crate::utils::normalize_tag;
 
 // --- Define TagMetadata ---
 #[derive(CandidType, Deserialize, Clone, Debug, Default)]
@@ -91,6 +93,33 @@ impl Ord for TagShelfCreationTimelineKey {
     }
 }
 
+// --- Tag Co-occurrence Key ---
+#[derive(CandidType, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct TagCooccurrenceKey {
+    pub tag: NormalizedTag,
+    pub related_tag: NormalizedTag,
+}
+
+impl Storable for TagCooccurrenceKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> { Cow::Owned(Encode!(&self.tag, &self.related_tag).unwrap()) }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let (tag, related_tag) = Decode!(bytes.as_ref(), NormalizedTag, NormalizedTag).unwrap();
+        Self { tag, related_tag }
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+impl PartialOrd for TagCooccurrenceKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+impl Ord for TagCooccurrenceKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match self.tag.cmp(&other.tag) {
+            Ordering::Equal => self.related_tag.cmp(&other.related_tag),
+            other_cmp => other_cmp,
+        }
+    }
+}
+
 // --- Constants ---
 pub const MAX_TAG_LENGTH: usize = 25;
 
@@ -101,6 +130,9 @@ pub(crate) const SHELF_TAG_ASSOCIATIONS_MEM_ID: MemoryId = MemoryId::new(12); //
 pub(crate) const TAG_POPULARITY_INDEX_MEM_ID: MemoryId = MemoryId::new(13); // Uses crate::types::TagPopularityKey
 pub(crate) const TAG_LEXICAL_INDEX_MEM_ID: MemoryId = MemoryId::new(14);
 pub(crate) const TAG_SHELF_CREATION_TIMELINE_INDEX_MEM_ID: MemoryId = MemoryId::new(19);
+pub(crate) const TAG_ALIASES_MEM_ID: MemoryId = MemoryId::new(24);
+pub(crate) const TAG_BLOCKLIST_MEM_ID: MemoryId = MemoryId::new(25);
+pub(crate) const TAG_COOCCURRENCE_MEM_ID: MemoryId = MemoryId::new(29);
 
 thread_local! {
     pub static TAG_METADATA: RefCell<StableBTreeMap<NormalizedTag, TagMetadata, Memory>> = RefCell::new(
@@ -124,6 +156,18 @@ thread_local! {
     pub static TAG_SHELF_CREATION_TIMELINE_INDEX: RefCell<StableBTreeMap<TagShelfCreationTimelineKey, (), Memory>> = RefCell::new(
         StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(TAG_SHELF_CREATION_TIMELINE_INDEX_MEM_ID)))
     );
+    // K: alias (normalized raw input, may contain characters a real tag cannot), V: canonical tag
+    pub static TAG_ALIASES: RefCell<StableBTreeMap<NormalizedTag, NormalizedTag, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(TAG_ALIASES_MEM_ID)))
+    );
+    // K: (tag, related_tag), V: number of shelves carrying both tags. Stored in both directions.
+    pub static TAG_COOCCURRENCE: RefCell<StableBTreeMap<TagCooccurrenceKey, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(TAG_COOCCURRENCE_MEM_ID)))
+    );
+    // K: banned tag
+    pub static TAG_BLOCKLIST: RefCell<StableBTreeMap<NormalizedTag, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(TAG_BLOCKLIST_MEM_ID)))
+    );
 }
 
 // --- Tag validation function ---
@@ -138,4 +182,115 @@ pub fn validate_tag_format(tag: &NormalizedTag) -> Result<(), String> {
          return Err("Tag cannot be empty".to_string());
     }
     Ok(())
-} 
\ No newline at end of file
+}
+
+// --- Tag alias / moderation helpers ---
+
+/// Returns the canonical tag for an already normalized tag, following TAG_ALIASES (one level).
+pub fn resolve_tag_alias(normalized_tag: &NormalizedTag) -> NormalizedTag {
+    TAG_ALIASES.with(|aliases| aliases.borrow().get(normalized_tag))
+        .unwrap_or_else(|| normalized_tag.clone())
+}
+
+/// Normalizes raw input and resolves aliases. Used for lookups (queries, removals, unfollows).
+pub fn resolve_tag(raw_tag: &str) -> NormalizedTag {
+    resolve_tag_alias(&normalize_tag(raw_tag))
+}
+
+/// Tags currently associated with a shelf, per SHELF_TAG_ASSOCIATIONS.
+pub fn shelf_associated_tags(shelf_id: &ShelfId) -> Vec<NormalizedTag> {
+    SHELF_TAG_ASSOCIATIONS.with(|map_ref| {
+        let start_key = ShelfTagAssociationKey { shelf_id: shelf_id.clone(), tag: String::new() };
+        map_ref.borrow()
+            .range(start_key..)
+            .take_while(|(key, _)| &key.shelf_id == shelf_id)
+            .map(|(key, _)| key.tag)
+            .collect()
+    })
+}
+
+pub fn is_tag_blocked(normalized_tag: &NormalizedTag) -> bool {
+    TAG_BLOCKLIST.with(|blocklist| blocklist.borrow().contains_key(normalized_tag))
+}
+
+/// Normalizes raw input, resolves aliases, and rejects blocked or malformed tags.
+/// Used wherever a tag is newly attached to a shelf or followed.
+pub fn canonicalize_tag(raw_tag: &str) -> Result<NormalizedTag, String> {
+    let normalized = normalize_tag(raw_tag);
+    let canonical = resolve_tag_alias(&normalized);
+    if is_tag_blocked(&normalized) || is_tag_blocked(&canonical) {
+        return Err(format!("Tag '{}' is not allowed", normalized));
+    }
+    validate_tag_format(&canonical)?;
+    Ok(canonical)
+}
+
+/// Adjusts the co-occurrence count of two tags (in both directions). Entries reaching zero are removed.
+pub fn adjust_tag_cooccurrence(tag_a: &NormalizedTag, tag_b: &NormalizedTag, increment: bool) {
+    if tag_a == tag_b {
+        return;
+    }
+    TAG_COOCCURRENCE.with(|map_ref| {
+        let mut map = map_ref.borrow_mut();
+        for (tag, related_tag) in [(tag_a, tag_b), (tag_b, tag_a)] {
+            let key = TagCooccurrenceKey { tag: tag.clone(), related_tag: related_tag.clone() };
+            let current = map.get(&key).unwrap_or(0);
+            let updated = if increment { current + 1 } else { current.saturating_sub(1) };
+            if updated == 0 {
+                map.remove(&key);
+            } else {
+                map.insert(key, updated);
+            }
+        }
+    });
+}
+
+/// Co-occurrence counts of `tag` as recorded in TAG_COOCCURRENCE (related tag -> shelves sharing both).
+pub fn stored_tag_cooccurrence(tag: &NormalizedTag) -> BTreeMap<NormalizedTag, u64> {
+    TAG_COOCCURRENCE.with(|map_ref| {
+        let start_key = TagCooccurrenceKey { tag: tag.clone(), related_tag: String::new() };
+        map_ref.borrow()
+            .range(start_key..)
+            .take_while(|(key, _)| &key.tag == tag)
+            .map(|(key, count)| (key.related_tag, count))
+            .collect()
+    })
+}
+
+/// Co-occurrence counts of `tag` derived from the shelf/tag association indexes.
+pub fn expected_tag_cooccurrence(tag: &NormalizedTag) -> BTreeMap<NormalizedTag, u64> {
+    let shelf_ids: Vec<ShelfId> = TAG_SHELF_ASSOCIATIONS.with(|map_ref| {
+        let start_key = CrateTagShelfAssociationKey(tag.clone(), String::new());
+        map_ref.borrow()
+            .range(start_key..)
+            .take_while(|(key, _)| &key.0 == tag)
+            .map(|(key, _)| key.1)
+            .collect()
+    });
+    let mut counts: BTreeMap<NormalizedTag, u64> = BTreeMap::new();
+    for shelf_id in &shelf_ids {
+        for related_tag in shelf_associated_tags(shelf_id) {
+            if &related_tag != tag {
+                *counts.entry(related_tag).or_insert(0) += 1;
+            }
+        }
+    }
+    counts
+}
+
+/// Replaces the TAG_COOCCURRENCE entries of `tag` with the given counts.
+/// Only the `(tag, related_tag)` direction is written; the reverse entries belong to the related tags.
+pub fn replace_tag_cooccurrence(tag: &NormalizedTag, counts: BTreeMap<NormalizedTag, u64>) {
+    let stale: Vec<NormalizedTag> = stored_tag_cooccurrence(tag).into_keys()
+        .filter(|related_tag| !counts.contains_key(related_tag))
+        .collect();
+    TAG_COOCCURRENCE.with(|map_ref| {
+        let mut map = map_ref.borrow_mut();
+        for related_tag in stale {
+            map.remove(&TagCooccurrenceKey { tag: tag.clone(), related_tag });
+        }
+        for (related_tag, count) in counts {
+            map.insert(TagCooccurrenceKey { tag: tag.clone(), related_tag }, count);
+        }
+    });
+}
@@ -0,0 +1,247 @@
+use candid::{CandidType, Decode, Deserialize, Encode};
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::cell::RefCell; // Required for MAP.with, etc.
+
+// Imports from parent storage module
+use super::{MEMORY_MANAGER, Memory, MemoryId};
+
+// Import common types from sibling module
+use super::common_types::{ShelfId, NormalizedTag};
+
+// Imports from sibling storage modules
+use super::shelf_storage::SHELF_DATA;
+use super::tag_storage::{is_tag_blocked, shelf_associated_tags};
+
+// --- Constants ---
+const NANOS_PER_HOUR: u64 = 60 * 60 * 1_000_000_000;
+pub const MAX_TRENDING_ENTRIES: usize = 500; // Per window, per index
+const MAX_EVENTS_SCANNED: usize = 50_000; // Bounds instruction usage of a recompute
+const MAX_EVENTS_PRUNED: usize = 10_000; // Per recompute; any backlog is pruned on later runs
+const SCORE_SCALE: f64 = 1_000.0; // Scores are stored as fixed point in index keys
+
+// --- TrendingWindow ---
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TrendingWindow {
+    Day,
+    Week,
+    Month,
+}
+
+impl TrendingWindow {
+    pub const ALL: [TrendingWindow; 3] = [TrendingWindow::Day, TrendingWindow::Week, TrendingWindow::Month];
+
+    /// Activity older than this no longer contributes to the window.
+    pub fn lookback_ns(&self) -> u64 {
+        match self {
+            TrendingWindow::Day => 24 * NANOS_PER_HOUR,
+            TrendingWindow::Week => 7 * 24 * NANOS_PER_HOUR,
+            TrendingWindow::Month => 30 * 24 * NANOS_PER_HOUR,
+        }
+    }
+
+    /// Age at which an event counts for half its weight.
+    pub fn half_life_ns(&self) -> u64 {
+        match self {
+            TrendingWindow::Day => 6 * NANOS_PER_HOUR,
+            TrendingWindow::Week => 2 * 24 * NANOS_PER_HOUR,
+            TrendingWindow::Month => 7 * 24 * NANOS_PER_HOUR,
+        }
+    }
+}
+
+// --- ActivityEvent (ACTIVITY_LOG value) ---
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ActivityKind {
+    ShelfCreated, // subject: shelf_id
+    ItemAdded,    // subject: shelf_id
+    TagFollowed,  // subject: tag
+}
+
+impl ActivityKind {
+    fn weight(&self) -> f64 {
+        match self {
+            ActivityKind::ShelfCreated => 3.0,
+            ActivityKind::ItemAdded => 1.0,
+            ActivityKind::TagFollowed => 2.0,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ActivityEvent {
+    pub kind: ActivityKind,
+    pub subject: String,
+}
+
+impl Storable for ActivityEvent {
+    fn to_bytes(&self) -> Cow<'_, [u8]> { Cow::Owned(Encode!(self).unwrap()) }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self { Decode!(bytes.as_ref(), Self).unwrap() }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// --- TrendingScoreKey (window, reversed score, id) for TRENDING_TAGS / TRENDING_SHELVES ---
+// Ascending iteration within a window yields the highest score first.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TrendingScoreKey {
+    pub window: TrendingWindow,
+    pub reversed_score: u64,
+    pub id: String, // Tag or ShelfId
+}
+
+impl TrendingScoreKey {
+    /// Smallest possible key for a window, used as the range start.
+    pub fn window_start(window: TrendingWindow) -> Self {
+        Self { window, reversed_score: 0, id: String::new() }
+    }
+}
+
+impl Storable for TrendingScoreKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> { Cow::Owned(Encode!(self).unwrap()) }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self { Decode!(bytes.as_ref(), Self).unwrap() }
+    const BOUND: Bound = Bound::Unbounded;
+}
+impl PartialOrd for TrendingScoreKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+impl Ord for TrendingScoreKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.window.cmp(&other.window)
+            .then_with(|| self.reversed_score.cmp(&other.reversed_score))
+            .then_with(|| self.id.cmp(&other.id))
+    }
+}
+
+// Memory IDs
+pub(crate) const ACTIVITY_LOG_MEM_ID: MemoryId = MemoryId::new(26);
+pub(crate) const TRENDING_TAGS_MEM_ID: MemoryId = MemoryId::new(27);
+pub(crate) const TRENDING_SHELVES_MEM_ID: MemoryId = MemoryId::new(28);
+
+thread_local! {
+    // K: timestamp (nanoseconds, bumped on collision), V: activity event
+    pub static ACTIVITY_LOG: RefCell<StableBTreeMap<u64, ActivityEvent, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(ACTIVITY_LOG_MEM_ID)))
+    );
+    // K: (window, reversed score, tag), recomputed periodically
+    pub static TRENDING_TAGS: RefCell<StableBTreeMap<TrendingScoreKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(TRENDING_TAGS_MEM_ID)))
+    );
+    // K: (window, reversed score, shelf_id), recomputed periodically
+    pub static TRENDING_SHELVES: RefCell<StableBTreeMap<TrendingScoreKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(TRENDING_SHELVES_MEM_ID)))
+    );
+}
+
+/// Appends an event to the activity log used for trending scores.
+pub fn record_activity(kind: ActivityKind, subject: String) {
+    let mut key = ic_cdk::api::time();
+    ACTIVITY_LOG.with(|log_ref| {
+        let mut log = log_ref.borrow_mut();
+        while log.contains_key(&key) {
+            key += 1; // Several events within one message share the same timestamp
+        }
+        log.insert(key, ActivityEvent { kind, subject });
+    });
+}
+
+fn decayed_weight(kind: ActivityKind, age_ns: u64, half_life_ns: u64) -> f64 {
+    kind.weight() * 0.5f64.powf(age_ns as f64 / half_life_ns as f64)
+}
+
+fn replace_window_entries(
+    index: &'static std::thread::LocalKey<RefCell<StableBTreeMap<TrendingScoreKey, (), Memory>>>,
+    window: TrendingWindow,
+    scores: BTreeMap<String, f64>,
+) {
+    let mut ranked: Vec<(String, f64)> = scores.into_iter().collect();
+    ranked.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal).then_with(|| a.0.cmp(&b.0)));
+    ranked.truncate(MAX_TRENDING_ENTRIES);
+
+    index.with(|index_ref| {
+        let mut index = index_ref.borrow_mut();
+        let stale_keys: Vec<TrendingScoreKey> = index
+            .range(TrendingScoreKey::window_start(window)..)
+            .take_while(|(key, _)| key.window == window)
+            .map(|(key, _)| key)
+            .collect();
+        for key in stale_keys {
+            index.remove(&key);
+        }
+
+        for (id, score) in ranked {
+            let scaled = (score * SCORE_SCALE) as u64;
+            index.insert(TrendingScoreKey { window, reversed_score: u64::MAX - scaled, id }, ());
+        }
+    });
+}
+
+/// Recomputes trending tags and shelves for every window from the activity log,
+/// and prunes up to `MAX_EVENTS_PRUNED` events older than the longest window.
+///
+/// Shelf events also count towards the shelf's current tags (read from
+/// SHELF_TAG_ASSOCIATIONS). Deleted shelves and blocked tags are skipped.
+pub fn recompute_trending_scores() {
+    let now = ic_cdk::api::time();
+    let max_lookback = TrendingWindow::ALL.iter().map(|w| w.lookback_ns()).max().unwrap_or(0);
+    let oldest_kept = now.saturating_sub(max_lookback);
+
+    // --- 1. Prune expired events ---
+    ACTIVITY_LOG.with(|log_ref| {
+        let mut log = log_ref.borrow_mut();
+        let expired: Vec<u64> = log.range(..oldest_kept).take(MAX_EVENTS_PRUNED).map(|(k, _)| k).collect();
+        for key in expired {
+            log.remove(&key);
+        }
+    });
+
+    // --- 2. Load recent events (newest first, bounded) ---
+    let events: Vec<(u64, ActivityEvent)> = ACTIVITY_LOG.with(|log_ref| {
+        log_ref.borrow().iter().rev().take(MAX_EVENTS_SCANNED).collect()
+    });
+
+    // Resolve each shelf's current tags once
+    let mut shelf_tags: BTreeMap<ShelfId, Option<Vec<NormalizedTag>>> = BTreeMap::new();
+    for (_, event) in &events {
+        if event.kind != ActivityKind::TagFollowed && !shelf_tags.contains_key(&event.subject) {
+            let exists = SHELF_DATA.with(|sds| sds.borrow().contains_key(&event.subject));
+            let tags = exists.then(|| shelf_associated_tags(&event.subject));
+            shelf_tags.insert(event.subject.clone(), tags);
+        }
+    }
+
+    // --- 3. Score every window ---
+    for window in TrendingWindow::ALL {
+        let window_start = now.saturating_sub(window.lookback_ns());
+        let mut tag_scores: BTreeMap<NormalizedTag, f64> = BTreeMap::new();
+        let mut shelf_scores: BTreeMap<ShelfId, f64> = BTreeMap::new();
+
+        for (timestamp, event) in &events {
+            if *timestamp < window_start {
+                break; // Events are newest first
+            }
+            let weight = decayed_weight(event.kind, now.saturating_sub(*timestamp), window.half_life_ns());
+
+            match event.kind {
+                ActivityKind::TagFollowed => {
+                    *tag_scores.entry(event.subject.clone()).or_insert(0.0) += weight;
+                }
+                ActivityKind::ShelfCreated | ActivityKind::ItemAdded => {
+                    let Some(Some(tags)) = shelf_tags.get(&event.subject) else {
+                        continue; // Shelf no longer exists
+                    };
+                    *shelf_scores.entry(event.subject.clone()).or_insert(0.0) += weight;
+                    for tag in tags {
+                        *tag_scores.entry(tag.clone()).or_insert(0.0) += weight;
+                    }
+                }
+            }
+        }
+
+        tag_scores.retain(|tag, _| !is_tag_blocked(tag));
+
+        replace_window_entries(&TRENDING_TAGS, window, tag_scores);
+        replace_window_entries(&TRENDING_SHELVES, window, shelf_scores);
+    }
+}
@@ -26,17 +26,31 @@ impl Storable for TimestampedShelves {
 // --- UserProfileOrder ---
 #[derive(CandidType, Deserialize, Clone, Debug, Default)]
 pub struct UserProfileOrderSerializable {
-    shelf_positions: Vec<(ShelfId, f64)>,
+    shelf_positions: Vec<(ShelfId, f64)>,          // Legacy f64 positions, only read to migrate old entries
+    shelf_ranks: Option<Vec<(ShelfId, String)>>,   // Rank keys in order (None for entries written before ranks)
     is_customized: bool,
 }
 
 impl UserProfileOrderSerializable {
      pub fn from_uop(uop: &UserProfileOrder) -> Self {
          Self {
-             shelf_positions: uop.shelf_positions.get_ordered_entries(),
+             shelf_positions: Vec::new(),
+             shelf_ranks: Some(uop.shelf_positions.get_ordered_entries()),
              is_customized: uop.is_customized,
          }
      }
+
+     /// Rebuilds the tracker, migrating legacy f64 positions to rank keys when no ranks are stored.
+     pub fn into_uop(self) -> UserProfileOrder {
+         let shelf_positions = match self.shelf_ranks {
+             Some(ranks) => PositionTracker::from_ranked_entries(ranks),
+             None => PositionTracker::from_legacy_positions(self.shelf_positions),
+         };
+         UserProfileOrder {
+             shelf_positions,
+             is_customized: self.is_customized,
+         }
+     }
 }
 
 #[derive(Clone, Debug, Default)]
@@ -47,22 +61,12 @@ pub struct UserProfileOrder {
 
 impl Storable for UserProfileOrder {
      fn to_bytes(&self) -> Cow<[u8]> {
-        let serializable = UserProfileOrderSerializable {
-             shelf_positions: self.shelf_positions.get_ordered_entries(),
-             is_customized: self.is_customized,
-         };
+         let serializable = UserProfileOrderSerializable::from_uop(self);
          Cow::Owned(Encode!(&serializable).expect("Failed to encode UserProfileOrderSerializable"))
      }
      fn from_bytes(bytes: Cow<[u8]>) -> Self {
          let serializable: UserProfileOrderSerializable = Decode!(bytes.as_ref(), UserProfileOrderSerializable).expect("Failed to decode UserProfileOrderSerializable");
-         let mut shelf_positions = PositionTracker::<ShelfId>::new();
-         for (key, pos) in serializable.shelf_positions {
-             shelf_positions.insert(key, pos);
-         }
-         Self {
-             shelf_positions,
-             is_customized: serializable.is_customized,
-         }
+         serializable.into_uop()
      }
     const BOUND: Bound = Bound::Unbounded;
 }
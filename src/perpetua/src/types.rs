@@ -5,7 +5,15 @@ use candid::{Encode, Decode};
 use ic_stable_structures::{storable::Bound, Storable};
 
 // Import necessary types (adjust path if needed)
-use crate::storage::{NormalizedTag, ShelfId, ShelfBackupData};
+use candid::Principal;
+use crate::storage::{
+    NormalizedTag, ShelfId, ShelfBackupData, ShelfData, GlobalTimelineItemValue, ShelfForkKey,
+    TimestampedShelves, UserProfileOrderSerializable, StringVec, TagMetadata, ShelfTagAssociationKey,
+    TagShelfCreationTimelineKey, TagCooccurrenceKey, PrincipalSet, NormalizedTagSet,
+    UserFollowerKey, TagFollowerKey, FollowLimits, SearchTermKey, SearchTermSet,
+    ActivityEvent, TrendingScoreKey, Notification, NotificationKindSet,
+    ShelfAccessKey, AccessGrant, ShelfStatsKey, ShelfStatsBucket, DailyShelfViewsKey, ShelfItemKey,
+};
 
 // --- TagPopularityKey Definition ---
 
@@ -79,8 +87,147 @@ pub struct BackupPaginationInput {
     pub limit: u64,
 }
 
+/// Where `migrate_position_ranks` resumes, one cursor per map (the last key rewritten).
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct RankMigrationCursor {
+    pub shelves_after: Option<ShelfId>,
+    pub profiles_after: Option<Principal>,
+    pub shelves_done: bool,
+    pub profiles_done: bool,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct RankMigrationProgress {
+    pub shelves_rewritten: u64,
+    pub profiles_rewritten: u64,
+    pub next_cursor: Option<RankMigrationCursor>, // None once both maps are migrated
+}
+
+/// Where `merge_tags` resumes, one cursor per index (the last shelf / follower moved).
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct TagMergeCursor {
+    pub shelves_after: Option<ShelfId>,
+    pub followers_after: Option<Principal>,
+    pub shelves_done: bool,
+    pub followers_done: bool,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TagMergeProgress {
+    pub shelves_retagged: u64,
+    pub followers_moved: u64,
+    pub next_cursor: Option<TagMergeCursor>, // None once every shelf and follower is moved
+}
+
 #[derive(CandidType, Deserialize, Clone, Debug)]
 pub struct ShelvesEssentialBackupChunk {
     pub data: Vec<ShelfBackupData>,
     pub total_count: u64,
-} 
\ No newline at end of file
+}
+
+// --- Full backup / restore ---
+
+/// Stable structure selected by `export_backup_chunk`.
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackupMap {
+    // Source data (accepted by `import_backup_chunk`)
+    Shelves,
+    UserProfileOrders,
+    FollowedUsers,
+    FollowedTags,
+    FollowLimits,
+    TagAliases,
+    TagBlocklist,
+    ActivityLog,
+    Notifications,
+    MutedNotificationKinds,
+    ShelfAccessGrants,
+    ShelfStats,
+    DailyShelfViews,
+    ItemOpenCounts,
+    // Derived indexes (rebuilt by `rebuild_restored_indexes` / `finish_restore`)
+    GlobalTimeline,
+    UserShelves,
+    NftShelves,
+    RandomShelfCandidates,
+    ShelfForks,
+    ShelfForkCounts,
+    TagMetadata,
+    TagShelfAssociations,
+    ShelfTagAssociations,
+    TagPopularityIndex,
+    TagLexicalIndex,
+    TagShelfCreationTimeline,
+    TagCooccurrence,
+    UserFollowers,
+    TagFollowers,
+    UserFollowerCounts,
+    TagFollowerCounts,
+    SearchIndex,
+    ShelfSearchTerms,
+    TrendingTags,
+    TrendingShelves,
+    MostViewedShelves,
+}
+
+/// Entries of one stable structure, in key order.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum BackupData {
+    Shelves(Vec<ShelfData>),
+    UserProfileOrders(Vec<(Principal, UserProfileOrderSerializable)>),
+    FollowedUsers(Vec<(Principal, PrincipalSet)>),
+    FollowedTags(Vec<(Principal, NormalizedTagSet)>),
+    FollowLimits(FollowLimits),
+    TagAliases(Vec<(NormalizedTag, NormalizedTag)>),
+    TagBlocklist(Vec<NormalizedTag>),
+    ActivityLog(Vec<(u64, ActivityEvent)>),
+    Notifications(Vec<(Principal, Notification)>),
+    MutedNotificationKinds(Vec<(Principal, NotificationKindSet)>),
+    ShelfAccessGrants(Vec<(ShelfAccessKey, AccessGrant)>),
+    ShelfStats(Vec<(ShelfStatsKey, ShelfStatsBucket)>),
+    DailyShelfViews(Vec<(DailyShelfViewsKey, u64)>),
+    ItemOpenCounts(Vec<(ShelfItemKey, u64)>),
+    GlobalTimeline(Vec<(u64, GlobalTimelineItemValue)>),
+    UserShelves(Vec<(Principal, TimestampedShelves)>),
+    NftShelves(Vec<(String, StringVec)>),
+    RandomShelfCandidates(Vec<(u32, ShelfId)>),
+    ShelfForks(Vec<ShelfForkKey>),
+    ShelfForkCounts(Vec<(ShelfId, u64)>),
+    TagMetadata(Vec<(NormalizedTag, TagMetadata)>),
+    TagShelfAssociations(Vec<TagShelfAssociationKey>),
+    ShelfTagAssociations(Vec<ShelfTagAssociationKey>),
+    TagPopularityIndex(Vec<TagPopularityKey>),
+    TagLexicalIndex(Vec<NormalizedTag>),
+    TagShelfCreationTimeline(Vec<TagShelfCreationTimelineKey>),
+    TagCooccurrence(Vec<(TagCooccurrenceKey, u64)>),
+    UserFollowers(Vec<UserFollowerKey>),
+    TagFollowers(Vec<TagFollowerKey>),
+    UserFollowerCounts(Vec<(Principal, u64)>),
+    TagFollowerCounts(Vec<(NormalizedTag, u64)>),
+    SearchIndex(Vec<(SearchTermKey, u32)>),
+    ShelfSearchTerms(Vec<(ShelfId, SearchTermSet)>),
+    TrendingTags(Vec<TrendingScoreKey>),
+    TrendingShelves(Vec<TrendingScoreKey>),
+    MostViewedShelves(Vec<TrendingScoreKey>),
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct BackupChunk {
+    pub data: BackupData,
+    pub total_count: u64,
+}
+
+/// Index sizes compared by `finish_restore`. `issues` is empty when everything matches.
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct RestoreReport {
+    pub shelves: u64,
+    pub timeline_entries: u64,
+    pub user_shelf_entries: u64,
+    pub expected_tag_associations: u64,
+    pub tag_associations: u64,
+    pub followed_user_entries: u64,
+    pub user_follower_entries: u64,
+    pub followed_tag_entries: u64,
+    pub tag_follower_entries: u64,
+    pub issues: Vec<String>,
+}
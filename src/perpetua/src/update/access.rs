@@ -1,6 +1,15 @@
-use crate::storage::{GLOBAL_TIMELINE, ShelfId, ShelfData, SHELF_DATA};
-use crate::guard::not_anon;
+use crate::storage::{
+    GLOBAL_TIMELINE, ShelfId, SHELF_DATA,
+    ShelfAccessPolicy, TokenGate, AccessGrant, AccessGrantKind,
+    has_shelf_access, get_active_access_grant, store_access_grant, TOKEN_ACCESS_GRANT_TTL_NS,
+    index_shelf_for_search,
+};
+use crate::guard::{not_anon, not_anon_writable, CallerGuard};
+use crate::auth::get_shelf_parts_for_owner_mut;
+use crate::update::utils::{verify_icrc7_token_ownership, verify_icrc7_collection_holder};
+use crate::nft_manager_principal;
 use ic_cdk;
+use ic_cdk::api::call::CallResult;
 use candid::Principal;
 
 /// Toggles public access for a shelf
@@ -8,7 +17,7 @@ use candid::Principal;
 /// When enabled, anyone can edit the shelf.
 /// Only the shelf owner can toggle this setting.
 /// This function ensures that updates to SHELF_METADATA and GLOBAL_TIMELINE are atomic.
-#[ic_cdk::update(guard = "not_anon")]
+#[ic_cdk::update(guard = "not_anon_writable")]
 pub fn toggle_shelf_public_access(shelf_id: ShelfId, public_editing: bool) -> Result<(), String> {
     let caller = ic_cdk::caller();
     let now = ic_cdk::api::time(); // Get time once for consistency
@@ -27,6 +36,9 @@ pub fn toggle_shelf_public_access(shelf_id: ShelfId, public_editing: bool) -> Re
                 if existing_shelf_data.metadata.owner != caller {
                     return Err("Unauthorized: Only shelf owner can toggle public access.".to_string());
                 }
+                if public_editing && existing_shelf_data.metadata.access_policy() != ShelfAccessPolicy::Public {
+                    return Err("Gated shelves cannot be publicly editable.".to_string());
+                }
 
                 // Clone and prepare the updated ShelfData
                 let mut updated_shelf_data = existing_shelf_data.clone();
@@ -105,4 +117,95 @@ pub fn is_shelf_public(shelf_id: String) -> Result<bool, String> {
             None => Err(format!("Shelf with ID '{}' not found", shelf_id))
         }
     })
-} 
\ No newline at end of file
+}
+
+/// Sets who can read a shelf's items (owner only)
+/// 
+/// Gated shelves show callers without access a preview of the first items. Existing unlocks
+/// are kept when the policy changes. Gated shelves cannot be publicly editable.
+/// The shelf is reindexed for search so only the preview items of gated shelves are searchable.
+#[ic_cdk::update(guard = "not_anon_writable")]
+pub fn set_shelf_access_policy(shelf_id: ShelfId, policy: ShelfAccessPolicy) -> Result<(), String> {
+    let caller = ic_cdk::caller();
+    policy.validate()?;
+
+    get_shelf_parts_for_owner_mut(&shelf_id, &caller, |metadata, _| {
+        if policy != ShelfAccessPolicy::Public && metadata.public_editing {
+            return Err("Disable public editing before gating this shelf".to_string());
+        }
+        metadata.access_policy = match policy {
+            ShelfAccessPolicy::Public => None,
+            gated => Some(gated),
+        };
+        Ok(())
+    })?;
+
+    if let Some(shelf_data) = SHELF_DATA.with(|sds| sds.borrow().get(&shelf_id)) {
+        index_shelf_for_search(&shelf_id, &shelf_data);
+    }
+    Ok(())
+}
+
+/// Unlocks a gated shelf for the caller
+/// 
+/// Token-gated shelves check the caller's holdings and cache the result for a day.
+/// Pay-per-access shelves charge the one-time LBRY price through nft_manager, paid to the
+/// shelf owner, and unlock the shelf permanently. Calling this with access already granted
+/// does nothing. A caller can only have one unlock in flight, so a retry can't be charged twice.
+#[ic_cdk::update(guard = "not_anon_writable")]
+pub async fn unlock_shelf(shelf_id: ShelfId) -> Result<(), String> {
+    let caller = ic_cdk::caller();
+    let _guard = CallerGuard::new(caller)?;
+
+    let metadata = SHELF_DATA.with(|sds| sds.borrow().get(&shelf_id).map(|sd| sd.metadata))
+        .ok_or_else(|| format!("Shelf with ID '{}' not found", shelf_id))?;
+    if has_shelf_access(&metadata, caller) {
+        return Ok(());
+    }
+
+    let now = ic_cdk::api::time();
+    let grant = match metadata.access_policy() {
+        ShelfAccessPolicy::Public => return Ok(()),
+        ShelfAccessPolicy::TokenGated(gate) => {
+            let holds_token = match &gate {
+                TokenGate::AnyTokenInCollection(collection) => verify_icrc7_collection_holder(*collection, caller).await?,
+                TokenGate::SpecificToken { collection, token_id } => verify_icrc7_token_ownership(*collection, token_id, caller).await?,
+            };
+            if !holds_token {
+                return Err("You do not hold the token required to unlock this shelf".to_string());
+            }
+            AccessGrant {
+                kind: AccessGrantKind::TokenHolder,
+                granted_at: now,
+                expires_at: Some(now + TOKEN_ACCESS_GRANT_TTL_NS),
+            }
+        }
+        ShelfAccessPolicy::PayPerAccess { price_e8s } => {
+            let payment_result: CallResult<(Result<String, String>,)> = ic_cdk::call(
+                nft_manager_principal(),
+                "pay_shelf_access_fee",
+                (caller, metadata.owner, price_e8s)
+            ).await;
+
+            match payment_result {
+                Ok((Ok(_),)) => {}
+                Ok((Err(error_msg),)) => return Err(error_msg),
+                Err((code, msg)) => return Err(format!("Payment service unavailable: {:?} - {}", code, msg)),
+            }
+            AccessGrant {
+                kind: AccessGrantKind::Paid { price_e8s },
+                granted_at: now,
+                expires_at: None,
+            }
+        }
+    };
+
+    store_access_grant(&shelf_id, caller, grant);
+    Ok(())
+}
+
+/// Gets the caller's unlock for a gated shelf, if they have an unexpired one
+#[ic_cdk::query(guard = "not_anon")]
+pub fn get_my_shelf_access(shelf_id: ShelfId) -> Option<AccessGrant> {
+    get_active_access_grant(&shelf_id, ic_cdk::caller())
+}
@@ -0,0 +1,585 @@
+use candid::Principal;
+use ic_cdk;
+use std::collections::BTreeSet;
+use std::ops::Bound;
+use std::time::Duration;
+
+use crate::storage::{
+    ShelfId, ShelfData, NormalizedTag, ItemContent, GlobalTimelineItemValue, TagMetadata,
+    SHELF_DATA, GLOBAL_TIMELINE, USER_SHELVES, NFT_SHELVES,
+    TAG_METADATA, TAG_SHELF_ASSOCIATIONS, SHELF_TAG_ASSOCIATIONS, TAG_POPULARITY_INDEX, TAG_LEXICAL_INDEX,
+    TAG_SHELF_CREATION_TIMELINE_INDEX,
+    ShelfTagAssociationKey, TagShelfCreationTimelineKey,
+    AuditState, AuditPhase, AuditIssueKind,
+    get_audit_state, update_audit_state, record_audit_finding, clear_audit_findings,
+    is_restore_in_progress, MAX_APPEARS_IN_COUNT,
+    stored_tag_cooccurrence, expected_tag_cooccurrence, replace_tag_cooccurrence,
+};
+use crate::types::{TagPopularityKey, TagShelfAssociationKey as TypesTagShelfAssociationKey};
+use crate::utils::id_conversion;
+use crate::guard::is_controller;
+use super::item::MAX_NFT_REFERENCES;
+use super::tags::{add_tag_to_metadata_maps, remove_tag_from_metadata_maps};
+
+// --- Constants ---
+const AUDIT_TICK_INTERVAL_SECS: u64 = 60;                           // One step per minute while a pass runs
+const AUDIT_PASS_INTERVAL_NS: u64 = 24 * 60 * 60 * 1_000_000_000;   // A new pass starts daily
+const AUDIT_STEP_BUDGET: usize = 100;                               // Entries checked per step
+
+/// Starts the periodic audit. Timers do not survive upgrades,
+/// so this runs from both `init` and `post_upgrade`.
+pub fn setup_audit_timer() {
+    ic_cdk_timers::set_timer_interval(Duration::from_secs(AUDIT_TICK_INTERVAL_SECS), audit_tick);
+}
+
+fn audit_tick() {
+    if is_restore_in_progress() {
+        return;
+    }
+    let state = get_audit_state();
+    if state.phase == AuditPhase::Idle {
+        if ic_cdk::api::time().saturating_sub(state.last_completed_at) < AUDIT_PASS_INTERVAL_NS {
+            return;
+        }
+        begin_audit_pass();
+    }
+    run_audit_step(AUDIT_STEP_BUDGET);
+}
+
+fn begin_audit_pass() {
+    clear_audit_findings();
+    update_audit_state(|state| {
+        state.phase = AuditPhase::Shelves { after: None };
+        state.pass_started_at = ic_cdk::api::time();
+        state.entries_checked = 0;
+        state.issues_found = 0;
+        state.issues_repaired = 0;
+    });
+}
+
+/// Checks up to `budget` entries of the current phase and advances the cursor.
+fn run_audit_step(budget: usize) {
+    let state = get_audit_state();
+    let repair = state.repair_enabled;
+
+    let (checked, next_phase) = match state.phase {
+        AuditPhase::Idle => return,
+        AuditPhase::Shelves { after } => audit_shelves(after, budget, repair),
+        AuditPhase::Timeline { after } => audit_timeline(after, budget, repair),
+        AuditPhase::UserShelves { after } => audit_user_shelves(after, budget, repair),
+        AuditPhase::Tags { after } => audit_tags(after, budget, repair),
+        AuditPhase::NftShelves { after } => audit_nft_shelves(after, budget, repair),
+    };
+
+    update_audit_state(|state| {
+        state.entries_checked += checked;
+        if next_phase == AuditPhase::Idle {
+            state.last_completed_at = ic_cdk::api::time();
+        }
+        state.phase = next_phase;
+    });
+}
+
+/// Reads the next page of a map after `after`, returning the page and whether more entries may follow.
+macro_rules! next_page {
+    ($map:expr, $after:expr, $budget:expr) => {{
+        let start_bound = match $after {
+            Some(ref key) => Bound::Excluded(key.clone()),
+            None => Bound::Unbounded,
+        };
+        let page: Vec<_> = $map.with(|map_ref| {
+            map_ref.borrow()
+                .range((start_bound, Bound::Unbounded))
+                .take($budget)
+                .collect()
+        });
+        let exhausted = page.len() < $budget;
+        (page, exhausted)
+    }};
+}
+
+fn shelf_has_nested(shelf_data: &ShelfData, nested_shelf_id: &ShelfId) -> bool {
+    shelf_data.content.items.values()
+        .any(|item| matches!(&item.content, ItemContent::Shelf(id) if id == nested_shelf_id))
+}
+
+fn timeline_value_for(shelf_data: &ShelfData) -> GlobalTimelineItemValue {
+    GlobalTimelineItemValue {
+        shelf_id: shelf_data.metadata.shelf_id.clone(),
+        owner: shelf_data.metadata.owner,
+        tags: shelf_data.metadata.tags.clone(),
+        public_editing: shelf_data.metadata.public_editing,
+    }
+}
+
+// --- Phase 1: SHELF_DATA against every derived index ---
+
+fn audit_shelves(after: Option<ShelfId>, budget: usize, repair: bool) -> (u64, AuditPhase) {
+    let (page, exhausted) = next_page!(SHELF_DATA, after, budget);
+    let last_key = page.last().map(|(shelf_id, _)| shelf_id.clone());
+
+    for (shelf_id, shelf_data) in &page {
+        audit_shelf(shelf_id, shelf_data, repair);
+    }
+
+    let next_phase = if exhausted {
+        AuditPhase::Timeline { after: None }
+    } else {
+        AuditPhase::Shelves { after: last_key }
+    };
+    (page.len() as u64, next_phase)
+}
+
+fn audit_shelf(shelf_id: &ShelfId, shelf_data: &ShelfData, repair: bool) {
+    let metadata = &shelf_data.metadata;
+    let now = ic_cdk::api::time();
+
+    // --- GLOBAL_TIMELINE ---
+    let expected_timeline_value = timeline_value_for(shelf_data);
+    match GLOBAL_TIMELINE.with(|t| t.borrow().get(&metadata.created_at)) {
+        None => {
+            if repair {
+                GLOBAL_TIMELINE.with(|t| t.borrow_mut().insert(metadata.created_at, expected_timeline_value));
+            }
+            record_audit_finding(AuditIssueKind::MissingTimelineEntry, Some(shelf_id.clone()),
+                format!("No GLOBAL_TIMELINE entry at {}", metadata.created_at), repair);
+        }
+        Some(existing) if existing.shelf_id != *shelf_id => {
+            // Another shelf owns this timestamp; overwriting it would only move the problem.
+            record_audit_finding(AuditIssueKind::MissingTimelineEntry, Some(shelf_id.clone()),
+                format!("GLOBAL_TIMELINE key {} holds shelf {}", metadata.created_at, existing.shelf_id), false);
+        }
+        Some(existing) => {
+            if existing.owner != expected_timeline_value.owner
+                || existing.tags != expected_timeline_value.tags
+                || existing.public_editing != expected_timeline_value.public_editing
+            {
+                if repair {
+                    GLOBAL_TIMELINE.with(|t| t.borrow_mut().insert(metadata.created_at, expected_timeline_value));
+                }
+                record_audit_finding(AuditIssueKind::StaleTimelineEntry, Some(shelf_id.clone()),
+                    "GLOBAL_TIMELINE owner, tags or public_editing differ from SHELF_DATA".to_string(), repair);
+            }
+        }
+    }
+
+    // --- USER_SHELVES ---
+    let user_entry = (metadata.created_at, shelf_id.clone());
+    let has_user_entry = USER_SHELVES.with(|us| {
+        us.borrow().get(&metadata.owner).is_some_and(|set| set.0.contains(&user_entry))
+    });
+    if !has_user_entry {
+        if repair {
+            USER_SHELVES.with(|us| {
+                let mut map = us.borrow_mut();
+                let mut set = map.get(&metadata.owner).unwrap_or_default();
+                set.0.insert(user_entry);
+                map.insert(metadata.owner, set);
+            });
+        }
+        record_audit_finding(AuditIssueKind::MissingUserShelfEntry, Some(shelf_id.clone()),
+            format!("Missing from USER_SHELVES of {}", metadata.owner), repair);
+    }
+
+    // --- Tag associations ---
+    let metadata_tags: BTreeSet<NormalizedTag> = metadata.tags.iter().cloned().collect();
+    let associated_tags: BTreeSet<NormalizedTag> = SHELF_TAG_ASSOCIATIONS.with(|map_ref| {
+        let start_key = ShelfTagAssociationKey { shelf_id: shelf_id.clone(), tag: String::new() };
+        map_ref.borrow()
+            .range(start_key..)
+            .take_while(|(key, _)| &key.shelf_id == shelf_id)
+            .map(|(key, _)| key.tag)
+            .collect()
+    });
+
+    for tag in metadata_tags.difference(&associated_tags) {
+        if repair {
+            add_tag_to_metadata_maps(shelf_id, tag, metadata.created_at, now);
+        }
+        record_audit_finding(AuditIssueKind::MissingTagAssociation, Some(shelf_id.clone()),
+            format!("Tag '{}' is not indexed", tag), repair);
+    }
+    for tag in associated_tags.difference(&metadata_tags) {
+        if repair {
+            remove_tag_from_metadata_maps(shelf_id, tag, metadata.created_at, now);
+        }
+        record_audit_finding(AuditIssueKind::StaleTagAssociation, Some(shelf_id.clone()),
+            format!("Tag '{}' is indexed but not on the shelf", tag), repair);
+    }
+    for tag in metadata_tags.intersection(&associated_tags) {
+        let tag_shelf_key = TypesTagShelfAssociationKey(tag.clone(), shelf_id.clone());
+        let timeline_key = TagShelfCreationTimelineKey {
+            tag: tag.clone(),
+            reversed_created_at: u64::MAX - metadata.created_at,
+            shelf_id: shelf_id.clone(),
+        };
+        let has_tag_shelf = TAG_SHELF_ASSOCIATIONS.with(|m| m.borrow().contains_key(&tag_shelf_key));
+        let has_timeline = TAG_SHELF_CREATION_TIMELINE_INDEX.with(|m| m.borrow().contains_key(&timeline_key));
+        if !has_tag_shelf || !has_timeline {
+            if repair {
+                TAG_SHELF_ASSOCIATIONS.with(|m| m.borrow_mut().insert(tag_shelf_key, ()));
+                TAG_SHELF_CREATION_TIMELINE_INDEX.with(|m| m.borrow_mut().insert(timeline_key, ()));
+            }
+            record_audit_finding(AuditIssueKind::MissingTagAssociation, Some(shelf_id.clone()),
+                format!("Tag '{}' is missing from TAG_SHELF_ASSOCIATIONS or the tag timeline", tag), repair);
+        }
+    }
+
+    // --- NFT_SHELVES ---
+    for item in shelf_data.content.items.values() {
+        let Some(key) = id_conversion::get_appearance_key_for_storage(&item.content) else {
+            continue;
+        };
+        let shelves = NFT_SHELVES.with(|m| m.borrow().get(&key)).unwrap_or_default();
+        // A full list is capped by MAX_NFT_REFERENCES, not drift.
+        if !shelves.0.contains(shelf_id) && shelves.0.len() < MAX_NFT_REFERENCES {
+            if repair {
+                let mut shelves = shelves;
+                shelves.0.push(shelf_id.clone());
+                NFT_SHELVES.with(|m| m.borrow_mut().insert(key.clone(), shelves));
+            }
+            record_audit_finding(AuditIssueKind::MissingNftReference, Some(shelf_id.clone()),
+                format!("Not listed in NFT_SHELVES for '{}'", key), repair);
+        }
+    }
+
+    // --- appears_in (this shelf's parents) ---
+    let stale_parents: Vec<ShelfId> = SHELF_DATA.with(|sds_ref| {
+        let sds = sds_ref.borrow();
+        metadata.appears_in.iter()
+            .filter(|parent_id| !sds.get(parent_id).is_some_and(|parent| shelf_has_nested(&parent, shelf_id)))
+            .cloned()
+            .collect()
+    });
+    if !stale_parents.is_empty() {
+        if repair {
+            SHELF_DATA.with(|sds_ref| {
+                let mut sds = sds_ref.borrow_mut();
+                if let Some(mut current) = sds.get(shelf_id) {
+                    current.metadata.appears_in.retain(|id| !stale_parents.contains(id));
+                    sds.insert(shelf_id.clone(), current);
+                }
+            });
+        }
+        for parent_id in &stale_parents {
+            record_audit_finding(AuditIssueKind::StaleAppearsIn, Some(shelf_id.clone()),
+                format!("appears_in lists '{}', which does not contain this shelf", parent_id), repair);
+        }
+    }
+
+    // --- appears_in (nested shelves pointing back to this one) ---
+    let nested_shelf_ids: BTreeSet<ShelfId> = shelf_data.content.items.values()
+        .filter_map(|item| match &item.content {
+            ItemContent::Shelf(nested_shelf_id) => Some(nested_shelf_id.clone()),
+            _ => None,
+        })
+        .collect();
+    for nested_shelf_id in nested_shelf_ids {
+        let Some(mut nested_shelf_data) = SHELF_DATA.with(|sds| sds.borrow().get(&nested_shelf_id)) else {
+            record_audit_finding(AuditIssueKind::DanglingNestedShelf, Some(shelf_id.clone()),
+                format!("Nested shelf '{}' does not exist", nested_shelf_id), false);
+            continue;
+        };
+        let appears_in = &nested_shelf_data.metadata.appears_in;
+        // A full list drops its oldest parents by design, so only check lists with room.
+        if !appears_in.contains(shelf_id) && appears_in.len() < MAX_APPEARS_IN_COUNT {
+            if repair {
+                nested_shelf_data.metadata.appears_in.push(shelf_id.clone());
+                SHELF_DATA.with(|sds| sds.borrow_mut().insert(nested_shelf_id.clone(), nested_shelf_data));
+            }
+            record_audit_finding(AuditIssueKind::MissingAppearsIn, Some(nested_shelf_id.clone()),
+                format!("appears_in does not list parent '{}'", shelf_id), repair);
+        }
+    }
+}
+
+// --- Phase 2: GLOBAL_TIMELINE entries without a matching shelf ---
+
+fn audit_timeline(after: Option<u64>, budget: usize, repair: bool) -> (u64, AuditPhase) {
+    let (page, exhausted) = next_page!(GLOBAL_TIMELINE, after, budget);
+    let last_key = page.last().map(|(timestamp, _)| *timestamp);
+
+    for (timestamp, entry) in &page {
+        let matches = SHELF_DATA.with(|sds| {
+            sds.borrow().get(&entry.shelf_id).is_some_and(|shelf| shelf.metadata.created_at == *timestamp)
+        });
+        if !matches {
+            if repair {
+                GLOBAL_TIMELINE.with(|t| t.borrow_mut().remove(timestamp));
+            }
+            record_audit_finding(AuditIssueKind::StaleTimelineEntry, Some(entry.shelf_id.clone()),
+                format!("GLOBAL_TIMELINE key {} has no matching shelf", timestamp), repair);
+        }
+    }
+
+    let next_phase = if exhausted {
+        AuditPhase::UserShelves { after: None }
+    } else {
+        AuditPhase::Timeline { after: last_key }
+    };
+    (page.len() as u64, next_phase)
+}
+
+// --- Phase 3: USER_SHELVES entries without a matching shelf ---
+
+fn audit_user_shelves(after: Option<Principal>, budget: usize, repair: bool) -> (u64, AuditPhase) {
+    let (page, exhausted) = next_page!(USER_SHELVES, after, budget);
+    let last_key = page.last().map(|(user, _)| *user);
+
+    for (user, shelves) in &page {
+        let stale: Vec<(u64, ShelfId)> = SHELF_DATA.with(|sds_ref| {
+            let sds = sds_ref.borrow();
+            shelves.0.iter()
+                .filter(|(created_at, shelf_id)| !sds.get(shelf_id).is_some_and(|shelf| {
+                    shelf.metadata.owner == *user && shelf.metadata.created_at == *created_at
+                }))
+                .cloned()
+                .collect()
+        });
+        if stale.is_empty() {
+            continue;
+        }
+        if repair {
+            let mut remaining = shelves.clone();
+            for entry in &stale {
+                remaining.0.remove(entry);
+            }
+            USER_SHELVES.with(|us| {
+                let mut map = us.borrow_mut();
+                if remaining.0.is_empty() {
+                    map.remove(user);
+                } else {
+                    map.insert(*user, remaining);
+                }
+            });
+        }
+        for (_, shelf_id) in stale {
+            record_audit_finding(AuditIssueKind::StaleUserShelfEntry, Some(shelf_id),
+                format!("USER_SHELVES of {} lists a shelf it does not own", user), repair);
+        }
+    }
+
+    let next_phase = if exhausted {
+        AuditPhase::Tags { after: None }
+    } else {
+        AuditPhase::UserShelves { after: last_key }
+    };
+    (page.len() as u64, next_phase)
+}
+
+// --- Phase 4: tag counts, popularity / lexical / co-occurrence indexes and dangling associations ---
+
+/// Next tag after `after` that has metadata or associations.
+fn next_audited_tag(after: &Option<NormalizedTag>) -> Option<NormalizedTag> {
+    let start_bound = match after {
+        Some(tag) => Bound::Excluded(tag.clone()),
+        None => Bound::Unbounded,
+    };
+    let next_metadata_tag = TAG_METADATA.with(|m| {
+        m.borrow().range((start_bound, Bound::Unbounded)).next().map(|(tag, _)| tag)
+    });
+    let next_associated_tag = TAG_SHELF_ASSOCIATIONS.with(|m| {
+        // (tag, "") sorts before every association of `tag`; skip past the current one.
+        let start_key = match after {
+            Some(tag) => TypesTagShelfAssociationKey(format!("{}\u{0}", tag), String::new()),
+            None => TypesTagShelfAssociationKey::default(),
+        };
+        m.borrow().range(start_key..).next().map(|(key, _)| key.0)
+    });
+    match (next_metadata_tag, next_associated_tag) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+fn audit_tags(after: Option<NormalizedTag>, budget: usize, repair: bool) -> (u64, AuditPhase) {
+    let mut checked: usize = 0;
+    let mut last_tag = after;
+
+    while checked < budget {
+        let Some(tag) = next_audited_tag(&last_tag) else {
+            return (checked as u64, AuditPhase::NftShelves { after: None });
+        };
+        checked += 1 + audit_tag(&tag, repair);
+        last_tag = Some(tag);
+    }
+
+    (checked as u64, AuditPhase::Tags { after: last_tag })
+}
+
+/// Audits one tag and returns the number of associations scanned.
+fn audit_tag(tag: &NormalizedTag, repair: bool) -> usize {
+    let associated_shelf_ids: Vec<ShelfId> = TAG_SHELF_ASSOCIATIONS.with(|m| {
+        let start_key = TypesTagShelfAssociationKey(tag.clone(), String::new());
+        m.borrow()
+            .range(start_key..)
+            .take_while(|(key, _)| &key.0 == tag)
+            .map(|(key, _)| key.1)
+            .collect()
+    });
+
+    // --- Associations for shelves that are gone or no longer carry the tag ---
+    let mut valid_count: u64 = 0;
+    for shelf_id in &associated_shelf_ids {
+        let shelf = SHELF_DATA.with(|sds| sds.borrow().get(shelf_id));
+        if shelf.as_ref().is_some_and(|s| s.metadata.tags.contains(tag)) {
+            valid_count += 1;
+            continue;
+        }
+        if repair {
+            TAG_SHELF_ASSOCIATIONS.with(|m| m.borrow_mut().remove(&TypesTagShelfAssociationKey(tag.clone(), shelf_id.clone())));
+            SHELF_TAG_ASSOCIATIONS.with(|m| m.borrow_mut().remove(&ShelfTagAssociationKey { shelf_id: shelf_id.clone(), tag: tag.clone() }));
+            TAG_SHELF_CREATION_TIMELINE_INDEX.with(|m| {
+                let mut map = m.borrow_mut();
+                let start_key = TagShelfCreationTimelineKey { tag: tag.clone(), reversed_created_at: 0, shelf_id: String::new() };
+                let stale_keys: Vec<TagShelfCreationTimelineKey> = map.range(start_key..)
+                    .take_while(|(key, _)| &key.tag == tag)
+                    .filter(|(key, _)| &key.shelf_id == shelf_id)
+                    .map(|(key, _)| key)
+                    .collect();
+                for key in stale_keys {
+                    map.remove(&key);
+                }
+            });
+        }
+        record_audit_finding(AuditIssueKind::StaleTagAssociation, Some(shelf_id.clone()),
+            format!("TAG_SHELF_ASSOCIATIONS links '{}' to a shelf without it", tag), repair);
+    }
+    if !repair {
+        valid_count = associated_shelf_ids.len() as u64; // Counts are compared against the index as it stands
+    }
+
+    // --- TAG_METADATA count, popularity and lexical indexes ---
+    let metadata = TAG_METADATA.with(|m| m.borrow().get(tag));
+    let recorded_count = metadata.as_ref().map_or(0, |md| md.current_shelf_count);
+    let has_popularity = recorded_count > 0
+        && TAG_POPULARITY_INDEX.with(|m| m.borrow().contains_key(&TagPopularityKey(recorded_count, tag.clone())));
+    let has_lexical = TAG_LEXICAL_INDEX.with(|m| m.borrow().contains_key(tag));
+
+    let consistent = recorded_count == valid_count
+        && has_popularity == (valid_count > 0)
+        && has_lexical == (valid_count > 0);
+    if !consistent {
+        if repair {
+            if recorded_count > 0 {
+                TAG_POPULARITY_INDEX.with(|m| m.borrow_mut().remove(&TagPopularityKey(recorded_count, tag.clone())));
+            }
+            if valid_count > 0 {
+                let now = ic_cdk::api::time();
+                let mut updated = metadata.unwrap_or_else(|| TagMetadata {
+                    first_seen_timestamp: now,
+                    ..TagMetadata::default()
+                });
+                updated.current_shelf_count = valid_count;
+                updated.last_active_timestamp = now;
+                TAG_METADATA.with(|m| m.borrow_mut().insert(tag.clone(), updated));
+                TAG_POPULARITY_INDEX.with(|m| m.borrow_mut().insert(TagPopularityKey(valid_count, tag.clone()), ()));
+                TAG_LEXICAL_INDEX.with(|m| m.borrow_mut().insert(tag.clone(), ()));
+            } else {
+                TAG_METADATA.with(|m| m.borrow_mut().remove(tag));
+                TAG_LEXICAL_INDEX.with(|m| m.borrow_mut().remove(tag));
+            }
+        }
+        record_audit_finding(AuditIssueKind::TagCountMismatch, None,
+            format!("Tag '{}': TAG_METADATA count {}, {} associations", tag, recorded_count, valid_count), repair);
+    }
+
+    // --- TAG_COOCCURRENCE (this tag's direction only; related tags check their own) ---
+    let expected_cooccurrence = expected_tag_cooccurrence(tag);
+    let stored_cooccurrence = stored_tag_cooccurrence(tag);
+    if expected_cooccurrence != stored_cooccurrence {
+        let differing = expected_cooccurrence.iter()
+            .filter(|(related_tag, count)| stored_cooccurrence.get(*related_tag) != Some(*count))
+            .count()
+            + stored_cooccurrence.keys().filter(|related_tag| !expected_cooccurrence.contains_key(*related_tag)).count();
+        if repair {
+            replace_tag_cooccurrence(tag, expected_cooccurrence);
+        }
+        record_audit_finding(AuditIssueKind::TagCooccurrenceMismatch, None,
+            format!("Tag '{}': {} TAG_COOCCURRENCE counts differ from the shelf associations", tag, differing), repair);
+    }
+
+    associated_shelf_ids.len()
+}
+
+// --- Phase 5: NFT_SHELVES entries for shelves that no longer hold the item ---
+
+fn audit_nft_shelves(after: Option<String>, budget: usize, repair: bool) -> (u64, AuditPhase) {
+    let (page, exhausted) = next_page!(NFT_SHELVES, after, budget);
+    let last_key = page.last().map(|(key, _)| key.clone());
+
+    for (key, shelves) in &page {
+        let stale: Vec<ShelfId> = SHELF_DATA.with(|sds_ref| {
+            let sds = sds_ref.borrow();
+            shelves.0.iter()
+                .filter(|shelf_id| !sds.get(shelf_id).is_some_and(|shelf| {
+                    shelf.content.items.values().any(|item| {
+                        id_conversion::get_appearance_key_for_storage(&item.content).as_ref() == Some(key)
+                    })
+                }))
+                .cloned()
+                .collect()
+        });
+        if stale.is_empty() {
+            continue;
+        }
+        if repair {
+            let mut remaining = shelves.clone();
+            remaining.0.retain(|id| !stale.contains(id));
+            NFT_SHELVES.with(|m| {
+                let mut map = m.borrow_mut();
+                if remaining.0.is_empty() {
+                    map.remove(key);
+                } else {
+                    map.insert(key.clone(), remaining);
+                }
+            });
+        }
+        for shelf_id in stale {
+            record_audit_finding(AuditIssueKind::StaleNftReference, Some(shelf_id),
+                format!("NFT_SHELVES lists the shelf for '{}', which it does not hold", key), repair);
+        }
+    }
+
+    let next_phase = if exhausted {
+        AuditPhase::Idle
+    } else {
+        AuditPhase::NftShelves { after: last_key }
+    };
+    (page.len() as u64, next_phase)
+}
+
+// --- Controller endpoints ---
+
+/// Starts a new consistency audit pass immediately (controllers only).
+///
+/// The pass runs in timer-driven steps; any pass in progress is restarted and previous findings are cleared.
+#[ic_cdk::update(guard = "is_controller")]
+pub fn start_consistency_audit() -> Result<(), String> {
+    if is_restore_in_progress() {
+        return Err("Cannot audit while a restore is in progress.".to_string());
+    }
+    begin_audit_pass();
+    Ok(())
+}
+
+/// Enables or disables repair mode for the consistency audit (controllers only).
+///
+/// With repair enabled, drift is fixed using SHELF_DATA as the source of truth.
+/// Otherwise findings are only reported.
+#[ic_cdk::update(guard = "is_controller")]
+pub fn set_audit_repair_mode(enabled: bool) -> Result<(), String> {
+    update_audit_state(|state| state.repair_enabled = enabled);
+    Ok(())
+}
+
+/// Runs one audit step now instead of waiting for the timer (controllers only).
+/// Returns the audit state after the step.
+#[ic_cdk::update(guard = "is_controller")]
+pub fn run_consistency_audit_step() -> Result<AuditState, String> {
+    if is_restore_in_progress() {
+        return Err("Cannot audit while a restore is in progress.".to_string());
+    }
+    run_audit_step(AUDIT_STEP_BUDGET);
+    Ok(get_audit_state())
+}
@@ -0,0 +1,315 @@
+use ic_cdk;
+use std::collections::BTreeSet;
+
+use crate::storage::{
+    ShelfId, ShelfData, NotificationKey,
+    SHELF_DATA, GLOBAL_TIMELINE, USER_SHELVES, USER_PROFILE_ORDER,
+    TAG_METADATA, TAG_SHELF_ASSOCIATIONS, SHELF_TAG_ASSOCIATIONS, TAG_POPULARITY_INDEX, TAG_LEXICAL_INDEX,
+    TAG_ALIASES, TAG_BLOCKLIST,
+    FOLLOWED_USERS, FOLLOWED_TAGS, USER_FOLLOWERS, TAG_FOLLOWERS, USER_FOLLOWER_COUNTS, TAG_FOLLOWER_COUNTS,
+    FOLLOW_LIMITS, ACTIVITY_LOG, NOTIFICATIONS, MUTED_NOTIFICATION_KINDS, SHELF_ACCESS_GRANTS,
+    SHELF_STATS, DAILY_SHELF_VIEWS, ITEM_OPEN_COUNTS,
+    RestoreState, get_restore_state, is_restore_in_progress, update_restore_state,
+    record_shelf_fork, recompute_trending_scores, recompute_most_viewed_shelves, refresh_random_shelf_candidates,
+};
+use crate::types::{BackupData, BackupPaginationInput, RestoreReport};
+use crate::guard::is_controller;
+use super::shelf::index_shelf;
+use super::follow::index_follower_page;
+
+fn require_restore_in_progress() -> Result<(), String> {
+    if is_restore_in_progress() {
+        Ok(())
+    } else {
+        Err("No restore in progress; call begin_restore first.".to_string())
+    }
+}
+
+fn distinct_tag_count(shelf_data: &ShelfData) -> u64 {
+    shelf_data.metadata.tags.iter().collect::<BTreeSet<_>>().len() as u64
+}
+
+/// Puts the canister into restore mode (controllers only).
+///
+/// Requires a canister without shelves, follows or profile orders. While the restore is in
+/// progress, user updates are rejected; source data is loaded with `import_backup_chunk`,
+/// derived indexes with `rebuild_restored_indexes`, and `finish_restore` checks the result.
+#[ic_cdk::update(guard = "is_controller")]
+pub fn begin_restore() -> Result<(), String> {
+    if is_restore_in_progress() {
+        return Err("A restore is already in progress.".to_string());
+    }
+
+    let is_empty = SHELF_DATA.with(|m| m.borrow().is_empty())
+        && FOLLOWED_USERS.with(|m| m.borrow().is_empty())
+        && FOLLOWED_TAGS.with(|m| m.borrow().is_empty())
+        && USER_PROFILE_ORDER.with(|m| m.borrow().is_empty());
+    if !is_empty {
+        return Err("Restore requires a canister without existing shelves, follows or profile orders.".to_string());
+    }
+
+    update_restore_state(|state| {
+        *state = RestoreState {
+            in_progress: true,
+            started_at: ic_cdk::api::time(),
+            ..RestoreState::default()
+        };
+    })
+}
+
+/// Imports one chunk of exported source data (controllers only, restore mode only).
+///
+/// Entries are written as-is, replacing any entry with the same key, so a chunk can be retried.
+/// Derived indexes are rejected; they are rebuilt from the imported shelves and follows.
+/// Returns the number of entries written.
+#[ic_cdk::update(guard = "is_controller")]
+pub fn import_backup_chunk(data: BackupData) -> Result<u64, String> {
+    require_restore_in_progress()?;
+
+    let imported = match data {
+        BackupData::Shelves(shelves) => {
+            let count = shelves.len() as u64;
+            let mut new_shelves: u64 = 0;
+            let mut added_tags: u64 = 0;
+            let mut replaced_tags: u64 = 0;
+            SHELF_DATA.with(|sds_ref| {
+                let mut sds = sds_ref.borrow_mut();
+                for shelf_data in shelves {
+                    added_tags += distinct_tag_count(&shelf_data);
+                    match sds.insert(shelf_data.metadata.shelf_id.clone(), shelf_data) {
+                        Some(previous) => replaced_tags += distinct_tag_count(&previous),
+                        None => new_shelves += 1,
+                    }
+                }
+            });
+            update_restore_state(|state| {
+                state.imported_shelves += new_shelves;
+                state.expected_tag_associations = (state.expected_tag_associations + added_tags).saturating_sub(replaced_tags);
+            })?;
+            count
+        }
+        BackupData::UserProfileOrders(orders) => USER_PROFILE_ORDER.with(|map_ref| {
+            let mut map = map_ref.borrow_mut();
+            let count = orders.len() as u64;
+            for (user, order) in orders {
+                map.insert(user, order.into_uop());
+            }
+            count
+        }),
+        BackupData::FollowedUsers(follows) => FOLLOWED_USERS.with(|map_ref| {
+            let mut map = map_ref.borrow_mut();
+            let count = follows.len() as u64;
+            for (follower, followed_set) in follows {
+                map.insert(follower, followed_set);
+            }
+            count
+        }),
+        BackupData::FollowedTags(follows) => FOLLOWED_TAGS.with(|map_ref| {
+            let mut map = map_ref.borrow_mut();
+            let count = follows.len() as u64;
+            for (follower, followed_set) in follows {
+                map.insert(follower, followed_set);
+            }
+            count
+        }),
+        BackupData::FollowLimits(limits) => {
+            FOLLOW_LIMITS.with(|cell| cell.borrow_mut().set(limits))
+                .map_err(|e| format!("Failed to store follow limits: {:?}", e))?;
+            1
+        }
+        BackupData::TagAliases(aliases) => TAG_ALIASES.with(|map_ref| {
+            let mut map = map_ref.borrow_mut();
+            let count = aliases.len() as u64;
+            for (alias, canonical) in aliases {
+                map.insert(alias, canonical);
+            }
+            count
+        }),
+        BackupData::TagBlocklist(tags) => TAG_BLOCKLIST.with(|map_ref| {
+            let mut map = map_ref.borrow_mut();
+            let count = tags.len() as u64;
+            for tag in tags {
+                map.insert(tag, ());
+            }
+            count
+        }),
+        BackupData::ActivityLog(events) => ACTIVITY_LOG.with(|map_ref| {
+            let mut map = map_ref.borrow_mut();
+            let count = events.len() as u64;
+            for (timestamp, event) in events {
+                map.insert(timestamp, event);
+            }
+            count
+        }),
+        BackupData::Notifications(notifications) => NOTIFICATIONS.with(|map_ref| {
+            let mut map = map_ref.borrow_mut();
+            let count = notifications.len() as u64;
+            for (recipient, notification) in notifications {
+                map.insert(NotificationKey { recipient, notification_id: notification.notification_id }, notification);
+            }
+            count
+        }),
+        BackupData::MutedNotificationKinds(muted) => MUTED_NOTIFICATION_KINDS.with(|map_ref| {
+            let mut map = map_ref.borrow_mut();
+            let count = muted.len() as u64;
+            for (user, kinds) in muted {
+                map.insert(user, kinds);
+            }
+            count
+        }),
+        BackupData::ShelfAccessGrants(grants) => SHELF_ACCESS_GRANTS.with(|map_ref| {
+            let mut map = map_ref.borrow_mut();
+            let count = grants.len() as u64;
+            for (key, grant) in grants {
+                map.insert(key, grant);
+            }
+            count
+        }),
+        BackupData::ShelfStats(buckets) => SHELF_STATS.with(|map_ref| {
+            let mut map = map_ref.borrow_mut();
+            let count = buckets.len() as u64;
+            for (key, bucket) in buckets {
+                map.insert(key, bucket);
+            }
+            count
+        }),
+        BackupData::DailyShelfViews(views) => DAILY_SHELF_VIEWS.with(|map_ref| {
+            let mut map = map_ref.borrow_mut();
+            let count = views.len() as u64;
+            for (key, day_views) in views {
+                map.insert(key, day_views);
+            }
+            count
+        }),
+        BackupData::ItemOpenCounts(opens) => ITEM_OPEN_COUNTS.with(|map_ref| {
+            let mut map = map_ref.borrow_mut();
+            let count = opens.len() as u64;
+            for (key, item_opens) in opens {
+                map.insert(key, item_opens);
+            }
+            count
+        }),
+        _ => return Err("This map is derived and cannot be imported; it is rebuilt by rebuild_restored_indexes.".to_string()),
+    };
+
+    Ok(imported)
+}
+
+/// Rebuilds derived indexes for a page of imported shelves and followers (controllers only, restore mode only).
+///
+/// Covers the timeline, user shelves, tag indexes, NFT_SHELVES, search, forks and follower
+/// indexes; the last page also recomputes MOST_VIEWED_SHELVES from the imported daily views.
+/// Idempotent; call repeatedly with increasing offsets until the returned count is below the limit.
+#[ic_cdk::update(guard = "is_controller")]
+pub fn rebuild_restored_indexes(pagination: BackupPaginationInput) -> Result<u64, String> {
+    require_restore_in_progress()?;
+
+    let shelf_page: Vec<(ShelfId, ShelfData)> = SHELF_DATA.with(|sds_ref| {
+        sds_ref.borrow()
+            .iter()
+            .skip(pagination.offset as usize)
+            .take(pagination.limit as usize)
+            .collect()
+    });
+    for (shelf_id, shelf_data) in &shelf_page {
+        index_shelf(shelf_data);
+        if let Some(source_shelf_id) = &shelf_data.metadata.forked_from {
+            record_shelf_fork(source_shelf_id, shelf_id);
+        }
+    }
+
+    let follower_page_len = index_follower_page(pagination.offset, pagination.limit);
+
+    let page_len = (shelf_page.len() as u64).max(follower_page_len);
+    if page_len < pagination.limit {
+        recompute_most_viewed_shelves();
+    }
+
+    Ok(page_len)
+}
+
+/// Compares derived index sizes with the imported source data.
+fn check_restored_indexes(state: &RestoreState) -> RestoreReport {
+    let mut report = RestoreReport {
+        shelves: SHELF_DATA.with(|m| m.borrow().len()),
+        timeline_entries: GLOBAL_TIMELINE.with(|m| m.borrow().len()),
+        user_shelf_entries: USER_SHELVES.with(|m| m.borrow().iter().map(|(_, set)| set.0.len() as u64).sum()),
+        expected_tag_associations: state.expected_tag_associations,
+        tag_associations: TAG_SHELF_ASSOCIATIONS.with(|m| m.borrow().len()),
+        followed_user_entries: FOLLOWED_USERS.with(|m| m.borrow().iter().map(|(_, set)| set.0.len() as u64).sum()),
+        user_follower_entries: USER_FOLLOWERS.with(|m| m.borrow().len()),
+        followed_tag_entries: FOLLOWED_TAGS.with(|m| m.borrow().iter().map(|(_, set)| set.0.len() as u64).sum()),
+        tag_follower_entries: TAG_FOLLOWERS.with(|m| m.borrow().len()),
+        issues: Vec::new(),
+    };
+
+    if report.shelves != state.imported_shelves {
+        report.issues.push(format!("SHELF_DATA has {} shelves, {} were imported", report.shelves, state.imported_shelves));
+    }
+    if report.timeline_entries != report.shelves {
+        report.issues.push(format!("GLOBAL_TIMELINE has {} entries for {} shelves", report.timeline_entries, report.shelves));
+    }
+    if report.user_shelf_entries != report.shelves {
+        report.issues.push(format!("USER_SHELVES has {} entries for {} shelves", report.user_shelf_entries, report.shelves));
+    }
+    if report.tag_associations != report.expected_tag_associations {
+        report.issues.push(format!("TAG_SHELF_ASSOCIATIONS has {} entries, shelves carry {} tags", report.tag_associations, report.expected_tag_associations));
+    }
+    let shelf_tag_associations = SHELF_TAG_ASSOCIATIONS.with(|m| m.borrow().len());
+    if shelf_tag_associations != report.tag_associations {
+        report.issues.push(format!("SHELF_TAG_ASSOCIATIONS has {} entries, TAG_SHELF_ASSOCIATIONS has {}", shelf_tag_associations, report.tag_associations));
+    }
+    let (tag_count, tag_shelf_count_sum) = TAG_METADATA.with(|m| {
+        let map = m.borrow();
+        (map.len(), map.iter().map(|(_, metadata)| metadata.current_shelf_count).sum::<u64>())
+    });
+    if tag_shelf_count_sum != report.tag_associations {
+        report.issues.push(format!("TAG_METADATA shelf counts sum to {}, expected {}", tag_shelf_count_sum, report.tag_associations));
+    }
+    let popularity_entries = TAG_POPULARITY_INDEX.with(|m| m.borrow().len());
+    let lexical_entries = TAG_LEXICAL_INDEX.with(|m| m.borrow().len());
+    if popularity_entries != tag_count || lexical_entries != tag_count {
+        report.issues.push(format!(
+            "{} tags in TAG_METADATA, {} in TAG_POPULARITY_INDEX, {} in TAG_LEXICAL_INDEX",
+            tag_count, popularity_entries, lexical_entries
+        ));
+    }
+    if report.user_follower_entries != report.followed_user_entries {
+        report.issues.push(format!("USER_FOLLOWERS has {} entries for {} follows", report.user_follower_entries, report.followed_user_entries));
+    }
+    let user_follower_count_sum: u64 = USER_FOLLOWER_COUNTS.with(|m| m.borrow().iter().map(|(_, count)| count).sum());
+    if user_follower_count_sum != report.user_follower_entries {
+        report.issues.push(format!("USER_FOLLOWER_COUNTS sum to {}, expected {}", user_follower_count_sum, report.user_follower_entries));
+    }
+    if report.tag_follower_entries != report.followed_tag_entries {
+        report.issues.push(format!("TAG_FOLLOWERS has {} entries for {} follows", report.tag_follower_entries, report.followed_tag_entries));
+    }
+    let tag_follower_count_sum: u64 = TAG_FOLLOWER_COUNTS.with(|m| m.borrow().iter().map(|(_, count)| count).sum());
+    if tag_follower_count_sum != report.tag_follower_entries {
+        report.issues.push(format!("TAG_FOLLOWER_COUNTS sum to {}, expected {}", tag_follower_count_sum, report.tag_follower_entries));
+    }
+
+    report
+}
+
+/// Finishes a restore (controllers only).
+///
+/// Recomputes trending scores and random feed candidates, then checks the rebuilt indexes
+/// against the imported data. Restore mode is left only when no issues are found, or when
+/// `force` is set; otherwise fix the data or re-run `rebuild_restored_indexes` and call again.
+#[ic_cdk::update(guard = "is_controller")]
+pub fn finish_restore(force: bool) -> Result<RestoreReport, String> {
+    require_restore_in_progress()?;
+
+    recompute_trending_scores();
+    refresh_random_shelf_candidates();
+
+    let state = get_restore_state();
+    let report = check_restored_indexes(&state);
+
+    if report.issues.is_empty() || force {
+        update_restore_state(|state| state.in_progress = false)?;
+    }
+
+    Ok(report)
+}
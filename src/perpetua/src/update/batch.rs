@@ -0,0 +1,442 @@
+use candid::{CandidType, Deserialize, Nat, Principal};
+use std::collections::{BTreeSet, HashSet};
+use crate::storage::{Item, ItemContent, ItemId, ShelfData, ShelfId, SHELF_DATA, NFT_SHELVES, StringVec, index_shelf_for_search, record_activity, ActivityKind, push_notification, NotificationKind};
+use crate::storage::common_types::{MAX_ITEMS_PER_SHELF, MAX_APPEARS_IN_COUNT};
+use crate::guard::not_anon_writable;
+use crate::update::item::{AddItemInput, would_create_cycle, notify_item_owners, MAX_NFT_REFERENCES};
+use crate::storage::{validate_item_content, validate_markdown};
+use crate::update::utils::{get_nft_owners_batch, get_icrc7_token_owners_batch};
+use crate::utils::id_conversion;
+
+// --- Constants ---
+const MAX_OPERATIONS_PER_BATCH: usize = 100;
+
+/// An ICRC-7 token as (collection, token ID)
+type Icrc7TokenRef = (Principal, Nat);
+
+/// A single item operation applied by `apply_shelf_operations`
+#[derive(CandidType, Deserialize, Clone)]
+pub enum ShelfOp {
+    /// Adds a new item, positioned the same way as `add_item_to_shelf`
+    Add(AddItemInput),
+    /// Removes an existing item
+    Remove { item_id: ItemId },
+    /// Moves an existing item before/after a reference item (or to the start/end if None)
+    Move { item_id: ItemId, reference_item_id: Option<ItemId>, before: bool },
+    /// Replaces the text of an existing markdown item
+    SetMarkdown { item_id: ItemId, markdown: String },
+}
+
+/// Applies a batch of item operations to a shelf in a single call
+///
+/// Operations are applied in order to a working copy of the shelf. Ownership of every
+/// added NFT or ICRC-7 token is verified up front with batched `icrc7_owner_of` calls.
+/// The shelf owner and the owners of added NFTs or tokens are notified.
+/// Item limits and circular reference checks are evaluated against the final state, then
+/// the parent shelf, nested shelf `appears_in` lists and NFT_SHELVES are committed
+/// together, or not at all.
+/// Returns the IDs assigned to added items, in operation order.
+#[ic_cdk::update(guard = "not_anon_writable")]
+pub async fn apply_shelf_operations(shelf_id: ShelfId, operations: Vec<ShelfOp>) -> Result<Vec<ItemId>, String> {
+    let caller = ic_cdk::caller();
+    let now = ic_cdk::api::time();
+
+    // --- Pre-validation Phase (no state read yet beyond permissions) ---
+    let (nft_ids_to_verify, tokens_to_verify) = validate_operations(&operations)?;
+    check_edit_permission(&shelf_id, &caller)?;
+
+    // --- Ownership Phase ---
+    // The owners looked up here are also notified once the batch is committed.
+    let mut item_owners: Vec<Principal> = Vec::new();
+    if !nft_ids_to_verify.is_empty() {
+        let owners = get_nft_owners_batch(&nft_ids_to_verify).await?;
+        for (nft_id, owner) in nft_ids_to_verify.iter().zip(owners) {
+            let owner = owner.ok_or_else(|| format!("NFT with ID '{}' not found or has no owner", nft_id))?;
+            if owner != caller {
+                return Err(format!("Unauthorized: You can only add NFTs that you own (NFT {})", nft_id));
+            }
+            item_owners.push(owner);
+        }
+    }
+    if !tokens_to_verify.is_empty() {
+        let owners = get_icrc7_token_owners_batch(&tokens_to_verify).await?;
+        for ((collection, token_id), owner) in tokens_to_verify.iter().zip(owners) {
+            let owner = owner.ok_or_else(|| format!("Token '{}' not found or has no owner in collection {}", token_id.0, collection))?;
+            if owner != caller {
+                return Err(format!("Unauthorized: You can only add tokens that you own (token {} in {})", token_id.0, collection));
+            }
+            item_owners.push(owner);
+        }
+    }
+
+    // --- Read & Apply Phase ---
+    // The shelf is (re)read after the ownership call so the batch applies to the latest state.
+    check_edit_permission(&shelf_id, &caller)?;
+    let mut shelf_data = SHELF_DATA.with(|sds| sds.borrow().get(&shelf_id))
+        .ok_or_else(|| format!("Shelf '{}' not found", shelf_id))?;
+
+    let initial_nested_shelves = nested_shelf_ids(&shelf_data);
+    let initial_nft_keys = nft_storage_keys(&shelf_data);
+
+    let mut next_item_id = shelf_data.content.items.keys()
+        .max()
+        .map_or(1, |max_id| max_id + 1);
+    let mut added_item_ids: Vec<ItemId> = Vec::new();
+
+    for (index, op) in operations.into_iter().enumerate() {
+        apply_operation(&mut shelf_data, op, &mut next_item_id, &mut added_item_ids)
+            .map_err(|e| op_error(index, e))?;
+    }
+
+    // --- Validate Final State ---
+    if shelf_data.content.items.len() > MAX_ITEMS_PER_SHELF {
+        return Err(format!("Maximum item limit reached ({}) for shelf {}", MAX_ITEMS_PER_SHELF, shelf_id));
+    }
+
+    let final_nested_shelves = nested_shelf_ids(&shelf_data);
+    let final_nft_keys = nft_storage_keys(&shelf_data);
+
+    // --- Prepare Phase (related records) ---
+    let mut prepared_nested_shelf_updates: Vec<(ShelfId, ShelfData)> = Vec::new();
+
+    for nested_shelf_id in final_nested_shelves.difference(&initial_nested_shelves) {
+        let mut nested_shelf_data = SHELF_DATA.with(|sds| sds.borrow().get(nested_shelf_id))
+            .ok_or_else(|| format!("Shelf to be added ('{}') does not exist", nested_shelf_id))?;
+
+        let mut visited_for_cycle_check = HashSet::new();
+        if would_create_cycle(&shelf_id, nested_shelf_id, &mut visited_for_cycle_check)? {
+            return Err(format!(
+                "Adding shelf '{}' to shelf '{}' would create a circular reference.",
+                nested_shelf_id, shelf_id
+            ));
+        }
+
+        if !nested_shelf_data.metadata.appears_in.contains(&shelf_id) {
+            if nested_shelf_data.metadata.appears_in.len() >= MAX_APPEARS_IN_COUNT {
+                nested_shelf_data.metadata.appears_in.remove(0); // Remove the oldest
+            }
+            nested_shelf_data.metadata.appears_in.push(shelf_id.clone());
+            nested_shelf_data.metadata.updated_at = now;
+            prepared_nested_shelf_updates.push((nested_shelf_id.clone(), nested_shelf_data));
+        }
+    }
+
+    for nested_shelf_id in initial_nested_shelves.difference(&final_nested_shelves) {
+        if let Some(mut nested_shelf_data) = SHELF_DATA.with(|sds| sds.borrow().get(nested_shelf_id)) {
+            let initial_len = nested_shelf_data.metadata.appears_in.len();
+            nested_shelf_data.metadata.appears_in.retain(|id| id != &shelf_id);
+            if nested_shelf_data.metadata.appears_in.len() != initial_len {
+                nested_shelf_data.metadata.updated_at = now;
+                prepared_nested_shelf_updates.push((nested_shelf_id.clone(), nested_shelf_data));
+            }
+        }
+    }
+
+    let mut prepared_nft_shelves_updates: Vec<(String, Option<StringVec>)> = Vec::new();
+
+    NFT_SHELVES.with(|map_ref| {
+        let map = map_ref.borrow();
+
+        for key in final_nft_keys.difference(&initial_nft_keys) {
+            let mut shelves_for_nft = map.get(key).unwrap_or_default();
+            if shelves_for_nft.0.contains(&shelf_id) {
+                continue;
+            }
+            if shelves_for_nft.0.len() >= MAX_NFT_REFERENCES {
+                ic_cdk::println!("NFT {} reference limit ({}) reached. Not adding shelf {} to NFT_SHELVES.", key, MAX_NFT_REFERENCES, shelf_id);
+                continue;
+            }
+            shelves_for_nft.0.push(shelf_id.clone());
+            prepared_nft_shelves_updates.push((key.clone(), Some(shelves_for_nft)));
+        }
+
+        for key in initial_nft_keys.difference(&final_nft_keys) {
+            if let Some(mut shelves_for_nft) = map.get(key) {
+                let initial_len = shelves_for_nft.0.len();
+                shelves_for_nft.0.retain(|id| id != &shelf_id);
+                if shelves_for_nft.0.is_empty() {
+                    prepared_nft_shelves_updates.push((key.clone(), None));
+                } else if shelves_for_nft.0.len() != initial_len {
+                    prepared_nft_shelves_updates.push((key.clone(), Some(shelves_for_nft)));
+                }
+            }
+        }
+    });
+
+    shelf_data.metadata.updated_at = now;
+
+    // --- Commit Phase ---
+    let shelf_owner = shelf_data.metadata.owner;
+    index_shelf_for_search(&shelf_id, &shelf_data);
+
+    SHELF_DATA.with(|sds| {
+        let mut map = sds.borrow_mut();
+        map.insert(shelf_id.clone(), shelf_data);
+        for (id, data) in prepared_nested_shelf_updates {
+            map.insert(id, data);
+        }
+    });
+
+    NFT_SHELVES.with(|map_ref| {
+        let mut map = map_ref.borrow_mut();
+        for (key, shelves_option) in prepared_nft_shelves_updates {
+            match shelves_option {
+                Some(shelves) => map.insert(key, shelves),
+                None => map.remove(&key),
+            };
+        }
+    });
+
+    for _ in &added_item_ids {
+        record_activity(ActivityKind::ItemAdded, shelf_id.clone());
+    }
+    notify_item_owners(&item_owners, caller, &shelf_id);
+    push_notification(shelf_owner, NotificationKind::ShelfEdited, caller, Some(shelf_id), None);
+
+    Ok(added_item_ids)
+}
+
+/// Applies a single operation to the working copy of a shelf.
+fn apply_operation(
+    shelf_data: &mut ShelfData,
+    op: ShelfOp,
+    next_item_id: &mut ItemId,
+    added_item_ids: &mut Vec<ItemId>,
+) -> Result<(), String> {
+    let content = &mut shelf_data.content;
+    match op {
+        ShelfOp::Add(input) => {
+            let new_item_id = *next_item_id;
+            let new_position = content.item_positions.calculate_position(
+                input.reference_item_id.as_ref(),
+                input.before,
+            )?;
+            content.items.insert(new_item_id, Item { id: new_item_id, content: input.content });
+            content.item_positions.insert(new_item_id, new_position);
+            *next_item_id += 1;
+            added_item_ids.push(new_item_id);
+        }
+        ShelfOp::Remove { item_id } => {
+            if content.items.remove(&item_id).is_none() {
+                return Err(format!("Item {} not found", item_id));
+            }
+            content.item_positions.remove(&item_id);
+        }
+        ShelfOp::Move { item_id, reference_item_id, before } => {
+            if !content.items.contains_key(&item_id) {
+                return Err(format!("Item {} not found", item_id));
+            }
+            if let Some(ref_id) = reference_item_id {
+                if ref_id == item_id {
+                    return Err("An item cannot be moved relative to itself".to_string());
+                }
+                if !content.item_positions.contains_key(&ref_id) {
+                    return Err("Reference item not found".to_string());
+                }
+            }
+            let new_position = content.item_positions.calculate_position(
+                reference_item_id.as_ref(),
+                before,
+            )?;
+            content.item_positions.insert(item_id, new_position);
+        }
+        ShelfOp::SetMarkdown { item_id, markdown } => {
+            let item = content.items.get_mut(&item_id)
+                .ok_or_else(|| format!("Item {} not found", item_id))?;
+            if !matches!(item.content, ItemContent::Markdown(_)) {
+                return Err(format!("Item {} is not a markdown item", item_id));
+            }
+            item.content = ItemContent::Markdown(markdown);
+        }
+    }
+    Ok(())
+}
+
+/// Checks batch size and the static content of every operation, returning the NFT IDs and
+/// ICRC-7 tokens (deduplicated, in first-seen order) whose ownership must be verified.
+fn validate_operations(operations: &[ShelfOp]) -> Result<(Vec<String>, Vec<Icrc7TokenRef>), String> {
+    if operations.is_empty() {
+        return Err("No operations provided".to_string());
+    }
+    if operations.len() > MAX_OPERATIONS_PER_BATCH {
+        return Err(format!("Too many operations in one batch (max {})", MAX_OPERATIONS_PER_BATCH));
+    }
+
+    let mut nft_ids_to_verify: Vec<String> = Vec::new();
+    let mut tokens_to_verify: Vec<Icrc7TokenRef> = Vec::new();
+    for (index, op) in operations.iter().enumerate() {
+        match op {
+            ShelfOp::Add(input) => {
+                validate_item_content(&input.content).map_err(|e| op_error(index, e))?;
+                match &input.content {
+                    ItemContent::Nft(nft_id) if !nft_ids_to_verify.contains(nft_id) => {
+                        nft_ids_to_verify.push(nft_id.clone());
+                    }
+                    ItemContent::Icrc7Token { collection, token_id } => {
+                        let token = (*collection, token_id.clone());
+                        if !tokens_to_verify.contains(&token) {
+                            tokens_to_verify.push(token);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            ShelfOp::SetMarkdown { markdown, .. } => {
+                validate_markdown(markdown).map_err(|e| op_error(index, e))?;
+            }
+            ShelfOp::Remove { .. } | ShelfOp::Move { .. } => {}
+        }
+    }
+    Ok((nft_ids_to_verify, tokens_to_verify))
+}
+
+fn check_edit_permission(shelf_id: &ShelfId, caller: &Principal) -> Result<(), String> {
+    let can_edit = SHELF_DATA.with(|sds| {
+        sds.borrow().get(shelf_id)
+            .map(|shelf_data| shelf_data.metadata.owner == *caller || shelf_data.metadata.public_editing)
+    }).ok_or_else(|| format!("Shelf '{}' not found", shelf_id))?;
+
+    if !can_edit {
+        return Err("Unauthorized: You don't have edit permissions for this shelf".to_string());
+    }
+    Ok(())
+}
+
+fn nested_shelf_ids(shelf_data: &ShelfData) -> BTreeSet<ShelfId> {
+    shelf_data.content.items.values()
+        .filter_map(|item| match &item.content {
+            ItemContent::Shelf(nested_shelf_id) => Some(nested_shelf_id.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn nft_storage_keys(shelf_data: &ShelfData) -> BTreeSet<String> {
+    shelf_data.content.items.values()
+        .filter_map(|item| id_conversion::get_appearance_key_for_storage(&item.content))
+        .collect()
+}
+
+fn op_error(index: usize, error: String) -> String {
+    format!("Operation {}: {}", index, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ordering::PositionTracker;
+    use crate::storage::{ShelfContent, ShelfMetadata};
+    use std::collections::BTreeMap;
+
+    fn add(content: ItemContent) -> ShelfOp {
+        ShelfOp::Add(AddItemInput { content, reference_item_id: None, before: false })
+    }
+
+    fn shelf_with_items(items: Vec<ItemContent>) -> ShelfData {
+        let items: BTreeMap<ItemId, Item> = items.into_iter()
+            .enumerate()
+            .map(|(index, content)| (index as ItemId + 1, Item { id: index as ItemId + 1, content }))
+            .collect();
+        ShelfData {
+            metadata: ShelfMetadata {
+                shelf_id: "shelf".to_string(),
+                title: "Shelf".to_string(),
+                description: None,
+                owner: Principal::anonymous(),
+                created_at: 0,
+                updated_at: 0,
+                appears_in: Vec::new(),
+                tags: Vec::new(),
+                public_editing: false,
+                forked_from: None,
+                access_policy: None,
+            },
+            content: ShelfContent {
+                item_positions: PositionTracker::from_ordered_keys(items.keys().copied()),
+                items,
+            },
+        }
+    }
+
+    fn apply(shelf_data: &mut ShelfData, op: ShelfOp) -> Result<Vec<ItemId>, String> {
+        let mut next_item_id = shelf_data.content.items.keys().max().map_or(1, |max_id| max_id + 1);
+        let mut added_item_ids = Vec::new();
+        apply_operation(shelf_data, op, &mut next_item_id, &mut added_item_ids)?;
+        Ok(added_item_ids)
+    }
+
+    #[test]
+    fn empty_and_oversized_batches_are_rejected() {
+        assert!(validate_operations(&[]).is_err());
+        let operations: Vec<ShelfOp> = (0..=MAX_OPERATIONS_PER_BATCH)
+            .map(|item_id| ShelfOp::Remove { item_id: item_id as ItemId })
+            .collect();
+        assert!(validate_operations(&operations).is_err());
+        assert!(validate_operations(&operations[..MAX_OPERATIONS_PER_BATCH]).is_ok());
+    }
+
+    #[test]
+    fn invalid_content_reports_the_operation_index() {
+        let operations = vec![
+            add(ItemContent::Markdown("ok".to_string())),
+            add(ItemContent::Nft("12a".to_string())),
+        ];
+        let error = validate_operations(&operations).unwrap_err();
+        assert!(error.starts_with("Operation 1:"), "{error}");
+
+        let operations = vec![ShelfOp::SetMarkdown {
+            item_id: 1,
+            markdown: "x".repeat(crate::storage::common_types::MAX_MARKDOWN_LENGTH + 1),
+        }];
+        let error = validate_operations(&operations).unwrap_err();
+        assert!(error.starts_with("Operation 0:"), "{error}");
+    }
+
+    #[test]
+    fn ownership_checks_are_deduplicated_in_first_seen_order() {
+        let collection = Principal::from_slice(&[1, 2, 3]);
+        let operations = vec![
+            add(ItemContent::Nft("7".to_string())),
+            add(ItemContent::Icrc7Token { collection, token_id: Nat::from(5u64) }),
+            add(ItemContent::Nft("3".to_string())),
+            add(ItemContent::Nft("7".to_string())),
+            add(ItemContent::Icrc7Token { collection, token_id: Nat::from(5u64) }),
+            ShelfOp::Remove { item_id: 1 },
+        ];
+        let (nft_ids, tokens) = validate_operations(&operations).unwrap();
+        assert_eq!(nft_ids, vec!["7".to_string(), "3".to_string()]);
+        assert_eq!(tokens, vec![(collection, Nat::from(5u64))]);
+    }
+
+    #[test]
+    fn operations_on_missing_or_mismatched_items_fail() {
+        let mut shelf_data = shelf_with_items(vec![
+            ItemContent::Markdown("a".to_string()),
+            ItemContent::Nft("1".to_string()),
+        ]);
+        assert!(apply(&mut shelf_data, ShelfOp::Remove { item_id: 9 }).is_err());
+        assert!(apply(&mut shelf_data, ShelfOp::Move { item_id: 1, reference_item_id: Some(1), before: true }).is_err());
+        assert!(apply(&mut shelf_data, ShelfOp::Move { item_id: 1, reference_item_id: Some(9), before: true }).is_err());
+        assert!(apply(&mut shelf_data, ShelfOp::SetMarkdown { item_id: 2, markdown: "b".to_string() }).is_err());
+    }
+
+    #[test]
+    fn operations_apply_in_order_to_the_working_copy() {
+        let mut shelf_data = shelf_with_items(vec![
+            ItemContent::Markdown("a".to_string()),
+            ItemContent::Markdown("b".to_string()),
+        ]);
+        let added = apply(&mut shelf_data, ShelfOp::Add(AddItemInput {
+            content: ItemContent::Markdown("c".to_string()),
+            reference_item_id: Some(1),
+            before: true,
+        })).unwrap();
+        assert_eq!(added, vec![3]);
+        apply(&mut shelf_data, ShelfOp::Move { item_id: 1, reference_item_id: None, before: false }).unwrap();
+        apply(&mut shelf_data, ShelfOp::Remove { item_id: 2 }).unwrap();
+        apply(&mut shelf_data, ShelfOp::SetMarkdown { item_id: 3, markdown: "d".to_string() }).unwrap();
+
+        assert_eq!(shelf_data.content.item_positions.get_ordered_keys(), vec![3, 1]);
+        assert!(matches!(&shelf_data.content.items[&3].content, ItemContent::Markdown(text) if text == "d"));
+    }
+}
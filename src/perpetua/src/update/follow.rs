@@ -1,33 +1,20 @@
 use candid::Principal;
 use ic_cdk;
 
+use crate::types::BackupPaginationInput;
+use crate::guard::{is_controller, not_anon_writable};
+
 use crate::storage::{
-    FOLLOWED_USERS, FOLLOWED_TAGS, 
-    validate_tag_format, 
+    FOLLOWED_USERS, FOLLOWED_TAGS, FOLLOW_LIMITS, FollowLimits, MAX_FOLLOW_LIMIT,
+    get_follow_limits, add_user_follower, remove_user_follower, add_tag_follower, remove_tag_follower,
+    canonicalize_tag, resolve_tag, record_activity, ActivityKind,
+    push_notification, NotificationKind, 
     // Removed TAG_METADATA, TagMetadata
 };
-use crate::utils::normalize_tag;
-// Assuming the guard function is in src/guards.rs
-// If not, adjust the path accordingly.
-// use crate::guards::not_anon;
-
-// Placeholder for the guard if it doesn't exist yet.
-// Remove this if you have a real guard function.
-fn not_anon() -> Result<(), String> {
-    if ic_cdk::caller() == Principal::anonymous() {
-        Err("Anonymous callers are not allowed.".to_string())
-    } else {
-        Ok(())
-    }
-}
-
-// --- Constants ---
-const MAX_FOLLOWED_USERS: usize = 100;
-const MAX_FOLLOWED_TAGS: usize = 100;
 
 pub type UpdateResult = Result<(), String>;
 
-#[ic_cdk::update(guard = "not_anon")]
+#[ic_cdk::update(guard = "not_anon_writable")]
 pub fn follow_user(user_to_follow: Principal) -> UpdateResult {
     let caller = ic_cdk::caller();
 
@@ -36,6 +23,8 @@ pub fn follow_user(user_to_follow: Principal) -> UpdateResult {
         return Err("Cannot follow yourself.".to_string());
     }
 
+    let max_followed_users = get_follow_limits().max_followed_users as usize;
+
     FOLLOWED_USERS.with(|followed| {
         let mut map = followed.borrow_mut();
         let mut followed_set = map.get(&caller).unwrap_or_default();
@@ -46,9 +35,9 @@ pub fn follow_user(user_to_follow: Principal) -> UpdateResult {
         }
 
         // Check limit before inserting
-        if followed_set.0.len() >= MAX_FOLLOWED_USERS {
+        if followed_set.0.len() >= max_followed_users {
             // This check is now simpler as we know the user isn't already followed
-            return Err(format!("Cannot follow more than {} users.", MAX_FOLLOWED_USERS));
+            return Err(format!("Cannot follow more than {} users.", max_followed_users));
         }
 
         // Add the user to the set
@@ -56,10 +45,14 @@ pub fn follow_user(user_to_follow: Principal) -> UpdateResult {
         followed_set.0.insert(user_to_follow);
         map.insert(caller, followed_set);
         Ok(())
-    })
+    })?;
+
+    add_user_follower(user_to_follow, caller);
+    push_notification(user_to_follow, NotificationKind::NewFollower, caller, None, None);
+    Ok(())
 }
 
-#[ic_cdk::update(guard = "not_anon")]
+#[ic_cdk::update(guard = "not_anon_writable")]
 pub fn unfollow_user(user_to_unfollow: Principal) -> UpdateResult {
     let caller = ic_cdk::caller();
 
@@ -72,21 +65,23 @@ pub fn unfollow_user(user_to_unfollow: Principal) -> UpdateResult {
                 map.insert(caller, followed_set);
             }
         }
-        // Always Ok, even if user wasn't followed (idempotent)
-        Ok(())
-    })
+    });
+
+    remove_user_follower(user_to_unfollow, caller);
+    // Always Ok, even if user wasn't followed (idempotent)
+    Ok(())
 }
 
-#[ic_cdk::update(guard = "not_anon")]
+#[ic_cdk::update(guard = "not_anon_writable")]
 pub fn follow_tag(tag: String) -> UpdateResult {
     let caller = ic_cdk::caller();
-    let normalized_tag = normalize_tag(&tag);
-
-    // Validate the normalized tag format
-    validate_tag_format(&normalized_tag)?;
+    // Normalize, resolve aliases and validate the tag (blocked tags cannot be followed)
+    let normalized_tag = canonicalize_tag(&tag)?;
     // Add any other tag-specific validations (e.g., existence in TAG_METADATA?)
     // For now, just validating format.
 
+    let max_followed_tags = get_follow_limits().max_followed_tags as usize;
+
     FOLLOWED_TAGS.with(|followed| {
         let mut map = followed.borrow_mut();
         let mut followed_set = map.get(&caller).unwrap_or_default();
@@ -97,23 +92,27 @@ pub fn follow_tag(tag: String) -> UpdateResult {
         }
 
         // Check limit before inserting
-        if followed_set.0.len() >= MAX_FOLLOWED_TAGS {
+        if followed_set.0.len() >= max_followed_tags {
              // This check is now simpler as we know the tag isn't already followed
-            return Err(format!("Cannot follow more than {} tags.", MAX_FOLLOWED_TAGS));
+            return Err(format!("Cannot follow more than {} tags.", max_followed_tags));
         }
 
         // Add the tag to the set
         // No need to check return value, we know it's a new tag
-        followed_set.0.insert(normalized_tag);
+        followed_set.0.insert(normalized_tag.clone());
         map.insert(caller, followed_set);
         Ok(())
-    })
+    })?;
+
+    add_tag_follower(&normalized_tag, caller);
+    record_activity(ActivityKind::TagFollowed, normalized_tag);
+    Ok(())
 }
 
-#[ic_cdk::update(guard = "not_anon")]
+#[ic_cdk::update(guard = "not_anon_writable")]
 pub fn unfollow_tag(tag: String) -> UpdateResult {
     let caller = ic_cdk::caller();
-    let normalized_tag = normalize_tag(&tag);
+    let normalized_tag = resolve_tag(&tag);
 
     // No need to validate format for removal
 
@@ -125,7 +124,68 @@ pub fn unfollow_tag(tag: String) -> UpdateResult {
                 map.insert(caller, followed_set);
             }
         }
-        // Always Ok, even if tag wasn't followed (idempotent)
-        Ok(())
-    })
-} 
\ No newline at end of file
+    });
+
+    remove_tag_follower(&normalized_tag, caller);
+    // Always Ok, even if tag wasn't followed (idempotent)
+    Ok(())
+}
+
+/// Sets the maximum number of users and tags a principal may follow (controllers only).
+///
+/// Lowering a limit does not drop existing follows; it only blocks new ones above the cap.
+#[ic_cdk::update(guard = "is_controller")]
+pub fn set_follow_limits(limits: FollowLimits) -> UpdateResult {
+    if limits.max_followed_users == 0 || limits.max_followed_users > MAX_FOLLOW_LIMIT {
+        return Err(format!("max_followed_users must be between 1 and {}.", MAX_FOLLOW_LIMIT));
+    }
+    if limits.max_followed_tags == 0 || limits.max_followed_tags > MAX_FOLLOW_LIMIT {
+        return Err(format!("max_followed_tags must be between 1 and {}.", MAX_FOLLOW_LIMIT));
+    }
+
+    FOLLOW_LIMITS.with(|cell| cell.borrow_mut().set(limits))
+        .map_err(|e| format!("Failed to store follow limits: {:?}", e))?;
+    Ok(())
+}
+
+/// Rebuilds the follower reverse indexes from a page of followers (controllers only).
+///
+/// Used to backfill `USER_FOLLOWERS` / `TAG_FOLLOWERS` and their counts for follows made
+/// before the indexes existed. Idempotent; call repeatedly with increasing offsets until
+/// the returned count is below the limit.
+#[ic_cdk::update(guard = "is_controller")]
+pub fn rebuild_follower_indexes(pagination: BackupPaginationInput) -> Result<u64, String> {
+    Ok(index_follower_page(pagination.offset, pagination.limit))
+}
+
+/// Adds the reverse-index entries for a page of FOLLOWED_USERS / FOLLOWED_TAGS.
+/// Returns the larger of the two page sizes.
+pub(super) fn index_follower_page(offset: u64, limit: u64) -> u64 {
+    let user_page: Vec<_> = FOLLOWED_USERS.with(|followed| {
+        followed.borrow()
+            .iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .collect()
+    });
+    for (follower, followed_set) in &user_page {
+        for followed in &followed_set.0 {
+            add_user_follower(*followed, *follower);
+        }
+    }
+
+    let tag_page: Vec<_> = FOLLOWED_TAGS.with(|followed| {
+        followed.borrow()
+            .iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .collect()
+    });
+    for (follower, followed_set) in &tag_page {
+        for tag in &followed_set.0 {
+            add_tag_follower(tag, *follower);
+        }
+    }
+
+    user_page.len().max(tag_page.len()) as u64
+}
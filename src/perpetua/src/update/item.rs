@@ -1,15 +1,29 @@
-use candid::{CandidType, Deserialize};
+use candid::{CandidType, Deserialize, Principal};
 use std::collections::HashSet;
-use crate::storage::{Item, ItemContent, ShelfData, SHELF_DATA, NFT_SHELVES, ShelfId, StringVec};
-use crate::storage::common_types::{MAX_NFT_ID_LENGTH, MAX_ITEMS_PER_SHELF, MAX_MARKDOWN_LENGTH, MAX_APPEARS_IN_COUNT, SHELF_ITEM_STEP_SIZE};
-use crate::guard::not_anon;
-use crate::update::utils::{verify_nft_ownership};
+use crate::storage::{Item, ItemContent, ShelfData, SHELF_DATA, NFT_SHELVES, ShelfId, StringVec, index_shelf_for_search, record_activity, ActivityKind, push_notification, NotificationKind};
+use crate::storage::common_types::{MAX_ITEMS_PER_SHELF, MAX_APPEARS_IN_COUNT};
+use crate::guard::not_anon_writable;
+use crate::storage::validate_item_content;
+use crate::update::utils::{get_nft_owner, get_icrc7_token_owner};
 use crate::utils::id_conversion;
+use crate::ordering::PositionTracker;
+
+/// Notifies the owners of NFTs or tokens added to `shelf_id`, once per owner.
+/// Owners adding their own items (the caller) are skipped by `push_notification`.
+pub(crate) fn notify_item_owners(owners: &[Principal], caller: Principal, shelf_id: &ShelfId) {
+    let mut notified: Vec<Principal> = Vec::new();
+    for owner in owners {
+        if !notified.contains(owner) {
+            push_notification(*owner, NotificationKind::ItemAddedToShelf, caller, Some(shelf_id.clone()), None);
+            notified.push(*owner);
+        }
+    }
+}
 
 // --- Helper function for deep circular reference check ---
 // Checks if adding 'shelf_to_evaluate_id' into 'target_parent_id' would create a cycle.
 // It does this by seeing if 'target_parent_id' can be reached by traversing 'shelf_to_evaluate_id's children.
-fn would_create_cycle(
+pub(crate) fn would_create_cycle(
     target_parent_id: &ShelfId,     // The shelf we are considering adding into (e.g., A)
     shelf_to_evaluate_id: &ShelfId, // The shelf being proposed to be added (e.g., B)
     visited_in_current_path: &mut HashSet<ShelfId>, // Tracks nodes visited in the current DFS path
@@ -60,7 +74,7 @@ fn would_create_cycle(
 }
 
 // --- Constants ---
-const MAX_NFT_REFERENCES: usize = 500; // Limit for NFT_SHELVES tracking
+pub(crate) const MAX_NFT_REFERENCES: usize = 500; // Limit for NFT_SHELVES tracking
 
 /// Input structure for adding a new item to a shelf
 #[derive(CandidType, Deserialize, Clone)]
@@ -78,7 +92,7 @@ pub struct AddItemInput {
 /// Atomically updates parent shelf (content and metadata), 
 /// nested shelf metadata (if adding a shelf), and NFT_SHELVES (if adding an NFT).
 /// Panics on failure during the commit phase to ensure atomicity.
-#[ic_cdk::update(guard = "not_anon")]
+#[ic_cdk::update(guard = "not_anon_writable")]
 pub async fn add_item_to_shelf(shelf_id: String, input: AddItemInput) -> Result<(), String> {
     let caller = ic_cdk::caller();
     let now = ic_cdk::api::time();
@@ -103,47 +117,18 @@ pub async fn add_item_to_shelf(shelf_id: String, input: AddItemInput) -> Result<
     // This variable will store the intent to add a specific shelf_id to an NFT's list.
     let mut nft_shelf_addition_intent: Option<(String, ShelfId)> = None;
 
+    // Owner of the added NFT or token, notified once the item is committed.
+    let mut item_owner: Option<Principal> = None;
+
+    validate_item_content(&input.content)?;
     match &input.content {
         ItemContent::Nft(ref nft_id_from_input) => {
             ic_cdk::println!("[add_item_to_shelf] Received ItemContent::Nft with nft_id_from_input: {}", nft_id_from_input);
-            if nft_id_from_input.chars().any(|c| !c.is_digit(10)) {
-                return Err("Invalid NFT ID: Contains non-digit characters.".to_string());
-            }
-            if nft_id_from_input.len() > MAX_NFT_ID_LENGTH { 
-                return Err(format!("NFT ID exceeds maximum length of {} characters", MAX_NFT_ID_LENGTH));
-            }
-            let is_owner = verify_nft_ownership(nft_id_from_input, caller).await?;
-            if !is_owner {
+            let owner = get_nft_owner(nft_id_from_input).await?;
+            if owner != caller {
                 return Err("Unauthorized: You can only add NFTs that you own".to_string());
             }
-            
-            let key_for_nft_shelves = id_conversion::get_original_nft_id_for_storage(nft_id_from_input);
-            ic_cdk::println!("[add_item_to_shelf] Preparing NFT_SHELVES update for key: {}", key_for_nft_shelves);
-
-            let decision = NFT_SHELVES.with(|map_ref| {
-                let map = map_ref.borrow();
-                if let Some(existing_shelves) = map.get(&key_for_nft_shelves) { // .get() clones from StableBTreeMap
-                    if existing_shelves.0.contains(&shelf_id) {
-                        Ok::<bool, String>(false) // Already present, no update needed
-                    } else if existing_shelves.0.len() >= MAX_NFT_REFERENCES {
-                        ic_cdk::println!("NFT {} reference limit ({}) reached. Not adding shelf {} to NFT_SHELVES.", key_for_nft_shelves, MAX_NFT_REFERENCES, shelf_id);
-                        Ok::<bool, String>(false) // Limit reached
-                    } else {
-                        Ok::<bool, String>(true) // Needs to be added
-                    }
-                } else { // Key doesn't exist
-                    if MAX_NFT_REFERENCES > 0 {
-                        Ok::<bool, String>(true) // Needs to be added (new key)
-                    } else {
-                        ic_cdk::println!("NFT {} reference limit ({}) is zero. Not adding new shelf {} to NFT_SHELVES.", key_for_nft_shelves, MAX_NFT_REFERENCES, shelf_id);
-                        Ok::<bool, String>(false) // Limit is zero, cannot add
-                    }
-                }
-            })?;
-
-            if decision {
-                nft_shelf_addition_intent = Some((key_for_nft_shelves.clone(), shelf_id.clone()));
-            }
+            item_owner = Some(owner);
         }
         ItemContent::Shelf(ref nested_shelf_id) => {
             // The direct self-reference (A cannot contain A) is implicitly handled by would_create_cycle
@@ -174,10 +159,43 @@ pub async fn add_item_to_shelf(shelf_id: String, input: AddItemInput) -> Result<
                 prepared_nested_shelf_data_update = Some((nested_shelf_id.clone(), modified_nested_shelf_data));
             }
         }
-        ItemContent::Markdown(markdown) => {
-            if markdown.len() > MAX_MARKDOWN_LENGTH {
-                 return Err(format!("Markdown content exceeds maximum length of {} characters", MAX_MARKDOWN_LENGTH));
+        ItemContent::Markdown(_) | ItemContent::Arweave(_) | ItemContent::Link { .. } => {}
+        ItemContent::Icrc7Token { collection, token_id } => {
+            let owner = get_icrc7_token_owner(*collection, token_id).await?;
+            if owner != caller {
+                return Err("Unauthorized: You can only add tokens that you own".to_string());
+            }
+            item_owner = Some(owner);
+        }
+    }
+
+    // Track where NFTs, Arweave transactions, links and external tokens appear (NFT_SHELVES).
+    if let Some(key_for_nft_shelves) = id_conversion::get_appearance_key_for_storage(&input.content) {
+        ic_cdk::println!("[add_item_to_shelf] Preparing NFT_SHELVES update for key: {}", key_for_nft_shelves);
+
+        let decision = NFT_SHELVES.with(|map_ref| {
+            let map = map_ref.borrow();
+            if let Some(existing_shelves) = map.get(&key_for_nft_shelves) { // .get() clones from StableBTreeMap
+                if existing_shelves.0.contains(&shelf_id) {
+                    Ok::<bool, String>(false) // Already present, no update needed
+                } else if existing_shelves.0.len() >= MAX_NFT_REFERENCES {
+                    ic_cdk::println!("NFT {} reference limit ({}) reached. Not adding shelf {} to NFT_SHELVES.", key_for_nft_shelves, MAX_NFT_REFERENCES, shelf_id);
+                    Ok::<bool, String>(false) // Limit reached
+                } else {
+                    Ok::<bool, String>(true) // Needs to be added
+                }
+            } else { // Key doesn't exist
+                if MAX_NFT_REFERENCES > 0 {
+                    Ok::<bool, String>(true) // Needs to be added (new key)
+                } else {
+                    ic_cdk::println!("NFT {} reference limit ({}) is zero. Not adding new shelf {} to NFT_SHELVES.", key_for_nft_shelves, MAX_NFT_REFERENCES, shelf_id);
+                    Ok::<bool, String>(false) // Limit is zero, cannot add
+                }
             }
+        })?;
+
+        if decision {
+            nft_shelf_addition_intent = Some((key_for_nft_shelves, shelf_id.clone()));
         }
     }
 
@@ -196,12 +214,16 @@ pub async fn add_item_to_shelf(shelf_id: String, input: AddItemInput) -> Result<
     let new_position = parent_shelf_data.content.item_positions.calculate_position(
         input.reference_item_id.as_ref(),
         input.before,
-        SHELF_ITEM_STEP_SIZE
     )?;
     parent_shelf_data.content.item_positions.insert(new_item_id, new_position);
     parent_shelf_data.metadata.updated_at = now;
 
     // --- Commit Phase ---
+    let shelf_owner = parent_shelf_data.metadata.owner;
+    if matches!(input.content, ItemContent::Markdown(_)) {
+        index_shelf_for_search(&shelf_id, &parent_shelf_data);
+    }
+
     SHELF_DATA.with(|sds| {
         sds.borrow_mut().insert(shelf_id.clone(), parent_shelf_data);
     });
@@ -235,6 +257,10 @@ pub async fn add_item_to_shelf(shelf_id: String, input: AddItemInput) -> Result<
         });
     }
 
+    notify_item_owners(item_owner.as_slice(), caller, &shelf_id);
+    push_notification(shelf_owner, NotificationKind::ShelfEdited, caller, Some(shelf_id.clone()), None);
+    record_activity(ActivityKind::ItemAdded, shelf_id);
+
     Ok(())
 }
 
@@ -242,7 +268,7 @@ pub async fn add_item_to_shelf(shelf_id: String, input: AddItemInput) -> Result<
 /// 
 /// Only users with edit permissions can remove items.
 /// This also handles cleanup of any references if the item contained an NFT or nested Shelf.
-#[ic_cdk::update(guard = "not_anon")]
+#[ic_cdk::update(guard = "not_anon_writable")]
 pub async fn remove_item_from_shelf(shelf_id: ShelfId, item_id: u32) -> Result<(), String> {
     let caller = ic_cdk::caller();
     let now = ic_cdk::api::time();
@@ -289,24 +315,30 @@ pub async fn remove_item_from_shelf(shelf_id: ShelfId, item_id: u32) -> Result<(
                 }
             });
         }
-        ItemContent::Nft(ref nft_id_on_shelf) => {
-            let key_for_nft_shelves = id_conversion::get_original_nft_id_for_storage(nft_id_on_shelf);
-            NFT_SHELVES.with(|nft_shelves_map_ref| {
-                if let Some(mut shelves_for_nft) = nft_shelves_map_ref.borrow().get(&key_for_nft_shelves).map(|sv| sv.clone()) {
-                    let initial_len = shelves_for_nft.0.len();
-                    shelves_for_nft.0.retain(|id| id != &shelf_id);
-                    if shelves_for_nft.0.is_empty() {
-                        prepared_nft_shelves_update = Some((key_for_nft_shelves.clone(), None));
-                    } else if shelves_for_nft.0.len() != initial_len {
-                        prepared_nft_shelves_update = Some((key_for_nft_shelves.clone(), Some(shelves_for_nft)));
+        ref other_content => {
+            // NFTs, Arweave transactions, links and external tokens are tracked in NFT_SHELVES
+            if let Some(key_for_nft_shelves) = id_conversion::get_appearance_key_for_storage(other_content) {
+                NFT_SHELVES.with(|nft_shelves_map_ref| {
+                    if let Some(mut shelves_for_nft) = nft_shelves_map_ref.borrow().get(&key_for_nft_shelves) {
+                        let initial_len = shelves_for_nft.0.len();
+                        shelves_for_nft.0.retain(|id| id != &shelf_id);
+                        if shelves_for_nft.0.is_empty() {
+                            prepared_nft_shelves_update = Some((key_for_nft_shelves.clone(), None));
+                        } else if shelves_for_nft.0.len() != initial_len {
+                            prepared_nft_shelves_update = Some((key_for_nft_shelves.clone(), Some(shelves_for_nft)));
+                        }
                     }
-                }
-            });
+                });
+            }
         }
-        _ => {} 
     }
 
     // --- Commit Phase ---
+    let shelf_owner = parent_shelf_data.metadata.owner;
+    if matches!(removed_item_content, ItemContent::Markdown(_)) {
+        index_shelf_for_search(&shelf_id, &parent_shelf_data);
+    }
+
     SHELF_DATA.with(|sds| {
         sds.borrow_mut().insert(shelf_id.clone(), parent_shelf_data);
     });
@@ -326,6 +358,8 @@ pub async fn remove_item_from_shelf(shelf_id: ShelfId, item_id: u32) -> Result<(
             };
         });
     }
+
+    push_notification(shelf_owner, NotificationKind::ShelfEdited, caller, Some(shelf_id), None);
     
     Ok(())
 }
@@ -336,7 +370,7 @@ pub async fn remove_item_from_shelf(shelf_id: ShelfId, item_id: u32) -> Result<(
 /// All item IDs in the input list must exist within the shelf.
 /// This function is refactored for atomicity. It reads, validates, prepares the change,
 /// then commits. Panics on commit failure.
-#[ic_cdk::update(guard = "not_anon")]
+#[ic_cdk::update(guard = "not_anon_writable")]
 pub fn set_item_order(shelf_id: ShelfId, ordered_item_ids: Vec<u32>) -> Result<(), String> {
     let caller = ic_cdk::caller();
     let now = ic_cdk::api::time();
@@ -377,17 +411,17 @@ pub fn set_item_order(shelf_id: ShelfId, ordered_item_ids: Vec<u32>) -> Result<(
 
     // --- Prepare Phase ---
     // Modify shelf_data directly
-    shelf_data.content.item_positions.clear();
-    for (index, item_id) in ordered_item_ids.iter().enumerate() {
-        let position = (index as f64 + 1.0) * SHELF_ITEM_STEP_SIZE; 
-        shelf_data.content.item_positions.insert(*item_id, position);
-    }
+    shelf_data.content.item_positions = PositionTracker::from_ordered_keys(ordered_item_ids.iter().cloned());
     shelf_data.metadata.updated_at = now;
     
     // --- Commit Phase ---
+    let shelf_owner = shelf_data.metadata.owner;
+    index_shelf_for_search(&shelf_id, &shelf_data); // Gated shelves index only their first items
     SHELF_DATA.with(|sds| {
         sds.borrow_mut().insert(shelf_id.clone(), shelf_data);
     });
 
+    push_notification(shelf_owner, NotificationKind::ShelfEdited, caller, Some(shelf_id), None);
+
     Ok(())
 } 
\ No newline at end of file
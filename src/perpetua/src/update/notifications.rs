@@ -0,0 +1,67 @@
+use ic_cdk;
+
+use crate::storage::{
+    NOTIFICATIONS, MUTED_NOTIFICATION_KINDS,
+    NotificationKey, NotificationKind, recipient_key_range,
+};
+use crate::guard::not_anon_writable;
+
+/// Marks the caller's notifications as read
+/// 
+/// Pass specific notification IDs, or `None` to mark every notification as read.
+/// Returns the number of notifications that changed from unread to read.
+#[ic_cdk::update(guard = "not_anon_writable")]
+pub fn mark_notifications_read(notification_ids: Option<Vec<u64>>) -> Result<u64, String> {
+    let caller = ic_cdk::caller();
+
+    NOTIFICATIONS.with(|notifications_ref| {
+        let mut notifications = notifications_ref.borrow_mut();
+
+        let keys: Vec<NotificationKey> = match notification_ids {
+            Some(ids) => ids.into_iter()
+                .map(|notification_id| NotificationKey { recipient: caller, notification_id })
+                .collect(),
+            None => {
+                let (start_key, end_key) = recipient_key_range(caller);
+                notifications.range(start_key..=end_key).map(|(k, _)| k).collect()
+            }
+        };
+
+        let mut marked_count: u64 = 0;
+        for key in keys {
+            if let Some(mut notification) = notifications.get(&key) {
+                if !notification.read {
+                    notification.read = true;
+                    notifications.insert(key, notification);
+                    marked_count += 1;
+                }
+            }
+        }
+        Ok(marked_count)
+    })
+}
+
+/// Mutes or unmutes a notification type for the caller
+/// 
+/// Muted types are not recorded at all; existing notifications are kept.
+#[ic_cdk::update(guard = "not_anon_writable")]
+pub fn set_notification_muted(kind: NotificationKind, muted: bool) -> Result<(), String> {
+    let caller = ic_cdk::caller();
+
+    MUTED_NOTIFICATION_KINDS.with(|muted_ref| {
+        let mut map = muted_ref.borrow_mut();
+        let mut muted_kinds = map.get(&caller).unwrap_or_default();
+        if muted {
+            muted_kinds.0.insert(kind);
+        } else {
+            muted_kinds.0.remove(&kind);
+        }
+
+        if muted_kinds.0.is_empty() {
+            map.remove(&caller);
+        } else {
+            map.insert(caller, muted_kinds);
+        }
+    });
+    Ok(())
+}
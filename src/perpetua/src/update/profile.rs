@@ -1,21 +1,17 @@
 use ic_cdk;
 
-use crate::storage::{USER_SHELVES, USER_PROFILE_ORDER, ShelfId};
-use crate::guard::not_anon;
+use candid::Principal;
+use std::ops::Bound;
 
-// Constants for profile shelf positioning
-// const PROFILE_SHELF_THRESHOLDS: [(usize, f64); 2] = [
-//     (100, 1e-8),
-//     (0, 1e-6)
-// ];
-
-const PROFILE_SHELF_STEP_SIZE: f64 = 1000.0;
+use crate::storage::{USER_SHELVES, USER_PROFILE_ORDER, SHELF_DATA, ShelfId, ShelfData, UserProfileOrder};
+use crate::types::{RankMigrationCursor, RankMigrationProgress};
+use crate::guard::{not_anon_writable, is_controller};
 
 /// Reorders a shelf in a user's profile relative to another shelf
 /// 
 /// This repositions a shelf relative to other shelves on the user's profile.
 /// The position can be specified as before or after another shelf.
-#[ic_cdk::update(guard = "not_anon")]
+#[ic_cdk::update(guard = "not_anon_writable")]
 pub fn reorder_profile_shelf(shelf_id: ShelfId, reference_shelf_id: Option<ShelfId>, before: bool) -> Result<(), String> {
     let caller = ic_cdk::caller();
     
@@ -59,7 +55,7 @@ pub fn reorder_profile_shelf(shelf_id: ShelfId, reference_shelf_id: Option<Shelf
         // This handles the case where a shelf is added but not yet explicitly ordered.
         if !user_order.shelf_positions.contains_key(&shelf_id) {
              // Calculate position at the end
-             let initial_pos = user_order.shelf_positions.calculate_position(None, false, PROFILE_SHELF_STEP_SIZE)?;
+             let initial_pos = user_order.shelf_positions.calculate_position(None, false)?;
              user_order.shelf_positions.insert(shelf_id.clone(), initial_pos);
         }
 
@@ -69,7 +65,7 @@ pub fn reorder_profile_shelf(shelf_id: ShelfId, reference_shelf_id: Option<Shelf
             if !user_order.shelf_positions.contains_key(ref_id) {
                  // If the reference shelf doesn't have a position yet, assign one at the end.
                  // This handles cases where the reference shelf itself hasn't been ordered before.
-                 let temp_pos = user_order.shelf_positions.calculate_position(None, false, PROFILE_SHELF_STEP_SIZE)?;
+                 let temp_pos = user_order.shelf_positions.calculate_position(None, false)?;
                  user_order.shelf_positions.insert(ref_id.clone(), temp_pos);
                  ic_cdk::println!("WARN: Reference shelf '{}' was not in profile order tracker. Assigned default position.", ref_id);
             }
@@ -78,8 +74,7 @@ pub fn reorder_profile_shelf(shelf_id: ShelfId, reference_shelf_id: Option<Shelf
         // Calculate the new position using PositionTracker method
         let new_position = user_order.shelf_positions.calculate_position(
             reference_shelf_id.as_ref(), // Pass Option<&ShelfId>
-            before, 
-            PROFILE_SHELF_STEP_SIZE
+            before,
         )?;
         
         // Update the shelf position in the tracker (insert handles update)
@@ -95,7 +90,7 @@ pub fn reorder_profile_shelf(shelf_id: ShelfId, reference_shelf_id: Option<Shelf
 /// Resets the profile order to default (chronological ordering)
 /// 
 /// This clears all customizations and returns the profile to its original state.
-#[ic_cdk::update(guard = "not_anon")]
+#[ic_cdk::update(guard = "not_anon_writable")]
 pub fn reset_profile_order() -> Result<(), String> {
     let caller = ic_cdk::caller();
     
@@ -116,4 +111,71 @@ pub fn reset_profile_order() -> Result<(), String> {
         
         Ok(())
     })
-} 
\ No newline at end of file
+} 
+
+const MAX_RANK_MIGRATION_BATCH: u64 = 200;
+
+/// Rewrites a batch of shelves and profile orders in the rank-key format (controllers only).
+///
+/// Entries still holding legacy f64 positions are migrated in order when read; rewriting them
+/// persists the rank keys. Each map is walked with its own cursor, up to `limit` entries of each
+/// per call (at most 200). Start with no cursor and pass back `next_cursor` until it is None.
+#[ic_cdk::update(guard = "is_controller")]
+pub fn migrate_position_ranks(cursor: Option<RankMigrationCursor>, limit: u64) -> Result<RankMigrationProgress, String> {
+    let mut cursor = cursor.unwrap_or_default();
+    let limit = limit.clamp(1, MAX_RANK_MIGRATION_BATCH) as usize;
+
+    let shelves: Vec<(ShelfId, ShelfData)> = if cursor.shelves_done {
+        Vec::new()
+    } else {
+        SHELF_DATA.with(|sds_map_ref| {
+            let start = cursor.shelves_after.clone().map_or(Bound::Unbounded, Bound::Excluded);
+            sds_map_ref.borrow()
+                .range((start, Bound::Unbounded))
+                .take(limit)
+                .collect()
+        })
+    };
+    let profile_orders: Vec<(Principal, UserProfileOrder)> = if cursor.profiles_done {
+        Vec::new()
+    } else {
+        USER_PROFILE_ORDER.with(|profile_order| {
+            let start = cursor.profiles_after.map_or(Bound::Unbounded, Bound::Excluded);
+            profile_order.borrow()
+                .range((start, Bound::Unbounded))
+                .take(limit)
+                .collect()
+        })
+    };
+
+    let shelves_rewritten = shelves.len() as u64;
+    let profiles_rewritten = profile_orders.len() as u64;
+    cursor.shelves_done = cursor.shelves_done || shelves.len() < limit;
+    cursor.profiles_done = cursor.profiles_done || profile_orders.len() < limit;
+    if let Some((shelf_id, _)) = shelves.last() {
+        cursor.shelves_after = Some(shelf_id.clone());
+    }
+    if let Some((user, _)) = profile_orders.last() {
+        cursor.profiles_after = Some(*user);
+    }
+
+    SHELF_DATA.with(|sds_map_ref| {
+        let mut sds_map = sds_map_ref.borrow_mut();
+        for (shelf_id, shelf_data) in shelves {
+            sds_map.insert(shelf_id, shelf_data);
+        }
+    });
+    USER_PROFILE_ORDER.with(|profile_order| {
+        let mut profile_map = profile_order.borrow_mut();
+        for (user, order) in profile_orders {
+            profile_map.insert(user, order);
+        }
+    });
+
+    let done = cursor.shelves_done && cursor.profiles_done;
+    Ok(RankMigrationProgress {
+        shelves_rewritten,
+        profiles_rewritten,
+        next_cursor: if done { None } else { Some(cursor) },
+    })
+}
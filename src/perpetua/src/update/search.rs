@@ -0,0 +1,26 @@
+use ic_cdk;
+
+use crate::storage::{SHELF_DATA, ShelfData, ShelfId, index_shelf_for_search};
+use crate::types::BackupPaginationInput;
+use crate::guard::is_controller;
+
+/// Re-indexes a page of shelves for full-text search (controllers only).
+///
+/// Used to backfill the search index for shelves created before it existed.
+/// Call repeatedly with increasing offsets until the returned count is below the limit.
+#[ic_cdk::update(guard = "is_controller")]
+pub fn rebuild_search_index(pagination: BackupPaginationInput) -> Result<u64, String> {
+    let page: Vec<(ShelfId, ShelfData)> = SHELF_DATA.with(|sds_map_ref| {
+        sds_map_ref.borrow()
+            .iter()
+            .skip(pagination.offset as usize)
+            .take(pagination.limit as usize)
+            .collect()
+    });
+
+    for (shelf_id, shelf_data) in &page {
+        index_shelf_for_search(shelf_id, shelf_data);
+    }
+
+    Ok(page.len() as u64)
+}
@@ -1,8 +1,12 @@
-use candid::{CandidType, Deserialize};
+use candid::{CandidType, Deserialize, Nat, Principal};
 use ic_cdk::api::call::CallResult;
-use crate::storage::{Item, ItemContent, ShelfData, SHELF_DATA, NFT_SHELVES, USER_SHELVES, create_shelf, GLOBAL_TIMELINE, ShelfId, GlobalTimelineItemValue, ShelfMetadata, ShelfContent};
-use crate::guard::not_anon;
+use crate::storage::{Item, ItemContent, ShelfData, SHELF_DATA, NFT_SHELVES, USER_SHELVES, create_shelf, GLOBAL_TIMELINE, ShelfId, GlobalTimelineItemValue, ShelfMetadata, ShelfContent, index_shelf_for_search, record_activity, ActivityKind, record_shelf_fork, is_tag_blocked, push_notification, NotificationKind, SHELF_TAG_ASSOCIATIONS, ShelfTagAssociationKey, has_shelf_access, ItemId, ShelfAccessPolicy};
+use crate::storage::common_types::MAX_APPEARS_IN_COUNT;
+use crate::guard::not_anon_writable;
 use crate::nft_manager_principal;
+use crate::utils::id_conversion;
+use super::item::MAX_NFT_REFERENCES;
+use super::utils::{get_nft_owners_batch, get_icrc7_token_owners_batch};
 use super::tags::add_tag_to_metadata_maps;
 
 // --- Constants ---
@@ -16,28 +20,9 @@ pub struct ShelfUpdate {
     pub description: Option<String>,
 }
 
-/// Creates a new shelf with the provided metadata and items
-/// 
-/// Stores the newly created shelf in the global registry and
-/// establishes the appropriate ownership and reference tracking.
-/// Note: Initial tag association must now happen via explicit calls to add_tag_to_shelf.
-#[ic_cdk::update(guard = "not_anon")]
-pub async fn store_shelf(
-    title: String,
-    description: Option<String>,
-    items: Vec<Item>,
-    tags: Option<Vec<String>>, // Still accepts raw tags for shelf creation
-) -> Result<ShelfId, String> { // Return ShelfId (String)
-    let caller = ic_cdk::caller();
-    
-    // TODO: If items are allowed during initial shelf creation in the future,
-    // a robust circular reference check (e.g., would_create_cycle from item.rs or storage/shelf_storage.rs)
-    // must be performed here or in create_shelf for any ItemContent::Shelf in the initial items.
-    // The current Item type in the signature is maintained for Candid compatibility.
-    if !items.is_empty() {
-        return Err("Initializing shelves with items is currently unsupported. Please create an empty shelf and add items separately.".to_string());
-    }
-    
+/// Charges the shelf creation fee when the caller already owns `SHELF_CREATION_FEE_THRESHOLD`
+/// or more shelves, and enforces `MAX_USER_SHELVES`.
+async fn charge_shelf_creation_fee(caller: Principal) -> Result<(), String> {
     // --- Check Shelf Count and Payment ---
     let current_shelf_count = USER_SHELVES.with(|user_shelves| {
         user_shelves.borrow()
@@ -74,53 +59,57 @@ pub async fn store_shelf(
     if current_shelf_count >= MAX_USER_SHELVES {
         return Err(format!("User cannot own more than {} shelves.", MAX_USER_SHELVES));
     }
-    
-    // Create the in-memory shelf - create_shelf now handles normalization/validation
-    let shelf_in_memory = create_shelf(title, description, items, tags).await?;
-    let shelf_id = shelf_in_memory.shelf_id.clone();
-    let now = shelf_in_memory.created_at; // Use created_at from the in-memory shelf
 
-    // Deconstruct the in-memory Shelf into ShelfMetadata and ShelfContent parts
-    let shelf_metadata_for_data = ShelfMetadata {
-        shelf_id: shelf_in_memory.shelf_id.clone(),
-        title: shelf_in_memory.title.clone(),
-        description: shelf_in_memory.description.clone(),
-        owner: shelf_in_memory.owner,
-        created_at: shelf_in_memory.created_at,
-        updated_at: shelf_in_memory.updated_at, // Should be same as created_at initially
-        appears_in: shelf_in_memory.appears_in.clone(),
-        tags: shelf_in_memory.tags.clone(),
-        public_editing: shelf_in_memory.public_editing,
-    };
-
-    let shelf_content_for_data = ShelfContent {
-        items: shelf_in_memory.items.clone(),
-        item_positions: shelf_in_memory.item_positions.clone(),
-    };
+    Ok(())
+}
 
-    let shelf_data_to_store = ShelfData {
-        metadata: shelf_metadata_for_data.clone(), // Clone for ShelfData, metadata_for_data is used below for timeline
-        content: shelf_content_for_data,
-    };
+/// Commits a newly created shelf: SHELF_DATA, search index, NFT_SHELVES,
+/// USER_SHELVES, GLOBAL_TIMELINE and tag indexes.
+///
+/// From this point onwards, any failure MUST cause a panic to ensure atomicity.
+fn commit_new_shelf(shelf_data: ShelfData) {
+    let shelf_id = shelf_data.metadata.shelf_id.clone();
 
-    // --- COMMIT PHASE ---
-    // From this point onwards, any failure MUST cause a panic to ensure atomicity.
+    index_shelf(&shelf_data);
 
     // Store ShelfData
     SHELF_DATA.with(|sds_map_ref| {
-        if sds_map_ref.borrow_mut().insert(shelf_id.clone(), shelf_data_to_store).is_some() {
+        if sds_map_ref.borrow_mut().insert(shelf_id.clone(), shelf_data).is_some() {
             // Log or handle potential overwrite if necessary, though shelf_id should be unique
         }
     });
 
-    // Store NFT references (using data from shelf_metadata or shelf_in_memory)
-    for item in shelf_in_memory.items.values() { // Iterate over items from in-memory shelf
-        if let ItemContent::Nft(nft_id) = &item.content {
+    record_activity(ActivityKind::ShelfCreated, shelf_id);
+}
+
+/// Adds a shelf to the indexes derived from SHELF_DATA: search index, NFT_SHELVES,
+/// USER_SHELVES, GLOBAL_TIMELINE and tag indexes.
+///
+/// Safe to call again for a shelf that is already indexed (used when rebuilding after a restore).
+pub(super) fn index_shelf(shelf_data: &ShelfData) {
+    let shelf_id = shelf_data.metadata.shelf_id.clone();
+    let owner = shelf_data.metadata.owner;
+    let now = shelf_data.metadata.created_at;
+    let shelf_metadata_for_data = &shelf_data.metadata;
+
+    // Index title/description for full-text search
+    index_shelf_for_search(&shelf_id, shelf_data);
+
+    // Store NFT references (and other tracked item keys)
+    for item in shelf_data.content.items.values() {
+        if let Some(key) = id_conversion::get_appearance_key_for_storage(&item.content) {
             NFT_SHELVES.with(|nft_shelves| {
                 let mut nft_map = nft_shelves.borrow_mut();
-                let mut shelves = nft_map.get(nft_id).unwrap_or_default();
+                let mut shelves = nft_map.get(&key).unwrap_or_default();
+                if shelves.0.contains(&shelf_id) {
+                    return;
+                }
+                if shelves.0.len() >= MAX_NFT_REFERENCES {
+                    ic_cdk::println!("[index_shelf] MAX_NFT_REFERENCES ({}) reached for key '{}'; shelf '{}' not tracked.", MAX_NFT_REFERENCES, key, shelf_id);
+                    return;
+                }
                 shelves.0.push(shelf_id.clone());
-                nft_map.insert(nft_id.to_string(), shelves);
+                nft_map.insert(key, shelves);
             });
         }
     }
@@ -128,51 +117,264 @@ pub async fn store_shelf(
     // Update user shelf tracking
     USER_SHELVES.with(|user_shelves| {
         let mut user_map = user_shelves.borrow_mut();
-        let mut user_shelves_set = user_map.get(&caller).unwrap_or_default();
+        let mut user_shelves_set = user_map.get(&owner).unwrap_or_default();
         user_shelves_set.0.insert((now, shelf_id.clone()));
-        user_map.insert(caller, user_shelves_set);
+        user_map.insert(owner, user_shelves_set);
     });
 
     // Add shelf to the global timeline for public discoverability
     GLOBAL_TIMELINE.with(|timeline_map_ref| {
-        if timeline_map_ref.borrow_mut().insert(
-            now, // This 'now' is shelf_in_memory.created_at
+        if let Some(replaced) = timeline_map_ref.borrow_mut().insert(
+            now, // Shelf creation time
             GlobalTimelineItemValue {
                 shelf_id: shelf_metadata_for_data.shelf_id.clone(), 
                 owner: shelf_metadata_for_data.owner, 
                 tags: shelf_metadata_for_data.tags.clone(), 
                 public_editing: shelf_metadata_for_data.public_editing, 
             }
-        ).is_some() {
+        ) {
             // This means a timeline entry for this exact timestamp 'now' was replaced.
             // Highly unlikely for u64 ns timestamps unless multiple shelves are created by same user in same transaction (not possible from UI)
             // or a hash collision on timestamp (extremely unlikely).
             // If this happens, it's a critical issue. For now, we let it replace. A panic could be justified if 'now' must be unique.
             // ic_cdk::trap(&format!("Duplicate timestamp in GLOBAL_TIMELINE: {}", now));
+            if replaced.shelf_id != shelf_id {
+                ic_cdk::println!("[index_shelf] GLOBAL_TIMELINE entry at {} for shelf '{}' replaced by '{}'.", now, replaced.shelf_id, shelf_id);
+            }
         }
     });
     
-    // MODIFICATION: Call add_tag_to_metadata_maps for each tag.
-    // This helper now panics on failure, ensuring atomicity for tag-related map updates.
-    // The original .map_err and ? are removed.
-    // shelf_metadata.tags are already normalized by create_shelf
+    // shelf_metadata.tags are already normalized by create_shelf.
+    // Tags already associated with the shelf are skipped so counts are not incremented twice.
     for tag_to_associate in &shelf_metadata_for_data.tags {
+        let already_associated = SHELF_TAG_ASSOCIATIONS.with(|map_ref| {
+            map_ref.borrow().contains_key(&ShelfTagAssociationKey { shelf_id: shelf_id.clone(), tag: tag_to_associate.clone() })
+        });
+        if already_associated {
+            continue;
+        }
         // The 'now' timestamp here refers to the shelf creation time.
         // add_tag_to_metadata_maps uses its 'now' param for last_association_timestamp etc.
-        // For initial creation, using shelf_metadata.created_at for both 'now' and 'shelf_created_at' in add_tag_to_metadata_maps call
-        // or more accurately, 'now' (shelf creation time) for both.
         add_tag_to_metadata_maps(&shelf_id, tag_to_associate, shelf_metadata_for_data.created_at, now); 
-            // REMOVED: .map_err(|e| format!("Failed to associate tag '{}': {}", tag_to_associate, e))?;
     }
+}
+
+/// Creates a new shelf with the provided metadata and items
+/// 
+/// Stores the newly created shelf in the global registry and
+/// establishes the appropriate ownership and reference tracking.
+/// Note: Initial tag association must now happen via explicit calls to add_tag_to_shelf.
+#[ic_cdk::update(guard = "not_anon_writable")]
+pub async fn store_shelf(
+    title: String,
+    description: Option<String>,
+    items: Vec<Item>,
+    tags: Option<Vec<String>>, // Still accepts raw tags for shelf creation
+) -> Result<ShelfId, String> { // Return ShelfId (String)
+    let caller = ic_cdk::caller();
+    
+    // TODO: If items are allowed during initial shelf creation in the future,
+    // a robust circular reference check (e.g., would_create_cycle from item.rs or storage/shelf_storage.rs)
+    // must be performed here or in create_shelf for any ItemContent::Shelf in the initial items.
+    // The current Item type in the signature is maintained for Candid compatibility.
+    if !items.is_empty() {
+        return Err("Initializing shelves with items is currently unsupported. Please create an empty shelf and add items separately.".to_string());
+    }
+    
+    charge_shelf_creation_fee(caller).await?;
+    
+    // Create the in-memory shelf - create_shelf now handles normalization/validation
+    let shelf_in_memory = create_shelf(title, description, items, tags).await?;
+    let shelf_id = shelf_in_memory.shelf_id.clone();
+
+    // Deconstruct the in-memory Shelf into ShelfMetadata and ShelfContent parts
+    let shelf_metadata_for_data = ShelfMetadata {
+        shelf_id: shelf_in_memory.shelf_id.clone(),
+        title: shelf_in_memory.title.clone(),
+        description: shelf_in_memory.description.clone(),
+        owner: shelf_in_memory.owner,
+        created_at: shelf_in_memory.created_at,
+        updated_at: shelf_in_memory.updated_at, // Should be same as created_at initially
+        appears_in: shelf_in_memory.appears_in.clone(),
+        tags: shelf_in_memory.tags.clone(),
+        public_editing: shelf_in_memory.public_editing,
+        forked_from: None,
+        access_policy: None,
+    };
+
+    let shelf_content_for_data = ShelfContent {
+        items: shelf_in_memory.items.clone(),
+        item_positions: shelf_in_memory.item_positions.clone(),
+    };
+
+    let shelf_data_to_store = ShelfData {
+        metadata: shelf_metadata_for_data,
+        content: shelf_content_for_data,
+    };
+
+    commit_new_shelf(shelf_data_to_store);
 
     // Returning just the shelf_id as per original function signature change in .did
     Ok(shelf_id) 
 }
 
+/// Copies shelf content for a fork, dropping NFTs and ICRC-7 tokens the caller does not own
+/// (or that no longer exist). Ownership is looked up with batched `icrc7_owner_of` calls.
+async fn owned_fork_content(content: &ShelfContent, caller: Principal) -> Result<ShelfContent, String> {
+    let mut nft_items: Vec<(ItemId, String)> = Vec::new();
+    let mut token_items: Vec<(ItemId, (Principal, Nat))> = Vec::new();
+    for (item_id, item) in &content.items {
+        match &item.content {
+            ItemContent::Nft(nft_id) => nft_items.push((*item_id, nft_id.clone())),
+            ItemContent::Icrc7Token { collection, token_id } => token_items.push((*item_id, (*collection, token_id.clone()))),
+            _ => {}
+        }
+    }
+
+    let mut unowned_item_ids: Vec<ItemId> = Vec::new();
+    if !nft_items.is_empty() {
+        let nft_ids: Vec<String> = nft_items.iter().map(|(_, nft_id)| nft_id.clone()).collect();
+        let owners = get_nft_owners_batch(&nft_ids).await?;
+        unowned_item_ids.extend(nft_items.iter().zip(owners)
+            .filter(|(_, owner)| *owner != Some(caller))
+            .map(|((item_id, _), _)| *item_id));
+    }
+    if !token_items.is_empty() {
+        let tokens: Vec<(Principal, Nat)> = token_items.iter().map(|(_, token)| token.clone()).collect();
+        let owners = get_icrc7_token_owners_batch(&tokens).await?;
+        unowned_item_ids.extend(token_items.iter().zip(owners)
+            .filter(|(_, owner)| *owner != Some(caller))
+            .map(|((item_id, _), _)| *item_id));
+    }
+
+    let mut fork_content = content.clone();
+    for item_id in &unowned_item_ids {
+        fork_content.items.remove(item_id);
+        fork_content.item_positions.remove(item_id);
+    }
+    Ok(fork_content)
+}
+
+/// Gated shelves can only be forked by their owner, so unlocking one does not allow
+/// republishing its content as a public fork.
+fn check_fork_access(source_metadata: &ShelfMetadata, caller: Principal) -> Result<(), String> {
+    if !has_shelf_access(source_metadata, caller) {
+        return Err("Unlock this shelf before forking it".to_string());
+    }
+    if source_metadata.access_policy() != ShelfAccessPolicy::Public && source_metadata.owner != caller {
+        return Err("Only the owner can fork a gated shelf".to_string());
+    }
+    Ok(())
+}
+
+/// Forks an existing shelf into a new shelf owned by the caller
+/// 
+/// Copies the source's items (NFTs, markdown, nested-shelf references, ...) in their current
+/// order, along with its title, description and tags. NFTs and ICRC-7 tokens the caller does
+/// not own are left out. The new shelf records the source in `forked_from`. Gated shelves can
+/// only be forked by their owner and the fork keeps the access policy. The same creation
+/// fee and shelf limit as `store_shelf` apply.
+#[ic_cdk::update(guard = "not_anon_writable")]
+pub async fn fork_shelf(source_shelf_id: ShelfId) -> Result<ShelfId, String> {
+    let caller = ic_cdk::caller();
+
+    // --- Read & Validate Phase ---
+    let source_metadata = SHELF_DATA.with(|sds| sds.borrow().get(&source_shelf_id).map(|sd| sd.metadata))
+        .ok_or_else(|| format!("Shelf with ID '{}' not found", source_shelf_id))?;
+    check_fork_access(&source_metadata, caller)?;
+
+    charge_shelf_creation_fee(caller).await?;
+
+    // Re-read the source after the fee call, as it (or its access policy) may have changed while awaiting.
+    let source_shelf_data = SHELF_DATA.with(|sds| sds.borrow().get(&source_shelf_id))
+        .ok_or_else(|| format!("Shelf with ID '{}' not found", source_shelf_id))?;
+    check_fork_access(&source_shelf_data.metadata, caller)?;
+
+    // NFTs and tokens are only carried over when the caller owns them, as with `add_item_to_shelf`.
+    // Every copied token is therefore the caller's own, so no item owners are notified.
+    let fork_content = owned_fork_content(&source_shelf_data.content, caller).await?;
+
+    // Tags blocked since they were added to the source are not carried over.
+    let fork_tags: Vec<String> = source_shelf_data.metadata.tags.iter()
+        .filter(|tag| !is_tag_blocked(tag))
+        .cloned()
+        .collect();
+
+    // --- Prepare Phase ---
+    let shelf_in_memory = create_shelf(
+        source_shelf_data.metadata.title.clone(),
+        source_shelf_data.metadata.description.clone(),
+        Vec::new(),
+        Some(fork_tags),
+    ).await?;
+    let fork_shelf_id = shelf_in_memory.shelf_id.clone();
+    let now = shelf_in_memory.created_at;
+
+    let fork_shelf_data = ShelfData {
+        metadata: ShelfMetadata {
+            shelf_id: fork_shelf_id.clone(),
+            title: shelf_in_memory.title,
+            description: shelf_in_memory.description,
+            owner: caller,
+            created_at: now,
+            updated_at: now,
+            appears_in: Vec::new(),
+            tags: shelf_in_memory.tags,
+            public_editing: false,
+            forked_from: Some(source_shelf_id.clone()),
+            access_policy: source_shelf_data.metadata.access_policy.clone(), // Only owners fork gated shelves; the fork stays gated
+        },
+        content: fork_content,
+    };
+
+    // Nested shelves now also appear in the fork.
+    let mut prepared_nested_shelf_updates: Vec<(ShelfId, ShelfData)> = Vec::new();
+    SHELF_DATA.with(|sds| {
+        let sds = sds.borrow();
+        for item in fork_shelf_data.content.items.values() {
+            let ItemContent::Shelf(nested_shelf_id) = &item.content else {
+                continue;
+            };
+            if prepared_nested_shelf_updates.iter().any(|(id, _)| id == nested_shelf_id) {
+                continue;
+            }
+            if let Some(mut nested_shelf_data) = sds.get(nested_shelf_id) {
+                if nested_shelf_data.metadata.appears_in.len() >= MAX_APPEARS_IN_COUNT {
+                    nested_shelf_data.metadata.appears_in.remove(0); // Remove the oldest
+                }
+                nested_shelf_data.metadata.appears_in.push(fork_shelf_id.clone());
+                nested_shelf_data.metadata.updated_at = now;
+                prepared_nested_shelf_updates.push((nested_shelf_id.clone(), nested_shelf_data));
+            }
+        }
+    });
+
+    // --- Commit Phase ---
+    commit_new_shelf(fork_shelf_data);
+
+    SHELF_DATA.with(|sds| {
+        let mut map = sds.borrow_mut();
+        for (id, data) in prepared_nested_shelf_updates {
+            map.insert(id, data);
+        }
+    });
+
+    record_shelf_fork(&source_shelf_id, &fork_shelf_id);
+    push_notification(
+        source_shelf_data.metadata.owner,
+        NotificationKind::ShelfForked,
+        caller,
+        Some(source_shelf_id),
+        Some(fork_shelf_id.clone()),
+    );
+
+    Ok(fork_shelf_id)
+}
+
 /// Updates the metadata (title and/or description) of an existing shelf
 /// 
 /// Only users with edit permissions can modify shelf metadata.
-#[ic_cdk::update(guard = "not_anon")]
+#[ic_cdk::update(guard = "not_anon_writable")]
 pub fn update_shelf_metadata(
     shelf_id: ShelfId, 
     title: Option<String>, 
@@ -207,7 +409,10 @@ pub fn update_shelf_metadata(
             
             shelf_data.metadata.updated_at = now;
 
+            let shelf_owner = shelf_data.metadata.owner;
+            index_shelf_for_search(&shelf_id, &shelf_data);
             shelf_data_map.insert(shelf_id.clone(), shelf_data); // Insert the modified ShelfData
+            push_notification(shelf_owner, NotificationKind::ShelfEdited, caller, Some(shelf_id.clone()), None);
             Ok(())
         } else {
             Err(format!("Shelf with ID '{}' not found", shelf_id))
@@ -0,0 +1,45 @@
+use ic_cdk;
+
+use crate::storage::{
+    SHELF_DATA, ShelfId, ItemId,
+    record_shelf_view_event, record_item_open_event,
+};
+use crate::guard::not_anon_writable;
+
+/// Records that the caller viewed a shelf
+///
+/// Views are counted at most once per visitor per hour, and the owner's own views are not counted.
+/// Returns whether this call was counted.
+#[ic_cdk::update(guard = "not_anon_writable")]
+pub fn record_shelf_view(shelf_id: ShelfId) -> Result<bool, String> {
+    let caller = ic_cdk::caller();
+
+    let owner = SHELF_DATA.with(|sds| sds.borrow().get(&shelf_id).map(|sd| sd.metadata.owner))
+        .ok_or_else(|| "Shelf not found".to_string())?;
+    if owner == caller {
+        return Ok(false);
+    }
+
+    Ok(record_shelf_view_event(&shelf_id, caller))
+}
+
+/// Records that the caller opened an item on a shelf
+///
+/// Opens are counted at most once per visitor and item per hour, and the owner's own opens are not counted.
+/// Returns whether this call was counted.
+#[ic_cdk::update(guard = "not_anon_writable")]
+pub fn record_item_open(shelf_id: ShelfId, item_id: ItemId) -> Result<bool, String> {
+    let caller = ic_cdk::caller();
+
+    let (owner, has_item) = SHELF_DATA.with(|sds| {
+        sds.borrow().get(&shelf_id).map(|sd| (sd.metadata.owner, sd.content.items.contains_key(&item_id)))
+    }).ok_or_else(|| "Shelf not found".to_string())?;
+    if !has_item {
+        return Err("Item not found".to_string());
+    }
+    if owner == caller {
+        return Ok(false);
+    }
+
+    Ok(record_item_open_event(&shelf_id, item_id, caller))
+}
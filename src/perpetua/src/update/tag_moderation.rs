@@ -0,0 +1,267 @@
+use ic_cdk;
+use candid::Principal;
+use std::ops::Bound;
+
+use crate::storage::{
+    ShelfId, NormalizedTag, SHELF_DATA, GLOBAL_TIMELINE, FOLLOWED_TAGS,
+    TAG_METADATA, TAG_SHELF_ASSOCIATIONS, TAG_ALIASES, TAG_BLOCKLIST, MAX_TAG_LENGTH,
+    TAG_FOLLOWERS, TagFollowerKey,
+    expected_tag_cooccurrence, replace_tag_cooccurrence,
+    validate_tag_format, is_tag_blocked, add_tag_follower, remove_tag_follower,
+};
+use crate::types::{TagShelfAssociationKey as TypesTagShelfAssociationKey, TagMergeCursor, TagMergeProgress, BackupPaginationInput};
+use crate::utils::normalize_tag;
+use crate::guard::is_controller;
+use super::tags::{add_tag_to_metadata_maps, remove_tag_from_metadata_maps};
+
+/// Normalizes an alias. Aliases may contain characters real tags cannot (e.g. "sci-fi").
+fn normalize_alias(raw_alias: &str) -> Result<NormalizedTag, String> {
+    let alias = normalize_tag(raw_alias);
+    if alias.is_empty() {
+        return Err("Alias cannot be empty".to_string());
+    }
+    if alias.len() > MAX_TAG_LENGTH {
+        return Err(format!("Alias exceeds maximum length of {}", MAX_TAG_LENGTH));
+    }
+    Ok(alias)
+}
+
+/// Normalizes a canonical tag and checks it can be the target of an alias or merge.
+fn normalize_canonical(raw_tag: &str) -> Result<NormalizedTag, String> {
+    let canonical = normalize_tag(raw_tag);
+    validate_tag_format(&canonical)?;
+    if is_tag_blocked(&canonical) {
+        return Err(format!("Tag '{}' is blocked", canonical));
+    }
+    if TAG_ALIASES.with(|aliases| aliases.borrow().contains_key(&canonical)) {
+        return Err(format!("Tag '{}' is itself an alias", canonical));
+    }
+    Ok(canonical)
+}
+
+/// Points every alias that currently resolves to `from` at `to`, keeping aliases one level deep.
+fn retarget_aliases(from: &NormalizedTag, to: &NormalizedTag) {
+    TAG_ALIASES.with(|aliases_ref| {
+        let mut aliases = aliases_ref.borrow_mut();
+        let to_retarget: Vec<NormalizedTag> = aliases.iter()
+            .filter(|(_, canonical)| canonical == from)
+            .map(|(alias, _)| alias)
+            .collect();
+        for alias in to_retarget {
+            aliases.insert(alias, to.clone());
+        }
+    });
+}
+
+/// Registers `alias` as a synonym of `canonical` (controllers only).
+///
+/// Tags added, followed or queried through the alias resolve to the canonical tag.
+/// An alias still attached to shelves must be merged with `merge_tags` instead.
+#[ic_cdk::update(guard = "is_controller")]
+pub fn set_tag_alias(alias: String, canonical: String) -> Result<(), String> {
+    let alias = normalize_alias(&alias)?;
+    let canonical = normalize_canonical(&canonical)?;
+
+    if alias == canonical {
+        return Err("Alias and canonical tag must differ".to_string());
+    }
+    if TAG_METADATA.with(|meta| meta.borrow().contains_key(&alias)) {
+        return Err(format!("Tag '{}' is still used by shelves; use merge_tags instead", alias));
+    }
+
+    retarget_aliases(&alias, &canonical);
+    TAG_ALIASES.with(|aliases| {
+        aliases.borrow_mut().insert(alias, canonical);
+    });
+    Ok(())
+}
+
+/// Removes a tag alias (controllers only).
+#[ic_cdk::update(guard = "is_controller")]
+pub fn remove_tag_alias(alias: String) -> Result<(), String> {
+    let alias = normalize_tag(&alias);
+    TAG_ALIASES.with(|aliases| aliases.borrow_mut().remove(&alias))
+        .map(|_| ())
+        .ok_or_else(|| format!("Alias '{}' not found", alias))
+}
+
+const MAX_TAG_MERGE_BATCH: u64 = 200;
+
+/// Merges `source` into `target` in batches (controllers only).
+///
+/// The first call makes `source` an alias of `target`, so new uses resolve to the target
+/// while the merge runs. Each call then retags up to `limit` shelves tagged with `source`
+/// (at most 200) and moves up to `limit` of its followers (from `TAG_FOLLOWERS`) to `target`.
+/// Start with no cursor and pass back `next_cursor` until it is None.
+#[ic_cdk::update(guard = "is_controller")]
+pub fn merge_tags(source: String, target: String, cursor: Option<TagMergeCursor>, limit: u64) -> Result<TagMergeProgress, String> {
+    let source = normalize_alias(&source)?;
+    let target = normalize_canonical(&target)?;
+
+    if source == target {
+        return Err("Cannot merge a tag into itself".to_string());
+    }
+    let resuming = cursor.is_some();
+    let mut cursor = cursor.unwrap_or_default();
+    let limit = limit.clamp(1, MAX_TAG_MERGE_BATCH) as usize;
+
+    // --- 1. Future uses of the source tag resolve to the target ---
+    if resuming {
+        let aliased_to = TAG_ALIASES.with(|aliases| aliases.borrow().get(&source));
+        if aliased_to.as_ref() != Some(&target) {
+            return Err(format!("No merge of '{}' into '{}' is in progress", source, target));
+        }
+    } else {
+        retarget_aliases(&source, &target);
+        TAG_ALIASES.with(|aliases| {
+            aliases.borrow_mut().insert(source.clone(), target.clone());
+        });
+    }
+
+    // --- 2. Retag a batch of shelves (metadata, tag indexes and timeline) ---
+    let shelf_ids: Vec<ShelfId> = if cursor.shelves_done {
+        Vec::new()
+    } else {
+        TAG_SHELF_ASSOCIATIONS.with(|assoc_ref| {
+            let start = match &cursor.shelves_after {
+                Some(shelf_id) => Bound::Excluded(TypesTagShelfAssociationKey(source.clone(), shelf_id.clone())),
+                None => Bound::Included(TypesTagShelfAssociationKey(source.clone(), String::new())),
+            };
+            assoc_ref.borrow()
+                .range((start, Bound::Unbounded))
+                .take_while(|(key, _)| key.0 == source)
+                .take(limit)
+                .map(|(key, _)| key.1)
+                .collect()
+        })
+    };
+    cursor.shelves_done = cursor.shelves_done || shelf_ids.len() < limit;
+    if let Some(shelf_id) = shelf_ids.last() {
+        cursor.shelves_after = Some(shelf_id.clone());
+    }
+
+    let now = ic_cdk::api::time();
+    let mut shelves_retagged: u64 = 0;
+
+    for shelf_id in &shelf_ids {
+        let Some(mut shelf_data) = SHELF_DATA.with(|sds| sds.borrow().get(shelf_id)) else {
+            continue;
+        };
+
+        let already_has_target = shelf_data.metadata.tags.contains(&target);
+        shelf_data.metadata.tags.retain(|t| t != &source);
+        if !already_has_target {
+            shelf_data.metadata.tags.push(target.clone());
+        }
+        let created_at = shelf_data.metadata.created_at;
+
+        remove_tag_from_metadata_maps(shelf_id, &source, created_at, now);
+        if !already_has_target {
+            add_tag_to_metadata_maps(shelf_id, &target, created_at, now);
+        }
+
+        GLOBAL_TIMELINE.with(|timeline_ref| {
+            let mut timeline = timeline_ref.borrow_mut();
+            if let Some(mut timeline_item) = timeline.get(&created_at) {
+                if &timeline_item.shelf_id == shelf_id {
+                    timeline_item.tags = shelf_data.metadata.tags.clone();
+                    timeline.insert(created_at, timeline_item);
+                }
+            }
+        });
+
+        SHELF_DATA.with(|sds| {
+            sds.borrow_mut().insert(shelf_id.clone(), shelf_data);
+        });
+        shelves_retagged += 1;
+    }
+
+    // --- 3. Move a batch of followers over to the target tag ---
+    let followers: Vec<Principal> = if cursor.followers_done {
+        Vec::new()
+    } else {
+        TAG_FOLLOWERS.with(|map_ref| {
+            let start = match cursor.followers_after {
+                Some(follower) => Bound::Excluded(TagFollowerKey { tag: source.clone(), follower }),
+                None => Bound::Included(TagFollowerKey { tag: source.clone(), follower: Principal::management_canister() }),
+            };
+            map_ref.borrow()
+                .range((start, Bound::Unbounded))
+                .take_while(|(key, _)| key.tag == source)
+                .take(limit)
+                .map(|(key, _)| key.follower)
+                .collect()
+        })
+    };
+    cursor.followers_done = cursor.followers_done || followers.len() < limit;
+    if let Some(follower) = followers.last() {
+        cursor.followers_after = Some(*follower);
+    }
+
+    FOLLOWED_TAGS.with(|followed_ref| {
+        let mut followed = followed_ref.borrow_mut();
+        for follower in &followers {
+            if let Some(mut tag_set) = followed.get(follower) {
+                tag_set.0.remove(&source);
+                tag_set.0.insert(target.clone());
+                followed.insert(*follower, tag_set);
+            }
+        }
+    });
+    for follower in &followers {
+        remove_tag_follower(&source, *follower);
+        add_tag_follower(&target, *follower);
+    }
+
+    let done = cursor.shelves_done && cursor.followers_done;
+    Ok(TagMergeProgress {
+        shelves_retagged,
+        followers_moved: followers.len() as u64,
+        next_cursor: if done { None } else { Some(cursor) },
+    })
+}
+
+/// Adds a term to the tag blocklist (controllers only).
+///
+/// Blocked tags cannot be added to shelves or followed, and are hidden from tag listings.
+/// Existing associations are left untouched.
+#[ic_cdk::update(guard = "is_controller")]
+pub fn block_tag(tag: String) -> Result<(), String> {
+    let tag = normalize_alias(&tag)?;
+    TAG_BLOCKLIST.with(|blocklist| {
+        blocklist.borrow_mut().insert(tag, ());
+    });
+    Ok(())
+}
+
+/// Removes a term from the tag blocklist (controllers only).
+#[ic_cdk::update(guard = "is_controller")]
+pub fn unblock_tag(tag: String) -> Result<(), String> {
+    let tag = normalize_tag(&tag);
+    TAG_BLOCKLIST.with(|blocklist| blocklist.borrow_mut().remove(&tag))
+        .map(|_| ())
+        .ok_or_else(|| format!("Tag '{}' is not blocked", tag))
+}
+
+/// Recomputes tag co-occurrence counts for a page of tags (controllers only).
+///
+/// Each tag's `TAG_COOCCURRENCE` entries are replaced with counts derived from the shelf
+/// tag associations, so pages can be rerun safely. Call repeatedly with increasing offsets
+/// until the returned count is below the limit.
+#[ic_cdk::update(guard = "is_controller")]
+pub fn rebuild_tag_cooccurrence(pagination: BackupPaginationInput) -> Result<u64, String> {
+    let tags: Vec<NormalizedTag> = TAG_METADATA.with(|meta| {
+        meta.borrow()
+            .iter()
+            .skip(pagination.offset as usize)
+            .take(pagination.limit as usize)
+            .map(|(tag, _)| tag)
+            .collect()
+    });
+
+    for tag in &tags {
+        replace_tag_cooccurrence(tag, expected_tag_cooccurrence(tag));
+    }
+
+    Ok(tags.len() as u64)
+}
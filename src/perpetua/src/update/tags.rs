@@ -6,12 +6,11 @@ use crate::storage::{
     TAG_METADATA, TAG_SHELF_ASSOCIATIONS, TAG_POPULARITY_INDEX, TAG_LEXICAL_INDEX,
     SHELF_TAG_ASSOCIATIONS, ShelfTagAssociationKey, MAX_TAGS_PER_SHELF,
     TAG_SHELF_CREATION_TIMELINE_INDEX, TagShelfCreationTimelineKey,
-    validate_tag_format
+    canonicalize_tag, resolve_tag, adjust_tag_cooccurrence, shelf_associated_tags
 };
 use crate::types::{TagPopularityKey, TagShelfAssociationKey as TypesTagShelfAssociationKey};
-use crate::utils::{normalize_tag};
 use crate::auth;
-use crate::guard::not_anon;
+use crate::guard::not_anon_writable;
 
 /// Input for tag operations (reusing similar structure)
 #[derive(CandidType, Deserialize)]
@@ -22,14 +21,14 @@ pub struct TagOperationInput {
 
 /// Adds a tag to a shelf and updates all relevant indices.
 /// This is the primary entry point for associating a tag with a shelf.
-#[ic_cdk::update(guard = "not_anon")]
+#[ic_cdk::update(guard = "not_anon_writable")]
 pub fn add_tag_to_shelf(input: TagOperationInput) -> Result<(), String> {
     let caller = ic_cdk::caller();
     let shelf_id = input.shelf_id;
     let raw_tag = input.tag;
 
-    let normalized_tag = normalize_tag(&raw_tag);
-    validate_tag_format(&normalized_tag)?;
+    // Resolves aliases (e.g. "sci-fi" -> "scifi") and rejects blocked tags.
+    let normalized_tag = canonicalize_tag(&raw_tag)?;
 
     auth::get_shelf_parts_for_edit_mut(&shelf_id, &caller, |metadata, _content| {
         if metadata.tags.len() >= MAX_TAGS_PER_SHELF && !metadata.tags.contains(&normalized_tag) {
@@ -53,13 +52,13 @@ pub fn add_tag_to_shelf(input: TagOperationInput) -> Result<(), String> {
 }
 
 /// Removes a tag from a shelf and updates all relevant indices.
-#[ic_cdk::update(guard = "not_anon")]
+#[ic_cdk::update(guard = "not_anon_writable")]
 pub fn remove_tag_from_shelf(input: TagOperationInput) -> Result<(), String> {
     let caller = ic_cdk::caller();
     let shelf_id = input.shelf_id;
     let raw_tag = input.tag;
 
-    let normalized_tag = normalize_tag(&raw_tag);
+    let normalized_tag = resolve_tag(&raw_tag);
 
     auth::get_shelf_parts_for_edit_mut(&shelf_id, &caller, |metadata, _content| {
         let initial_len = metadata.tags.len();
@@ -77,6 +76,13 @@ pub fn remove_tag_from_shelf(input: TagOperationInput) -> Result<(), String> {
 }
 
 pub(super) fn add_tag_to_metadata_maps(shelf_id: &ShelfId, normalized_tag: &NormalizedTag, shelf_created_at: u64, now: u64) {
+    // --- Phase 0: Co-occurrence with the shelf's other tags (read before the association is added) ---
+    for other_tag in shelf_associated_tags(shelf_id) {
+        if &other_tag != normalized_tag {
+            adjust_tag_cooccurrence(normalized_tag, &other_tag, true);
+        }
+    }
+
     // --- Phase 1: Update primary associations and basic indexes --- 
     TAG_SHELF_ASSOCIATIONS.with(|map_ref| {
         map_ref.borrow_mut().insert(TypesTagShelfAssociationKey(normalized_tag.clone(), shelf_id.clone()), ());
@@ -139,7 +145,16 @@ pub(super) fn add_tag_to_metadata_maps(shelf_id: &ShelfId, normalized_tag: &Norm
     });
 }
 
-fn remove_tag_from_metadata_maps(shelf_id: &ShelfId, normalized_tag: &NormalizedTag, shelf_created_at: u64, now: u64) {
+pub(super) fn remove_tag_from_metadata_maps(shelf_id: &ShelfId, normalized_tag: &NormalizedTag, shelf_created_at: u64, now: u64) {
+    // --- Phase 0: Co-occurrence with the shelf's other tags ---
+    // Only shelves that actually carry the tag contributed to its counts.
+    let associated_tags = shelf_associated_tags(shelf_id);
+    if associated_tags.contains(normalized_tag) {
+        for other_tag in associated_tags.iter().filter(|t| *t != normalized_tag) {
+            adjust_tag_cooccurrence(normalized_tag, other_tag, false);
+        }
+    }
+
     // --- Phase 1: Remove primary associations and timeline index --- 
     TAG_SHELF_ASSOCIATIONS.with(|map_ref| {
         map_ref.borrow_mut().remove(&TypesTagShelfAssociationKey(normalized_tag.clone(), shelf_id.clone()));
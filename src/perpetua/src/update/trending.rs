@@ -0,0 +1,28 @@
+use ic_cdk;
+use std::time::Duration;
+
+use crate::storage::{recompute_trending_scores, recompute_most_viewed_shelves};
+use crate::guard::is_controller;
+
+const TRENDING_REFRESH_INTERVAL_SECS: u64 = 60 * 60; // Every hour
+
+fn recompute_discovery_rankings() {
+    recompute_trending_scores();
+    recompute_most_viewed_shelves();
+}
+
+/// Starts the periodic trending and most-viewed recompute. Timers do not survive upgrades,
+/// so this runs from both `init` and `post_upgrade`.
+pub fn setup_trending_timer() {
+    ic_cdk_timers::set_timer_interval(
+        Duration::from_secs(TRENDING_REFRESH_INTERVAL_SECS),
+        recompute_discovery_rankings,
+    );
+}
+
+/// Recomputes trending tags and shelves, and the most viewed shelves, immediately (controllers only).
+#[ic_cdk::update(guard = "is_controller")]
+pub fn refresh_trending() -> Result<(), String> {
+    recompute_discovery_rankings();
+    Ok(())
+}
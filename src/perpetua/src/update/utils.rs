@@ -1,6 +1,7 @@
 use candid::{Nat, Principal};
 use ic_cdk::api::call::CallResult;
 use icrc_ledger_types::icrc1::account::Account;
+use std::collections::BTreeMap;
 use std::str::FromStr;
 use crate::storage::SHELF_DATA;
 
@@ -18,6 +19,17 @@ use crate::storage::SHELF_DATA;
 /// * `Ok(false)` if the caller does not own the NFT
 /// * `Err(...)` if there was a validation or canister call error
 pub async fn verify_nft_ownership(nft_id: &str, caller: Principal) -> Result<bool, String> {
+    Ok(get_nft_owner(nft_id).await? == caller)
+}
+
+/// Looks up the current owner of an NFT
+/// 
+/// Validates the NFT ID format, then calls `icrc7_owner_of` on the NFT or SBT collection.
+/// 
+/// # Returns
+/// * `Ok(owner)` with the principal owning the NFT
+/// * `Err(...)` if the ID is invalid, the NFT has no owner, or the canister call fails
+pub async fn get_nft_owner(nft_id: &str) -> Result<Principal, String> {
     // First validate that we have a proper NFT ID format
     // NFT IDs should be numerical and quite long
     if !nft_id.chars().all(|c| c.is_digit(10)) {
@@ -56,9 +68,8 @@ pub async fn verify_nft_ownership(nft_id: &str, caller: Principal) -> Result<boo
     
     match owner_call_result {
         Ok((owners,)) => {
-            // Check if the first element matches the caller
             if let Some(Some(account)) = owners.first() {
-                return Ok(account.owner == caller);
+                return Ok(account.owner);
             }
             // No owner returned means NFT doesn't exist
             Err(format!("NFT with ID '{}' not found or has no owner", nft_id))
@@ -98,4 +109,133 @@ pub fn shelf_exists(shelf_id: &str) -> bool {
 pub fn is_self_reference(shelf_id: &str, nested_shelf_id: &str) -> bool {
     // Only check for direct self-references (shelf A cannot contain shelf A)
     shelf_id == nested_shelf_id
-} 
\ No newline at end of file
+}
+
+/// Looks up the owners of several NFTs with as few canister calls as possible
+/// 
+/// IDs are grouped by their target collection (NFT vs SBT, using the same
+/// length rule as `get_nft_owner`) and each group is checked with a
+/// single `icrc7_owner_of` call.
+/// 
+/// # Arguments
+/// * `nft_ids` - The IDs of the NFTs to look up
+/// 
+/// # Returns
+/// * `Ok(vec)` with one owner per input ID, in input order (`None` if the NFT has no owner)
+/// * `Err(...)` if any ID is invalid or a canister call fails
+pub async fn get_nft_owners_batch(nft_ids: &[String]) -> Result<Vec<Option<Principal>>, String> {
+    let mut results = vec![None; nft_ids.len()];
+    let mut nft_group: Vec<(usize, Nat)> = Vec::new();
+    let mut sbt_group: Vec<(usize, Nat)> = Vec::new();
+
+    for (index, nft_id) in nft_ids.iter().enumerate() {
+        if !nft_id.chars().all(|c| c.is_ascii_digit()) {
+            return Err(format!("Invalid NFT ID format: '{}'. The ID must be numeric. You may be trying to use an Arweave transaction ID instead of the actual NFT ID.", nft_id));
+        }
+        if nft_id.len() < 10 {
+            return Err(format!("Invalid NFT ID: '{}'. NFT IDs are typically long numeric strings (>10 digits).", nft_id));
+        }
+        let token_nat = Nat::from_str(nft_id)
+            .map_err(|_| format!("Could not convert '{}' to a valid NFT ID. Make sure you're using the actual NFT ID and not the Arweave transaction ID.", nft_id))?;
+
+        if nft_id.len() > 90 {
+            sbt_group.push((index, token_nat));
+        } else {
+            nft_group.push((index, token_nat));
+        }
+    }
+
+    for (canister_principal, group) in [
+        (crate::icrc7_principal(), nft_group),
+        (crate::icrc7_scion_principal(), sbt_group),
+    ] {
+        fetch_group_owners(canister_principal, group, &mut results).await?;
+    }
+
+    Ok(results)
+}
+
+/// Looks up the owners of tokens from arbitrary ICRC-7 collections, one call per collection
+/// 
+/// # Returns
+/// * `Ok(vec)` with one owner per input token, in input order (`None` if the token has no owner)
+/// * `Err(...)` if a canister call fails
+pub async fn get_icrc7_token_owners_batch(tokens: &[(Principal, Nat)]) -> Result<Vec<Option<Principal>>, String> {
+    let mut results = vec![None; tokens.len()];
+    let mut groups: BTreeMap<Principal, Vec<(usize, Nat)>> = BTreeMap::new();
+
+    for (index, (collection, token_id)) in tokens.iter().enumerate() {
+        groups.entry(*collection).or_default().push((index, token_id.clone()));
+    }
+
+    for (collection, group) in groups {
+        fetch_group_owners(collection, group, &mut results).await?;
+    }
+
+    Ok(results)
+}
+
+/// Verifies that the caller owns a token from an arbitrary ICRC-7 collection
+pub async fn verify_icrc7_token_ownership(collection: Principal, token_id: &Nat, caller: Principal) -> Result<bool, String> {
+    Ok(get_icrc7_token_owner(collection, token_id).await? == caller)
+}
+
+/// Looks up the current owner of a token from an arbitrary ICRC-7 collection
+pub async fn get_icrc7_token_owner(collection: Principal, token_id: &Nat) -> Result<Principal, String> {
+    let owners = get_icrc7_token_owners_batch(&[(collection, token_id.clone())]).await?;
+    owners.first().copied().flatten()
+        .ok_or_else(|| format!("Token '{}' not found or has no owner in collection {}", token_id.0, collection))
+}
+
+/// Queries `icrc7_owner_of` for one group of tokens on a single canister and
+/// writes each token's owner into `results` at its original index.
+async fn fetch_group_owners(
+    canister_principal: Principal,
+    group: Vec<(usize, Nat)>,
+    results: &mut [Option<Principal>],
+) -> Result<(), String> {
+    if group.is_empty() {
+        return Ok(());
+    }
+
+    let token_nats: Vec<Nat> = group.iter().map(|(_, nat)| nat.clone()).collect();
+    let owner_call_result: CallResult<(Vec<Option<Account>>,)> = ic_cdk::call(
+        canister_principal,
+        "icrc7_owner_of",
+        (token_nats,)
+    ).await;
+
+    let owners = match owner_call_result {
+        Ok((owners,)) => owners,
+        Err((code, msg)) => {
+            return Err(format!("Error fetching owners from {} for {} tokens: {:?} - {}", canister_principal, group.len(), code, msg));
+        }
+    };
+
+    if owners.len() != group.len() {
+        return Err(format!("Ownership check on {} returned {} results for {} tokens", canister_principal, owners.len(), group.len()));
+    }
+
+    for ((index, _), owner) in group.iter().zip(owners) {
+        results[*index] = owner.map(|account| account.owner);
+    }
+
+    Ok(())
+}
+
+/// Verifies that the caller holds at least one token from an ICRC-7 collection
+/// 
+/// Uses `icrc7_balance_of` on the caller's default account.
+pub async fn verify_icrc7_collection_holder(collection: Principal, caller: Principal) -> Result<bool, String> {
+    let account = Account { owner: caller, subaccount: None };
+    let balance_call_result: CallResult<(Vec<Nat>,)> = ic_cdk::call(
+        collection,
+        "icrc7_balance_of",
+        (vec![account],)
+    ).await;
+
+    match balance_call_result {
+        Ok((balances,)) => Ok(balances.first().is_some_and(|balance| balance.0 > 0u32.into())),
+        Err((code, msg)) => Err(format!("Error fetching balance from {}: {:?} - {}", collection, code, msg)),
+    }
+}
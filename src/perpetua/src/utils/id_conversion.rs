@@ -6,6 +6,8 @@ use num_bigint::BigUint;
 use sha2::{Sha256, Digest};
 use ic_cdk;
 
+use crate::storage::ItemContent;
+
 // --- Copied from src/nft_manager/src/id_converter.rs ---
 
 // Helper function to hash a Principal (copied from nft_manager)
@@ -70,4 +72,25 @@ pub fn get_original_nft_id_for_storage(id_on_shelf_str: &str) -> String {
             id_on_shelf_str.to_string()
         }
     }
-} 
\ No newline at end of file
+} 
+
+/// Returns the NFT_SHELVES key used to track which shelves an item appears in.
+///
+/// NFTs keep their original-ID key (see `get_original_nft_id_for_storage`). Arweave
+/// transactions and links are keyed by their raw ID / URL, which can never collide with
+/// the purely numeric NFT keys. Tokens from other ICRC-7 collections are keyed as
+/// `<collection>:<token_id>`. Markdown and nested shelves are not tracked.
+pub fn get_appearance_key_for_storage(content: &ItemContent) -> Option<String> {
+    match content {
+        ItemContent::Nft(nft_id) => Some(get_original_nft_id_for_storage(nft_id)),
+        ItemContent::Arweave(tx_id) => Some(tx_id.clone()),
+        ItemContent::Link { url, .. } => Some(url.clone()),
+        ItemContent::Icrc7Token { collection, token_id } => Some(icrc7_token_appearance_key(collection, token_id)),
+        ItemContent::Markdown(_) | ItemContent::Shelf(_) => None,
+    }
+}
+
+/// NFT_SHELVES key for a token from an arbitrary ICRC-7 collection.
+pub fn icrc7_token_appearance_key(collection: &Principal, token_id: &Nat) -> String {
+    format!("{}:{}", collection.to_text(), token_id.0)
+}